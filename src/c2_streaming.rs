@@ -0,0 +1,214 @@
+extern mod glcore;
+extern mod common;
+
+use glcore::*;
+use common::app;
+use common::app::{App, WindowDesc};
+use common::input::{Input, KeyDown, KEY_1, KEY_2, KEY_3};
+use common::buffer::{VertexArray, Buffer, StreamBuffer};
+use common::context::Context;
+use common::shader::{VertexShader, FragmentShader};
+use common::soft::{Emulation, VertexOut};
+use common::watch::WatchedProgram;
+use common::vertex::{ColorVertex, bind_attributes};
+
+/// How many frames' worth of vertices the ring buffer holds.
+const RING_REGIONS: uint = 3;
+
+/// How the vertices are sent to GL every frame.
+enum Strategy {
+    /// Overwriting the buffer in place with `glBufferSubData`.
+    SubData,
+    /// Giving the buffer new storage with `glBufferData`.
+    Orphan,
+    /// Mapping the next region of a ring buffer.
+    Ring,
+}
+
+impl Strategy : ToStr {
+    pure fn to_str(&self) -> ~str {
+        match *self {
+            SubData => ~"glBufferSubData",
+            Orphan => ~"orphaning",
+            Ring => ~"a ring buffer",
+        }
+    }
+}
+
+/**
+ * The triangle `time` seconds in: its corners go round its centre, a turn
+ * every four seconds, and each swings in and out a little behind the one
+ * before. The colors are those of `c2_color_triangle`.
+ */
+fn triangle(time: float) -> ~[ColorVertex] {
+    let colors: [[GLfloat * 3] * 3] = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+    let t = time as GLfloat;
+    let pi = f32::consts::pi;
+
+    do vec::from_fn(3) |i| {
+        // Start at the top and go clockwise, a third of a turn apart
+        let angle = pi / 2.0 - t * pi / 2.0 - i as GLfloat * pi * 2.0 / 3.0;
+        let radius = 0.5 + 0.1 * f32::sin(t * 3.0 + i as GLfloat * 2.0);
+        ColorVertex { position: [f32::cos(angle) * radius, f32::sin(angle) * radius], color: colors[i] }
+    }
+}
+
+struct Streaming {
+    vao: Option<VertexArray>,
+    vbo: Option<Buffer<ColorVertex>>,
+    /// Reads the ring buffer rather than `vbo`.
+    ring_vao: Option<VertexArray>,
+    ring: Option<StreamBuffer<ColorVertex>>,
+    shader_program: Option<WatchedProgram>,
+    strategy: Strategy,
+    time: float,
+}
+
+impl Streaming {
+    /**
+     * Points the vertex data of both vertex arrays at the attributes of
+     * the current program. Called again whenever the program is rebuilt,
+     * as they may have moved.
+     */
+    fn resolve_program(&mut self) {
+        self.vao.get_ref().bind();
+        self.vbo.get_ref().bind();
+        
+        do self.shader_program.get_ref().with_program |program| {
+            // Only the layout of the vertex matters, not its values
+            bind_attributes(program, &ColorVertex { position: [0.0, 0.0], color: [0.0, 0.0, 0.0] });
+        }
+        
+        self.ring_vao.get_ref().bind();
+        self.ring.get_ref().bind();
+        
+        do self.shader_program.get_ref().with_program |program| {
+            bind_attributes(program, &ColorVertex { position: [0.0, 0.0], color: [0.0, 0.0, 0.0] });
+        }
+    }
+}
+
+impl Streaming : App {
+    fn init(&mut self, ctx: &Context) -> Result<(), ~str> {
+        // Create a Vertex Array Object for each way of sending the vertices
+        let vao = VertexArray::new(ctx);
+        let ring_vao = VertexArray::new(ctx);
+        
+        // Create a Vertex Buffer Object holding the first frame's vertices,
+        // which will change often
+        let vbo = Buffer::new(ctx, GL_ARRAY_BUFFER, triangle(0.0), GL_DYNAMIC_DRAW);
+        
+        // And a ring buffer with room for a few frames of them
+        let ring = StreamBuffer::new(ctx, GL_ARRAY_BUFFER, 3, RING_REGIONS);
+        
+        // Load the shaders and link them into a program, which is rebuilt
+        // whenever one of the files is edited
+        let shaderProgram = match WatchedProgram::load(ctx, [(VertexShader, "shaders/c2_streaming.vert"),
+                                                             (FragmentShader, "shaders/c2_streaming.frag")],
+                                                       ["outColor"]) {
+            Ok(move program) => program,
+            Err(move err) => return Err(err.to_str())
+        };
+        shaderProgram.bind();
+        
+        // Stand-ins for the shaders, for running on the software renderer
+        shaderProgram.emulate(Emulation {
+            vertex: |_uniforms, inputs| {
+                let position = inputs.get("position");
+                VertexOut {
+                    position: [position[0], position[1], 0.0, 1.0],
+                    varyings: vec::slice(inputs.get("color"), 0, 3)
+                }
+            },
+            geometry: None,
+            fragment: |_uniforms, color| [color[0], color[1], color[2], 1.0]
+        });
+        
+        self.shader_program = Some(move shaderProgram);
+        self.ring = Some(move ring);
+        self.vbo = Some(move vbo);
+        self.ring_vao = Some(move ring_vao);
+        self.vao = Some(move vao);
+        
+        // Specify the layout of the vertex data
+        self.resolve_program();
+        
+        Ok(())
+    }
+    
+    fn update(&mut self, input: &Input, dt: float) {
+        self.time += dt;
+        
+        // 1 to 3 switch between the ways of sending the vertices
+        for input.events().each |event| {
+            self.strategy = match *event {
+                KeyDown(key) if key == KEY_1 => SubData,
+                KeyDown(key) if key == KEY_2 => Orphan,
+                KeyDown(key) if key == KEY_3 => Ring,
+                _ => loop
+            };
+            io::println(~"Streaming with " + self.strategy.to_str());
+        }
+        
+        // Pick up any edits to the shader files
+        if self.shader_program.get_ref().poll(dt) {
+            self.resolve_program();
+        }
+    }
+    
+    fn render(&self, ctx: &Context) {
+        let gl = ctx.gl();
+        
+        // Clear the screen to black
+        ctx.cache().clear_color(0.1, 0.1, 0.1, 1.0);
+        gl.clear(GL_COLOR_BUFFER_BIT);
+        
+        // Move the vertices on the CPU, then send them to GL
+        let vertices = triangle(self.time);
+        match self.strategy {
+            SubData => {
+                self.vao.get_ref().bind();
+                self.vbo.get_ref().update(0, vertices);
+                gl.draw_arrays(GL_TRIANGLES, 0, 3);
+            }
+            Orphan => {
+                self.vao.get_ref().bind();
+                self.vbo.get_ref().orphan(vertices);
+                gl.draw_arrays(GL_TRIANGLES, 0, 3);
+            }
+            Ring => {
+                // Draw from the region just written, then fence it off
+                // until GL is done drawing
+                let ring = self.ring.get_ref();
+                self.ring_vao.get_ref().bind();
+                let first = ring.write(vertices);
+                gl.draw_arrays(GL_TRIANGLES, first as GLint, 3);
+                ring.fence();
+            }
+        }
+    }
+    
+    fn cleanup(&mut self) {
+        self.shader_program = None;
+        
+        self.ring = None;
+        self.vbo = None;
+        
+        self.ring_vao = None;
+        self.vao = None;
+    }
+}
+
+fn main() {
+    do app::run(WindowDesc::new("OpenGL", 800, 600)) {
+        Streaming {
+            vao: None,
+            vbo: None,
+            ring_vao: None,
+            ring: None,
+            shader_program: None,
+            strategy: Ring,
+            time: 0.0,
+        }
+    }
+}
@@ -1,157 +1,195 @@
-extern mod glfw3;
 extern mod glcore;
-
-use cast::transmute;
-use ptr::{is_null, null, to_unsafe_ptr};
-use str::as_c_str;
-use sys::size_of;
-use vec::raw::to_ptr;
+extern mod common;
 
 use glcore::*;
+use common::app;
+use common::app::{App, WindowDesc};
+use common::input::Input;
+use common::buffer::{VertexArray, Buffer, IndexBuffer};
+use common::context::Context;
+use common::shader::{VertexShader, FragmentShader};
+use common::soft::{Emulation, VertexOut};
+use common::watch::WatchedProgram;
+use common::vertex::{ColorVertex, bind_attributes};
 
-fn main() {
-    do task::task().sched_mode(task::PlatformThread).spawn {
-        if (glfw3::init() == 0) {
-            glfw3::terminate();
-            fail(~"glfwInit() failed\n");
-        }
-        
-        // Choose a GL profile that is compatible with OS X 10.7+
-        glfw3::window_hint(glfw3::OPENGL_VERSION_MAJOR, 3);
-        glfw3::window_hint(glfw3::OPENGL_VERSION_MINOR, 2);
-        glfw3::window_hint(glfw3::OPENGL_PROFILE, glfw3::OPENGL_CORE_PROFILE);
-        glfw3::window_hint(glfw3::OPENGL_FORWARD_COMPAT, 1);
-        
-        let mut window = glfw3::create_window(800, 600, glfw3::WINDOWED, ~"OpenGL");
-        
-        if (is_null(window.ptr)) {
-            glfw3::terminate();
-            io::println(~"Error: " + glfw3::error_string(glfw3::get_error()));
-            fail(~"glfwOpenWindow() failed\n");
+struct TriangleElements {
+    vao: Option<VertexArray>,
+    vbo: Option<Buffer<ColorVertex>>,
+    ebo: Option<IndexBuffer>,
+    shader_program: Option<WatchedProgram>,
+}
+
+impl TriangleElements {
+    /**
+     * Points the vertex data at the attributes of the current program.
+     * Called again whenever the program is rebuilt, as they may have moved.
+     */
+    fn resolve_program(&mut self) {
+        self.vao.get_ref().bind();
+        self.vbo.get_ref().bind();
+        
+        do self.shader_program.get_ref().with_program |program| {
+            // Only the layout of the vertex matters, not its values
+            bind_attributes(program, &ColorVertex { position: [0.0, 0.0], color: [0.0, 0.0, 0.0] });
         }
-        
-        window.make_context_current();
-        
+    }
+}
+
+impl TriangleElements : App {
+    fn init(&mut self, ctx: &Context) -> Result<(), ~str> {
         // Create Vertex Array Object
-        let vao: GLuint = 0;
-        glGenVertexArrays(1, to_unsafe_ptr(&vao));
-        glBindVertexArray(vao);
+        let vao = VertexArray::new(ctx);
+        vao.bind();
         
         // Create a Vertex Buffer Object and copy the vertex data to it
-        let vbo: GLuint = 0;
-        glGenBuffers(1, to_unsafe_ptr(&vbo));
-        
-        let vertices: [GLfloat * 20] = [
-            -0.5,  0.5,   1.0, 0.0, 0.0, // Top-left
-             0.5,  0.5,   0.0, 1.0, 0.0, // Top-right
-             0.5, -0.5,   0.0, 0.0, 1.0, // Bottom-right
-            -0.5, -0.5,   1.0, 1.0, 1.0  // Bottom-left
+        let vertices: [ColorVertex * 4] = [
+            ColorVertex { position: [-0.5,  0.5], color: [1.0, 0.0, 0.0] }, // Top-left
+            ColorVertex { position: [ 0.5,  0.5], color: [0.0, 1.0, 0.0] }, // Top-right
+            ColorVertex { position: [ 0.5, -0.5], color: [0.0, 0.0, 1.0] }, // Bottom-right
+            ColorVertex { position: [-0.5, -0.5], color: [1.0, 1.0, 1.0] }  // Bottom-left
         ];
         
-        glBindBuffer(GL_ARRAY_BUFFER, vbo);
-        unsafe {
-            glBufferData(GL_ARRAY_BUFFER,
-                         (vertices.len() * size_of::<GLfloat>()) as GLsizeiptr,
-                         transmute(to_ptr(vertices)),
-                         GL_STATIC_DRAW);
-        }
+        let vbo = Buffer::new(ctx, GL_ARRAY_BUFFER, vertices, GL_STATIC_DRAW);
         
         // Create an element array
-        let ebo: GLuint = 0;
-        glGenBuffers(1, to_unsafe_ptr(&ebo));
-        
         let elements: [GLuint*6] = [
             0, 1, 2,
             2, 3, 0
         ];
         
-        glBindBuffer(GL_ELEMENT_ARRAY_BUFFER, ebo);
-        unsafe {
-            glBufferData(GL_ELEMENT_ARRAY_BUFFER,
-                         (elements.len() * size_of::<GLfloat>()) as GLsizeiptr,
-                         transmute(to_ptr(elements)),
-                         GL_STATIC_DRAW);
-        }
-        
-        // Shader sources
-        let vertexSource =
-          ~"#version 150\n\
-            in vec2 position;\n\
-            in vec3 color;\n\
-            out vec3 Color;\n\
-            void main() {\n\
-                Color = color;\n\
-                gl_Position = vec4(position, 0.0, 1.0);\n\
-            }";
-
-        let fragmentSource =
-          ~"#version 150\n\
-            in vec3 Color;\n\
-            out vec4 outColor;\n\
-            void main() {\n\
-                outColor = vec4(Color, 1.0);\n\
-            }";
-
-        // Create and compile the vertex shader
-        let vertexShader = glCreateShader(GL_VERTEX_SHADER);
-        do as_c_str(vertexSource) |data| {
-            glShaderSource(vertexShader, 1, to_unsafe_ptr(&data), null());
-            glCompileShader(vertexShader);
-        }
-        
-        // Create and compile the fragment shader
-        let fragmentShader = glCreateShader(GL_FRAGMENT_SHADER);
-        do as_c_str(fragmentSource) |data| {
-            glShaderSource(fragmentShader, 1, to_unsafe_ptr(&data), null());
-            glCompileShader(fragmentShader);
-        }
-        
-        // Link the vertex and fragment shader into a shader program
-        let shaderProgram = glCreateProgram();
-        glAttachShader(shaderProgram, vertexShader);
-        glAttachShader(shaderProgram, fragmentShader);
-        glBindFragDataLocation(shaderProgram, 0, as_c_str("outColor", |s| s));
-        glLinkProgram(shaderProgram);
-        glUseProgram(shaderProgram);
+        let ebo = IndexBuffer::narrowest(ctx, elements, GL_STATIC_DRAW);
+        
+        // Load the shaders and link them into a program, which is rebuilt
+        // whenever one of the files is edited
+        let shaderProgram = match WatchedProgram::load(ctx, [(VertexShader, "shaders/c2_triangle_elements.vert"),
+                                                             (FragmentShader, "shaders/c2_triangle_elements.frag")],
+                                                       ["outColor"]) {
+            Ok(move program) => program,
+            Err(move err) => return Err(err.to_str())
+        };
+        shaderProgram.bind();
+        
+        // Stand-ins for the shaders, for running on the software renderer
+        shaderProgram.emulate(Emulation {
+            vertex: |_uniforms, inputs| {
+                let position = inputs.get("position");
+                VertexOut {
+                    position: [position[0], position[1], 0.0, 1.0],
+                    varyings: vec::slice(inputs.get("color"), 0, 3)
+                }
+            },
+            geometry: None,
+            fragment: |_uniforms, color| [color[0], color[1], color[2], 1.0]
+        });
+        
+        self.shader_program = Some(move shaderProgram);
+        self.ebo = Some(move ebo);
+        self.vbo = Some(move vbo);
+        self.vao = Some(move vao);
         
         // Specify the layout of the vertex data
-        let posAttrib = glGetAttribLocation(shaderProgram, as_c_str("position", |s| s)) as GLuint;
-        glEnableVertexAttribArray(posAttrib);
-        glVertexAttribPointer(posAttrib, 2, GL_FLOAT, GL_FALSE,
-                              5 * size_of::<GLfloat>() as GLsizei,
-                              null());
+        self.resolve_program();
         
-        let colAttrib = glGetAttribLocation(shaderProgram, as_c_str("color", |s| s)) as GLuint;
-        glEnableVertexAttribArray(colAttrib);
-        unsafe {
-            glVertexAttribPointer(colAttrib, 3, GL_FLOAT, GL_FALSE,
-                                  5 * size_of::<GLfloat>() as GLsizei,
-                                  transmute(2 * size_of::<GLfloat>() as uint));
-        }
-        
-        while window.get_param(glfw3::CLOSE_REQUESTED) == 0 {
-            // Poll events
-            glfw3::poll_events();
-            
-            // Clear the screen to black
-            glClearColor(0.1, 0.1, 0.1, 1.0);
-            glClear(GL_COLOR_BUFFER_BIT);
-        
-            // Draw a rectangle from the 2 triangles using 6 indices
-            glDrawElements(GL_TRIANGLES, 6, GL_UNSIGNED_INT, null());
-            
-            // Swap buffers
-            window.swap_buffers();
+        Ok(())
+    }
+    
+    fn update(&mut self, _input: &Input, dt: float) {
+        // Pick up any edits to the shader files
+        if self.shader_program.get_ref().poll(dt) {
+            self.resolve_program();
         }
+    }
+    
+    fn render(&self, ctx: &Context) {
+        let gl = ctx.gl();
         
-        glDeleteProgram(shaderProgram);
-        glDeleteShader(fragmentShader);
-        glDeleteShader(vertexShader);
+        // Clear the screen to black
+        ctx.cache().clear_color(0.1, 0.1, 0.1, 1.0);
+        gl.clear(GL_COLOR_BUFFER_BIT);
         
-        glDeleteBuffers(1, to_unsafe_ptr(&vbo));
+        // Draw a rectangle from the 2 triangles using 6 indices
+        self.ebo.get_ref().draw(GL_TRIANGLES);
+    }
+    
+    fn cleanup(&mut self) {
+        self.shader_program = None;
         
-        glDeleteVertexArrays(1, to_unsafe_ptr(&vao));
+        self.ebo = None;
+        self.vbo = None;
         
-        glfw3::terminate();
+        self.vao = None;
+    }
+}
+
+fn main() {
+    do app::run(WindowDesc::new("OpenGL", 800, 600)) {
+        TriangleElements {
+            vao: None,
+            vbo: None,
+            ebo: None,
+            shader_program: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glcore::*;
+    use common::app::App;
+    use common::context::Context;
+    use common::gl::Gl;
+    use common::mock::{MockGl, Arg, Enum, Int, Data};
+    use TriangleElements;
+
+    fn is_enum(arg: &Arg, e: GLenum) -> bool {
+        match *arg { Enum(x) => x == e, _ => false }
+    }
+
+    fn is_int(arg: &Arg, i: int) -> bool {
+        match *arg { Int(x) => x == i, _ => false }
+    }
+
+    /// The chapter draws its rectangle from an element buffer of 6 indices,
+    /// bound before `glDrawElements`. Run from `src`, so the shaders are found.
+    #[test]
+    fn draws_from_the_element_buffer() {
+        let mock = @MockGl::new();
+        let ctx = Context::new(mock as @Gl, 800, 600);
+        {
+            let mut app = TriangleElements { vao: None, vbo: None, ebo: None, shader_program: None };
+            assert app.init(&ctx).is_ok();
+            app.render(&ctx);
+            app.cleanup();
+        }
+        ctx.destroy();
+
+        let calls = mock.calls();
+        assert mock.errors().is_empty();
+
+        // The indices are uploaded once, a byte each as they are all small
+        let uploads = mock.calls_to("glBufferData").filtered(|c| is_enum(&c.args[0], GL_ELEMENT_ARRAY_BUFFER));
+        assert uploads.len() == 1;
+        match uploads[0].args[1] {
+            Data(ref bytes) => assert *bytes == ~[0, 1, 2, 2, 3, 0],
+            _ => fail(~"glBufferData without data")
+        }
+        let ebo = uploads[0].state.element_array_buffer;
+        assert ebo != 0;
+
+        // And drawn as 6 indices of that type from the start of the buffer
+        let draws = mock.calls_to("glDrawElements");
+        assert draws.len() == 1;
+        let draw = &draws[0];
+        assert is_enum(&draw.args[0], GL_TRIANGLES) && is_int(&draw.args[1], 6);
+        assert is_enum(&draw.args[2], GL_UNSIGNED_BYTE) && is_int(&draw.args[3], 0);
+        assert draw.state.element_array_buffer == ebo;
+
+        // With the element buffer bound before the draw
+        let bound = vec::position(calls, |c| {
+            str::eq_slice(c.name, "glBindBuffer") && is_enum(&c.args[0], GL_ELEMENT_ARRAY_BUFFER) &&
+                is_int(&c.args[1], ebo as int)
+        });
+        let drawn = vec::position(calls, |c| str::eq_slice(c.name, "glDrawElements"));
+        assert bound.is_some() && drawn.is_some() && bound.get() < drawn.get();
     }
-}
\ No newline at end of file
+}
@@ -1,135 +1,137 @@
-extern mod glfw3;
 extern mod glcore;
+extern mod common;
 
-use cast::transmute;
 use float::sin;
-use ptr::{is_null, null, to_unsafe_ptr};
-use str::as_c_str;
-use sys::size_of;
-use vec::raw::to_ptr;
 
 use glcore::*;
+use common::app;
+use common::app::{App, WindowDesc};
+use common::input::Input;
+use common::buffer::{VertexArray, Buffer};
+use common::context::Context;
+use common::shader::{VertexShader, FragmentShader};
+use common::soft::{Emulation, VertexOut};
+use common::uniform::Uniform;
+use common::watch::WatchedProgram;
+use common::vertex::{Vertex2, bind_attributes};
 
-fn main() {
-    do task::task().sched_mode(task::PlatformThread).spawn {
-        if (glfw3::init() == 0) {
-            glfw3::terminate();
-            fail(~"glfwInit() failed\n");
-        }
-        
-        // Choose a GL profile that is compatible with OS X 10.7+
-        glfw3::window_hint(glfw3::OPENGL_VERSION_MAJOR, 3);
-        glfw3::window_hint(glfw3::OPENGL_VERSION_MINOR, 2);
-        glfw3::window_hint(glfw3::OPENGL_PROFILE, glfw3::OPENGL_CORE_PROFILE);
-        glfw3::window_hint(glfw3::OPENGL_FORWARD_COMPAT, 1);
-        
-        let mut window = glfw3::create_window(800, 600, glfw3::WINDOWED, ~"OpenGL");
-        
-        if (is_null(window.ptr)) {
-            glfw3::terminate();
-            io::println(~"Error: " + glfw3::error_string(glfw3::get_error()));
-            fail(~"glfwOpenWindow() failed\n");
-        }
-        
-        window.make_context_current();
-        
+struct TriangleUniform {
+    vao: Option<VertexArray>,
+    vbo: Option<Buffer<Vertex2>>,
+    shader_program: Option<WatchedProgram>,
+    uni_color: Option<Uniform<[GLfloat * 3]>>,
+    time: float,
+}
+
+impl TriangleUniform {
+    /**
+     * Points the vertex data at the attributes of the current program and
+     * looks up its uniforms. Called again whenever the program is rebuilt,
+     * as they may have moved.
+     */
+    fn resolve_program(&mut self) {
+        self.vao.get_ref().bind();
+        self.vbo.get_ref().bind();
+        
+        self.uni_color = Some(do self.shader_program.get_ref().with_program |program| {
+            // Only the layout of the vertex matters, not its values
+            bind_attributes(program, &Vertex2 { position: [0.0, 0.0] });
+            
+            Uniform::lookup(program, "triangleColor")
+        });
+    }
+}
+
+impl TriangleUniform : App {
+    fn init(&mut self, ctx: &Context) -> Result<(), ~str> {
         // Create Vertex Array Object
-        let vao: GLuint = 0;
-        glGenVertexArrays(1, to_unsafe_ptr(&vao));
-        glBindVertexArray(vao);
+        let vao = VertexArray::new(ctx);
+        vao.bind();
         
         // Create a Vertex Buffer Object and copy the vertex data to it
-        let vbo: GLuint = 0;
-        glGenBuffers(1, to_unsafe_ptr(&vbo));
-        
-        let vertices: [GLfloat * 6] = [
-             0.0,  0.5,
-             0.5, -0.5,
-            -0.5, -0.5
+        let vertices: [Vertex2 * 3] = [
+            Vertex2 { position: [ 0.0,  0.5] },
+            Vertex2 { position: [ 0.5, -0.5] },
+            Vertex2 { position: [-0.5, -0.5] }
         ];
         
-        glBindBuffer(GL_ARRAY_BUFFER, vbo);
+        let vbo = Buffer::new(ctx, GL_ARRAY_BUFFER, vertices, GL_STATIC_DRAW);
+        
+        // Load the shaders and link them into a program, which is rebuilt
+        // whenever one of the files is edited
+        let shaderProgram = match WatchedProgram::load(ctx, [(VertexShader, "shaders/c2_triangle_uniform.vert"),
+                                                             (FragmentShader, "shaders/c2_triangle_uniform.frag")],
+                                                       ["outColor"]) {
+            Ok(move program) => program,
+            Err(move err) => return Err(err.to_str())
+        };
+        shaderProgram.bind();
+        
+        // Stand-ins for the shaders, for running on the software renderer
+        shaderProgram.emulate(Emulation {
+            vertex: |_uniforms, inputs| {
+                let position = inputs.get("position");
+                VertexOut { position: [position[0], position[1], 0.0, 1.0], varyings: ~[] }
+            },
+            geometry: None,
+            fragment: |uniforms, _varyings| {
+                let color = uniforms.vec3("triangleColor");
+                [color[0], color[1], color[2], 1.0]
+            }
+        });
+        
+        self.shader_program = Some(move shaderProgram);
+        self.vbo = Some(move vbo);
+        self.vao = Some(move vao);
+        
+        // Specify the layout of the vertex data and look up the uniforms
+        self.resolve_program();
+        
+        Ok(())
+    }
+    
+    fn update(&mut self, _input: &Input, dt: float) {
+        self.time += dt;
         
-        unsafe {
-            glBufferData(GL_ARRAY_BUFFER,
-                         (vertices.len() * size_of::<GLfloat>()) as GLsizeiptr,
-                         transmute(to_ptr(vertices)),
-                         GL_STATIC_DRAW);
+        // Pick up any edits to the shader files
+        if self.shader_program.get_ref().poll(dt) {
+            self.resolve_program();
         }
+    }
+    
+    fn render(&self, ctx: &Context) {
+        let gl = ctx.gl();
         
-        // Shader sources
-        let vertexSource =
-          ~"#version 150\n\
-            in vec2 position;\n\
-            void main() {\n\
-               gl_Position = vec4(position, 0.0, 1.0);\n\
-            }";
-
-        let fragmentSource =
-          ~"#version 150\n\
-            out vec4 outColor;\n\
-            uniform vec3 triangleColor;\n
-            void main() {\n\
-               outColor = vec4(triangleColor, 1.0);\n\
-            }";
-
-        // Create and compile the vertex shader
-        let vertexShader = glCreateShader(GL_VERTEX_SHADER);
-        do as_c_str(vertexSource) |data| {
-            glShaderSource(vertexShader, 1, to_unsafe_ptr(&data), null());
-            glCompileShader(vertexShader);
-        }
+        // Clear the screen to black
+        ctx.cache().clear_color(0.1, 0.1, 0.1, 1.0);
+        gl.clear(GL_COLOR_BUFFER_BIT);
         
-        // Create and compile the fragment shader
-        let fragmentShader = glCreateShader(GL_FRAGMENT_SHADER);
-        do as_c_str(fragmentSource) |data| {
-            glShaderSource(fragmentShader, 1, to_unsafe_ptr(&data), null());
-            glCompileShader(fragmentShader);
-        }
+        // Set the color of the triangle
+        self.uni_color.get_ref().set(&[((sin(self.time * 4.0) + 1.0) / 2.0) as GLfloat, 0.0, 0.0]);
         
-        // Link the vertex and fragment shader into a shader program
-        let shaderProgram = glCreateProgram();
-        glAttachShader(shaderProgram, vertexShader);
-        glAttachShader(shaderProgram, fragmentShader);
-        glBindFragDataLocation(shaderProgram, 0, as_c_str("outColor", |s| s));
-        glLinkProgram(shaderProgram);
-        glUseProgram(shaderProgram);
+        // Draw a triangle from the 3 vertices
+        gl.draw_arrays(GL_TRIANGLES, 0, 3);
+    }
+    
+    fn cleanup(&mut self) {
+        self.uni_color = None;
         
-        // Specify the layout of the vertex data
-        let posAttrib = glGetAttribLocation(shaderProgram, as_c_str("position", |s| s)) as GLuint;
-        glEnableVertexAttribArray(posAttrib);
-        glVertexAttribPointer(posAttrib, 2, GL_FLOAT, GL_FALSE, 0, null());
-
-        // Get the location of the color uniform
-        let uniColor = glGetUniformLocation(shaderProgram, as_c_str("triangleColor", |s| s));
+        self.shader_program = None;
         
-        while window.get_param(glfw3::CLOSE_REQUESTED) == 0 {
-            // Poll events
-            glfw3::poll_events();
-            
-            // Set the color of the triangle
-            let time = glfw3::get_time() as float;
-            glUniform3f(uniColor, ((sin(time * 4.0) + 1.0) / 2.0) as GLfloat, 0.0, 0.0);
-            
-            // Clear the screen to black
-            glClearColor(0.1, 0.1, 0.1, 1.0);
-            glClear(GL_COLOR_BUFFER_BIT);
+        self.vbo = None;
         
-            // Draw a triangle from the 3 vertices
-            glDrawArrays(GL_TRIANGLES, 0, 3);
-            
-            // Swap buffers
-            window.swap_buffers();
+        self.vao = None;
+    }
+}
+
+fn main() {
+    do app::run(WindowDesc::new("OpenGL", 800, 600)) {
+        TriangleUniform {
+            vao: None,
+            vbo: None,
+            shader_program: None,
+            uni_color: None,
+            time: 0.0,
         }
-        
-        glDeleteProgram(shaderProgram);
-        glDeleteShader(fragmentShader);
-        glDeleteShader(vertexShader);
-        
-        glDeleteBuffers(1, to_unsafe_ptr(&vbo));
-        
-        glDeleteVertexArrays(1, to_unsafe_ptr(&vao));
-        
-        glfw3::terminate();
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,388 @@
+extern mod glcore;
+extern mod lmath;
+extern mod numeric;
+extern mod common;
+
+use glcore::*;
+use lmath::vec::vec3::Vec3;
+use lmath::mat::mat3::Mat3;
+use lmath::mat::mat4::Mat4;
+use numeric::types::angle::*;
+use common::app;
+use common::app::{App, WindowDesc};
+use common::block::{UniformBlock, Std140Writer, UniformBuffer, BindingPoints};
+use common::camera::{perspective, aspect_ratio};
+use common::controller::CameraController;
+use common::buffer::{VertexArray, Buffer, IndexBuffer};
+use common::context::Context;
+use common::input::Input;
+use common::texture::{Texture2D, Linear, Downsampled};
+use common::mipmap::LanczosFilter;
+use common::sampler::{Sampler, SamplerDesc, ClampToEdge};
+use common::preprocess::Preprocessor;
+use common::shader::{VertexShader, FragmentShader};
+use common::soft::{Emulation, VertexOut, transform};
+use common::uniform::{Uniform, Sampler2D};
+use common::watch::WatchedProgram;
+use common::vertex::{VertexFormat, Attribute, TexturedVertex, attribute, bind_attributes,
+                     bind_instance_attributes};
+
+/// How much of the second texture shows through the first.
+const MIX_FACTOR: float = 0.5;
+/// The quads are laid out in a square of `GRID` by `GRID`.
+const GRID: uint = 40;
+/// The distance between the centres of neighbouring quads.
+const SPACING: GLfloat = 0.075;
+/// The width of each quad, a little less than the spacing so that they
+/// just miss each other as they turn.
+const QUAD_SIZE: GLfloat = 0.05;
+
+/// The contents of the `Transform` block of the vertex shader.
+struct Transform {
+    model: Mat4<GLfloat>,
+    view: Mat4<GLfloat>,
+    proj: Mat4<GLfloat>,
+}
+
+impl Transform : UniformBlock {
+    fn write_members(&self, writer: &mut Std140Writer) {
+        writer.member(&self.model);
+        writer.member(&self.view);
+        writer.member(&self.proj);
+    }
+}
+
+/// What each instance of the quad reads from the instance buffer.
+struct Instance {
+    /// Places the turning quad in the grid.
+    model: Mat4<GLfloat>,
+}
+
+impl Instance : VertexFormat {
+    fn attributes(&self) -> ~[Attribute] {
+        ~[
+            attribute("instanceModel", self, &self.model),
+        ]
+    }
+}
+
+/**
+ * The instances of the quad, row by row: each shrunk, turned by an angle
+ * of its own so they do not all turn in step, and moved to its place in
+ * the grid, which is centred on the origin.
+ */
+fn grid() -> ~[Instance] {
+    let mut instances = ~[];
+    for uint::range(0, GRID) |row| {
+        for uint::range(0, GRID) |column| {
+            let x = (column as GLfloat - (GRID - 1) as GLfloat / 2.0) * SPACING;
+            let y = (row as GLfloat - (GRID - 1) as GLfloat / 2.0) * SPACING;
+            let angle = ((row * GRID + column) * 37 % 360) as GLfloat * f32::consts::pi / 180.0;
+            let (c, s) = (f32::cos(angle) * QUAD_SIZE, f32::sin(angle) * QUAD_SIZE);
+            instances.push(Instance {
+                model: Mat4::new(  c,   s,       0.0, 0.0,
+                                  -s,   c,       0.0, 0.0,
+                                 0.0, 0.0, QUAD_SIZE, 0.0,
+                                   x,   y,       0.0, 1.0)
+            });
+        }
+    }
+    move instances
+}
+
+struct Instancing {
+    vao: Option<VertexArray>,
+    vbo: Option<Buffer<TexturedVertex>>,
+    instances: Option<Buffer<Instance>>,
+    ebo: Option<IndexBuffer>,
+    textures: ~[Texture2D],
+    sampler: Option<Sampler>,
+    shader_program: Option<WatchedProgram>,
+    transform: Option<UniformBuffer<Transform>>,
+    bindings: BindingPoints,
+    camera: CameraController,
+    time: float,
+}
+
+impl Instancing {
+    /**
+     * Points the vertex and instance data at the attributes of the current
+     * program and looks up its uniforms and blocks. Called again whenever
+     * the program is rebuilt, as they may have moved.
+     */
+    fn resolve_program(&mut self) {
+        self.vao.get_ref().bind();
+        self.vbo.get_ref().bind();
+        
+        let binding = self.bindings.point("Transform");
+        do self.shader_program.get_ref().with_program |program| {
+            // Only the layout of the vertex matters, not its values
+            bind_attributes(program, &TexturedVertex { position: [0.0, 0.0], color: [0.0, 0.0, 0.0],
+                                                       texcoord: [0.0, 0.0] });
+        
+            // Tell each sampler uniform which texture unit to read
+            let tex_kitten: Uniform<Sampler2D> = Uniform::lookup(program, "texKitten");
+            tex_kitten.set(&Sampler2D { unit: 0 });
+            let tex_puppy: Uniform<Sampler2D> = Uniform::lookup(program, "texPuppy");
+            tex_puppy.set(&Sampler2D { unit: 1 });
+        
+            // Read the Transform block from the buffer bound for it
+            match program.bind_block("Transform", binding) {
+                Ok(()) => (),
+                Err(move err) => io::println(~"Warning: " + err.to_str())
+            }
+        }
+        
+        // The instance data comes from a buffer of its own
+        self.instances.get_ref().bind();
+        do self.shader_program.get_ref().with_program |program| {
+            bind_instance_attributes(program, &Instance { model: Mat4::identity() });
+        }
+    }
+}
+
+impl Instancing : App {
+    fn init(&mut self, ctx: &Context) -> Result<(), ~str> {
+        // Create Vertex Array Object
+        let vao = VertexArray::new(ctx);
+        vao.bind();
+        
+        // Create a Vertex Buffer Object and copy the vertex data to it
+        let vertices: [TexturedVertex * 4] = [
+            TexturedVertex { position: [-0.5,  0.5], color: [1.0, 0.0, 0.0], texcoord: [0.0, 0.0] }, // Top-left
+            TexturedVertex { position: [ 0.5,  0.5], color: [0.0, 1.0, 0.0], texcoord: [1.0, 0.0] }, // Top-right
+            TexturedVertex { position: [ 0.5, -0.5], color: [0.0, 0.0, 1.0], texcoord: [1.0, 1.0] }, // Bottom-right
+            TexturedVertex { position: [-0.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [0.0, 1.0] }  // Bottom-left
+        ];
+        
+        let vbo = Buffer::new(ctx, GL_ARRAY_BUFFER, vertices, GL_STATIC_DRAW);
+        
+        // Create a second buffer holding where each instance of the quad goes
+        let instances = Buffer::new(ctx, GL_ARRAY_BUFFER, grid(), GL_STATIC_DRAW);
+        
+        // Create an element array
+        let elements: [GLuint * 6] = [
+            0, 1, 2,
+            2, 3, 0
+        ];
+        
+        let ebo = IndexBuffer::narrowest(ctx, elements, GL_STATIC_DRAW);
+        
+        // Load the shaders and link them into a program, which is rebuilt
+        // whenever one of the files is edited
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.define("MIX_FACTOR", float::to_str(MIX_FACTOR, 3));
+        let shaders = [(VertexShader, "shaders/c4_instancing.vert"),
+                       (FragmentShader, "shaders/c4_instancing.frag")];
+        let shaderProgram = match WatchedProgram::load_with(ctx, move preprocessor, shaders, ["outColor"]) {
+            Ok(move program) => program,
+            Err(move err) => return Err(err.to_str())
+        };
+        shaderProgram.bind();
+        
+        // Stand-ins for the shaders, for running on the software renderer
+        shaderProgram.emulate(Emulation {
+            vertex: |uniforms, inputs| {
+                let position = inputs.get("position");
+                let turned = transform(uniforms.mat4("model"), [position[0], position[1], 0.0, 1.0]);
+                let world = transform(inputs.get("instanceModel"), turned);
+                VertexOut {
+                    position: transform(uniforms.mat4("proj"), transform(uniforms.mat4("view"), world)),
+                    varyings: vec::slice(inputs.get("color"), 0, 3) +
+                              vec::slice(inputs.get("texcoord"), 0, 2)
+                }
+            },
+            geometry: None,
+            fragment: |uniforms, varyings| {
+                let kitten = uniforms.texture("texKitten", varyings[3], varyings[4]);
+                let puppy = uniforms.texture("texPuppy", varyings[3], varyings[4]);
+                let t = MIX_FACTOR as f32;
+                [kitten[0] + (puppy[0] - kitten[0]) * t, kitten[1] + (puppy[1] - kitten[1]) * t,
+                 kitten[2] + (puppy[2] - kitten[2]) * t, kitten[3] + (puppy[3] - kitten[3]) * t]
+            }
+        });
+        
+        // Load textures
+        let kitten = match Texture2D::from_file(ctx, "resources/sample.png", Linear, Downsampled(LanczosFilter)) {
+            Ok(move texture) => texture,
+            Err(move err) => return Err(err.to_str())
+        };
+        
+        let puppy = match Texture2D::from_file(ctx, "resources/sample2.png", Linear, Downsampled(LanczosFilter)) {
+            Ok(move texture) => texture,
+            Err(move err) => return Err(err.to_str())
+        };
+        
+        // Create a buffer for the Transform block, rewritten every frame
+        let identity = Mat4::identity();
+        let transform = UniformBuffer::new(ctx, &Transform { model: identity, view: identity, proj: identity },
+                                           GL_DYNAMIC_DRAW);
+        transform.bind_to(self.bindings.point("Transform"));
+        
+        // Clamp to the edges and filter trilinearly between the mipmaps,
+        // which the quads, this small, are mostly drawn from
+        let sampler = Sampler::new(ctx, &SamplerDesc::trilinear(ClampToEdge));
+        
+        kitten.bind_to(0);
+        sampler.bind_to(0);
+        puppy.bind_to(1);
+        sampler.bind_to(1);
+        
+        self.transform = Some(move transform);
+        self.textures = ~[move kitten, move puppy];
+        self.sampler = Some(move sampler);
+        self.shader_program = Some(move shaderProgram);
+        self.ebo = Some(move ebo);
+        self.instances = Some(move instances);
+        self.vbo = Some(move vbo);
+        self.vao = Some(move vao);
+        
+        // Specify the layout of the vertex and instance data and look up
+        // the uniforms
+        self.resolve_program();
+        
+        Ok(())
+    }
+    
+    fn update(&mut self, input: &Input, dt: float) {
+        self.time += dt;
+        
+        // Orbit or fly around the grid with the mouse and keyboard
+        self.camera.update(input, dt);
+        
+        // Pick up any edits to the shader files
+        if self.shader_program.get_ref().poll(dt) {
+            self.resolve_program();
+        }
+    }
+    
+    fn render(&self, ctx: &Context) {
+        let gl = ctx.gl();
+        
+        // Clear the screen to black
+        ctx.cache().clear_color(0.1, 0.1, 0.1, 1.0);
+        gl.clear(GL_COLOR_BUFFER_BIT);
+        
+        // Calculate the turn every quad makes about its own centre
+        let model: Mat4<GLfloat> = Mat3::from_axis_angle(
+            &Vec3::new(
+                0.0 as GLfloat,
+                0.0 as GLfloat,
+                1.0 as GLfloat),
+            Degrees(self.time as GLfloat * 180.0)
+        ).to_mat4();
+        
+        // Look through the camera, with the z axis up
+        let view = self.camera.view();
+        
+        // Project with a 45 degree field of view, stretched to the framebuffer
+        let (width, height) = ctx.framebuffer_size();
+        let proj = perspective(45.0, aspect_ratio(width, height), 1.0, 10.0);
+        
+        // Upload the transformations to the Transform block
+        self.transform.get_ref().update(&Transform { model: model, view: view, proj: proj });
+        
+        // Draw the rectangle once for every instance, in a single call
+        self.ebo.get_ref().draw_instanced(GL_TRIANGLES, self.instances.get_ref().len());
+    }
+    
+    fn cleanup(&mut self) {
+        self.transform = None;
+        self.textures = ~[];
+        self.sampler = None;
+        
+        self.shader_program = None;
+        
+        self.ebo = None;
+        self.instances = None;
+        self.vbo = None;
+        
+        self.vao = None;
+    }
+}
+
+fn main() {
+    do app::run(WindowDesc::new("OpenGL", 800, 600)) {
+        Instancing {
+            vao: None,
+            vbo: None,
+            instances: None,
+            ebo: None,
+            textures: ~[],
+            sampler: None,
+            shader_program: None,
+            transform: None,
+            bindings: BindingPoints::new(),
+            // Start above and to one side of the grid, looking at its centre
+            camera: CameraController::orbit(&Vec3::new(2.5f32, 2.5f32, 2.5f32),
+                                            &Vec3::new(0.0f32, 0.0f32, 0.0f32)),
+            time: 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glcore::*;
+    use lmath::mat::mat4::Mat4;
+    use common::buffer::{VertexArray, Buffer};
+    use common::context::Context;
+    use common::gl::Gl;
+    use common::mock::MockGl;
+    use common::shader::{Shader, Program, Source, VertexShader, FragmentShader};
+    use common::vertex::{VertexFormat, stride, locations, bind_instance_attributes};
+    use Instance;
+
+    /// The model matrix takes a location for each of its columns.
+    #[test]
+    fn lays_out_instance() {
+        let instance = Instance { model: Mat4::identity() };
+        let attributes = instance.attributes();
+        assert stride::<Instance>() == 64;
+        assert attributes.len() == 1 && attributes[0].name == ~"instanceModel";
+        assert locations(&attributes[0]) == ~[(4, 0), (4, 16), (4, 32), (4, 48)];
+    }
+
+    #[test]
+    fn advances_every_column_once_per_instance() {
+        let mock = @MockGl::new();
+        let ctx = Context::new(mock as @Gl, 800, 600);
+        {
+            let compile = |kind, text: &str| match Shader::compile(&ctx, kind, &Source::new(text)) {
+                Ok(move shader) => move shader,
+                Err(move err) => fail(err.to_str()),
+            };
+            let shaders = [compile(VertexShader, "#version 330\n\
+                                                  in vec2 position;\n\
+                                                  in mat4 instanceModel;\n\
+                                                  void main() {}\n"),
+                           compile(FragmentShader, "#version 330\n\
+                                                    out vec4 outColor;\n\
+                                                    void main() {}\n")];
+            let program = match Program::link(&ctx, shaders) {
+                Ok(move program) => move program,
+                Err(move err) => fail(err.to_str()),
+            };
+
+            let vao = VertexArray::new(&ctx);
+            vao.bind();
+            let instances = Buffer::new(&ctx, GL_ARRAY_BUFFER, [Instance { model: Mat4::identity() }],
+                                        GL_STATIC_DRAW);
+            instances.bind();
+            mock.clear_trace();
+            bind_instance_attributes(&program, &Instance { model: Mat4::identity() });
+        }
+        ctx.destroy();
+
+        // `position` comes first, so the matrix takes locations 1 to 4
+        assert mock.errors().is_empty();
+        let pointers = mock.calls_to("glVertexAttribPointer").map(|c| c.to_str());
+        assert pointers == ~[~"glVertexAttribPointer(1, 4, GL_FLOAT, 0, 64, 0)",
+                             ~"glVertexAttribPointer(2, 4, GL_FLOAT, 0, 64, 16)",
+                             ~"glVertexAttribPointer(3, 4, GL_FLOAT, 0, 64, 32)",
+                             ~"glVertexAttribPointer(4, 4, GL_FLOAT, 0, 64, 48)"];
+        let divisors = mock.calls_to("glVertexAttribDivisor").map(|c| c.to_str());
+        assert divisors == ~[~"glVertexAttribDivisor(1, 1)", ~"glVertexAttribDivisor(2, 1)",
+                             ~"glVertexAttribDivisor(3, 1)", ~"glVertexAttribDivisor(4, 1)"];
+    }
+}
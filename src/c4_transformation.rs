@@ -1,267 +1,260 @@
-extern mod glfw3;
 extern mod glcore;
 extern mod lmath;
 extern mod numeric;
-extern mod stb_image;
-
-use cast::transmute;
-use ptr::{is_null, null, to_unsafe_ptr};
-use str::as_c_str;
-use sys::size_of;
-use vec::raw::to_ptr;
+extern mod common;
 
 use glcore::*;
 use lmath::vec::vec3::Vec3;
 use lmath::mat::mat3::Mat3;
 use lmath::mat::mat4::Mat4;
 use numeric::types::angle::*;
-use stb_image::image::load_with_depth;
+use common::app;
+use common::app::{App, WindowDesc};
+use common::block::{UniformBlock, Std140Writer, UniformBuffer, BindingPoints};
+use common::camera::{perspective, aspect_ratio};
+use common::controller::CameraController;
+use common::buffer::{VertexArray, Buffer, IndexBuffer};
+use common::context::Context;
+use common::input::Input;
+use common::texture::{Texture2D, Linear, Downsampled};
+use common::mipmap::LanczosFilter;
+use common::sampler::{Sampler, SamplerDesc, ClampToEdge};
+use common::preprocess::Preprocessor;
+use common::shader::{VertexShader, FragmentShader};
+use common::soft::{Emulation, VertexOut, transform};
+use common::uniform::{Uniform, Sampler2D};
+use common::watch::WatchedProgram;
+use common::vertex::{TexturedVertex, bind_attributes};
 
-fn main() {
-    do task::task().sched_mode(task::PlatformThread).spawn {
-        if (glfw3::init() == 0) {
-            glfw3::terminate();
-            fail(~"glfwInit() failed\n");
-        }
-        
-        // Choose a GL profile that is compatible with OS X 10.7+
-        glfw3::window_hint(glfw3::OPENGL_VERSION_MAJOR, 3);
-        glfw3::window_hint(glfw3::OPENGL_VERSION_MINOR, 2);
-        glfw3::window_hint(glfw3::OPENGL_PROFILE, glfw3::OPENGL_CORE_PROFILE);
-        glfw3::window_hint(glfw3::OPENGL_FORWARD_COMPAT, 1);
-        
-        let mut window = glfw3::create_window(800, 600, glfw3::WINDOWED, ~"OpenGL");
+/// How much of the second texture shows through the first.
+const MIX_FACTOR: float = 0.5;
+
+/// The contents of the `Transform` block of the vertex shader.
+struct Transform {
+    model: Mat4<GLfloat>,
+    view: Mat4<GLfloat>,
+    proj: Mat4<GLfloat>,
+}
+
+impl Transform : UniformBlock {
+    fn write_members(&self, writer: &mut Std140Writer) {
+        writer.member(&self.model);
+        writer.member(&self.view);
+        writer.member(&self.proj);
+    }
+}
+
+struct Transformation {
+    vao: Option<VertexArray>,
+    vbo: Option<Buffer<TexturedVertex>>,
+    ebo: Option<IndexBuffer>,
+    textures: ~[Texture2D],
+    sampler: Option<Sampler>,
+    shader_program: Option<WatchedProgram>,
+    transform: Option<UniformBuffer<Transform>>,
+    bindings: BindingPoints,
+    camera: CameraController,
+    time: float,
+}
+
+impl Transformation {
+    /**
+     * Points the vertex data at the attributes of the current program and
+     * looks up its uniforms and blocks. Called again whenever the program
+     * is rebuilt, as they may have moved.
+     */
+    fn resolve_program(&mut self) {
+        self.vao.get_ref().bind();
+        self.vbo.get_ref().bind();
         
-        if (is_null(window.ptr)) {
-            glfw3::terminate();
-            io::println(~"Error: " + glfw3::error_string(glfw3::get_error()));
-            fail(~"glfwOpenWindow() failed\n");
+        let binding = self.bindings.point("Transform");
+        do self.shader_program.get_ref().with_program |program| {
+            // Only the layout of the vertex matters, not its values
+            bind_attributes(program, &TexturedVertex { position: [0.0, 0.0], color: [0.0, 0.0, 0.0],
+                                                       texcoord: [0.0, 0.0] });
+            
+            // Tell each sampler uniform which texture unit to read
+            let tex_kitten: Uniform<Sampler2D> = Uniform::lookup(program, "texKitten");
+            tex_kitten.set(&Sampler2D { unit: 0 });
+            let tex_puppy: Uniform<Sampler2D> = Uniform::lookup(program, "texPuppy");
+            tex_puppy.set(&Sampler2D { unit: 1 });
+            
+            // Read the Transform block from the buffer bound for it
+            match program.bind_block("Transform", binding) {
+                Ok(()) => (),
+                Err(move err) => io::println(~"Warning: " + err.to_str())
+            }
         }
-        
-        window.make_context_current();
-        
+    }
+}
+
+impl Transformation : App {
+    fn init(&mut self, ctx: &Context) -> Result<(), ~str> {
         // Create Vertex Array Object
-        let vao: GLuint = 0;
-        glGenVertexArrays(1, to_unsafe_ptr(&vao));
-        glBindVertexArray(vao);
+        let vao = VertexArray::new(ctx);
+        vao.bind();
         
         // Create a Vertex Buffer Object and copy the vertex data to it
-        let vbo: GLuint = 0;
-        glGenBuffers(1, to_unsafe_ptr(&vbo));
-        
-        let vertices: [GLfloat * 28] = [
-        //   Position     Color            Texcoords
-            -0.5,  0.5,   1.0, 0.0, 0.0,   0.0, 0.0, // Top-left
-             0.5,  0.5,   0.0, 1.0, 0.0,   1.0, 0.0, // Top-right
-             0.5, -0.5,   0.0, 0.0, 1.0,   1.0, 1.0, // Bottom-right
-            -0.5, -0.5,   1.0, 1.0, 1.0,   0.0, 1.0  // Bottom-left
+        let vertices: [TexturedVertex * 4] = [
+            TexturedVertex { position: [-0.5,  0.5], color: [1.0, 0.0, 0.0], texcoord: [0.0, 0.0] }, // Top-left
+            TexturedVertex { position: [ 0.5,  0.5], color: [0.0, 1.0, 0.0], texcoord: [1.0, 0.0] }, // Top-right
+            TexturedVertex { position: [ 0.5, -0.5], color: [0.0, 0.0, 1.0], texcoord: [1.0, 1.0] }, // Bottom-right
+            TexturedVertex { position: [-0.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [0.0, 1.0] }  // Bottom-left
         ];
         
-        glBindBuffer(GL_ARRAY_BUFFER, vbo);
-        unsafe {
-            glBufferData(GL_ARRAY_BUFFER,
-                         (vertices.len() * size_of::<GLfloat>()) as GLsizeiptr,
-                         transmute(to_ptr(vertices)),
-                         GL_STATIC_DRAW);
-        }
+        let vbo = Buffer::new(ctx, GL_ARRAY_BUFFER, vertices, GL_STATIC_DRAW);
         
         // Create an element array
-        let ebo: GLuint = 0;
-        glGenBuffers(1, to_unsafe_ptr(&ebo));
-        
         let elements: [GLuint * 6] = [
             0, 1, 2,
             2, 3, 0
         ];
         
-        glBindBuffer(GL_ELEMENT_ARRAY_BUFFER, ebo);
-        unsafe {
-            glBufferData(GL_ELEMENT_ARRAY_BUFFER,
-                         (elements.len() * size_of::<GLfloat>()) as GLsizeiptr,
-                         transmute(to_ptr(elements)),
-                         GL_STATIC_DRAW);
-        }
+        let ebo = IndexBuffer::narrowest(ctx, elements, GL_STATIC_DRAW);
         
-        // Shader sources
-        let vertexSource =
-          ~"#version 150\n\
-            in vec2 position;\n\
-            in vec3 color;\n\
-            in vec2 texcoord;\n\
-            out vec3 Color;\n\
-            out vec2 Texcoord;\n\
-            uniform mat4 trans;\n\
-            void main() {\n\
-                Color = color;\n\
-                Texcoord = texcoord;\n\
-                gl_Position = trans * vec4(position, 0.0, 1.0);\n\
-            }";
-
-        let fragmentSource =
-          ~"#version 150\n\
-            in vec3 Color;\n\
-            in vec2 Texcoord;\n\
-            out vec4 outColor;\n\
-            uniform sampler2D texKitten;\n\
-            uniform sampler2D texPuppy;\n\
-            void main() {\n\
-                outColor = mix(texture(texKitten, Texcoord), texture(texPuppy, Texcoord), 0.5);\n\
-            }";
-
-        // Create and compile the vertex shader
-        let vertexShader = glCreateShader(GL_VERTEX_SHADER);
-        do as_c_str(vertexSource) |data| {
-            glShaderSource(vertexShader, 1, to_unsafe_ptr(&data), null());
-            glCompileShader(vertexShader);
-        }
+        // Load the shaders and link them into a program, which is rebuilt
+        // whenever one of the files is edited
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.define("MIX_FACTOR", float::to_str(MIX_FACTOR, 3));
+        let shaders = [(VertexShader, "shaders/c4_transformation.vert"),
+                       (FragmentShader, "shaders/c4_transformation.frag")];
+        let shaderProgram = match WatchedProgram::load_with(ctx, move preprocessor, shaders, ["outColor"]) {
+            Ok(move program) => program,
+            Err(move err) => return Err(err.to_str())
+        };
+        shaderProgram.bind();
         
-        // Create and compile the fragment shader
-        let fragmentShader = glCreateShader(GL_FRAGMENT_SHADER);
-        do as_c_str(fragmentSource) |data| {
-            glShaderSource(fragmentShader, 1, to_unsafe_ptr(&data), null());
-            glCompileShader(fragmentShader);
-        }
+        // Stand-ins for the shaders, for running on the software renderer
+        shaderProgram.emulate(Emulation {
+            vertex: |uniforms, inputs| {
+                let position = inputs.get("position");
+                let world = transform(uniforms.mat4("model"), [position[0], position[1], 0.0, 1.0]);
+                VertexOut {
+                    position: transform(uniforms.mat4("proj"), transform(uniforms.mat4("view"), world)),
+                    varyings: vec::slice(inputs.get("color"), 0, 3) +
+                              vec::slice(inputs.get("texcoord"), 0, 2)
+                }
+            },
+            geometry: None,
+            fragment: |uniforms, varyings| {
+                let kitten = uniforms.texture("texKitten", varyings[3], varyings[4]);
+                let puppy = uniforms.texture("texPuppy", varyings[3], varyings[4]);
+                let t = MIX_FACTOR as f32;
+                [kitten[0] + (puppy[0] - kitten[0]) * t, kitten[1] + (puppy[1] - kitten[1]) * t,
+                 kitten[2] + (puppy[2] - kitten[2]) * t, kitten[3] + (puppy[3] - kitten[3]) * t]
+            }
+        });
         
-        // Link the vertex and fragment shader into a shader program
-        let shaderProgram = glCreateProgram();
-        glAttachShader(shaderProgram, vertexShader);
-        glAttachShader(shaderProgram, fragmentShader);
-        glBindFragDataLocation(shaderProgram, 0, as_c_str("outColor", |s| s));
-        glLinkProgram(shaderProgram);
-        glUseProgram(shaderProgram);
+        // Load textures
+        let kitten = match Texture2D::from_file(ctx, "resources/sample.png", Linear, Downsampled(LanczosFilter)) {
+            Ok(move texture) => texture,
+            Err(move err) => return Err(err.to_str())
+        };
         
-        // Specify the layout of the vertex data
-        let posAttrib = glGetAttribLocation(shaderProgram, as_c_str("position", |s| s)) as GLuint;
-        glEnableVertexAttribArray(posAttrib);
-        glVertexAttribPointer(posAttrib, 2, GL_FLOAT, GL_FALSE,
-                              7 * size_of::<GLfloat>() as GLsizei,
-                              null());
+        let puppy = match Texture2D::from_file(ctx, "resources/sample2.png", Linear, Downsampled(LanczosFilter)) {
+            Ok(move texture) => texture,
+            Err(move err) => return Err(err.to_str())
+        };
         
-        let colAttrib = glGetAttribLocation(shaderProgram, as_c_str("color", |s| s)) as GLuint;
-        glEnableVertexAttribArray(colAttrib);
-        unsafe {
-            glVertexAttribPointer(colAttrib, 3, GL_FLOAT, GL_FALSE,
-                                  7 * size_of::<GLfloat>() as GLsizei,
-                                  transmute(2 * size_of::<GLfloat>()));
-        }
+        // Create a buffer for the Transform block, rewritten every frame
+        let identity = Mat4::identity();
+        let transform = UniformBuffer::new(ctx, &Transform { model: identity, view: identity, proj: identity },
+                                           GL_DYNAMIC_DRAW);
+        transform.bind_to(self.bindings.point("Transform"));
         
-        let texAttrib = glGetAttribLocation(shaderProgram, as_c_str("texcoord", |s| s)) as GLuint;
-        glEnableVertexAttribArray(texAttrib);
-        unsafe {
-            glVertexAttribPointer(texAttrib, 2, GL_FLOAT, GL_FALSE,
-                                  7 * size_of::<GLfloat>() as GLsizei,
-                                  transmute(5 * size_of::<GLfloat>()));
-        }
+        // Clamp to the edges and filter trilinearly between the mipmaps
+        let sampler = Sampler::new(ctx, &SamplerDesc::trilinear(ClampToEdge));
         
-        // Load textures
-        let textures: ~[GLuint] = ~[0, 0];
-        unsafe { glGenTextures(2, to_ptr(textures)); }
+        kitten.bind_to(0);
+        sampler.bind_to(0);
+        puppy.bind_to(1);
+        sampler.bind_to(1);
         
-        let kitten_loaded: bool;
-        match load_with_depth(~"resources/sample.png", 3) {
-            Some(image) => {
-                glActiveTexture(GL_TEXTURE0);
-                glBindTexture(GL_TEXTURE_2D, textures[0]);
-                
-                glUniform1i(glGetUniformLocation(shaderProgram, as_c_str("texKitten", |s| s)), 0);
-                
-                unsafe {
-                    glTexImage2D(
-                        GL_TEXTURE_2D, 0,
-                        GL_RGB as GLint,
-                        image.width as GLsizei,
-                        image.height as GLsizei,
-                        0, GL_RGB, GL_UNSIGNED_BYTE,
-                        transmute(to_ptr(image.data))
-                    );
-                }
-                
-                glTexParameteri(GL_TEXTURE_2D, GL_TEXTURE_WRAP_S, GL_CLAMP_TO_EDGE as GLint);
-                glTexParameteri(GL_TEXTURE_2D, GL_TEXTURE_WRAP_T, GL_CLAMP_TO_EDGE as GLint);
-                glTexParameteri(GL_TEXTURE_2D, GL_TEXTURE_MIN_FILTER, GL_LINEAR as GLint);
-                glTexParameteri(GL_TEXTURE_2D, GL_TEXTURE_MAG_FILTER, GL_LINEAR as GLint);
-                
-                kitten_loaded = true;
-            }
-            None => {
-                io::println(~"Failed to load kitten.");
-                kitten_loaded = false;
-            }
-        }
+        self.transform = Some(move transform);
+        self.textures = ~[move kitten, move puppy];
+        self.sampler = Some(move sampler);
+        self.shader_program = Some(move shaderProgram);
+        self.ebo = Some(move ebo);
+        self.vbo = Some(move vbo);
+        self.vao = Some(move vao);
         
-        let puppy_loaded: bool;
-        match load_with_depth(~"resources/sample2.png", 3) {
-            Some(image) => {
-                glActiveTexture(GL_TEXTURE1);
-                glBindTexture(GL_TEXTURE_2D, textures[1]);
-                
-                glUniform1i(glGetUniformLocation(shaderProgram, as_c_str("texPuppy", |s| s)), 1);
-                
-                unsafe {
-                    glTexImage2D(
-                        GL_TEXTURE_2D, 0,
-                        GL_RGB as GLint,
-                        image.width as GLsizei,
-                        image.height as GLsizei,
-                        0, GL_RGB, GL_UNSIGNED_BYTE,
-                        transmute(to_ptr(image.data))
-                    );
-                }
-                
-                glTexParameteri(GL_TEXTURE_2D, GL_TEXTURE_WRAP_S, GL_CLAMP_TO_EDGE as GLint);
-                glTexParameteri(GL_TEXTURE_2D, GL_TEXTURE_WRAP_T, GL_CLAMP_TO_EDGE as GLint);
-                glTexParameteri(GL_TEXTURE_2D, GL_TEXTURE_MIN_FILTER, GL_LINEAR as GLint);
-                glTexParameteri(GL_TEXTURE_2D, GL_TEXTURE_MAG_FILTER, GL_LINEAR as GLint);
-                
-                puppy_loaded = true;
-            }
-            None => {
-                io::println(~"Failed to load puppy.");
-                puppy_loaded = false;
-            }
-        }
+        // Specify the layout of the vertex data and look up the uniforms
+        self.resolve_program();
+        
+        Ok(())
+    }
+    
+    fn update(&mut self, input: &Input, dt: float) {
+        self.time += dt;
         
-        let uniTrans = glGetUniformLocation(shaderProgram, as_c_str("trans", |s| s));
+        // Orbit or fly around the quad with the mouse and keyboard
+        self.camera.update(input, dt);
         
-        if kitten_loaded && puppy_loaded {
-            while window.get_param(glfw3::CLOSE_REQUESTED) == 0 {
-                // Poll events
-                glfw3::poll_events();
-                
-                // Clear the screen to black
-                glClearColor(0.1, 0.1, 0.1, 1.0);
-                glClear(GL_COLOR_BUFFER_BIT);
-                
-                // Calculate transformation
-                let trans: Mat4<GLfloat> = Mat3::from_axis_angle(
-                    &Vec3::new(
-                        0.0 as GLfloat,
-                        0.0 as GLfloat,
-                        1.0 as GLfloat),
-                    Degrees(glfw3::get_time() as GLfloat * 180.0)
-                ).to_mat4();
-                
-                // Set uniform to transform
-                glUniformMatrix4fv(uniTrans, 1, GL_FALSE, trans.to_ptr());
-            
-                // Draw a rectangle from the 2 triangles using 6 indices
-                glDrawElements(GL_TRIANGLES, 6, GL_UNSIGNED_INT, null());
-                
-                // Swap buffers
-                window.swap_buffers();
-            }
+        // Pick up any edits to the shader files
+        if self.shader_program.get_ref().poll(dt) {
+            self.resolve_program();
         }
+    }
+    
+    fn render(&self, ctx: &Context) {
+        let gl = ctx.gl();
         
-        unsafe { glDeleteTextures(2, to_ptr(textures)); }
+        // Clear the screen to black
+        ctx.cache().clear_color(0.1, 0.1, 0.1, 1.0);
+        gl.clear(GL_COLOR_BUFFER_BIT);
         
-        glDeleteProgram(shaderProgram);
-        glDeleteShader(fragmentShader);
-        glDeleteShader(vertexShader);
+        // Calculate the model transformation
+        let model: Mat4<GLfloat> = Mat3::from_axis_angle(
+            &Vec3::new(
+                0.0 as GLfloat,
+                0.0 as GLfloat,
+                1.0 as GLfloat),
+            Degrees(self.time as GLfloat * 180.0)
+        ).to_mat4();
         
-        glDeleteBuffers(1, to_unsafe_ptr(&vbo));
+        // Look through the camera, with the z axis up
+        let view = self.camera.view();
         
-        glDeleteVertexArrays(1, to_unsafe_ptr(&vao));
+        // Project with a 45 degree field of view, stretched to the framebuffer
+        let (width, height) = ctx.framebuffer_size();
+        let proj = perspective(45.0, aspect_ratio(width, height), 1.0, 10.0);
+        
+        // Upload the transformations to the Transform block
+        self.transform.get_ref().update(&Transform { model: model, view: view, proj: proj });
+        
+        // Draw a rectangle from the 2 triangles using 6 indices
+        self.ebo.get_ref().draw(GL_TRIANGLES);
+    }
+    
+    fn cleanup(&mut self) {
+        self.transform = None;
+        self.textures = ~[];
+        self.sampler = None;
         
-        glfw3::terminate();
+        self.shader_program = None;
+        
+        self.ebo = None;
+        self.vbo = None;
+        
+        self.vao = None;
+    }
+}
+
+fn main() {
+    do app::run(WindowDesc::new("OpenGL", 800, 600)) {
+        Transformation {
+            vao: None,
+            vbo: None,
+            ebo: None,
+            textures: ~[],
+            sampler: None,
+            shader_program: None,
+            transform: None,
+            bindings: BindingPoints::new(),
+            // Start above and to one side of the quad, looking at it
+            camera: CameraController::orbit(&Vec3::new(1.2f32, 1.2f32, 1.2f32),
+                                            &Vec3::new(0.0f32, 0.0f32, 0.0f32)),
+            time: 0.0,
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,320 @@
+extern mod glcore;
+extern mod lmath;
+extern mod common;
+
+use glcore::*;
+use lmath::vec::vec3::Vec3;
+use lmath::mat::mat4::Mat4;
+use common::app;
+use common::app::{App, WindowDesc};
+use common::camera::{perspective, aspect_ratio, back_to_front};
+use common::controller::CameraController;
+use common::buffer::{VertexArray, Buffer};
+use common::context::Context;
+use common::input::{Input, KeyDown, KEY_0, KEY_1, KEY_2, KEY_3};
+use common::texture::{Texture2D, Linear, Downsampled, premultiply};
+use common::mipmap::{BoxFilter, LanczosFilter};
+use common::sampler::{Sampler, SamplerDesc, ClampToEdge};
+use common::shader::{VertexShader, FragmentShader};
+use common::soft::{Emulation, VertexOut, transform};
+use common::state::{Blend, RenderState};
+use common::uniform::{Uniform, Sampler2D};
+use common::watch::WatchedProgram;
+use common::vertex::{TexturedVertex3, bind_attributes};
+
+/// The width and height of the pane texture, in texels.
+const PANE_SIZE: uint = 64;
+
+/// The centre of each pane on the floor, and the color it is tinted.
+const PANES: [([GLfloat * 2], [GLfloat * 3]) * 4] = [
+    ([-0.85, -0.42], [1.0, 0.3, 0.3]),
+    ([-0.14, -0.28], [0.3, 1.0, 0.3]),
+    ([ 0.14,  0.28], [0.3, 0.5, 1.0]),
+    ([ 0.85,  0.42], [1.0, 0.9, 0.3])
+];
+
+/// How the panes are blended with what is behind them.
+enum Mode {
+    Over,
+    Add,
+    Multiply,
+}
+
+impl Mode {
+    fn blend(&self) -> Blend {
+        match *self {
+            Over => Blend::premultiplied_alpha(),
+            Add => Blend::additive(),
+            Multiply => Blend::multiply(),
+        }
+    }
+}
+
+impl Mode : ToStr {
+    pure fn to_str(&self) -> ~str {
+        match *self {
+            Over => ~"premultiplied alpha",
+            Add => ~"additive blending",
+            Multiply => ~"multiplying",
+        }
+    }
+}
+
+/**
+ * The image of a pane of glass, as straight 8-bit RGBA: white, almost
+ * opaque around the edges and mostly clear inside.
+ */
+fn pane_image() -> ~[u8] {
+    let mut pixels = vec::with_capacity(PANE_SIZE * PANE_SIZE * 4);
+    for uint::range(0, PANE_SIZE) |y| {
+        for uint::range(0, PANE_SIZE) |x| {
+            let edge = uint::min(uint::min(x, PANE_SIZE - 1 - x), uint::min(y, PANE_SIZE - 1 - y));
+            let alpha = if edge < 4 { 230 } else { 100 };
+            pixels.push_all([255, 255, 255, alpha]);
+        }
+    }
+    move pixels
+}
+
+/**
+ * The two triangles of a pane a unit wide and high, standing on the floor
+ * at `centre` and facing along the diagonal the camera starts on.
+ */
+fn pane(centre: &[GLfloat * 2], color: &[GLfloat * 3]) -> ~[TexturedVertex3] {
+    // Half the width, along the floor at right angles to the diagonal
+    let (dx, dy) = (0.3536f32, -0.3536f32);
+    let corner = |side: GLfloat, up: GLfloat| TexturedVertex3 {
+        position: [centre[0] + side * dx, centre[1] + side * dy, up - 0.5],
+        color: *color,
+        texcoord: [(side + 1.0) * 0.5, 1.0 - up]
+    };
+    ~[corner(-1.0, 0.0), corner(1.0, 0.0), corner(1.0, 1.0),
+      corner(1.0, 1.0), corner(-1.0, 1.0), corner(-1.0, 0.0)]
+}
+
+struct Blending {
+    vao: Option<VertexArray>,
+    vbo: Option<Buffer<TexturedVertex3>>,
+    /// The floor's texture, then the panes'.
+    textures: ~[Texture2D],
+    sampler: Option<Sampler>,
+    shader_program: Option<WatchedProgram>,
+    /// The view and projection matrices.
+    transform: Option<(Uniform<Mat4<GLfloat>>, Uniform<Mat4<GLfloat>>)>,
+    camera: CameraController,
+    mode: Mode,
+    /// Draws the panes back to front, rather than in the order of `PANES`.
+    sorted: bool,
+}
+
+impl Blending {
+    /**
+     * Points the vertex data at the attributes of the current program and
+     * looks up its uniforms. Called again whenever the program is rebuilt,
+     * as they may have moved.
+     */
+    fn resolve_program(&mut self) {
+        self.vao.get_ref().bind();
+        self.vbo.get_ref().bind();
+        
+        self.transform = Some(do self.shader_program.get_ref().with_program |program| {
+            // Only the layout of the vertex matters, not its values
+            bind_attributes(program, &TexturedVertex3 { position: [0.0, 0.0, 0.0], color: [0.0, 0.0, 0.0],
+                                                        texcoord: [0.0, 0.0] });
+        
+            // Everything is drawn with the texture on unit 0
+            let tex: Uniform<Sampler2D> = Uniform::lookup(program, "tex");
+            tex.set(&Sampler2D { unit: 0 });
+        
+            let view: Uniform<Mat4<GLfloat>> = Uniform::lookup(program, "view");
+            let proj: Uniform<Mat4<GLfloat>> = Uniform::lookup(program, "proj");
+            (move view, move proj)
+        });
+    }
+}
+
+impl Blending : App {
+    fn init(&mut self, ctx: &Context) -> Result<(), ~str> {
+        // Create Vertex Array Object
+        let vao = VertexArray::new(ctx);
+        vao.bind();
+        
+        // Create a Vertex Buffer Object and copy the vertex data to it: the
+        // floor, then each of the panes standing on it
+        let mut vertices = ~[
+            TexturedVertex3 { position: [-1.5, -1.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [0.0, 0.0] },
+            TexturedVertex3 { position: [ 1.5, -1.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [1.0, 0.0] },
+            TexturedVertex3 { position: [ 1.5,  1.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [1.0, 1.0] },
+            TexturedVertex3 { position: [ 1.5,  1.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [1.0, 1.0] },
+            TexturedVertex3 { position: [-1.5,  1.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [0.0, 1.0] },
+            TexturedVertex3 { position: [-1.5, -1.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [0.0, 0.0] }
+        ];
+        for vec::each(PANES) |entry| {
+            let (centre, color) = *entry;
+            vertices.push_all(pane(&centre, &color));
+        }
+        
+        let vbo = Buffer::new(ctx, GL_ARRAY_BUFFER, vertices, GL_STATIC_DRAW);
+        
+        // Load the shaders and link them into a program, which is rebuilt
+        // whenever one of the files is edited
+        let shaderProgram = match WatchedProgram::load(ctx, [(VertexShader, "shaders/c5_blending.vert"),
+                                                             (FragmentShader, "shaders/c5_blending.frag")],
+                                                       ["outColor"]) {
+            Ok(move program) => program,
+            Err(move err) => return Err(err.to_str())
+        };
+        shaderProgram.bind();
+        
+        // Stand-ins for the shaders, for running on the software renderer
+        shaderProgram.emulate(Emulation {
+            vertex: |uniforms, inputs| {
+                let position = inputs.get("position");
+                let world = [position[0], position[1], position[2], 1.0];
+                VertexOut {
+                    position: transform(uniforms.mat4("proj"), transform(uniforms.mat4("view"), world)),
+                    varyings: vec::slice(inputs.get("color"), 0, 3) +
+                              vec::slice(inputs.get("texcoord"), 0, 2)
+                }
+            },
+            geometry: None,
+            fragment: |uniforms, varyings| {
+                let texel = uniforms.texture("tex", varyings[3], varyings[4]);
+                [texel[0] * varyings[0], texel[1] * varyings[1], texel[2] * varyings[2], texel[3]]
+            }
+        });
+        
+        // Load the floor's texture
+        let floor = match Texture2D::from_file(ctx, "resources/sample.png", Linear, Downsampled(LanczosFilter)) {
+            Ok(move texture) => texture,
+            Err(move err) => return Err(err.to_str())
+        };
+        
+        // Create the panes' texture, keeping its alpha channel. Its colors
+        // are premultiplied, as the blend modes expect, which also keeps
+        // the clear inside from going dark where it is averaged with the
+        // edges in the mipmaps
+        let glass = match Texture2D::from_pixels(ctx, PANE_SIZE, PANE_SIZE, 4, premultiply(pane_image()),
+                                                 Linear, Downsampled(BoxFilter)) {
+            Ok(move texture) => texture,
+            Err(move err) => return Err(err.to_str())
+        };
+        
+        // Clamp to the edges and filter trilinearly between the mipmaps
+        let sampler = Sampler::new(ctx, &SamplerDesc::trilinear(ClampToEdge));
+        sampler.bind_to(0);
+        
+        self.textures = ~[move floor, move glass];
+        self.sampler = Some(move sampler);
+        self.shader_program = Some(move shaderProgram);
+        self.vbo = Some(move vbo);
+        self.vao = Some(move vao);
+        
+        // Specify the layout of the vertex data and look up the uniforms
+        self.resolve_program();
+        
+        Ok(())
+    }
+    
+    fn update(&mut self, input: &Input, dt: float) {
+        // 1 to 3 switch between the blend modes, and 0 switches sorting
+        // the panes off and on
+        for input.events().each |event| {
+            match *event {
+                KeyDown(key) if key == KEY_1 => self.mode = Over,
+                KeyDown(key) if key == KEY_2 => self.mode = Add,
+                KeyDown(key) if key == KEY_3 => self.mode = Multiply,
+                KeyDown(key) if key == KEY_0 => self.sorted = !self.sorted,
+                _ => loop
+            }
+            io::println(fmt!("Blending with %s, %s", self.mode.to_str(),
+                             if self.sorted { "back to front" } else { "unsorted" }));
+        }
+        
+        // Orbit or fly around the panes with the mouse and keyboard
+        self.camera.update(input, dt);
+        
+        // Pick up any edits to the shader files
+        if self.shader_program.get_ref().poll(dt) {
+            self.resolve_program();
+        }
+    }
+    
+    fn render(&self, ctx: &Context) {
+        let gl = ctx.gl();
+        let cache = ctx.cache();
+        
+        // The floor is opaque and hides what is behind it. The panes are
+        // blended over what is drawn, and tested against the depth buffer
+        // without writing to it, so that those behind one still show
+        let opaque = RenderState { depth_test: Some(GL_LESS), .. RenderState::default() };
+        let transparent = RenderState { blend: Some(self.mode.blend()), depth_write: false, .. opaque };
+        cache.apply(&opaque);
+        
+        // Clear the screen to white, and the depth buffer to the far plane
+        cache.clear_color(1.0, 1.0, 1.0, 1.0);
+        gl.clear(GL_COLOR_BUFFER_BIT | GL_DEPTH_BUFFER_BIT);
+        
+        // Look at the panes through the camera
+        let view = self.camera.view();
+        let (width, height) = ctx.framebuffer_size();
+        let proj = perspective(45.0, aspect_ratio(width, height), 1.0, 10.0);
+        
+        match self.transform {
+            Some((ref view_uniform, ref proj_uniform)) => {
+                view_uniform.set(&view);
+                proj_uniform.set(&proj);
+            }
+            None => ()
+        }
+        
+        // Draw the floor first, as everything transparent must be drawn
+        // over what is opaque behind it
+        self.textures[0].bind_to(0);
+        gl.draw_arrays(GL_TRIANGLES, 0, 6);
+        
+        // Then the panes, the farthest from the camera first, so that each
+        // is blended over those behind it
+        cache.apply(&transparent);
+        self.textures[1].bind_to(0);
+        
+        let centres = do vec::map(PANES) |entry| {
+            let (centre, _) = *entry;
+            Vec3::new(centre[0], centre[1], 0.0f32)
+        };
+        let order = if self.sorted { back_to_front(&view, centres) } else { vec::from_fn(PANES.len(), |i| i) };
+        for order.each |i| {
+            gl.draw_arrays(GL_TRIANGLES, (6 + 6 * *i) as GLint, 6);
+        }
+    }
+    
+    fn cleanup(&mut self) {
+        self.transform = None;
+        self.textures = ~[];
+        self.sampler = None;
+        
+        self.shader_program = None;
+        
+        self.vbo = None;
+        
+        self.vao = None;
+    }
+}
+
+fn main() {
+    do app::run(WindowDesc::new("OpenGL", 800, 600)) {
+        Blending {
+            vao: None,
+            vbo: None,
+            textures: ~[],
+            sampler: None,
+            shader_program: None,
+            transform: None,
+            // Start above and to one side of the panes, looking through them
+            camera: CameraController::orbit(&Vec3::new(2.5f32, 2.5f32, 1.5f32),
+                                            &Vec3::new(0.0f32, 0.0f32, 0.0f32)),
+            mode: Over,
+            sorted: true,
+        }
+    }
+}
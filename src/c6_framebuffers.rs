@@ -0,0 +1,431 @@
+extern mod glcore;
+extern mod lmath;
+extern mod common;
+
+use glcore::*;
+use lmath::vec::vec3::Vec3;
+use lmath::mat::mat4::Mat4;
+use common::app;
+use common::app::{App, WindowDesc};
+use common::block::{UniformBlock, Std140Writer, UniformBuffer, BindingPoints};
+use common::camera::{perspective, aspect_ratio};
+use common::controller::CameraController;
+use common::buffer::{VertexArray, Buffer};
+use common::context::Context;
+use common::input::{Input, KeyDown, KEY_0, KEY_1, KEY_4};
+use common::postprocess::PostProcess;
+use common::texture::{Texture2D, Linear, Downsampled};
+use common::mipmap::LanczosFilter;
+use common::sampler::{Sampler, SamplerDesc, ClampToEdge};
+use common::preprocess::Preprocessor;
+use common::shader::{VertexShader, FragmentShader};
+use common::soft::{Emulation, FragmentShaderFn, Uniforms, VertexOut, transform};
+use common::state::RenderState;
+use common::uniform::{Uniform, Sampler2D};
+use common::watch::WatchedProgram;
+use common::vertex::{TexturedVertex3, bind_attributes};
+
+/// How much of the second texture shows through the first.
+const MIX_FACTOR: float = 0.5;
+
+/// The contents of the `Transform` block of the vertex shader.
+struct Transform {
+    model: Mat4<GLfloat>,
+    view: Mat4<GLfloat>,
+    proj: Mat4<GLfloat>,
+}
+
+impl Transform : UniformBlock {
+    fn write_members(&self, writer: &mut Std140Writer) {
+        writer.member(&self.model);
+        writer.member(&self.view);
+        writer.member(&self.proj);
+    }
+}
+
+/// The texel `x` and `y` texels away from (`s`, `t`) in the framebuffer a
+/// post-processing pass reads, as `texture` returns it.
+fn texel_at(uniforms: &Uniforms, s: f32, t: f32, x: int, y: int) -> [f32 * 4] {
+    let (width, height) = uniforms.texture_size("texFramebuffer");
+    uniforms.texture("texFramebuffer", s + x as f32 / width as f32, t + y as f32 / height as f32)
+}
+
+/**
+ * The model matrix of the cube turned `degrees` about the z axis, then
+ * scaled by `scale` along z and moved by `offset` along it. A scale and
+ * offset of -1 mirror the cube in the floor.
+ */
+fn cube_model(degrees: GLfloat, scale: GLfloat, offset: GLfloat) -> Mat4<GLfloat> {
+    let angle = degrees * f32::consts::pi / 180.0;
+    let (c, s) = (f32::cos(angle), f32::sin(angle));
+    Mat4::new(  c,   s,   0.0, 0.0,
+               -s,   c,   0.0, 0.0,
+              0.0, 0.0, scale, 0.0,
+              0.0, 0.0, offset, 1.0)
+}
+
+struct Framebuffers {
+    vao: Option<VertexArray>,
+    vbo: Option<Buffer<TexturedVertex3>>,
+    textures: ~[Texture2D],
+    sampler: Option<Sampler>,
+    shader_program: Option<WatchedProgram>,
+    transform: Option<UniformBuffer<Transform>>,
+    override_color: Option<Uniform<[GLfloat * 3]>>,
+    post: Option<PostProcess>,
+    bindings: BindingPoints,
+    camera: CameraController,
+    time: float,
+}
+
+impl Framebuffers {
+    /**
+     * Points the vertex data at the attributes of the current program and
+     * looks up its uniforms and blocks. Called again whenever the program
+     * is rebuilt, as they may have moved.
+     */
+    fn resolve_program(&mut self) {
+        self.vao.get_ref().bind();
+        self.vbo.get_ref().bind();
+        
+        let binding = self.bindings.point("Transform");
+        self.override_color = Some(do self.shader_program.get_ref().with_program |program| {
+            // Only the layout of the vertex matters, not its values
+            bind_attributes(program, &TexturedVertex3 { position: [0.0, 0.0, 0.0], color: [0.0, 0.0, 0.0],
+                                                        texcoord: [0.0, 0.0] });
+            
+            // Tell each sampler uniform which texture unit to read
+            let tex_kitten: Uniform<Sampler2D> = Uniform::lookup(program, "texKitten");
+            tex_kitten.set(&Sampler2D { unit: 0 });
+            let tex_puppy: Uniform<Sampler2D> = Uniform::lookup(program, "texPuppy");
+            tex_puppy.set(&Sampler2D { unit: 1 });
+            
+            // Read the Transform block from the buffer bound for it
+            match program.bind_block("Transform", binding) {
+                Ok(()) => (),
+                Err(move err) => io::println(~"Warning: " + err.to_str())
+            }
+            
+            // Draw in the colors of the vertices until told otherwise
+            let override_color = Uniform::lookup(program, "overrideColor");
+            override_color.set(&[1.0, 1.0, 1.0]);
+            move override_color
+        });
+    }
+}
+
+impl Framebuffers : App {
+    fn init(&mut self, ctx: &Context) -> Result<(), ~str> {
+        // Create Vertex Array Object
+        let vao = VertexArray::new(ctx);
+        vao.bind();
+        
+        // Create a Vertex Buffer Object and copy the vertex data to it: the
+        // 6 faces of the cube, then the floor it stands on
+        let vertices: [TexturedVertex3 * 42] = [
+            TexturedVertex3 { position: [-0.5, -0.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [0.0, 0.0] },
+            TexturedVertex3 { position: [ 0.5, -0.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [1.0, 0.0] },
+            TexturedVertex3 { position: [ 0.5,  0.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [1.0, 1.0] },
+            TexturedVertex3 { position: [ 0.5,  0.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [1.0, 1.0] },
+            TexturedVertex3 { position: [-0.5,  0.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [0.0, 1.0] },
+            TexturedVertex3 { position: [-0.5, -0.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [0.0, 0.0] },
+            
+            TexturedVertex3 { position: [-0.5, -0.5,  0.5], color: [1.0, 1.0, 1.0], texcoord: [0.0, 0.0] },
+            TexturedVertex3 { position: [ 0.5, -0.5,  0.5], color: [1.0, 1.0, 1.0], texcoord: [1.0, 0.0] },
+            TexturedVertex3 { position: [ 0.5,  0.5,  0.5], color: [1.0, 1.0, 1.0], texcoord: [1.0, 1.0] },
+            TexturedVertex3 { position: [ 0.5,  0.5,  0.5], color: [1.0, 1.0, 1.0], texcoord: [1.0, 1.0] },
+            TexturedVertex3 { position: [-0.5,  0.5,  0.5], color: [1.0, 1.0, 1.0], texcoord: [0.0, 1.0] },
+            TexturedVertex3 { position: [-0.5, -0.5,  0.5], color: [1.0, 1.0, 1.0], texcoord: [0.0, 0.0] },
+            
+            TexturedVertex3 { position: [-0.5,  0.5,  0.5], color: [1.0, 1.0, 1.0], texcoord: [1.0, 0.0] },
+            TexturedVertex3 { position: [-0.5,  0.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [1.0, 1.0] },
+            TexturedVertex3 { position: [-0.5, -0.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [0.0, 1.0] },
+            TexturedVertex3 { position: [-0.5, -0.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [0.0, 1.0] },
+            TexturedVertex3 { position: [-0.5, -0.5,  0.5], color: [1.0, 1.0, 1.0], texcoord: [0.0, 0.0] },
+            TexturedVertex3 { position: [-0.5,  0.5,  0.5], color: [1.0, 1.0, 1.0], texcoord: [1.0, 0.0] },
+            
+            TexturedVertex3 { position: [ 0.5,  0.5,  0.5], color: [1.0, 1.0, 1.0], texcoord: [1.0, 0.0] },
+            TexturedVertex3 { position: [ 0.5,  0.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [1.0, 1.0] },
+            TexturedVertex3 { position: [ 0.5, -0.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [0.0, 1.0] },
+            TexturedVertex3 { position: [ 0.5, -0.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [0.0, 1.0] },
+            TexturedVertex3 { position: [ 0.5, -0.5,  0.5], color: [1.0, 1.0, 1.0], texcoord: [0.0, 0.0] },
+            TexturedVertex3 { position: [ 0.5,  0.5,  0.5], color: [1.0, 1.0, 1.0], texcoord: [1.0, 0.0] },
+            
+            TexturedVertex3 { position: [-0.5, -0.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [0.0, 1.0] },
+            TexturedVertex3 { position: [ 0.5, -0.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [1.0, 1.0] },
+            TexturedVertex3 { position: [ 0.5, -0.5,  0.5], color: [1.0, 1.0, 1.0], texcoord: [1.0, 0.0] },
+            TexturedVertex3 { position: [ 0.5, -0.5,  0.5], color: [1.0, 1.0, 1.0], texcoord: [1.0, 0.0] },
+            TexturedVertex3 { position: [-0.5, -0.5,  0.5], color: [1.0, 1.0, 1.0], texcoord: [0.0, 0.0] },
+            TexturedVertex3 { position: [-0.5, -0.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [0.0, 1.0] },
+            
+            TexturedVertex3 { position: [-0.5,  0.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [0.0, 1.0] },
+            TexturedVertex3 { position: [ 0.5,  0.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [1.0, 1.0] },
+            TexturedVertex3 { position: [ 0.5,  0.5,  0.5], color: [1.0, 1.0, 1.0], texcoord: [1.0, 0.0] },
+            TexturedVertex3 { position: [ 0.5,  0.5,  0.5], color: [1.0, 1.0, 1.0], texcoord: [1.0, 0.0] },
+            TexturedVertex3 { position: [-0.5,  0.5,  0.5], color: [1.0, 1.0, 1.0], texcoord: [0.0, 0.0] },
+            TexturedVertex3 { position: [-0.5,  0.5, -0.5], color: [1.0, 1.0, 1.0], texcoord: [0.0, 1.0] },
+            
+            TexturedVertex3 { position: [-1.0, -1.0, -0.5], color: [0.0, 0.0, 0.0], texcoord: [0.0, 0.0] },
+            TexturedVertex3 { position: [ 1.0, -1.0, -0.5], color: [0.0, 0.0, 0.0], texcoord: [1.0, 0.0] },
+            TexturedVertex3 { position: [ 1.0,  1.0, -0.5], color: [0.0, 0.0, 0.0], texcoord: [1.0, 1.0] },
+            TexturedVertex3 { position: [ 1.0,  1.0, -0.5], color: [0.0, 0.0, 0.0], texcoord: [1.0, 1.0] },
+            TexturedVertex3 { position: [-1.0,  1.0, -0.5], color: [0.0, 0.0, 0.0], texcoord: [0.0, 1.0] },
+            TexturedVertex3 { position: [-1.0, -1.0, -0.5], color: [0.0, 0.0, 0.0], texcoord: [0.0, 0.0] }
+        ];
+        
+        let vbo = Buffer::new(ctx, GL_ARRAY_BUFFER, vertices, GL_STATIC_DRAW);
+        
+        // Load the shaders and link them into a program, which is rebuilt
+        // whenever one of the files is edited
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.define("MIX_FACTOR", float::to_str(MIX_FACTOR, 3));
+        let shaders = [(VertexShader, "shaders/c6_framebuffers.vert"),
+                       (FragmentShader, "shaders/c6_framebuffers.frag")];
+        let shaderProgram = match WatchedProgram::load_with(ctx, move preprocessor, shaders, ["outColor"]) {
+            Ok(move program) => program,
+            Err(move err) => return Err(err.to_str())
+        };
+        shaderProgram.bind();
+        
+        // Stand-ins for the shaders, for running on the software renderer
+        shaderProgram.emulate(Emulation {
+            vertex: |uniforms, inputs| {
+                let position = inputs.get("position");
+                let color = inputs.get("color");
+                let tint = uniforms.vec3("overrideColor");
+                let world = transform(uniforms.mat4("model"), [position[0], position[1], position[2], 1.0]);
+                VertexOut {
+                    position: transform(uniforms.mat4("proj"), transform(uniforms.mat4("view"), world)),
+                    varyings: ~[color[0] * tint[0], color[1] * tint[1], color[2] * tint[2]] +
+                              vec::slice(inputs.get("texcoord"), 0, 2)
+                }
+            },
+            geometry: None,
+            fragment: |uniforms, varyings| {
+                let kitten = uniforms.texture("texKitten", varyings[3], varyings[4]);
+                let puppy = uniforms.texture("texPuppy", varyings[3], varyings[4]);
+                let t = MIX_FACTOR as f32;
+                [(kitten[0] + (puppy[0] - kitten[0]) * t) * varyings[0],
+                 (kitten[1] + (puppy[1] - kitten[1]) * t) * varyings[1],
+                 (kitten[2] + (puppy[2] - kitten[2]) * t) * varyings[2],
+                 kitten[3] + (puppy[3] - kitten[3]) * t]
+            }
+        });
+        
+        // Load textures
+        let kitten = match Texture2D::from_file(ctx, "resources/sample.png", Linear, Downsampled(LanczosFilter)) {
+            Ok(move texture) => texture,
+            Err(move err) => return Err(err.to_str())
+        };
+        
+        let puppy = match Texture2D::from_file(ctx, "resources/sample2.png", Linear, Downsampled(LanczosFilter)) {
+            Ok(move texture) => texture,
+            Err(move err) => return Err(err.to_str())
+        };
+        
+        // Create a buffer for the Transform block, rewritten for every draw
+        let identity = Mat4::identity();
+        let transform = UniformBuffer::new(ctx, &Transform { model: identity, view: identity, proj: identity },
+                                           GL_DYNAMIC_DRAW);
+        transform.bind_to(self.bindings.point("Transform"));
+        
+        // Clamp to the edges and filter trilinearly between the mipmaps
+        let sampler = Sampler::new(ctx, &SamplerDesc::trilinear(ClampToEdge));
+        
+        kitten.bind_to(0);
+        sampler.bind_to(0);
+        puppy.bind_to(1);
+        sampler.bind_to(1);
+        
+        // Draw the scene into a framebuffer, then run it through the
+        // effects that are switched on on its way to the screen
+        let mut post = match PostProcess::new(ctx) {
+            Ok(move post) => post,
+            Err(move err) => return Err(err.to_str())
+        };
+        let effects: [(&str, &str, FragmentShaderFn) * 4] = [
+            ("invert", "shaders/post_invert.frag", |uniforms, varyings| {
+                let color = uniforms.texture("texFramebuffer", varyings[0], varyings[1]);
+                [1.0 - color[0], 1.0 - color[1], 1.0 - color[2], 1.0]
+            }),
+            ("grayscale", "shaders/post_grayscale.frag", |uniforms, varyings| {
+                let color = uniforms.texture("texFramebuffer", varyings[0], varyings[1]);
+                let luma = color[0] * 0.2126 + color[1] * 0.7152 + color[2] * 0.0722;
+                [luma, luma, luma, 1.0]
+            }),
+            ("blur", "shaders/post_blur.frag", |uniforms, varyings| {
+                let mut sum = [0.0f32, 0.0, 0.0];
+                for int::range(-1, 2) |x| {
+                    for int::range(-1, 2) |y| {
+                        let color = texel_at(uniforms, varyings[0], varyings[1], x, y);
+                        for uint::range(0, 3) |k| { sum[k] += color[k]; }
+                    }
+                }
+                [sum[0] / 9.0, sum[1] / 9.0, sum[2] / 9.0, 1.0]
+            }),
+            ("sobel", "shaders/post_sobel.frag", |uniforms, varyings| {
+                let at = |x: int, y: int| texel_at(uniforms, varyings[0], varyings[1], x, y);
+                let (top_left, top, top_right) = (at(-1, 1), at(0, 1), at(1, 1));
+                let (left, right) = (at(-1, 0), at(1, 0));
+                let (bottom_left, bottom, bottom_right) = (at(-1, -1), at(0, -1), at(1, -1));
+                let mut edge = [0.0f32, 0.0, 0.0, 1.0];
+                for uint::range(0, 3) |k| {
+                    let sx = -top_left[k] - 2.0 * left[k] - bottom_left[k] +
+                             top_right[k] + 2.0 * right[k] + bottom_right[k];
+                    let sy = -top_left[k] - 2.0 * top[k] - top_right[k] +
+                             bottom_left[k] + 2.0 * bottom[k] + bottom_right[k];
+                    edge[k] = f32::sqrt(sx * sx + sy * sy);
+                }
+                edge
+            })
+        ];
+        for vec::each(effects) |effect| {
+            let (name, path, shade) = *effect;
+            match post.add_pass(name, path, shade) {
+                Ok(()) => (),
+                Err(move err) => return Err(err.to_str())
+            }
+        }
+        
+        self.post = Some(move post);
+        self.transform = Some(move transform);
+        self.textures = ~[move kitten, move puppy];
+        self.sampler = Some(move sampler);
+        self.shader_program = Some(move shaderProgram);
+        self.vbo = Some(move vbo);
+        self.vao = Some(move vao);
+        
+        // Only the floor writes to the stencil buffer, replacing what is there,
+        // so the operations can be set once and left
+        ctx.gl().stencil_op(GL_KEEP, GL_KEEP, GL_REPLACE);
+        
+        // Specify the layout of the vertex data and look up the uniforms
+        self.resolve_program();
+        
+        Ok(())
+    }
+    
+    fn update(&mut self, input: &Input, dt: float) {
+        self.time += dt;
+        
+        // Orbit or fly around the cube with the mouse and keyboard
+        self.camera.update(input, dt);
+        
+        // 1 to 4 switch the effects on and off, 0 switches them all off
+        for input.events().each |event| {
+            let post = self.post.get_ref();
+            match *event {
+                KeyDown(key) if key == KEY_0 => post.disable_all(),
+                KeyDown(key) if key >= KEY_1 && key <= KEY_4 => post.toggle((key - KEY_1) as uint),
+                _ => loop
+            }
+            let enabled = post.enabled();
+            let names = if enabled.is_empty() { ~"none" } else { str::connect(enabled, ", ") };
+            io::println(~"Effects: " + names);
+        }
+        
+        // Pick up any edits to the shader files
+        if self.shader_program.get_ref().poll(dt) {
+            self.resolve_program();
+        }
+        self.post.get_ref().poll(dt);
+    }
+    
+    fn render(&self, ctx: &Context) {
+        let gl = ctx.gl();
+        let cache = ctx.cache();
+        let post = self.post.get_ref();
+        
+        // Hide the faces of the cube behind the others, and keep the floor
+        // from hiding the reflection under it
+        let scene = RenderState { depth_test: Some(GL_LESS), .. RenderState::default() };
+        let floor = RenderState { depth_write: false, .. scene };
+        
+        // Draw the scene into the framebuffer of the effects, with the
+        // state the passes of the last frame left behind set up again
+        post.begin();
+        self.shader_program.get_ref().bind();
+        self.vao.get_ref().bind();
+        self.textures[0].bind_to(0);
+        self.sampler.get_ref().bind_to(0);
+        self.textures[1].bind_to(1);
+        self.sampler.get_ref().bind_to(1);
+        cache.apply(&scene);
+        
+        // Clear the screen to white, and the depth buffer to the far plane
+        cache.clear_color(1.0, 1.0, 1.0, 1.0);
+        gl.clear(GL_COLOR_BUFFER_BIT | GL_DEPTH_BUFFER_BIT);
+        
+        // Turn the cube half a turn a second, and look at it through the camera
+        let angle = self.time as GLfloat * 180.0;
+        let view = self.camera.view();
+        let (width, height) = ctx.framebuffer_size();
+        let proj = perspective(45.0, aspect_ratio(width, height), 1.0, 10.0);
+        
+        let block = self.transform.get_ref();
+        let override_color = self.override_color.get_ref();
+        
+        // Draw cube
+        block.update(&Transform { model: cube_model(angle, 1.0, 0.0), view: view, proj: proj });
+        gl.draw_arrays(GL_TRIANGLES, 0, 36);
+        
+        cache.enable(GL_STENCIL_TEST);
+        
+        // Draw floor
+        gl.stencil_func(GL_ALWAYS, 1, 0xFF); // Set any stencil to 1
+        gl.stencil_mask(0xFF); // Write to stencil buffer
+        cache.apply(&floor); // Don't write to depth buffer
+        gl.clear(GL_STENCIL_BUFFER_BIT); // Clear stencil buffer (0 by default)
+        
+        gl.draw_arrays(GL_TRIANGLES, 36, 6);
+        
+        // Draw cube reflection
+        gl.stencil_func(GL_EQUAL, 1, 0xFF); // Pass test if stencil value is 1
+        gl.stencil_mask(0x00); // Don't write anything to stencil buffer
+        cache.apply(&scene); // Write to depth buffer
+        
+        block.update(&Transform { model: cube_model(angle, -1.0, -1.0), view: view, proj: proj });
+        override_color.set(&[0.3, 0.3, 0.3]);
+        gl.draw_arrays(GL_TRIANGLES, 0, 36);
+        override_color.set(&[1.0, 1.0, 1.0]);
+        
+        cache.disable(GL_STENCIL_TEST);
+        
+        // Run the scene through the effects onto the screen
+        post.finish();
+    }
+    
+    fn cleanup(&mut self) {
+        self.post = None;
+        self.override_color = None;
+        self.transform = None;
+        self.textures = ~[];
+        self.sampler = None;
+        
+        self.shader_program = None;
+        
+        self.vbo = None;
+        
+        self.vao = None;
+    }
+}
+
+fn main() {
+    do app::run(WindowDesc::new("OpenGL", 800, 600)) {
+        Framebuffers {
+            vao: None,
+            vbo: None,
+            textures: ~[],
+            sampler: None,
+            shader_program: None,
+            transform: None,
+            override_color: None,
+            post: None,
+            bindings: BindingPoints::new(),
+            // Start above and to one side of the cube, looking at it
+            camera: CameraController::orbit(&Vec3::new(2.5f32, 2.5f32, 2.0f32),
+                                            &Vec3::new(0.0f32, 0.0f32, 0.0f32)),
+            time: 0.0,
+        }
+    }
+}
@@ -0,0 +1,146 @@
+extern mod glcore;
+extern mod common;
+
+use glcore::*;
+use common::app;
+use common::app::{App, WindowDesc};
+use common::input::Input;
+use common::buffer::{VertexArray, Buffer};
+use common::context::Context;
+use common::shader::{VertexShader, GeometryShader, FragmentShader};
+use common::soft::{Emulation, GeometryShaderFn, VertexOut};
+use common::watch::WatchedProgram;
+use common::vertex::{ShapeVertex, bind_attributes};
+
+/**
+ * The line strip the geometry shader emits for `point`, whose varyings
+ * are its color and number of sides: `sides + 1` vertices going round
+ * it, the last back where the first is, all in its color.
+ */
+fn outline(point: &VertexOut) -> ~[VertexOut] {
+    let sides = point.varyings[3];
+    let color = vec::slice(point.varyings, 0, 3);
+
+    do vec::from_fn(sides as uint + 1) |i| {
+        // Angle between each side in radians
+        let ang = f32::consts::pi * 2.0 / sides * i as f32;
+
+        // Offset from the center of the point, squashed to make up for
+        // the aspect ratio of the window
+        VertexOut {
+            position: [point.position[0] + f32::cos(ang) * 0.3, point.position[1] - f32::sin(ang) * 0.4,
+                       point.position[2], point.position[3]],
+            varyings: copy color
+        }
+    }
+}
+
+struct GeometryShaders {
+    vao: Option<VertexArray>,
+    vbo: Option<Buffer<ShapeVertex>>,
+    shader_program: Option<WatchedProgram>,
+}
+
+impl GeometryShaders {
+    /**
+     * Points the vertex data at the attributes of the current program.
+     * Called again whenever the program is rebuilt, as they may have moved.
+     */
+    fn resolve_program(&mut self) {
+        self.vao.get_ref().bind();
+        self.vbo.get_ref().bind();
+        
+        do self.shader_program.get_ref().with_program |program| {
+            // Only the layout of the vertex matters, not its values
+            bind_attributes(program, &ShapeVertex { position: [0.0, 0.0], color: [0.0, 0.0, 0.0], sides: 0.0 });
+        }
+    }
+}
+
+impl GeometryShaders : App {
+    fn init(&mut self, ctx: &Context) -> Result<(), ~str> {
+        // Create Vertex Array Object
+        let vao = VertexArray::new(ctx);
+        vao.bind();
+        
+        // Create a Vertex Buffer Object and copy the vertex data to it: a
+        // point in each quarter of the screen, each a shape of its own
+        let points: [ShapeVertex * 4] = [
+            ShapeVertex { position: [-0.45,  0.45], color: [1.0, 0.0, 0.0], sides: 4.0 },
+            ShapeVertex { position: [ 0.45,  0.45], color: [0.0, 1.0, 0.0], sides: 8.0 },
+            ShapeVertex { position: [ 0.45, -0.45], color: [0.0, 0.0, 1.0], sides: 16.0 },
+            ShapeVertex { position: [-0.45, -0.45], color: [1.0, 1.0, 0.0], sides: 32.0 }
+        ];
+        
+        let vbo = Buffer::new(ctx, GL_ARRAY_BUFFER, points, GL_STATIC_DRAW);
+        
+        // Load the shaders and link them into a program, which is rebuilt
+        // whenever one of the files is edited
+        let shaderProgram = match WatchedProgram::load(ctx, [(VertexShader, "shaders/c7_geometry_shaders.vert"),
+                                                             (GeometryShader, "shaders/c7_geometry_shaders.geom"),
+                                                             (FragmentShader, "shaders/c7_geometry_shaders.frag")],
+                                                       ["outColor"]) {
+            Ok(move program) => program,
+            Err(move err) => return Err(err.to_str())
+        };
+        shaderProgram.bind();
+        
+        // Stand-ins for the shaders, for running on the software renderer
+        let expand: GeometryShaderFn = |_uniforms, points| ~[outline(&points[0])];
+        shaderProgram.emulate(Emulation {
+            vertex: |_uniforms, inputs| {
+                let position = inputs.get("position");
+                let mut varyings = vec::slice(inputs.get("color"), 0, 3);
+                varyings.push(inputs.get("sides")[0]);
+                VertexOut { position: [position[0], position[1], 0.0, 1.0], varyings: move varyings }
+            },
+            geometry: Some(expand),
+            fragment: |_uniforms, color| [color[0], color[1], color[2], 1.0]
+        });
+        
+        self.shader_program = Some(move shaderProgram);
+        self.vbo = Some(move vbo);
+        self.vao = Some(move vao);
+        
+        // Specify the layout of the vertex data
+        self.resolve_program();
+        
+        Ok(())
+    }
+    
+    fn update(&mut self, _input: &Input, dt: float) {
+        // Pick up any edits to the shader files
+        if self.shader_program.get_ref().poll(dt) {
+            self.resolve_program();
+        }
+    }
+    
+    fn render(&self, ctx: &Context) {
+        let gl = ctx.gl();
+        
+        // Clear the screen to black
+        ctx.cache().clear_color(0.0, 0.0, 0.0, 1.0);
+        gl.clear(GL_COLOR_BUFFER_BIT);
+        
+        // Draw the 4 points, which the geometry shader turns into shapes
+        gl.draw_arrays(GL_POINTS, 0, 4);
+    }
+    
+    fn cleanup(&mut self) {
+        self.shader_program = None;
+        
+        self.vbo = None;
+        
+        self.vao = None;
+    }
+}
+
+fn main() {
+    do app::run(WindowDesc::new("OpenGL", 800, 600)) {
+        GeometryShaders {
+            vao: None,
+            vbo: None,
+            shader_program: None,
+        }
+    }
+}
@@ -0,0 +1,407 @@
+/*!
+ * The window and main loop shared by every chapter.
+ *
+ * A chapter implements `App` and hands a constructor to `run`, which sets
+ * up a GL 3.3 core context, drives the `init`/`update`/`render`/`cleanup`
+ * hooks and tears everything down again once the window is closed.
+ *
+ * Run with `--headless --frames N --out DIR` instead, a program renders N
+ * frames with the software renderer, advancing a simulated clock by a fixed
+ * step between them, and writes them to DIR as `frame-0000.png`, ... No
+ * window is opened, so this works on machines without a display or GPU.
+ *
+ * With `--trace [--frames N]`, the frames are rendered against a `MockGl`
+ * and the GL calls made are printed instead, followed by any a driver would
+ * have rejected, any that set state to what it already was, and how many
+ * calls the state cache made and skipped.
+ *
+ * Input is passed to `App::update`. `--record FILE` saves the input of a
+ * session in a window to FILE, and `--replay FILE` plays it back, in a
+ * window or, on the simulated clock, headless or traced.
+ */
+
+use ptr::is_null;
+
+use glcore::GLsizei;
+
+use context::Context;
+use gl::{Gl, NativeGl};
+use input::{Input, Replay, Event, TimedEvent, KeyDown, KeyUp, ButtonDown, ButtonUp, CursorMoved, Scrolled,
+            read_events, write_events};
+use mock::MockGl;
+use png::write;
+use soft::SoftGl;
+
+/// The simulated time between two frames when running without a window, in seconds.
+pub const FRAME_TIME: float = 1.0 / 60.0;
+
+/// The hooks a tutorial program provides to the runner.
+pub trait App {
+    /// Called once the GL context is current. GL objects are created from
+    /// `ctx`. Returning `Err` skips the main loop, but `cleanup` is still
+    /// called.
+    fn init(&mut self, ctx: &Context) -> Result<(), ~str>;
+
+    /// Advances the program by `dt` seconds, during which the events in
+    /// `input` happened.
+    fn update(&mut self, input: &Input, dt: float);
+
+    /// Draws one frame into the current framebuffer.
+    fn render(&self, ctx: &Context);
+
+    /// Called once before the context is destroyed. Anything the program
+    /// still owns afterwards is dropped before the context goes away.
+    fn cleanup(&mut self);
+}
+
+/// The window a program is run in.
+pub struct WindowDesc {
+    title: ~str,
+    width: int,
+    height: int,
+}
+
+impl WindowDesc {
+    static fn new(title: &str, width: int, height: int) -> WindowDesc {
+        WindowDesc { title: str::from_slice(title), width: width, height: height }
+    }
+}
+
+/// How a program was asked to run, from its command line.
+pub struct Options {
+    headless: bool,
+    trace: bool,
+    frames: uint,
+    out: ~str,
+    /// The file to save the input of the session to.
+    record: Option<~str>,
+    /// The file to play input back from.
+    replay: Option<~str>,
+}
+
+impl Options {
+    /**
+     * Parses the arguments following the program name. Without
+     * `--headless` or `--trace` the program opens a window; `--frames`
+     * defaults to 1 and `--out` to the current directory.
+     */
+    static fn parse(args: &[~str]) -> Result<Options, ~str> {
+        let mut options = Options {
+            headless: false,
+            trace: false,
+            frames: 1,
+            out: ~".",
+            record: None,
+            replay: None,
+        };
+
+        let mut i = 0;
+        while i < args.len() {
+            if args[i] == ~"--headless" {
+                options.headless = true;
+            } else if args[i] == ~"--trace" {
+                options.trace = true;
+            } else if args[i] == ~"--frames" && i + 1 < args.len() {
+                match uint::from_str(args[i + 1]) {
+                    Some(n) => options.frames = n,
+                    None => return Err(fmt!("invalid frame count: %s", args[i + 1])),
+                }
+                i += 1;
+            } else if args[i] == ~"--out" && i + 1 < args.len() {
+                options.out = copy args[i + 1];
+                i += 1;
+            } else if args[i] == ~"--record" && i + 1 < args.len() {
+                options.record = Some(copy args[i + 1]);
+                i += 1;
+            } else if args[i] == ~"--replay" && i + 1 < args.len() {
+                options.replay = Some(copy args[i + 1]);
+                i += 1;
+            } else {
+                return Err(fmt!("unexpected argument: %s", args[i]));
+            }
+            i += 1;
+        }
+
+        if options.record.is_some() && (options.headless || options.trace) {
+            return Err(~"--record needs a window");
+        }
+        Ok(move options)
+    }
+}
+
+/**
+ * Opens a window described by `desc`, builds the program returned by
+ * `new_app` and runs it until the window is closed. With `--headless` or
+ * `--trace` on the command line, runs it offscreen instead; see the module
+ * documentation.
+ *
+ * GLFW has to be driven from the platform thread, so the whole session,
+ * including the construction of the program, happens in a task scheduled
+ * on it.
+ */
+pub fn run<A: App>(desc: WindowDesc, new_app: fn~() -> A) {
+    let args = os::args();
+    let options = match Options::parse(vec::view(args, 1, args.len())) {
+        Ok(move options) => options,
+        Err(move msg) => {
+            io::println(~"Error: " + msg);
+            return;
+        }
+    };
+
+    let replay = match load_replay(&options) {
+        Ok(move replay) => replay,
+        Err(move msg) => {
+            io::println(~"Error: " + msg);
+            return;
+        }
+    };
+
+    if options.trace {
+        run_traced(&desc, &options, move replay, new_app);
+        return;
+    }
+    if options.headless {
+        run_headless(&desc, &options, move replay, new_app);
+        return;
+    }
+
+    let record = options.record.map(|path| Path(*path));
+    do task::task().sched_mode(task::PlatformThread).spawn |move desc, move new_app, move replay, move record| {
+        if (glfw3::init() == 0) {
+            glfw3::terminate();
+            fail(~"glfwInit() failed\n");
+        }
+
+        // Choose a GL profile that is compatible with OS X 10.9+. 3.3 is
+        // the first with sampler objects and instanced attribute divisors
+        glfw3::window_hint(glfw3::OPENGL_VERSION_MAJOR, 3);
+        glfw3::window_hint(glfw3::OPENGL_VERSION_MINOR, 3);
+        glfw3::window_hint(glfw3::OPENGL_PROFILE, glfw3::OPENGL_CORE_PROFILE);
+        glfw3::window_hint(glfw3::OPENGL_FORWARD_COMPAT, 1);
+
+        // Ask for a depth and a stencil buffer alongside the colors
+        glfw3::window_hint(glfw3::DEPTH_BITS, 24);
+        glfw3::window_hint(glfw3::STENCIL_BITS, 8);
+
+        let mut window = glfw3::create_window(desc.width, desc.height, glfw3::WINDOWED, copy desc.title);
+
+        if (is_null(window.ptr)) {
+            glfw3::terminate();
+            io::println(~"Error: " + glfw3::error_string(glfw3::get_error()));
+            fail(~"glfwOpenWindow() failed\n");
+        }
+
+        window.make_context_current();
+
+        let (width, height) = window.get_size();
+        let ctx = Context::new(@NativeGl as @Gl, width as uint, height as uint);
+        session(&ctx, &mut window, new_app(), replay, record);
+        ctx.destroy();
+
+        glfw3::terminate();
+    }
+}
+
+/// The events to replay, if `options` asks for any.
+fn load_replay(options: &Options) -> Result<Option<Replay>, ~str> {
+    match options.replay {
+        Some(ref path) => match read_events(&Path(*path)) {
+            Ok(move events) => Ok(Some(Replay::new(move events))),
+            Err(move msg) => Err(move msg),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Has the callbacks of `window` pass what the user does on to `sink`.
+fn forward_input(window: &mut glfw3::Window, sink: fn@(Event)) {
+    do window.set_key_callback |_window, key, action| {
+        if action == glfw3::PRESS {
+            sink(KeyDown(key as int));
+        } else if action == glfw3::RELEASE {
+            sink(KeyUp(key as int));
+        }
+    }
+    do window.set_mouse_button_callback |_window, button, action| {
+        if action == glfw3::PRESS {
+            sink(ButtonDown(button as int));
+        } else if action == glfw3::RELEASE {
+            sink(ButtonUp(button as int));
+        }
+    }
+    do window.set_cursor_pos_callback |_window, x, y| {
+        sink(CursorMoved(x as float, y as float));
+    }
+    do window.set_scroll_callback |_window, x, y| {
+        sink(Scrolled(x as float, y as float));
+    }
+}
+
+/**
+ * Runs `app` to completion, dropping it before returning. Input comes from
+ * the window and from `replay`, and is saved to `record` if given.
+ */
+fn session<A: App>(ctx: &Context, window: &mut glfw3::Window, app: A,
+                   replay: Option<Replay>, record: Option<Path>) {
+    let mut app = move app;
+
+    let input = @Input::new();
+    let recorded = @mut ~[];
+    let start = glfw3::get_time();
+    do forward_input(window) |event| {
+        input.push(event);
+        recorded.push(TimedEvent { time: (glfw3::get_time() - start) as float, event: event });
+    }
+
+    match app.init(ctx) {
+        Ok(()) => {
+            let mut last = glfw3::get_time();
+
+            while window.get_param(glfw3::CLOSE_REQUESTED) == 0 {
+                // Poll events
+                glfw3::poll_events();
+
+                let now = glfw3::get_time();
+                match replay {
+                    Some(ref replay) => replay.advance((now - start) as float, input),
+                    None => (),
+                }
+                app.update(input, (now - last) as float);
+                input.end_frame();
+                last = now;
+
+                // Follow the window if it was resized
+                let (width, height) = window.get_size();
+                if ctx.framebuffer_size() != (width as uint, height as uint) {
+                    ctx.resize(width as uint, height as uint);
+                    ctx.cache().viewport(0, 0, width as GLsizei, height as GLsizei);
+                }
+
+                app.render(ctx);
+
+                // Swap buffers
+                window.swap_buffers();
+            }
+        }
+        Err(move msg) => {
+            io::println(~"Error: " + msg);
+        }
+    }
+
+    app.cleanup();
+
+    match record {
+        Some(ref path) => match write_events(path, *recorded) {
+            Ok(()) => io::println(fmt!("Recorded %u events to %s", recorded.len(), path.to_str())),
+            Err(move msg) => io::println(~"Error: " + msg),
+        },
+        None => (),
+    }
+}
+
+/// Renders the program returned by `new_app` into a software framebuffer
+/// the size of the window described by `desc`, dumping frames as `options`
+/// asks and feeding it the input in `replay`.
+fn run_headless<A: App>(desc: &WindowDesc, options: &Options, replay: Option<Replay>,
+                        new_app: fn~() -> A) {
+    let width = desc.width as uint;
+    let height = desc.height as uint;
+
+    let dir = Path(options.out);
+    // rwxr-xr-x
+    if !os::path_is_dir(&dir) && !os::make_dir(&dir, 493) {
+        io::println(~"Error: cannot create " + dir.to_str());
+        return;
+    }
+
+    let ctx = Context::new(@SoftGl::new(width, height) as @Gl, width, height);
+    do offscreen_session(&ctx, options.frames, new_app(), replay) |frame| {
+        // Read back the frame, bottom row first, and store it
+        let pixels = ctx.gl().read_pixels(0, 0, width as GLsizei, height as GLsizei);
+        let path = dir.push(fmt!("frame-%04u.png", frame));
+        match write(&path, width, height, flip_rows(pixels, width * 4)) {
+            Ok(()) => true,
+            Err(move msg) => {
+                io::println(~"Error: " + msg);
+                false
+            }
+        }
+    }
+    ctx.destroy();
+}
+
+/**
+ * Runs the program returned by `new_app` against a `MockGl`, as if in a
+ * window described by `desc`, for the frames `options` asks for and with
+ * the input in `replay`, then prints the calls it made. Calls a driver
+ * would have rejected are listed again at the end and make the exit
+ * status 1.
+ */
+fn run_traced<A: App>(desc: &WindowDesc, options: &Options, replay: Option<Replay>,
+                      new_app: fn~() -> A) {
+    let mock = @MockGl::new();
+
+    let ctx = Context::new(mock as @Gl, desc.width as uint, desc.height as uint);
+    do offscreen_session(&ctx, options.frames, new_app(), replay) |_frame| { true }
+    let stats = ctx.cache().stats();
+    ctx.destroy();
+
+    io::println(mock.trace());
+
+    let errors = mock.errors();
+    for errors.each |call| {
+        io::println(~"Error: " + call.to_str());
+    }
+    for mock.redundant().each |call| {
+        io::println(~"Redundant: " + call.to_str());
+    }
+    io::println(~"State cache: " + stats.to_str());
+    if !errors.is_empty() {
+        os::set_exit_status(1);
+    }
+}
+
+/**
+ * Runs `app` for `frames` frames of simulated time, calling `capture` with
+ * the number of each frame once it is rendered and stopping early if that
+ * returns `false`. The events in `replay` are fed to it as the simulated
+ * clock reaches them. Drops `app` before returning.
+ */
+fn offscreen_session<A: App>(ctx: &Context, frames: uint, app: A, replay: Option<Replay>,
+                             capture: fn(uint) -> bool) {
+    let mut app = move app;
+    let input = Input::new();
+
+    match app.init(ctx) {
+        Ok(()) => {
+            for uint::range(0, frames) |frame| {
+                app.render(ctx);
+                if !capture(frame) { break; }
+
+                match replay {
+                    Some(ref replay) => replay.advance((frame + 1) as float * FRAME_TIME, &input),
+                    None => (),
+                }
+                app.update(&input, FRAME_TIME);
+                input.end_frame();
+            }
+        }
+        Err(move msg) => {
+            io::println(~"Error: " + msg);
+        }
+    }
+
+    app.cleanup();
+}
+
+/// Reverses the order of the `stride`-byte rows of `pixels`.
+fn flip_rows(pixels: &[u8], stride: uint) -> ~[u8] {
+    let rows = pixels.len() / stride;
+    let mut out = vec::with_capacity(pixels.len());
+    for uint::range(0, rows) |i| {
+        let row = rows - 1 - i;
+        out.push_all(vec::view(pixels, row * stride, (row + 1) * stride));
+    }
+    move out
+}
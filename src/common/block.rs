@@ -0,0 +1,416 @@
+/*!
+ * Uniform blocks and the buffers feeding them.
+ *
+ * A uniform block declared `layout(std140)` has the same layout in every
+ * program and on every driver, so one buffer can feed the block in any
+ * number of programs. The layout is worked out here: a block type lists
+ * its members once in `UniformBlock::write_members`, in the order the GLSL
+ * declares them, and `Std140Writer` inserts the padding between them.
+ *
+ * The rules of the standard uniform block layout in the GL specification,
+ * for the types the tutorials use: scalars are aligned to 4 bytes, `vec2`
+ * to 8, `vec3` and `vec4` to 16. A `vec3` takes only 12 bytes, so a float
+ * can follow it in the same 16. A matrix is laid out as an array of its
+ * columns, and every element of an array starts on a multiple of 16
+ * bytes, however small it is, so a `float[4]` takes 64 bytes; that holds
+ * for an array of one element too. A struct starts on a multiple of 16
+ * and is padded to one, so whatever follows it does too.
+ */
+
+use glcore::*;
+use lmath::vec::vec3::Vec3;
+use lmath::mat::mat4::Mat4;
+
+use context::Context;
+use gl::as_bytes;
+
+/// `n` rounded up to a multiple of `to`.
+pub pure fn round_up(n: uint, to: uint) -> uint {
+    (n + to - 1) / to * to
+}
+
+/// The std140 base alignment of a member of the GLSL type `kind`, not an array.
+pub pure fn base_alignment(kind: GLenum) -> uint {
+    if kind == GL_FLOAT || kind == GL_INT || kind == GL_UNSIGNED_INT || kind == GL_BOOL {
+        4
+    } else if kind == GL_FLOAT_VEC2 || kind == GL_INT_VEC2 {
+        8
+    } else {
+        16
+    }
+}
+
+/// The number of bytes std140 gives a member of the GLSL type `kind`, not an array.
+pub pure fn kind_size(kind: GLenum) -> uint {
+    if kind == GL_FLOAT || kind == GL_INT || kind == GL_UNSIGNED_INT || kind == GL_BOOL {
+        4
+    } else if kind == GL_FLOAT_VEC2 || kind == GL_INT_VEC2 {
+        8
+    } else if kind == GL_FLOAT_VEC3 || kind == GL_INT_VEC3 {
+        12
+    } else if kind == GL_FLOAT_MAT2 {
+        32
+    } else if kind == GL_FLOAT_MAT3 {
+        48
+    } else if kind == GL_FLOAT_MAT4 {
+        64
+    } else {
+        16
+    }
+}
+
+/// The distance in bytes between the elements of an array of `kind`.
+pub pure fn array_stride(kind: GLenum) -> uint {
+    round_up(kind_size(kind), 16)
+}
+
+/**
+ * The offsets of the members of a std140 block, each given as its GLSL
+ * type and array size, `None` for members that are not arrays.
+ */
+pub fn member_offsets(members: &[(GLenum, Option<uint>)]) -> ~[uint] {
+    let mut offsets = ~[];
+    let mut end = 0;
+    for members.each |member| {
+        match *member {
+            (kind, Some(count)) => {
+                let offset = round_up(end, 16);
+                offsets.push(offset);
+                end = offset + count * array_stride(kind);
+            }
+            (kind, None) => {
+                let offset = round_up(end, base_alignment(kind));
+                offsets.push(offset);
+                end = offset + kind_size(kind);
+            }
+        }
+    }
+    move offsets
+}
+
+/// Appends the bytes of `values` to `out`.
+fn push_floats(out: &mut ~[u8], values: &[f32]) {
+    do as_bytes(values) |bytes| {
+        out.push_all(bytes);
+    }
+}
+
+/// A Rust type that can be a member of a std140 block.
+pub trait Std140 {
+    /// The GLSL type of the member, e.g. `GL_FLOAT_VEC3`.
+    pure fn kind(&self) -> GLenum;
+
+    /// Appends the value, `kind_size(self.kind())` bytes of it, to `out`.
+    fn write(&self, out: &mut ~[u8]);
+}
+
+impl f32 : Std140 {
+    pure fn kind(&self) -> GLenum { GL_FLOAT }
+
+    fn write(&self, out: &mut ~[u8]) {
+        push_floats(out, [*self]);
+    }
+}
+
+impl GLint : Std140 {
+    pure fn kind(&self) -> GLenum { GL_INT }
+
+    fn write(&self, out: &mut ~[u8]) {
+        do as_bytes([*self]) |bytes| {
+            out.push_all(bytes);
+        }
+    }
+}
+
+impl [f32 * 2] : Std140 {
+    pure fn kind(&self) -> GLenum { GL_FLOAT_VEC2 }
+
+    fn write(&self, out: &mut ~[u8]) {
+        push_floats(out, *self);
+    }
+}
+
+impl [f32 * 3] : Std140 {
+    pure fn kind(&self) -> GLenum { GL_FLOAT_VEC3 }
+
+    fn write(&self, out: &mut ~[u8]) {
+        push_floats(out, *self);
+    }
+}
+
+impl Vec3<f32> : Std140 {
+    pure fn kind(&self) -> GLenum { GL_FLOAT_VEC3 }
+
+    fn write(&self, out: &mut ~[u8]) {
+        push_floats(out, [self.x, self.y, self.z]);
+    }
+}
+
+impl [f32 * 4] : Std140 {
+    pure fn kind(&self) -> GLenum { GL_FLOAT_VEC4 }
+
+    fn write(&self, out: &mut ~[u8]) {
+        push_floats(out, *self);
+    }
+}
+
+impl [f32 * 16] : Std140 {
+    pure fn kind(&self) -> GLenum { GL_FLOAT_MAT4 }
+
+    /// The elements are taken to be in column-major order, as GLSL stores them.
+    fn write(&self, out: &mut ~[u8]) {
+        push_floats(out, *self);
+    }
+}
+
+impl Mat4<f32> : Std140 {
+    pure fn kind(&self) -> GLenum { GL_FLOAT_MAT4 }
+
+    fn write(&self, out: &mut ~[u8]) {
+        // The columns are 16 bytes each already, so need no padding
+        unsafe {
+            do vec::raw::buf_as_slice(self.to_ptr(), 16) |m| {
+                push_floats(out, m);
+            }
+        }
+    }
+}
+
+/// Lays out the members of a block one after another, padded as std140 requires.
+pub struct Std140Writer {
+    priv bytes: ~[u8],
+}
+
+impl Std140Writer {
+    static fn new() -> Std140Writer {
+        Std140Writer { bytes: ~[] }
+    }
+
+    /// Pads with zeros up to a multiple of `alignment`.
+    priv fn align(&mut self, alignment: uint) {
+        let end = round_up(self.bytes.len(), alignment);
+        while self.bytes.len() < end {
+            self.bytes.push(0);
+        }
+    }
+
+    /// Appends the member `value`, returning its offset.
+    fn member<T: Std140>(&mut self, value: &T) -> uint {
+        self.align(base_alignment(value.kind()));
+        let offset = self.bytes.len();
+        value.write(&mut self.bytes);
+        offset
+    }
+
+    /// Appends the array member `values`, returning its offset.
+    fn array<T: Std140>(&mut self, values: &[T]) -> uint {
+        self.align(16);
+        let offset = self.bytes.len();
+        for values.each |value| {
+            value.write(&mut self.bytes);
+            self.align(16);
+        }
+        offset
+    }
+
+    /// Appends the struct member `value`, returning its offset.
+    fn structure<T: UniformBlock>(&mut self, value: &T) -> uint {
+        self.align(16);
+        let offset = self.bytes.len();
+        value.write_members(self);
+        self.align(16);
+        offset
+    }
+
+    /// The bytes of the block, padded to a multiple of 16.
+    fn finish(&mut self) -> ~[u8] {
+        self.align(16);
+        copy self.bytes
+    }
+}
+
+/// A struct that can be uploaded as the contents of a std140 uniform block.
+pub trait UniformBlock {
+    /// Writes the members with `writer.member`, `writer.array` or
+    /// `writer.structure`, in the order the GLSL block declares them.
+    fn write_members(&self, writer: &mut Std140Writer);
+}
+
+/// The bytes of `block` as std140 lays them out.
+pub fn std140_bytes<T: UniformBlock>(block: &T) -> ~[u8] {
+    let mut writer = Std140Writer::new();
+    block.write_members(&mut writer);
+    writer.finish()
+}
+
+/**
+ * A buffer holding the contents of a uniform block of type `T`, deleted
+ * when dropped. Bind it to the binding point the programs' blocks are
+ * bound to with `Program::bind_block`.
+ */
+pub struct UniformBuffer<T> {
+    priv ctx: Context,
+    name: GLuint,
+    usage: GLenum,
+}
+
+impl<T: UniformBlock> UniformBuffer<T> {
+    /// Creates a buffer holding `block`. Use `GL_DYNAMIC_DRAW` for one
+    /// that is updated every frame.
+    static fn new(ctx: &Context, block: &T, usage: GLenum) -> UniformBuffer<T> {
+        let buffer = UniformBuffer { ctx: ctx.acquire(), name: ctx.gl().gen_buffer(), usage: usage };
+        buffer.update(block);
+        move buffer
+    }
+
+    /// Replaces the contents of the buffer with `block`.
+    fn update(&self, block: &T) {
+        let gl = self.ctx.gl();
+        gl.bind_buffer(GL_UNIFORM_BUFFER, self.name);
+        gl.buffer_data(GL_UNIFORM_BUFFER, std140_bytes(block), self.usage);
+    }
+
+    /// Binds the buffer to uniform buffer binding point `binding`.
+    fn bind_to(&self, binding: uint) {
+        self.ctx.gl().bind_buffer_base(GL_UNIFORM_BUFFER, binding as GLuint, self.name);
+    }
+}
+
+impl<T> UniformBuffer<T> : Drop {
+    fn finalize(&self) {
+        if self.ctx.release() {
+            self.ctx.gl().delete_buffer(self.name);
+        }
+    }
+}
+
+/**
+ * Hands out uniform buffer binding points by block name, so that every
+ * program declaring a block, and the buffer feeding it, agree on where it
+ * is bound.
+ */
+pub struct BindingPoints {
+    priv mut names: ~[~str],
+}
+
+impl BindingPoints {
+    static fn new() -> BindingPoints {
+        BindingPoints { names: ~[] }
+    }
+
+    /// The binding point of block `name`, taking the next free one the
+    /// first time the name is asked for.
+    fn point(&self, name: &str) -> uint {
+        match vec::position(self.names, |n| str::eq_slice(*n, name)) {
+            Some(i) => i,
+            None => {
+                self.names.push(str::from_slice(name));
+                self.names.len() - 1
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glcore::*;
+    use gl::as_bytes;
+    use block::*;
+
+    /// Whether `bytes` holds `values` from offset `at` on.
+    fn floats_at(bytes: &[u8], at: uint, values: &[f32]) -> bool {
+        do as_bytes(values) |expected| {
+            let end = at + expected.len();
+            end <= bytes.len() && vec::slice(bytes, at, end) == vec::from_slice(expected)
+        }
+    }
+
+    #[test]
+    fn packs_a_float_after_a_vec3() {
+        assert member_offsets([(GL_FLOAT_VEC3, None), (GL_FLOAT, None)]) == ~[0, 12];
+
+        let position: [f32 * 3] = [1.0, 2.0, 3.0];
+        let mut writer = Std140Writer::new();
+        assert writer.member(&position) == 0;
+        assert writer.member(&4.0f32) == 12;
+        let bytes = writer.finish();
+        assert bytes.len() == 16;
+        assert floats_at(bytes, 0, [1.0, 2.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn strides_arrays_by_16_bytes() {
+        assert array_stride(GL_FLOAT) == 16;
+        assert array_stride(GL_FLOAT_VEC3) == 16;
+        assert array_stride(GL_FLOAT_MAT4) == 64;
+        assert member_offsets([(GL_FLOAT, Some(4)), (GL_FLOAT, None)]) == ~[0, 64];
+        assert member_offsets([(GL_FLOAT, None), (GL_FLOAT_VEC2, Some(2)), (GL_FLOAT, None)]) == ~[0, 16, 48];
+
+        let mut writer = Std140Writer::new();
+        assert writer.array([1.0f32, 2.0, 3.0]) == 0;
+        assert writer.member(&4.0f32) == 48;
+        let bytes = writer.finish();
+        assert bytes.len() == 64;
+        assert floats_at(bytes, 16, [2.0]) && floats_at(bytes, 32, [3.0]) && floats_at(bytes, 48, [4.0]);
+    }
+
+    #[test]
+    fn lays_out_arrays_of_one_element_as_arrays() {
+        assert member_offsets([(GL_FLOAT, Some(1)), (GL_FLOAT, None)]) == ~[0, 16];
+        assert member_offsets([(GL_FLOAT, None), (GL_FLOAT, None)]) == ~[0, 4];
+
+        let mut writer = Std140Writer::new();
+        writer.array([1.0f32]);
+        assert writer.member(&2.0f32) == 16;
+    }
+
+    #[test]
+    fn starts_matrix_columns_on_16_bytes() {
+        assert member_offsets([(GL_FLOAT, None), (GL_FLOAT_MAT4, None), (GL_FLOAT_VEC2, None)]) == ~[0, 16, 80];
+        assert member_offsets([(GL_FLOAT, None), (GL_FLOAT_MAT3, None), (GL_FLOAT, None)]) == ~[0, 16, 64];
+
+        let columns: [f32 * 16] = [0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0,
+                                   8.0, 9.0, 10.0, 11.0, 12.0, 13.0, 14.0, 15.0];
+        let mut writer = Std140Writer::new();
+        writer.member(&1.0f32);
+        assert writer.member(&columns) == 16;
+        let bytes = writer.finish();
+        for uint::range(0, 4) |column| {
+            let c = column as f32 * 4.0;
+            assert floats_at(bytes, 16 + column * 16, [c, c + 1.0, c + 2.0, c + 3.0]);
+        }
+    }
+
+    struct Light {
+        color: [f32 * 3],
+    }
+
+    impl Light : UniformBlock {
+        fn write_members(&self, writer: &mut Std140Writer) {
+            writer.member(&self.color);
+        }
+    }
+
+    struct Scene {
+        ambient: f32,
+        light: Light,
+        exposure: f32,
+    }
+
+    impl Scene : UniformBlock {
+        fn write_members(&self, writer: &mut Std140Writer) {
+            writer.member(&self.ambient);
+            assert writer.structure(&self.light) == 16;
+            assert writer.member(&self.exposure) == 32;
+        }
+    }
+
+    #[test]
+    fn aligns_nested_structs_to_16_bytes() {
+        let bytes = std140_bytes(&Scene { ambient: 0.5, light: Light { color: [1.0, 2.0, 3.0] }, exposure: 4.0 });
+        assert bytes.len() == 48;
+        assert floats_at(bytes, 0, [0.5]);
+        assert floats_at(bytes, 16, [1.0, 2.0, 3.0]);
+        assert floats_at(bytes, 32, [4.0]);
+    }
+}
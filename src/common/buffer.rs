@@ -0,0 +1,491 @@
+/*!
+ * Vertex array and buffer objects.
+ *
+ * Data that changes every frame can be sent three ways. `Buffer::update`
+ * overwrites a buffer in place, which may wait for draws still reading
+ * it; `Buffer::orphan` gives it new storage instead, leaving the old to
+ * those draws. A `StreamBuffer` keeps several frames' worth of storage
+ * and writes each frame into the next part of it, fencing off the parts
+ * GL may still be reading.
+ */
+
+use sys::size_of;
+
+use glcore::*;
+
+use context::Context;
+use gl::as_bytes;
+
+/// A vertex array object, deleted when dropped.
+pub struct VertexArray {
+    priv ctx: Context,
+    name: GLuint,
+}
+
+impl VertexArray {
+    static fn new(ctx: &Context) -> VertexArray {
+        VertexArray { ctx: ctx.acquire(), name: ctx.gl().gen_vertex_array() }
+    }
+
+    fn bind(&self) {
+        self.ctx.cache().bind_vertex_array(self.name);
+    }
+}
+
+impl VertexArray : Drop {
+    fn finalize(&self) {
+        if self.ctx.release() {
+            self.ctx.cache().vertex_array_deleted(self.name);
+            self.ctx.gl().delete_vertex_array(self.name);
+        }
+    }
+}
+
+/**
+ * A buffer object holding elements of type `T`, deleted when dropped.
+ *
+ * The size passed to GL is always derived from `T`. Element data is better
+ * kept in an `IndexBuffer`, which also knows the type to draw it with.
+ * Contents that change are best created with `GL_DYNAMIC_DRAW`, if they
+ * are updated now and then, or `GL_STREAM_DRAW`, if they are replaced
+ * every frame.
+ */
+pub struct Buffer<T> {
+    priv ctx: Context,
+    name: GLuint,
+    target: GLenum,
+    usage: GLenum,
+    len: uint,
+}
+
+impl<T> Buffer<T> {
+    /// Creates a buffer bound to `target` and fills it with `data`.
+    static fn new(ctx: &Context, target: GLenum, data: &[T], usage: GLenum) -> Buffer<T> {
+        let buffer = Buffer {
+            ctx: ctx.acquire(),
+            name: ctx.gl().gen_buffer(),
+            target: target,
+            usage: usage,
+            len: data.len(),
+        };
+
+        buffer.bind();
+        do as_bytes(data) |bytes| {
+            ctx.gl().buffer_data(target, bytes, usage);
+        }
+        move buffer
+    }
+
+    fn bind(&self) {
+        self.ctx.gl().bind_buffer(self.target, self.name);
+    }
+
+    /// The number of elements in the buffer.
+    fn len(&self) -> uint {
+        self.len
+    }
+
+    /// Overwrites the elements from `offset` on with `data`, leaving the
+    /// buffer bound.
+    fn update(&self, offset: uint, data: &[T]) {
+        assert offset + data.len() <= self.len;
+        self.bind();
+        do as_bytes(data) |bytes| {
+            self.ctx.gl().buffer_sub_data(self.target, offset * size_of::<T>(), bytes);
+        }
+    }
+
+    /**
+     * Replaces every element with those of `data`, which must be as many,
+     * in new storage, leaving the buffer bound. The old storage is orphaned
+     * rather than overwritten: draws still reading it go on doing so, and
+     * GL frees it once they are done, where `update` might have to wait
+     * for them.
+     */
+    fn orphan(&self, data: &[T]) {
+        assert data.len() == self.len;
+        self.bind();
+        do as_bytes(data) |bytes| {
+            self.ctx.gl().buffer_data(self.target, bytes, self.usage);
+        }
+    }
+}
+
+impl<T> Buffer<T> : Drop {
+    fn finalize(&self) {
+        if self.ctx.release() {
+            self.ctx.gl().delete_buffer(self.name);
+        }
+    }
+}
+
+/// How long `StreamBuffer::write` waits for a fence at a time, in nanoseconds.
+const FENCE_TIMEOUT: GLuint64 = 1000000000;
+
+/**
+ * A ring of `regions` parts of a buffer object, each holding up to `len`
+ * elements of type `T`, for data written afresh every frame. Deleted when
+ * dropped.
+ *
+ * Each `write` goes into the next region round, through a mapping GL is
+ * told not to synchronize, so it never waits for draws reading the other
+ * regions. Instead, `fence` marks the end of the draws reading the region
+ * last written, and a region is only written again once its fence has
+ * been signalled; with three regions, that is two frames later, long
+ * after GL is done with it. Vertex attributes are laid out for the start
+ * of the buffer, so draws pick out the region written by the element
+ * `write` returns.
+ *
+ * Each region is mapped while it is written rather than the buffer staying
+ * mapped for good, as keeping it mapped while drawing from it takes the
+ * persistent mappings of GL 4.4.
+ */
+pub struct StreamBuffer<T> {
+    priv ctx: Context,
+    name: GLuint,
+    target: GLenum,
+    len: uint,
+    priv regions: uint,
+    /// The region the next `write` goes into.
+    priv mut next: uint,
+    /// The fence after the last draws reading each region, if any.
+    priv mut fences: ~[Option<GLsync>],
+}
+
+impl<T> StreamBuffer<T> {
+    /// Creates a buffer bound to `target`, with room for `regions` times
+    /// `len` elements. There must be at least one region.
+    static fn new(ctx: &Context, target: GLenum, len: uint, regions: uint) -> StreamBuffer<T> {
+        assert regions > 0;
+        let buffer = StreamBuffer {
+            ctx: ctx.acquire(),
+            name: ctx.gl().gen_buffer(),
+            target: target,
+            len: len,
+            regions: regions,
+            next: 0,
+            fences: vec::from_elem(regions, None),
+        };
+
+        // Allocate the storage, which every write then fills a region of
+        buffer.bind();
+        ctx.gl().buffer_data(target, vec::from_elem(len * regions * size_of::<T>(), 0u8), GL_STREAM_DRAW);
+        move buffer
+    }
+
+    fn bind(&self) {
+        self.ctx.gl().bind_buffer(self.target, self.name);
+    }
+
+    /// The number of elements in each region.
+    fn len(&self) -> uint {
+        self.len
+    }
+
+    /**
+     * Writes `data`, up to `len` elements, into the next region, once GL
+     * has finished reading what was last written there, and leaves the
+     * buffer bound. Returns the index of the first element written,
+     * counting from the start of the buffer. Should the region fail to
+     * map, the data is copied in with `glBufferSubData` instead.
+     */
+    fn write(&self, data: &[T]) -> uint {
+        assert data.len() <= self.len;
+        let gl = self.ctx.gl();
+        let region = self.next;
+        self.next = (region + 1) % self.regions;
+
+        match self.fences[region] {
+            Some(sync) => {
+                while gl.client_wait_sync(sync, GL_SYNC_FLUSH_COMMANDS_BIT, FENCE_TIMEOUT) == GL_TIMEOUT_EXPIRED {}
+                gl.delete_sync(sync);
+                self.fences[region] = None;
+            }
+            None => (),
+        }
+
+        self.bind();
+        if !data.is_empty() {
+            do as_bytes(data) |bytes| {
+                // Nothing is read from the region, and the fence has already
+                // made sure GL is done with it
+                let access = GL_MAP_WRITE_BIT | GL_MAP_INVALIDATE_RANGE_BIT | GL_MAP_UNSYNCHRONIZED_BIT;
+                let offset = region * self.len * size_of::<T>();
+                let mapping = gl.map_buffer_range(self.target, offset, bytes.len(), access);
+                let written = if ptr::is_null(mapping) {
+                    false
+                } else {
+                    unsafe { ptr::copy_memory(mapping, vec::raw::to_ptr(bytes), bytes.len()); }
+                    // The contents are lost if the mapping was disturbed
+                    gl.unmap_buffer(self.target) == GL_TRUE
+                };
+
+                // Otherwise copy the data in, so the region never holds
+                // what was written to it rounds ago
+                if !written {
+                    gl.buffer_sub_data(self.target, offset, bytes);
+                }
+            }
+        }
+        region * self.len
+    }
+
+    /**
+     * Inserts a fence after the draws made so far, which the region last
+     * written is not written again before. Call it once the draws reading
+     * that region have been made.
+     */
+    fn fence(&self) {
+        let gl = self.ctx.gl();
+        let region = (self.next + self.regions - 1) % self.regions;
+        match self.fences[region] {
+            Some(sync) => gl.delete_sync(sync),
+            None => (),
+        }
+        self.fences[region] = Some(gl.fence_sync());
+    }
+}
+
+impl<T> StreamBuffer<T> : Drop {
+    fn finalize(&self) {
+        if self.ctx.release() {
+            let gl = self.ctx.gl();
+            for self.fences.each |fence| {
+                match *fence {
+                    Some(sync) => gl.delete_sync(sync),
+                    None => (),
+                }
+            }
+            gl.delete_buffer(self.name);
+        }
+    }
+}
+
+/// An unsigned integer type GL accepts as an element index.
+pub trait Index: Copy {
+    pure fn to_uint(&self) -> uint;
+}
+
+impl u8 : Index {
+    pure fn to_uint(&self) -> uint { *self as uint }
+}
+
+impl u16 : Index {
+    pure fn to_uint(&self) -> uint { *self as uint }
+}
+
+impl u32 : Index {
+    pure fn to_uint(&self) -> uint { *self as uint }
+}
+
+/// The `glDrawElements` type of indices of type `T`.
+pub pure fn index_type<T: Index>() -> GLenum {
+    match size_of::<T>() {
+        1 => GL_UNSIGNED_BYTE,
+        2 => GL_UNSIGNED_SHORT,
+        _ => GL_UNSIGNED_INT,
+    }
+}
+
+/**
+ * An element buffer, deleted when dropped.
+ *
+ * The buffer remembers the type of its indices, so the size uploaded and
+ * the type passed to `glDrawElements` always agree. The element buffer
+ * binding belongs to the vertex array, so bind that first.
+ */
+pub struct IndexBuffer {
+    priv ctx: Context,
+    name: GLuint,
+    kind: GLenum,
+    len: uint,
+}
+
+impl IndexBuffer {
+    /// Creates an element buffer holding `indices` as they are.
+    static fn new<T: Index>(ctx: &Context, indices: &[T], usage: GLenum) -> IndexBuffer {
+        let buffer = IndexBuffer {
+            ctx: ctx.acquire(),
+            name: ctx.gl().gen_buffer(),
+            kind: index_type::<T>(),
+            len: indices.len(),
+        };
+
+        buffer.bind();
+        do as_bytes(indices) |bytes| {
+            ctx.gl().buffer_data(GL_ELEMENT_ARRAY_BUFFER, bytes, usage);
+        }
+        move buffer
+    }
+
+    /**
+     * Creates an element buffer holding `indices` as the narrowest type
+     * their largest value fits in, which for small meshes saves half or
+     * three quarters of the memory `GLuint` indices would take.
+     */
+    static fn narrowest<T: Index>(ctx: &Context, indices: &[T], usage: GLenum) -> IndexBuffer {
+        let max = vec::foldl(0, indices, |m, i| uint::max(m, i.to_uint()));
+
+        if max <= u8::max_value as uint {
+            IndexBuffer::new(ctx, indices.map(|i| i.to_uint() as u8), usage)
+        } else if max <= u16::max_value as uint {
+            IndexBuffer::new(ctx, indices.map(|i| i.to_uint() as u16), usage)
+        } else {
+            IndexBuffer::new(ctx, indices.map(|i| i.to_uint() as u32), usage)
+        }
+    }
+
+    fn bind(&self) {
+        self.ctx.gl().bind_buffer(GL_ELEMENT_ARRAY_BUFFER, self.name);
+    }
+
+    /// The number of indices in the buffer.
+    fn len(&self) -> uint {
+        self.len
+    }
+
+    /// The type of the indices: `GL_UNSIGNED_BYTE`, `_SHORT` or `_INT`.
+    fn kind(&self) -> GLenum {
+        self.kind
+    }
+
+    /// Draws all the indices as primitives of type `mode`.
+    fn draw(&self, mode: GLenum) {
+        self.ctx.gl().draw_elements(mode, self.len as GLsizei, self.kind, 0);
+    }
+
+    /**
+     * Draws all the indices as primitives of type `mode`, `instances`
+     * times over. Attributes bound with `bind_instance_attributes` read
+     * the data of the instance being drawn.
+     */
+    fn draw_instanced(&self, mode: GLenum, instances: uint) {
+        self.ctx.gl().draw_elements_instanced(mode, self.len as GLsizei, self.kind, 0, instances as GLsizei);
+    }
+}
+
+impl IndexBuffer : Drop {
+    fn finalize(&self) {
+        if self.ctx.release() {
+            self.ctx.gl().delete_buffer(self.name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glcore::*;
+    use buffer::*;
+    use context::Context;
+    use gl::Gl;
+    use mock::{MockGl, Call, Int, Data};
+
+    /// The type `narrowest` picks for `indices`, and the bytes it uploads.
+    fn narrowest(indices: &[u32]) -> (GLenum, ~[u8]) {
+        let mock = @MockGl::new();
+        let ctx = Context::new(mock as @Gl, 800, 600);
+        let kind = {
+            let vao = VertexArray::new(&ctx);
+            vao.bind();
+            IndexBuffer::narrowest(&ctx, indices, GL_STATIC_DRAW).kind()
+        };
+        ctx.destroy();
+
+        let uploads = mock.calls_to("glBufferData");
+        assert mock.errors().is_empty() && uploads.len() == 1;
+        match uploads[0].args[1] {
+            Data(ref bytes) => (kind, copy *bytes),
+            _ => fail(~"glBufferData without data"),
+        }
+    }
+
+    // The indices are stored in the machine's byte order, little-endian here
+
+    #[test]
+    fn stores_up_to_255_as_bytes() {
+        assert narrowest([0, 255]) == (GL_UNSIGNED_BYTE, ~[0, 255]);
+        assert narrowest([256, 0]) == (GL_UNSIGNED_SHORT, ~[0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn stores_up_to_65535_as_shorts() {
+        assert narrowest([65535, 1]) == (GL_UNSIGNED_SHORT, ~[255, 255, 1, 0]);
+        assert narrowest([1, 65536]) == (GL_UNSIGNED_INT, ~[1, 0, 0, 0, 0, 0, 1, 0]);
+    }
+
+    fn names(calls: &[Call]) -> ~[~str] {
+        calls.map(|c| copy c.name)
+    }
+
+    fn offset(call: &Call) -> int {
+        match call.args[1] { Int(offset) => offset, _ => fail(~"an offset that is not an int") }
+    }
+
+    #[test]
+    fn writes_each_region_in_turn() {
+        let mock = @MockGl::new();
+        let ctx = Context::new(mock as @Gl, 800, 600);
+        {
+            let stream: StreamBuffer<f32> = StreamBuffer::new(&ctx, GL_ARRAY_BUFFER, 4, 3);
+            let firsts = vec::from_fn(4, |_| stream.write([1.0, 2.0]));
+            assert firsts == ~[0, 4, 8, 0];
+        }
+        ctx.destroy();
+
+        // Each region is 4 floats, 16 bytes, long
+        assert mock.errors().is_empty();
+        assert mock.calls_to("glMapBufferRange").map(|c| offset(c)) == ~[0, 16, 32, 0];
+        assert mock.calls_to("glUnmapBuffer").len() == 4;
+    }
+
+    #[test]
+    fn waits_for_a_fenced_region_before_writing_it() {
+        let mock = @MockGl::new();
+        let ctx = Context::new(mock as @Gl, 800, 600);
+        {
+            let stream: StreamBuffer<f32> = StreamBuffer::new(&ctx, GL_ARRAY_BUFFER, 4, 2);
+            stream.write([1.0]);
+            stream.fence();
+            stream.write([2.0]);
+
+            // The first region is fenced, so it is waited for
+            mock.clear_trace();
+            stream.write([3.0]);
+            assert names(mock.calls()) == ~[~"glClientWaitSync", ~"glDeleteSync", ~"glBindBuffer",
+                                            ~"glMapBufferRange", ~"glUnmapBuffer"];
+
+            // And the second is not
+            mock.clear_trace();
+            stream.write([4.0]);
+            assert names(mock.calls()) == ~[~"glBindBuffer", ~"glMapBufferRange", ~"glUnmapBuffer"];
+        }
+        ctx.destroy();
+        assert mock.errors().is_empty();
+    }
+
+    #[test]
+    fn copies_the_data_in_when_a_region_fails_to_map() {
+        let mock = @MockGl::new();
+        let ctx = Context::new(mock as @Gl, 800, 600);
+        {
+            let stream: StreamBuffer<f32> = StreamBuffer::new(&ctx, GL_ARRAY_BUFFER, 4, 2);
+            stream.write([0.0]);
+            mock.fail_maps(true);
+            mock.clear_trace();
+            assert stream.write([1.0, 2.0]) == 4;
+        }
+        ctx.destroy();
+
+        let errors = mock.errors();
+        assert errors.len() == 1 && errors[0].error == Some(GL_OUT_OF_MEMORY);
+        assert mock.calls_to("glUnmapBuffer").is_empty();
+
+        // The second region starts 16 bytes in; 1.0 and 2.0, little-endian
+        let copies = mock.calls_to("glBufferSubData");
+        assert copies.len() == 1 && offset(&copies[0]) == 16;
+        match copies[0].args[2] {
+            Data(ref bytes) => assert *bytes == ~[0, 0, 128, 63, 0, 0, 0, 64],
+            _ => fail(~"glBufferSubData without data"),
+        }
+    }
+}
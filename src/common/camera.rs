@@ -0,0 +1,189 @@
+/*!
+ * View and projection matrices.
+ *
+ * These build the same matrices as `gluLookAt`, `gluPerspective` and
+ * `glOrtho` (or GLM's functions of the same names), column-major, for a
+ * right-handed world looked at down the negative z axis of eye space and
+ * clip coordinates from -1 to 1 in z. `back_to_front` orders transparent
+ * objects by how far in front of the eye a view matrix puts them.
+ */
+
+use std::sort::merge_sort;
+
+use lmath::vec::vec3::Vec3;
+use lmath::mat::mat4::Mat4;
+
+pure fn sub(a: &Vec3<f32>, b: &Vec3<f32>) -> Vec3<f32> {
+    Vec3::new(a.x - b.x, a.y - b.y, a.z - b.z)
+}
+
+pure fn dot(a: &Vec3<f32>, b: &Vec3<f32>) -> f32 {
+    a.x * b.x + a.y * b.y + a.z * b.z
+}
+
+pure fn cross(a: &Vec3<f32>, b: &Vec3<f32>) -> Vec3<f32> {
+    Vec3::new(a.y * b.z - a.z * b.y, a.z * b.x - a.x * b.z, a.x * b.y - a.y * b.x)
+}
+
+pure fn normalize(a: &Vec3<f32>) -> Vec3<f32> {
+    let length = f32::sqrt(dot(a, a));
+    Vec3::new(a.x / length, a.y / length, a.z / length)
+}
+
+/**
+ * The view matrix of a camera at `eye` looking at `center`, turned so
+ * that `up` points up on the screen. `up` must not be parallel to the
+ * direction looked in.
+ */
+pub pure fn look_at(eye: &Vec3<f32>, center: &Vec3<f32>, up: &Vec3<f32>) -> Mat4<f32> {
+    let f = normalize(&sub(center, eye));
+    let s = normalize(&cross(&f, up));
+    let u = cross(&s, &f);
+
+    Mat4::new(s.x, u.x, -f.x, 0.0,
+              s.y, u.y, -f.y, 0.0,
+              s.z, u.z, -f.z, 0.0,
+              -dot(&s, eye), -dot(&u, eye), dot(&f, eye), 1.0)
+}
+
+/**
+ * A perspective projection with a vertical field of view of `fovy`
+ * degrees and a width `aspect` times the height, keeping what lies between
+ * `near` and `far` in front of the eye. Both must be positive.
+ */
+pub pure fn perspective(fovy: f32, aspect: f32, near: f32, far: f32) -> Mat4<f32> {
+    let f = 1.0 / f32::tan(fovy * f32::consts::pi / 360.0);
+
+    Mat4::new(f / aspect, 0.0, 0.0, 0.0,
+              0.0, f, 0.0, 0.0,
+              0.0, 0.0, (far + near) / (near - far), -1.0,
+              0.0, 0.0, 2.0 * far * near / (near - far), 0.0)
+}
+
+/**
+ * An orthographic projection of the box from `left` to `right`, `bottom`
+ * to `top` and `near` to `far` in front of the eye onto the whole of clip
+ * space.
+ */
+pub pure fn ortho(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> Mat4<f32> {
+    Mat4::new(2.0 / (right - left), 0.0, 0.0, 0.0,
+              0.0, 2.0 / (top - bottom), 0.0, 0.0,
+              0.0, 0.0, -2.0 / (far - near), 0.0,
+              -(right + left) / (right - left), -(top + bottom) / (top - bottom),
+              -(far + near) / (far - near), 1.0)
+}
+
+/// How far in front of the eye `view` puts `point`, along the direction looked in.
+pub fn view_depth(view: &Mat4<f32>, point: &Vec3<f32>) -> f32 {
+    // Only the z row matters, and the eye looks down -z
+    unsafe {
+        do vec::raw::buf_as_slice(view.to_ptr(), 16) |m| {
+            -(m[2] * point.x + m[6] * point.y + m[10] * point.z + m[14])
+        }
+    }
+}
+
+/**
+ * The order to draw transparent objects around `centres` in, seen through
+ * `view`: the indices of the centres, farthest from the eye first, so that
+ * each is blended over those behind it. Objects at the same depth keep
+ * the order they are given in.
+ */
+pub fn back_to_front(view: &Mat4<f32>, centres: &[Vec3<f32>]) -> ~[uint] {
+    let depths = vec::mapi(centres, |i, centre| (view_depth(view, centre), i));
+    let sorted = merge_sort(depths, |a, b| {
+        let (depth_a, _) = *a;
+        let (depth_b, _) = *b;
+        depth_a >= depth_b
+    });
+    sorted.map(|pair| { let (_, i) = *pair; i })
+}
+
+/// The width of a `width` by `height` framebuffer over its height, for `perspective`.
+pub pure fn aspect_ratio(width: uint, height: uint) -> f32 {
+    if height == 0 { 1.0 } else { width as f32 / height as f32 }
+}
+
+#[cfg(test)]
+mod tests {
+    use lmath::vec::vec3::Vec3;
+    use lmath::mat::mat4::Mat4;
+    use camera::*;
+
+    /// Whether `m` is `expected`, given column by column, to within rounding.
+    fn matrix_is(m: &Mat4<f32>, expected: &[f32]) -> bool {
+        unsafe {
+            do vec::raw::buf_as_slice(m.to_ptr(), 16) |elements| {
+                vec::all2(elements, expected, |a, b| f32::abs(*a - *b) < 1e-5)
+            }
+        }
+    }
+
+    fn vec3(x: f32, y: f32, z: f32) -> Vec3<f32> {
+        Vec3::new(x, y, z)
+    }
+
+    #[test]
+    fn looks_down_the_z_axis() {
+        let view = look_at(&vec3(0.0, 0.0, 5.0), &vec3(0.0, 0.0, 0.0), &vec3(0.0, 1.0, 0.0));
+        assert matrix_is(&view, [1.0, 0.0, 0.0, 0.0,
+                                 0.0, 1.0, 0.0, 0.0,
+                                 0.0, 0.0, 1.0, 0.0,
+                                 0.0, 0.0, -5.0, 1.0]);
+    }
+
+    #[test]
+    fn looks_at_the_origin_with_z_up() {
+        // glm::lookAt(vec3(1.2f), vec3(0.0f), vec3(0.0f, 0.0f, 1.0f))
+        let view = look_at(&vec3(1.2, 1.2, 1.2), &vec3(0.0, 0.0, 0.0), &vec3(0.0, 0.0, 1.0));
+        assert matrix_is(&view, [-0.707107, -0.408248, 0.577350, 0.0,
+                                 0.707107, -0.408248, 0.577350, 0.0,
+                                 0.0, 0.816497, 0.577350, 0.0,
+                                 0.0, 0.0, -2.078461, 1.0]);
+    }
+
+    #[test]
+    fn projects_in_perspective() {
+        // glm::perspective(45.0f, 800.0f / 600.0f, 1.0f, 10.0f)
+        let proj = perspective(45.0, 800.0 / 600.0, 1.0, 10.0);
+        assert matrix_is(&proj, [1.810660, 0.0, 0.0, 0.0,
+                                 0.0, 2.414214, 0.0, 0.0,
+                                 0.0, 0.0, -1.222222, -1.0,
+                                 0.0, 0.0, -2.222222, 0.0]);
+    }
+
+    #[test]
+    fn projects_orthographically() {
+        let proj = ortho(0.0, 800.0, 0.0, 600.0, -1.0, 1.0);
+        assert matrix_is(&proj, [0.0025, 0.0, 0.0, 0.0,
+                                 0.0, 0.003333, 0.0, 0.0,
+                                 0.0, 0.0, -1.0, 0.0,
+                                 -1.0, -1.0, 0.0, 1.0]);
+
+        // The unit cube only has z flipped, as eye space looks down -z
+        let proj = ortho(-1.0, 1.0, -1.0, 1.0, -1.0, 1.0);
+        assert matrix_is(&proj, [1.0, 0.0, 0.0, 0.0,
+                                 0.0, 1.0, 0.0, 0.0,
+                                 0.0, 0.0, -1.0, 0.0,
+                                 0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn divides_width_by_height() {
+        assert f32::abs(aspect_ratio(800, 600) - 4.0 / 3.0) < 1e-6;
+        assert aspect_ratio(600, 600) == 1.0;
+        // A minimized window has no height
+        assert aspect_ratio(800, 0) == 1.0;
+    }
+
+    #[test]
+    fn sorts_farthest_first() {
+        let view = look_at(&vec3(0.0, 0.0, 5.0), &vec3(0.0, 0.0, 0.0), &vec3(0.0, 1.0, 0.0));
+        assert view_depth(&view, &vec3(1.0, 1.0, 2.0)) == 3.0;
+
+        let centres = ~[vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 2.0),
+                        vec3(0.0, 0.0, -3.0), vec3(1.0, 0.0, 0.0)];
+        // The two at the same depth keep their order
+        assert back_to_front(&view, centres) == ~[2, 0, 3, 1];
+    }
+}
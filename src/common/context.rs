@@ -0,0 +1,113 @@
+/*!
+ * A handle on the GL context the runner created.
+ *
+ * The context carries the `Gl` backend everything is drawn with, either
+ * the driver or the software renderer, the size of the framebuffer drawn
+ * to, and the `StateCache` fixed-function state is set through.
+ *
+ * Every GL object wrapper is created from a `Context` and keeps a handle on
+ * it. The runner drops the program before it destroys the context, so
+ * wrappers delete their names while the context is still current. An
+ * object that escapes the program and would outlive the context makes
+ * destroying the context fail, naming how many escaped, rather than leak
+ * its name. Handles are managed boxes, so they, and the objects holding
+ * them, cannot leave the task that owns the context.
+ */
+
+use gl::Gl;
+use state::StateCache;
+
+struct ContextState {
+    gl: @Gl,
+    cache: @StateCache,
+    mut width: uint,
+    mut height: uint,
+    mut alive: bool,
+    mut objects: uint,
+}
+
+pub struct Context {
+    priv state: @ContextState,
+}
+
+impl Context {
+    /// A handle on the context that is current on this task, drawn to
+    /// through `gl`, with a `width` by `height` framebuffer.
+    static fn new(gl: @Gl, width: uint, height: uint) -> Context {
+        Context {
+            state: @ContextState {
+                gl: gl,
+                cache: @StateCache::new(gl, width, height),
+                width: width,
+                height: height,
+                alive: true,
+                objects: 0
+            }
+        }
+    }
+
+    /// The backend GL calls are made through.
+    fn gl(&self) -> @Gl {
+        self.state.gl
+    }
+
+    /// The cache fixed-function state, the current program and the bound
+    /// vertex array are set through.
+    fn cache(&self) -> @StateCache {
+        self.state.cache
+    }
+
+    /// The width and height of the framebuffer, in pixels.
+    fn framebuffer_size(&self) -> (uint, uint) {
+        (self.state.width, self.state.height)
+    }
+
+    /// Records that the framebuffer is now `width` by `height` pixels.
+    /// Called by the runner when the window is resized.
+    fn resize(&self, width: uint, height: uint) {
+        self.state.width = width;
+        self.state.height = height;
+    }
+
+    /// Registers a new GL object, returning the handle it should keep.
+    fn acquire(&self) -> Context {
+        assert self.state.alive;
+        self.state.objects += 1;
+        Context { state: self.state }
+    }
+
+    /**
+     * Unregisters a GL object that is being dropped. Returns `false` if the
+     * context has already been destroyed, in which case the object's name
+     * must not be deleted. That only happens while the task unwinds from
+     * the failure `destroy` reported it with.
+     */
+    fn release(&self) -> bool {
+        if !self.state.alive {
+            return false;
+        }
+        self.state.objects -= 1;
+        true
+    }
+
+    fn is_alive(&self) -> bool {
+        self.state.alive
+    }
+
+    /// The number of GL objects created from this context and not yet dropped.
+    fn live_objects(&self) -> uint {
+        self.state.objects
+    }
+
+    /**
+     * Marks the context as gone. Called by the runner before terminating
+     * GLFW. Fails if any GL object created from the context is still alive,
+     * as it could no longer be deleted.
+     */
+    fn destroy(&self) {
+        self.state.alive = false;
+        if self.state.objects > 0 {
+            fail(fmt!("%u GL objects outlived their context", self.state.objects));
+        }
+    }
+}
@@ -0,0 +1,260 @@
+/*!
+ * A camera steered with the keyboard and mouse.
+ *
+ * The controller has two modes, switched between with Tab:
+ *
+ * - `Orbit` circles a target point. Dragging with the left mouse button,
+ *   or holding W, A, S or D, swings the camera around it, and scrolling
+ *   moves it closer or further away.
+ * - `Fly` moves freely. W and S move along the direction looked in, A and
+ *   D sideways, and Q and E down and up. Dragging turns the camera, and
+ *   scrolling changes how fast it moves.
+ *
+ * Movement is scaled by the time passed, so it is as fast at 30 frames a
+ * second as at 144. The world's up is the z axis, as in the chapters.
+ */
+
+use lmath::vec::vec3::Vec3;
+use lmath::mat::mat4::Mat4;
+
+use camera::look_at;
+use input::*;
+
+/// How the controller moves the camera.
+pub enum Mode {
+    Orbit,
+    Fly,
+}
+
+/// Degrees turned per pixel dragged.
+const DRAG_SENSITIVITY: f32 = 0.25;
+/// Degrees turned per second a key is held, in `Orbit` mode.
+const ORBIT_SPEED: f32 = 90.0;
+/// How much each step of the scroll wheel zooms or speeds up by.
+const SCROLL_FACTOR: f32 = 1.1;
+/// How far up or down the camera may look, in degrees, short of straight
+/// along the up axis, where the view would be undefined.
+const MAX_PITCH: f32 = 89.0;
+
+pure fn radians(degrees: f32) -> f32 {
+    degrees * f32::consts::pi / 180.0
+}
+
+pure fn degrees(radians: f32) -> f32 {
+    radians * 180.0 / f32::consts::pi
+}
+
+/// A camera position and direction, updated from `Input`.
+pub struct CameraController {
+    mode: Mode,
+    /// Where the camera is.
+    eye: Vec3<f32>,
+    /// The direction looked in, in degrees: `yaw` counterclockwise from
+    /// the x axis, `pitch` up from the xy plane.
+    yaw: f32,
+    pitch: f32,
+    /// How far the target orbited is in front of the camera.
+    distance: f32,
+    /// How fast the camera moves in `Fly` mode, in units per second.
+    speed: f32,
+    priv mut dragged_from: Option<(float, float)>,
+    /// Where the cursor was when the last update's events were over.
+    priv mut cursor: Option<(float, float)>,
+}
+
+impl CameraController {
+    /// A controller orbiting `target` from `eye`.
+    static fn orbit(eye: &Vec3<f32>, target: &Vec3<f32>) -> CameraController {
+        let (dx, dy, dz) = (target.x - eye.x, target.y - eye.y, target.z - eye.z);
+        let distance = f32::sqrt(dx * dx + dy * dy + dz * dz);
+
+        CameraController {
+            mode: Orbit,
+            eye: *eye,
+            yaw: degrees(f32::atan2(dy, dx)),
+            pitch: degrees(f32::asin(dz / distance)),
+            distance: distance,
+            speed: 1.0,
+            dragged_from: None,
+            cursor: None,
+        }
+    }
+
+    /// The unit vector the camera looks along.
+    pure fn forward(&self) -> Vec3<f32> {
+        let (yaw, pitch) = (radians(self.yaw), radians(self.pitch));
+        Vec3::new(f32::cos(pitch) * f32::cos(yaw), f32::cos(pitch) * f32::sin(yaw), f32::sin(pitch))
+    }
+
+    /// The point orbited, or in `Fly` mode the point as far in front.
+    pure fn target(&self) -> Vec3<f32> {
+        let f = self.forward();
+        Vec3::new(self.eye.x + f.x * self.distance,
+                  self.eye.y + f.y * self.distance,
+                  self.eye.z + f.z * self.distance)
+    }
+
+    /// The view matrix of the camera.
+    pure fn view(&self) -> Mat4<f32> {
+        look_at(&self.eye, &self.target(), &Vec3::new(0.0f32, 0.0f32, 1.0f32))
+    }
+
+    /// Turns the camera by `yaw` and `pitch` degrees, around the target in
+    /// `Orbit` mode and where it stands in `Fly` mode.
+    fn turn(&mut self, yaw: f32, pitch: f32) {
+        let target = self.target();
+        let pitch = self.pitch + pitch;
+        self.yaw += yaw;
+        self.pitch = if pitch > MAX_PITCH { MAX_PITCH } else if pitch < -MAX_PITCH { -MAX_PITCH } else { pitch };
+
+        match self.mode {
+            Orbit => self.back_away_from(&target),
+            Fly => (),
+        }
+    }
+
+    /// Moves the camera to `distance` behind `target`, looking at it.
+    priv fn back_away_from(&mut self, target: &Vec3<f32>) {
+        let f = self.forward();
+        self.eye = Vec3::new(target.x - f.x * self.distance,
+                             target.y - f.y * self.distance,
+                             target.z - f.z * self.distance);
+    }
+
+    /// Applies the events of the frame and the keys held through the last
+    /// `dt` seconds of it.
+    fn update(&mut self, input: &Input, dt: float) {
+        let dt = dt as f32;
+
+        // Where the cursor is as of each event, not at the end of the frame,
+        // so a drag starts where the button went down even if the cursor
+        // moved on in the same frame. Until the controller has seen a frame,
+        // the input's last known position is the best guess.
+        let mut cursor = match self.cursor { None => input.cursor(), seen => seen };
+        for input.events().each |event| {
+            match *event {
+                KeyDown(key) if key == KEY_TAB => {
+                    self.mode = match self.mode { Orbit => Fly, Fly => Orbit };
+                }
+                ButtonDown(button) if button == MOUSE_BUTTON_LEFT => self.dragged_from = cursor,
+                ButtonUp(button) if button == MOUSE_BUTTON_LEFT => self.dragged_from = None,
+                CursorMoved(x, y) => {
+                    cursor = Some((x, y));
+                    match self.dragged_from {
+                        Some((from_x, from_y)) => {
+                            // Dragging right turns right when flying, and
+                            // swings the camera left around the target when
+                            // orbiting, as if the scene were dragged along
+                            self.turn((from_x - x) as f32 * DRAG_SENSITIVITY,
+                                      (from_y - y) as f32 * DRAG_SENSITIVITY);
+                            self.dragged_from = Some((x, y));
+                        }
+                        None => (),
+                    }
+                }
+                Scrolled(_, y) => {
+                    let factor = f32::pow(SCROLL_FACTOR, y as f32);
+                    match self.mode {
+                        Orbit => {
+                            let target = self.target();
+                            self.distance /= factor;
+                            self.back_away_from(&target);
+                        }
+                        Fly => self.speed *= factor,
+                    }
+                }
+                _ => (),
+            }
+        }
+        self.cursor = cursor;
+
+        // Held keys
+        let axis = |negative: int, positive: int| {
+            (if input.is_key_down(positive) { 1.0f32 } else { 0.0 }) -
+                (if input.is_key_down(negative) { 1.0f32 } else { 0.0 })
+        };
+        match self.mode {
+            Orbit => {
+                // A and D swing the camera left and right, W and S up and down
+                self.turn(axis(KEY_A, KEY_D) * ORBIT_SPEED * dt, axis(KEY_W, KEY_S) * ORBIT_SPEED * dt);
+            }
+            Fly => {
+                let f = self.forward();
+                // Sideways, in the xy plane
+                let (rx, ry) = (f32::sin(radians(self.yaw)), -f32::cos(radians(self.yaw)));
+                let ahead = axis(KEY_S, KEY_W) * self.speed * dt;
+                let aside = axis(KEY_A, KEY_D) * self.speed * dt;
+                let up = axis(KEY_Q, KEY_E) * self.speed * dt;
+                self.eye = Vec3::new(self.eye.x + f.x * ahead + rx * aside,
+                                     self.eye.y + f.y * ahead + ry * aside,
+                                     self.eye.z + f.z * ahead + up);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lmath::vec::vec3::Vec3;
+    use lmath::mat::mat4::Mat4;
+    use app::FRAME_TIME;
+    use camera::look_at;
+    use controller::*;
+    use input::*;
+
+    fn elements(m: &Mat4<f32>) -> ~[f32] {
+        unsafe { vec::raw::buf_as_slice(m.to_ptr(), 16, |e| vec::from_slice(e)) }
+    }
+
+    /// Replays `recorded` for 30 frames into a controller orbiting the
+    /// origin from along the x axis, as the runner would drive it.
+    fn replayed(recorded: &str) -> CameraController {
+        let replay = match parse_events(recorded) {
+            Ok(move events) => Replay::new(move events),
+            Err(move msg) => fail(move msg),
+        };
+
+        let mut camera = CameraController::orbit(&Vec3::new(2.0f32, 0.0f32, 0.0f32),
+                                                 &Vec3::new(0.0f32, 0.0f32, 0.0f32));
+        let input = Input::new();
+        for uint::range(0, 30) |frame| {
+            replay.advance((frame + 1) as float * FRAME_TIME, &input);
+            camera.update(&input, FRAME_TIME);
+            input.end_frame();
+        }
+        assert replay.is_done();
+        move camera
+    }
+
+    fn views_match(camera: &CameraController, eye: &Vec3<f32>) -> bool {
+        let expected = look_at(eye, &Vec3::new(0.0f32, 0.0f32, 0.0f32),
+                               &Vec3::new(0.0f32, 0.0f32, 1.0f32));
+        vec::all2(elements(&camera.view()), elements(&expected), |a, b| f32::abs(*a - *b) < 1e-4)
+    }
+
+    #[test]
+    fn replays_a_drag_and_a_scroll() {
+        // Drag a quarter turn to the right with the left button, let go,
+        // then scroll a step towards the target
+        let camera = replayed("0.000 cursor 100 100\n\
+                               0.000 button_down 0\n\
+                               0.100 cursor 460 100\n\
+                               0.200 button_up 0\n\
+                               0.300 scroll 0 1\n");
+
+        // Swung round to the far side of the y axis, and a step closer
+        assert views_match(&camera, &Vec3::new(0.0f32, -2.0f32 / 1.1f32, 0.0f32));
+    }
+
+    #[test]
+    fn starts_a_drag_where_the_button_went_down() {
+        // The press and the whole drag land in the seventh frame, so the
+        // cursor has moved on by the end of the frame the press is in
+        let camera = replayed("0.000 cursor 100 100\n\
+                               0.105 button_down 0\n\
+                               0.110 cursor 460 100\n\
+                               0.200 button_up 0\n");
+
+        assert views_match(&camera, &Vec3::new(0.0f32, -2.0f32, 0.0f32));
+    }
+}
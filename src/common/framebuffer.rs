@@ -0,0 +1,215 @@
+/*!
+ * Framebuffer objects, for drawing into a texture.
+ *
+ * A `Framebuffer` has a color texture, which can be sampled once it has
+ * been drawn into, and a renderbuffer for depth and stencil, which is only
+ * ever read by the tests. While one is bound, everything drawn goes into
+ * it instead of the window.
+ */
+
+use glcore::*;
+
+use context::Context;
+use texture::Texture2D;
+
+/// Why a framebuffer cannot be drawn into, from `glCheckFramebufferStatus`.
+pub enum FramebufferError {
+    /// An attachment has no image, or one of a format it cannot have.
+    IncompleteAttachment,
+    /// Nothing is attached.
+    MissingAttachment,
+    /// The driver does not support the combination of formats attached.
+    Unsupported,
+    /// The attachments have different numbers of samples.
+    IncompleteMultisample,
+    /// Any other status.
+    OtherStatus(GLenum),
+}
+
+impl FramebufferError : ToStr {
+    pure fn to_str(&self) -> ~str {
+        match *self {
+            IncompleteAttachment => ~"framebuffer incomplete: an attachment has no usable image",
+            MissingAttachment => ~"framebuffer incomplete: nothing is attached",
+            Unsupported => ~"framebuffer unsupported: the driver cannot draw into these formats",
+            IncompleteMultisample => ~"framebuffer incomplete: attachments differ in their samples",
+            OtherStatus(status) => fmt!("framebuffer incomplete: status 0x%04x", status as uint),
+        }
+    }
+}
+
+/// The error for the framebuffer status `status`, if it is not complete.
+pub pure fn status_error(status: GLenum) -> Option<FramebufferError> {
+    if status == GL_FRAMEBUFFER_COMPLETE {
+        None
+    } else if status == GL_FRAMEBUFFER_INCOMPLETE_ATTACHMENT {
+        Some(IncompleteAttachment)
+    } else if status == GL_FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT {
+        Some(MissingAttachment)
+    } else if status == GL_FRAMEBUFFER_UNSUPPORTED {
+        Some(Unsupported)
+    } else if status == GL_FRAMEBUFFER_INCOMPLETE_MULTISAMPLE {
+        Some(IncompleteMultisample)
+    } else {
+        Some(OtherStatus(status))
+    }
+}
+
+/// A renderbuffer object, deleted when dropped.
+pub struct Renderbuffer {
+    priv ctx: Context,
+    name: GLuint,
+}
+
+impl Renderbuffer {
+    /// Creates a `width` by `height` renderbuffer of `internal_format`,
+    /// e.g. `GL_DEPTH24_STENCIL8`, leaving it bound.
+    static fn new(ctx: &Context, internal_format: GLenum, width: uint, height: uint) -> Renderbuffer {
+        let renderbuffer = Renderbuffer { ctx: ctx.acquire(), name: ctx.gl().gen_renderbuffer() };
+        renderbuffer.bind();
+        ctx.gl().renderbuffer_storage(GL_RENDERBUFFER, internal_format, width as GLsizei, height as GLsizei);
+        move renderbuffer
+    }
+
+    fn bind(&self) {
+        self.ctx.gl().bind_renderbuffer(GL_RENDERBUFFER, self.name);
+    }
+}
+
+impl Renderbuffer : Drop {
+    fn finalize(&self) {
+        if self.ctx.release() {
+            self.ctx.gl().delete_renderbuffer(self.name);
+        }
+    }
+}
+
+/**
+ * A framebuffer object with an RGBA color texture and a 24-bit depth,
+ * 8-bit stencil renderbuffer, all deleted when dropped.
+ */
+pub struct Framebuffer {
+    priv ctx: Context,
+    name: GLuint,
+    width: uint,
+    height: uint,
+    color: Texture2D,
+    depth_stencil: Renderbuffer,
+}
+
+impl Framebuffer {
+    /**
+     * Creates a `width` by `height` framebuffer and checks that it can be
+     * drawn into. The window's framebuffer is bound again afterwards.
+     */
+    static fn new(ctx: &Context, width: uint, height: uint) -> Result<Framebuffer, FramebufferError> {
+        let gl = ctx.gl();
+
+        // The color texture is read back one texel per pixel, so it needs
+        // no mipmaps, and edge effects should not wrap around
+        let color = Texture2D::blank(ctx, width, height);
+        gl.tex_parameter_i(GL_TEXTURE_2D, GL_TEXTURE_MIN_FILTER, GL_LINEAR as GLint);
+        gl.tex_parameter_i(GL_TEXTURE_2D, GL_TEXTURE_WRAP_S, GL_CLAMP_TO_EDGE as GLint);
+        gl.tex_parameter_i(GL_TEXTURE_2D, GL_TEXTURE_WRAP_T, GL_CLAMP_TO_EDGE as GLint);
+
+        let depth_stencil = Renderbuffer::new(ctx, GL_DEPTH24_STENCIL8, width, height);
+
+        let framebuffer = Framebuffer {
+            ctx: ctx.acquire(),
+            name: gl.gen_framebuffer(),
+            width: width,
+            height: height,
+            color: move color,
+            depth_stencil: move depth_stencil,
+        };
+        gl.bind_framebuffer(GL_FRAMEBUFFER, framebuffer.name);
+        gl.framebuffer_texture_2d(GL_FRAMEBUFFER, GL_COLOR_ATTACHMENT0, GL_TEXTURE_2D,
+                                  framebuffer.color.name, 0);
+        gl.framebuffer_renderbuffer(GL_FRAMEBUFFER, GL_DEPTH_STENCIL_ATTACHMENT, GL_RENDERBUFFER,
+                                    framebuffer.depth_stencil.name);
+
+        let status = gl.check_framebuffer_status(GL_FRAMEBUFFER);
+        gl.bind_framebuffer(GL_FRAMEBUFFER, 0);
+
+        match status_error(status) {
+            None => Ok(move framebuffer),
+            Some(err) => Err(err),
+        }
+    }
+
+    /// Draws into the framebuffer from now on, over the whole of it.
+    fn bind(&self) {
+        self.ctx.gl().bind_framebuffer(GL_FRAMEBUFFER, self.name);
+        self.ctx.cache().viewport(0, 0, self.width as GLsizei, self.height as GLsizei);
+    }
+
+    /// Draws into the window from now on, over the whole of it.
+    static fn bind_default(ctx: &Context) {
+        let (width, height) = ctx.framebuffer_size();
+        ctx.gl().bind_framebuffer(GL_FRAMEBUFFER, 0);
+        ctx.cache().viewport(0, 0, width as GLsizei, height as GLsizei);
+    }
+
+    /// Binds the color texture to texture unit `unit`, leaving that unit active.
+    fn bind_color_to(&self, unit: uint) {
+        self.color.bind_to(unit);
+    }
+}
+
+impl Framebuffer : Drop {
+    fn finalize(&self) {
+        if self.ctx.release() {
+            self.ctx.gl().delete_framebuffer(self.name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glcore::*;
+    use framebuffer::*;
+
+    #[test]
+    fn is_happy_with_a_complete_framebuffer() {
+        assert status_error(GL_FRAMEBUFFER_COMPLETE).is_none();
+    }
+
+    #[test]
+    fn names_what_is_wrong_with_an_incomplete_one() {
+        match status_error(GL_FRAMEBUFFER_INCOMPLETE_ATTACHMENT) {
+            Some(err @ IncompleteAttachment) => {
+                assert err.to_str() == ~"framebuffer incomplete: an attachment has no usable image";
+            }
+            _ => fail(~"not IncompleteAttachment"),
+        }
+        match status_error(GL_FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT) {
+            Some(err @ MissingAttachment) => {
+                assert err.to_str() == ~"framebuffer incomplete: nothing is attached";
+            }
+            _ => fail(~"not MissingAttachment"),
+        }
+        match status_error(GL_FRAMEBUFFER_UNSUPPORTED) {
+            Some(err @ Unsupported) => {
+                assert err.to_str() == ~"framebuffer unsupported: the driver cannot draw into these formats";
+            }
+            _ => fail(~"not Unsupported"),
+        }
+        match status_error(GL_FRAMEBUFFER_INCOMPLETE_MULTISAMPLE) {
+            Some(err @ IncompleteMultisample) => {
+                assert err.to_str() == ~"framebuffer incomplete: attachments differ in their samples";
+            }
+            _ => fail(~"not IncompleteMultisample"),
+        }
+    }
+
+    #[test]
+    fn gives_the_value_of_other_statuses() {
+        match status_error(GL_FRAMEBUFFER_UNDEFINED) {
+            Some(err @ OtherStatus(status)) => {
+                assert status == GL_FRAMEBUFFER_UNDEFINED;
+                assert err.to_str() == ~"framebuffer incomplete: status 0x8219";
+            }
+            _ => fail(~"not OtherStatus"),
+        }
+    }
+}
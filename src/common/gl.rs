@@ -0,0 +1,594 @@
+/*!
+ * The subset of GL the tutorials use, behind a trait.
+ *
+ * Everything in `common` and in the chapters goes through the `Gl` of the
+ * current `Context` rather than calling `glcore` directly, so the same code
+ * can drive the driver (`NativeGl`) or the software renderer in `soft`.
+ * The methods mirror the `glcore` functions they stand for, with names
+ * dropped to snake case, generated names returned instead of written
+ * through pointers, and slices in place of pointer/size pairs.
+ */
+
+use cast::transmute;
+use ptr::{null, to_unsafe_ptr};
+use str::as_c_str;
+use sys::size_of;
+use vec::raw::to_ptr;
+
+use glcore::*;
+
+use soft::Emulation;
+
+pub trait Gl {
+    fn clear_color(&self, red: GLfloat, green: GLfloat, blue: GLfloat, alpha: GLfloat);
+    fn clear(&self, mask: GLbitfield);
+    fn viewport(&self, x: GLint, y: GLint, width: GLsizei, height: GLsizei);
+
+    fn enable(&self, capability: GLenum);
+    fn disable(&self, capability: GLenum);
+    fn depth_func(&self, func: GLenum);
+    fn depth_mask(&self, flag: GLboolean);
+    fn stencil_func(&self, func: GLenum, reference: GLint, mask: GLuint);
+    fn stencil_op(&self, stencil_fail: GLenum, depth_fail: GLenum, depth_pass: GLenum);
+    fn stencil_mask(&self, mask: GLuint);
+    fn cull_face(&self, face: GLenum);
+    fn scissor(&self, x: GLint, y: GLint, width: GLsizei, height: GLsizei);
+    fn color_mask(&self, red: GLboolean, green: GLboolean, blue: GLboolean, alpha: GLboolean);
+    fn polygon_mode(&self, face: GLenum, mode: GLenum);
+    fn blend_equation(&self, mode: GLenum);
+    fn blend_func(&self, source: GLenum, destination: GLenum);
+
+    fn gen_vertex_array(&self) -> GLuint;
+    fn bind_vertex_array(&self, array: GLuint);
+    fn delete_vertex_array(&self, array: GLuint);
+
+    fn gen_buffer(&self) -> GLuint;
+    fn bind_buffer(&self, target: GLenum, buffer: GLuint);
+    fn buffer_data(&self, target: GLenum, data: &[u8], usage: GLenum);
+    /// Binds `buffer` to the indexed binding point `index` of `target`, and
+    /// to `target` itself.
+    fn bind_buffer_base(&self, target: GLenum, index: GLuint, buffer: GLuint);
+    fn buffer_sub_data(&self, target: GLenum, offset: uint, data: &[u8]);
+    /// Maps `length` bytes of the buffer bound to `target`, from `offset`,
+    /// into memory. Null if GL refuses.
+    fn map_buffer_range(&self, target: GLenum, offset: uint, length: uint, access: GLbitfield) -> *mut u8;
+    /// `GL_FALSE` if the contents of the buffer were lost while it was mapped.
+    fn unmap_buffer(&self, target: GLenum) -> GLboolean;
+    fn delete_buffer(&self, buffer: GLuint);
+
+    /// Inserts a fence, signalled once the commands before it have finished.
+    fn fence_sync(&self) -> GLsync;
+    fn client_wait_sync(&self, sync: GLsync, flags: GLbitfield, timeout: GLuint64) -> GLenum;
+    fn delete_sync(&self, sync: GLsync);
+
+    fn create_shader(&self, kind: GLenum) -> GLuint;
+    fn shader_source(&self, shader: GLuint, source: &str);
+    fn compile_shader(&self, shader: GLuint);
+    fn get_shader_iv(&self, shader: GLuint, pname: GLenum) -> GLint;
+    fn get_shader_info_log(&self, shader: GLuint) -> ~str;
+    fn delete_shader(&self, shader: GLuint);
+
+    fn create_program(&self) -> GLuint;
+    fn attach_shader(&self, program: GLuint, shader: GLuint);
+    fn detach_shader(&self, program: GLuint, shader: GLuint);
+    fn bind_frag_data_location(&self, program: GLuint, color: GLuint, name: &str);
+    fn link_program(&self, program: GLuint);
+    fn get_program_iv(&self, program: GLuint, pname: GLenum) -> GLint;
+    fn get_program_info_log(&self, program: GLuint) -> ~str;
+    fn use_program(&self, program: GLuint);
+    fn delete_program(&self, program: GLuint);
+
+    /**
+     * Supplies Rust stand-ins for the shaders of `program`, for backends
+     * that cannot run GLSL. `NativeGl` ignores it.
+     */
+    fn emulate_program(&self, program: GLuint, emulation: @Emulation);
+
+    /// The name, array size and type of the active attribute `index`,
+    /// counting up to `GL_ACTIVE_ATTRIBUTES`.
+    fn get_active_attrib(&self, program: GLuint, index: GLuint) -> (~str, GLint, GLenum);
+    /// The name, array size and type of the active uniform `index`,
+    /// counting up to `GL_ACTIVE_UNIFORMS`.
+    fn get_active_uniform(&self, program: GLuint, index: GLuint) -> (~str, GLint, GLenum);
+    fn get_attrib_location(&self, program: GLuint, name: &str) -> GLint;
+    fn get_uniform_location(&self, program: GLuint, name: &str) -> GLint;
+    /// The index of uniform block `name`, or `GL_INVALID_INDEX`.
+    fn get_uniform_block_index(&self, program: GLuint, name: &str) -> GLuint;
+    fn uniform_block_binding(&self, program: GLuint, block: GLuint, binding: GLuint);
+
+    fn enable_vertex_attrib_array(&self, index: GLuint);
+    fn vertex_attrib_pointer(&self, index: GLuint, size: GLint, kind: GLenum,
+                             normalized: GLboolean, stride: GLsizei, offset: uint);
+    /// Makes attribute `index` advance once every `divisor` instances
+    /// instead of once a vertex, or once a vertex again if it is 0.
+    fn vertex_attrib_divisor(&self, index: GLuint, divisor: GLuint);
+
+    fn uniform_1i(&self, location: GLint, x: GLint);
+    fn uniform_1f(&self, location: GLint, x: GLfloat);
+    fn uniform_3f(&self, location: GLint, x: GLfloat, y: GLfloat, z: GLfloat);
+    fn uniform_matrix_4fv(&self, location: GLint, transpose: GLboolean, value: &[GLfloat]);
+
+    fn gen_texture(&self) -> GLuint;
+    fn active_texture(&self, unit: GLenum);
+    fn bind_texture(&self, target: GLenum, texture: GLuint);
+    /// An empty `data` allocates the image without filling it in.
+    fn tex_image_2d(&self, target: GLenum, level: GLint, internal_format: GLint,
+                    width: GLsizei, height: GLsizei, format: GLenum, kind: GLenum,
+                    data: &[u8]);
+    fn tex_parameter_i(&self, target: GLenum, pname: GLenum, param: GLint);
+    fn pixel_store_i(&self, pname: GLenum, param: GLint);
+    fn generate_mipmap(&self, target: GLenum);
+    fn delete_texture(&self, texture: GLuint);
+
+    fn gen_sampler(&self) -> GLuint;
+    fn bind_sampler(&self, unit: GLuint, sampler: GLuint);
+    fn sampler_parameter_i(&self, sampler: GLuint, pname: GLenum, param: GLint);
+    fn sampler_parameter_f(&self, sampler: GLuint, pname: GLenum, param: GLfloat);
+    fn sampler_parameter_fv(&self, sampler: GLuint, pname: GLenum, params: &[GLfloat]);
+    fn delete_sampler(&self, sampler: GLuint);
+
+    fn gen_framebuffer(&self) -> GLuint;
+    fn bind_framebuffer(&self, target: GLenum, framebuffer: GLuint);
+    fn framebuffer_texture_2d(&self, target: GLenum, attachment: GLenum, texture_target: GLenum,
+                              texture: GLuint, level: GLint);
+    fn framebuffer_renderbuffer(&self, target: GLenum, attachment: GLenum, renderbuffer_target: GLenum,
+                                renderbuffer: GLuint);
+    fn check_framebuffer_status(&self, target: GLenum) -> GLenum;
+    fn delete_framebuffer(&self, framebuffer: GLuint);
+
+    fn gen_renderbuffer(&self) -> GLuint;
+    fn bind_renderbuffer(&self, target: GLenum, renderbuffer: GLuint);
+    fn renderbuffer_storage(&self, target: GLenum, internal_format: GLenum,
+                            width: GLsizei, height: GLsizei);
+    fn delete_renderbuffer(&self, renderbuffer: GLuint);
+
+    fn draw_arrays(&self, mode: GLenum, first: GLint, count: GLsizei);
+    fn draw_elements(&self, mode: GLenum, count: GLsizei, kind: GLenum, offset: uint);
+    fn draw_arrays_instanced(&self, mode: GLenum, first: GLint, count: GLsizei, instances: GLsizei);
+    fn draw_elements_instanced(&self, mode: GLenum, count: GLsizei, kind: GLenum, offset: uint,
+                               instances: GLsizei);
+
+    /// Reads back a block of the framebuffer as RGBA bytes, bottom row first.
+    fn read_pixels(&self, x: GLint, y: GLint, width: GLsizei, height: GLsizei) -> ~[u8];
+}
+
+/// Calls `f` with the bytes making up `data`.
+pub fn as_bytes<T, U>(data: &[T], f: fn(&[u8]) -> U) -> U {
+    unsafe {
+        vec::raw::buf_as_slice(transmute(to_ptr(data)), data.len() * size_of::<T>(), f)
+    }
+}
+
+/// The GL driver of the current context.
+pub enum NativeGl { NativeGl }
+
+impl NativeGl : Gl {
+    fn clear_color(&self, red: GLfloat, green: GLfloat, blue: GLfloat, alpha: GLfloat) {
+        glClearColor(red, green, blue, alpha);
+    }
+
+    fn clear(&self, mask: GLbitfield) {
+        glClear(mask);
+    }
+
+    fn viewport(&self, x: GLint, y: GLint, width: GLsizei, height: GLsizei) {
+        glViewport(x, y, width, height);
+    }
+
+    fn enable(&self, capability: GLenum) {
+        glEnable(capability);
+    }
+
+    fn disable(&self, capability: GLenum) {
+        glDisable(capability);
+    }
+
+    fn depth_func(&self, func: GLenum) {
+        glDepthFunc(func);
+    }
+
+    fn depth_mask(&self, flag: GLboolean) {
+        glDepthMask(flag);
+    }
+
+    fn stencil_func(&self, func: GLenum, reference: GLint, mask: GLuint) {
+        glStencilFunc(func, reference, mask);
+    }
+
+    fn stencil_op(&self, stencil_fail: GLenum, depth_fail: GLenum, depth_pass: GLenum) {
+        glStencilOp(stencil_fail, depth_fail, depth_pass);
+    }
+
+    fn stencil_mask(&self, mask: GLuint) {
+        glStencilMask(mask);
+    }
+
+    fn cull_face(&self, face: GLenum) {
+        glCullFace(face);
+    }
+
+    fn scissor(&self, x: GLint, y: GLint, width: GLsizei, height: GLsizei) {
+        glScissor(x, y, width, height);
+    }
+
+    fn color_mask(&self, red: GLboolean, green: GLboolean, blue: GLboolean, alpha: GLboolean) {
+        glColorMask(red, green, blue, alpha);
+    }
+
+    fn polygon_mode(&self, face: GLenum, mode: GLenum) {
+        glPolygonMode(face, mode);
+    }
+
+    fn blend_equation(&self, mode: GLenum) {
+        glBlendEquation(mode);
+    }
+
+    fn blend_func(&self, source: GLenum, destination: GLenum) {
+        glBlendFunc(source, destination);
+    }
+
+    fn gen_vertex_array(&self) -> GLuint {
+        let array: GLuint = 0;
+        glGenVertexArrays(1, to_unsafe_ptr(&array));
+        array
+    }
+
+    fn bind_vertex_array(&self, array: GLuint) {
+        glBindVertexArray(array);
+    }
+
+    fn delete_vertex_array(&self, array: GLuint) {
+        glDeleteVertexArrays(1, to_unsafe_ptr(&array));
+    }
+
+    fn gen_buffer(&self) -> GLuint {
+        let buffer: GLuint = 0;
+        glGenBuffers(1, to_unsafe_ptr(&buffer));
+        buffer
+    }
+
+    fn bind_buffer(&self, target: GLenum, buffer: GLuint) {
+        glBindBuffer(target, buffer);
+    }
+
+    fn buffer_data(&self, target: GLenum, data: &[u8], usage: GLenum) {
+        unsafe {
+            glBufferData(target, data.len() as GLsizeiptr, transmute(to_ptr(data)), usage);
+        }
+    }
+
+    fn bind_buffer_base(&self, target: GLenum, index: GLuint, buffer: GLuint) {
+        glBindBufferBase(target, index, buffer);
+    }
+
+    fn buffer_sub_data(&self, target: GLenum, offset: uint, data: &[u8]) {
+        unsafe {
+            glBufferSubData(target, offset as GLintptr, data.len() as GLsizeiptr, transmute(to_ptr(data)));
+        }
+    }
+
+    fn map_buffer_range(&self, target: GLenum, offset: uint, length: uint, access: GLbitfield) -> *mut u8 {
+        unsafe {
+            transmute(glMapBufferRange(target, offset as GLintptr, length as GLsizeiptr, access))
+        }
+    }
+
+    fn unmap_buffer(&self, target: GLenum) -> GLboolean {
+        glUnmapBuffer(target)
+    }
+
+    fn delete_buffer(&self, buffer: GLuint) {
+        glDeleteBuffers(1, to_unsafe_ptr(&buffer));
+    }
+
+    fn fence_sync(&self) -> GLsync {
+        glFenceSync(GL_SYNC_GPU_COMMANDS_COMPLETE, 0)
+    }
+
+    fn client_wait_sync(&self, sync: GLsync, flags: GLbitfield, timeout: GLuint64) -> GLenum {
+        glClientWaitSync(sync, flags, timeout)
+    }
+
+    fn delete_sync(&self, sync: GLsync) {
+        glDeleteSync(sync);
+    }
+
+    fn create_shader(&self, kind: GLenum) -> GLuint {
+        glCreateShader(kind)
+    }
+
+    fn shader_source(&self, shader: GLuint, source: &str) {
+        do as_c_str(source) |data| {
+            glShaderSource(shader, 1, to_unsafe_ptr(&data), null());
+        }
+    }
+
+    fn compile_shader(&self, shader: GLuint) {
+        glCompileShader(shader);
+    }
+
+    fn get_shader_iv(&self, shader: GLuint, pname: GLenum) -> GLint {
+        let param: GLint = 0;
+        glGetShaderiv(shader, pname, to_unsafe_ptr(&param));
+        param
+    }
+
+    fn get_shader_info_log(&self, shader: GLuint) -> ~str {
+        let len = self.get_shader_iv(shader, GL_INFO_LOG_LENGTH);
+        if len <= 1 { return ~""; }
+
+        let buf = vec::from_elem(len as uint, 0u8);
+        unsafe {
+            glGetShaderInfoLog(shader, len as GLsizei, null(), transmute(to_ptr(buf)));
+            str::raw::from_buf(to_ptr(buf))
+        }
+    }
+
+    fn delete_shader(&self, shader: GLuint) {
+        glDeleteShader(shader);
+    }
+
+    fn create_program(&self) -> GLuint {
+        glCreateProgram()
+    }
+
+    fn attach_shader(&self, program: GLuint, shader: GLuint) {
+        glAttachShader(program, shader);
+    }
+
+    fn detach_shader(&self, program: GLuint, shader: GLuint) {
+        glDetachShader(program, shader);
+    }
+
+    fn bind_frag_data_location(&self, program: GLuint, color: GLuint, name: &str) {
+        do as_c_str(name) |s| {
+            glBindFragDataLocation(program, color, s);
+        }
+    }
+
+    fn link_program(&self, program: GLuint) {
+        glLinkProgram(program);
+    }
+
+    fn get_program_iv(&self, program: GLuint, pname: GLenum) -> GLint {
+        let param: GLint = 0;
+        glGetProgramiv(program, pname, to_unsafe_ptr(&param));
+        param
+    }
+
+    fn get_program_info_log(&self, program: GLuint) -> ~str {
+        let len = self.get_program_iv(program, GL_INFO_LOG_LENGTH);
+        if len <= 1 { return ~""; }
+
+        let buf = vec::from_elem(len as uint, 0u8);
+        unsafe {
+            glGetProgramInfoLog(program, len as GLsizei, null(), transmute(to_ptr(buf)));
+            str::raw::from_buf(to_ptr(buf))
+        }
+    }
+
+    fn use_program(&self, program: GLuint) {
+        glUseProgram(program);
+    }
+
+    fn delete_program(&self, program: GLuint) {
+        glDeleteProgram(program);
+    }
+
+    fn emulate_program(&self, _program: GLuint, _emulation: @Emulation) {}
+
+    fn get_active_attrib(&self, program: GLuint, index: GLuint) -> (~str, GLint, GLenum) {
+        let len = self.get_program_iv(program, GL_ACTIVE_ATTRIBUTE_MAX_LENGTH);
+        let buf = vec::from_elem(len as uint + 1, 0u8);
+        let size: GLint = 0;
+        let kind: GLenum = 0;
+        unsafe {
+            glGetActiveAttrib(program, index, buf.len() as GLsizei, null(), to_unsafe_ptr(&size),
+                              to_unsafe_ptr(&kind), transmute(to_ptr(buf)));
+            (str::raw::from_buf(to_ptr(buf)), size, kind)
+        }
+    }
+
+    fn get_active_uniform(&self, program: GLuint, index: GLuint) -> (~str, GLint, GLenum) {
+        let len = self.get_program_iv(program, GL_ACTIVE_UNIFORM_MAX_LENGTH);
+        let buf = vec::from_elem(len as uint + 1, 0u8);
+        let size: GLint = 0;
+        let kind: GLenum = 0;
+        unsafe {
+            glGetActiveUniform(program, index, buf.len() as GLsizei, null(), to_unsafe_ptr(&size),
+                               to_unsafe_ptr(&kind), transmute(to_ptr(buf)));
+            (str::raw::from_buf(to_ptr(buf)), size, kind)
+        }
+    }
+
+    fn get_attrib_location(&self, program: GLuint, name: &str) -> GLint {
+        do as_c_str(name) |s| { glGetAttribLocation(program, s) }
+    }
+
+    fn get_uniform_location(&self, program: GLuint, name: &str) -> GLint {
+        do as_c_str(name) |s| { glGetUniformLocation(program, s) }
+    }
+
+    fn get_uniform_block_index(&self, program: GLuint, name: &str) -> GLuint {
+        do as_c_str(name) |s| { glGetUniformBlockIndex(program, s) }
+    }
+
+    fn uniform_block_binding(&self, program: GLuint, block: GLuint, binding: GLuint) {
+        glUniformBlockBinding(program, block, binding);
+    }
+
+    fn enable_vertex_attrib_array(&self, index: GLuint) {
+        glEnableVertexAttribArray(index);
+    }
+
+    fn vertex_attrib_pointer(&self, index: GLuint, size: GLint, kind: GLenum,
+                             normalized: GLboolean, stride: GLsizei, offset: uint) {
+        unsafe {
+            glVertexAttribPointer(index, size, kind, normalized, stride, transmute(offset));
+        }
+    }
+
+    fn vertex_attrib_divisor(&self, index: GLuint, divisor: GLuint) {
+        glVertexAttribDivisor(index, divisor);
+    }
+
+    fn uniform_1i(&self, location: GLint, x: GLint) {
+        glUniform1i(location, x);
+    }
+
+    fn uniform_1f(&self, location: GLint, x: GLfloat) {
+        glUniform1f(location, x);
+    }
+
+    fn uniform_3f(&self, location: GLint, x: GLfloat, y: GLfloat, z: GLfloat) {
+        glUniform3f(location, x, y, z);
+    }
+
+    fn uniform_matrix_4fv(&self, location: GLint, transpose: GLboolean, value: &[GLfloat]) {
+        glUniformMatrix4fv(location, (value.len() / 16) as GLsizei, transpose, to_ptr(value));
+    }
+
+    fn gen_texture(&self) -> GLuint {
+        let texture: GLuint = 0;
+        glGenTextures(1, to_unsafe_ptr(&texture));
+        texture
+    }
+
+    fn active_texture(&self, unit: GLenum) {
+        glActiveTexture(unit);
+    }
+
+    fn bind_texture(&self, target: GLenum, texture: GLuint) {
+        glBindTexture(target, texture);
+    }
+
+    fn tex_image_2d(&self, target: GLenum, level: GLint, internal_format: GLint,
+                    width: GLsizei, height: GLsizei, format: GLenum, kind: GLenum,
+                    data: &[u8]) {
+        let pixels = if data.is_empty() { null() } else { to_ptr(data) };
+        unsafe {
+            glTexImage2D(target, level, internal_format, width, height, 0, format, kind,
+                         transmute(pixels));
+        }
+    }
+
+    fn tex_parameter_i(&self, target: GLenum, pname: GLenum, param: GLint) {
+        glTexParameteri(target, pname, param);
+    }
+
+    fn pixel_store_i(&self, pname: GLenum, param: GLint) {
+        glPixelStorei(pname, param);
+    }
+
+    fn generate_mipmap(&self, target: GLenum) {
+        glGenerateMipmap(target);
+    }
+
+    fn delete_texture(&self, texture: GLuint) {
+        glDeleteTextures(1, to_unsafe_ptr(&texture));
+    }
+
+    fn gen_sampler(&self) -> GLuint {
+        let sampler: GLuint = 0;
+        glGenSamplers(1, to_unsafe_ptr(&sampler));
+        sampler
+    }
+
+    fn bind_sampler(&self, unit: GLuint, sampler: GLuint) {
+        glBindSampler(unit, sampler);
+    }
+
+    fn sampler_parameter_i(&self, sampler: GLuint, pname: GLenum, param: GLint) {
+        glSamplerParameteri(sampler, pname, param);
+    }
+
+    fn sampler_parameter_f(&self, sampler: GLuint, pname: GLenum, param: GLfloat) {
+        glSamplerParameterf(sampler, pname, param);
+    }
+
+    fn sampler_parameter_fv(&self, sampler: GLuint, pname: GLenum, params: &[GLfloat]) {
+        glSamplerParameterfv(sampler, pname, to_ptr(params));
+    }
+
+    fn delete_sampler(&self, sampler: GLuint) {
+        glDeleteSamplers(1, to_unsafe_ptr(&sampler));
+    }
+
+    fn gen_framebuffer(&self) -> GLuint {
+        let framebuffer: GLuint = 0;
+        glGenFramebuffers(1, to_unsafe_ptr(&framebuffer));
+        framebuffer
+    }
+
+    fn bind_framebuffer(&self, target: GLenum, framebuffer: GLuint) {
+        glBindFramebuffer(target, framebuffer);
+    }
+
+    fn framebuffer_texture_2d(&self, target: GLenum, attachment: GLenum, texture_target: GLenum,
+                              texture: GLuint, level: GLint) {
+        glFramebufferTexture2D(target, attachment, texture_target, texture, level);
+    }
+
+    fn framebuffer_renderbuffer(&self, target: GLenum, attachment: GLenum, renderbuffer_target: GLenum,
+                                renderbuffer: GLuint) {
+        glFramebufferRenderbuffer(target, attachment, renderbuffer_target, renderbuffer);
+    }
+
+    fn check_framebuffer_status(&self, target: GLenum) -> GLenum {
+        glCheckFramebufferStatus(target)
+    }
+
+    fn delete_framebuffer(&self, framebuffer: GLuint) {
+        glDeleteFramebuffers(1, to_unsafe_ptr(&framebuffer));
+    }
+
+    fn gen_renderbuffer(&self) -> GLuint {
+        let renderbuffer: GLuint = 0;
+        glGenRenderbuffers(1, to_unsafe_ptr(&renderbuffer));
+        renderbuffer
+    }
+
+    fn bind_renderbuffer(&self, target: GLenum, renderbuffer: GLuint) {
+        glBindRenderbuffer(target, renderbuffer);
+    }
+
+    fn renderbuffer_storage(&self, target: GLenum, internal_format: GLenum,
+                            width: GLsizei, height: GLsizei) {
+        glRenderbufferStorage(target, internal_format, width, height);
+    }
+
+    fn delete_renderbuffer(&self, renderbuffer: GLuint) {
+        glDeleteRenderbuffers(1, to_unsafe_ptr(&renderbuffer));
+    }
+
+    fn draw_arrays(&self, mode: GLenum, first: GLint, count: GLsizei) {
+        glDrawArrays(mode, first, count);
+    }
+
+    fn draw_elements(&self, mode: GLenum, count: GLsizei, kind: GLenum, offset: uint) {
+        unsafe {
+            glDrawElements(mode, count, kind, transmute(offset));
+        }
+    }
+
+    fn draw_arrays_instanced(&self, mode: GLenum, first: GLint, count: GLsizei, instances: GLsizei) {
+        glDrawArraysInstanced(mode, first, count, instances);
+    }
+
+    fn draw_elements_instanced(&self, mode: GLenum, count: GLsizei, kind: GLenum, offset: uint,
+                               instances: GLsizei) {
+        unsafe {
+            glDrawElementsInstanced(mode, count, kind, transmute(offset), instances);
+        }
+    }
+
+    fn read_pixels(&self, x: GLint, y: GLint, width: GLsizei, height: GLsizei) -> ~[u8] {
+        let pixels = vec::from_elem((width * height * 4) as uint, 0u8);
+        self.pixel_store_i(GL_PACK_ALIGNMENT, 1);
+        unsafe {
+            glReadPixels(x, y, width, height, GL_RGBA, GL_UNSIGNED_BYTE,
+                         transmute(to_ptr(pixels)));
+        }
+        move pixels
+    }
+}
@@ -0,0 +1,163 @@
+/*!
+ * Comparing rendered frames against reference images.
+ *
+ * Two images match if few enough of their pixels differ by more than a
+ * small amount in some channel. The per-channel slack absorbs rounding
+ * differences between platforms; the share of pixels allowed to differ
+ * absorbs the odd edge pixel landing on the other side of a triangle.
+ */
+
+use png::Image;
+
+/// How far a rendered frame may stray from its reference.
+pub struct Tolerance {
+    /// The largest difference in any one channel that still counts as equal.
+    channel_delta: u8,
+    /// The largest share of the pixels, from 0 to 1, allowed to differ.
+    max_differing: float,
+}
+
+impl Tolerance {
+    /// A delta of 8 in 255 and one pixel in a thousand.
+    static fn default() -> Tolerance {
+        Tolerance { channel_delta: 8, max_differing: 0.001 }
+    }
+}
+
+/// The outcome of comparing two images of the same size.
+pub struct Comparison {
+    /// The number of pixels differing by more than the tolerance allows.
+    differing: uint,
+    pixels: uint,
+    /// The largest difference in any channel of any pixel.
+    max_delta: u8,
+}
+
+impl Comparison {
+    /// The share of the pixels that differ.
+    pure fn ratio(&self) -> float {
+        if self.pixels == 0 { 0.0 } else { self.differing as float / self.pixels as float }
+    }
+
+    pure fn passes(&self, tolerance: &Tolerance) -> bool {
+        self.ratio() <= tolerance.max_differing
+    }
+}
+
+impl Comparison : ToStr {
+    pure fn to_str(&self) -> ~str {
+        fmt!("%u of %u pixels differ (%f%%), largest channel delta %u",
+             self.differing, self.pixels, self.ratio() * 100.0, self.max_delta as uint)
+    }
+}
+
+/// The largest difference between the channels of pixel `i` of `a` and `b`.
+pure fn pixel_delta(a: &Image, b: &Image, i: uint) -> u8 {
+    let mut delta = 0u8;
+    for uint::range(i * 4, i * 4 + 4) |c| {
+        let d = if a.rgba[c] > b.rgba[c] { a.rgba[c] - b.rgba[c] } else { b.rgba[c] - a.rgba[c] };
+        if d > delta { delta = d; }
+    }
+    delta
+}
+
+/// Compares `actual` against `expected`. Fails if their sizes differ.
+pub fn compare(actual: &Image, expected: &Image, tolerance: &Tolerance) -> Result<Comparison, ~str> {
+    if actual.width != expected.width || actual.height != expected.height {
+        return Err(fmt!("size %ux%u does not match the reference's %ux%u",
+                        actual.width, actual.height, expected.width, expected.height));
+    }
+
+    let pixels = actual.width * actual.height;
+    let mut differing = 0;
+    let mut max_delta = 0u8;
+    for uint::range(0, pixels) |i| {
+        let delta = pixel_delta(actual, expected, i);
+        if delta > tolerance.channel_delta { differing += 1; }
+        if delta > max_delta { max_delta = delta; }
+    }
+
+    Ok(Comparison { differing: differing, pixels: pixels, max_delta: max_delta })
+}
+
+/**
+ * An image showing where `actual` departs from `expected`, which must be
+ * the same size: differing pixels are red, brighter the larger the
+ * difference, and the rest is the reference faded to a dark grey.
+ */
+pub fn diff_image(actual: &Image, expected: &Image, tolerance: &Tolerance) -> Image {
+    let pixels = expected.width * expected.height;
+    let mut rgba = vec::with_capacity(pixels * 4);
+
+    for uint::range(0, pixels) |i| {
+        let delta = pixel_delta(actual, expected, i);
+        if delta > tolerance.channel_delta {
+            rgba.push_all([128 + delta / 2, 0, 0, 255]);
+        } else {
+            let c = &expected.rgba;
+            let luma = (c[i * 4] as uint * 3 + c[i * 4 + 1] as uint * 6 + c[i * 4 + 2] as uint) / 10;
+            rgba.push_all([(luma / 4) as u8, (luma / 4) as u8, (luma / 4) as u8, 255]);
+        }
+    }
+
+    Image { width: expected.width, height: expected.height, rgba: move rgba }
+}
+
+#[cfg(test)]
+mod tests {
+    use golden::*;
+    use png::Image;
+
+    fn grey(width: uint, height: uint, value: u8) -> Image {
+        let mut rgba = ~[];
+        for uint::range(0, width * height) |_| { rgba.push_all([value, value, value, 255]); }
+        Image { width: width, height: height, rgba: move rgba }
+    }
+
+    #[test]
+    fn refuses_images_of_different_sizes() {
+        assert compare(&grey(4, 2, 0), &grey(2, 4, 0), &Tolerance::default()).is_err();
+        assert compare(&grey(4, 4, 0), &grey(4, 3, 0), &Tolerance::default()).is_err();
+    }
+
+    #[test]
+    fn a_delta_of_exactly_channel_delta_is_equal() {
+        let expected = grey(2, 1, 100);
+        let mut actual = grey(2, 1, 100);
+        actual.rgba[1] = 108;
+        actual.rgba[4] = 91;
+
+        let comparison = compare(&actual, &expected, &Tolerance::default()).get();
+        assert comparison.differing == 1;
+        assert comparison.pixels == 2;
+        assert comparison.max_delta == 9;
+    }
+
+    #[test]
+    fn passes_up_to_max_differing_and_no_further() {
+        let tolerance = Tolerance::default();
+        let expected = grey(100, 10, 0);
+        let mut actual = grey(100, 10, 0);
+
+        actual.rgba[0] = 255;
+        let one = compare(&actual, &expected, &tolerance).get();
+        assert one.ratio() == 0.001;
+        assert one.passes(&tolerance);
+
+        actual.rgba[4] = 255;
+        let two = compare(&actual, &expected, &tolerance).get();
+        assert two.differing == 2;
+        assert !two.passes(&tolerance);
+    }
+
+    #[test]
+    fn marks_differing_pixels_red_and_fades_the_rest() {
+        let expected = grey(2, 1, 200);
+        let mut actual = grey(2, 1, 200);
+        actual.rgba[6] = 100;
+
+        let diff = diff_image(&actual, &expected, &Tolerance::default());
+        assert diff.width == 2 && diff.height == 1;
+        assert diff.rgba == ~[50, 50, 50, 255, 178, 0, 0, 255];
+    }
+}
@@ -0,0 +1,224 @@
+/*!
+ * Keyboard and mouse input.
+ *
+ * In a window, the runner turns GLFW's key, mouse button, cursor and
+ * scroll callbacks into `Event`s and collects them in an `Input`, which
+ * is handed to `App::update` and emptied after every frame. `Input` also
+ * keeps the state the events add up to: which keys and buttons are held
+ * and where the cursor is.
+ *
+ * Events can be recorded to a file with their times and replayed from it,
+ * in a window or headless, so input handling can be checked without
+ * anyone at the keyboard. The file has one event per line, as written by
+ * `format_event`:
+ *
+ *     0.250 key_down 87
+ *     0.750 key_up 87
+ *     1.000 cursor 412 300
+ *     1.500 scroll 0 -1
+ *     2.000 button_down 0
+ *
+ * Times are seconds since the program started; keys and buttons are GLFW's
+ * codes. Empty lines and lines starting with `#` are skipped.
+ */
+
+/// GLFW's codes for the keys the tutorials use. Letters and digits are
+/// their ASCII characters, letters in capitals.
+pub const KEY_SPACE: int = 32;
+pub const KEY_0: int = 48;
+pub const KEY_1: int = 49;
+pub const KEY_2: int = 50;
+pub const KEY_3: int = 51;
+pub const KEY_4: int = 52;
+pub const KEY_A: int = 65;
+pub const KEY_D: int = 68;
+pub const KEY_E: int = 69;
+pub const KEY_Q: int = 81;
+pub const KEY_S: int = 83;
+pub const KEY_W: int = 87;
+pub const KEY_TAB: int = 258;
+
+pub const MOUSE_BUTTON_LEFT: int = 0;
+
+/// Something the user did.
+pub enum Event {
+    KeyDown(int),
+    KeyUp(int),
+    ButtonDown(int),
+    ButtonUp(int),
+    /// The cursor moved to (x, y), in pixels from the top-left corner of the window.
+    CursorMoved(float, float),
+    /// The wheel or trackpad scrolled by (x, y); positive y is away from the user.
+    Scrolled(float, float),
+}
+
+/// An event and when it happened, in seconds since the program started.
+pub struct TimedEvent {
+    time: float,
+    event: Event,
+}
+
+/// `event` as a line of an event file, without the newline.
+pub fn format_event(event: &TimedEvent) -> ~str {
+    let time = float::to_str(event.time, 3);
+    match event.event {
+        KeyDown(key) => fmt!("%s key_down %d", time, key),
+        KeyUp(key) => fmt!("%s key_up %d", time, key),
+        ButtonDown(button) => fmt!("%s button_down %d", time, button),
+        ButtonUp(button) => fmt!("%s button_up %d", time, button),
+        CursorMoved(x, y) => fmt!("%s cursor %s %s", time, float::to_str(x, 2), float::to_str(y, 2)),
+        Scrolled(x, y) => fmt!("%s scroll %s %s", time, float::to_str(x, 2), float::to_str(y, 2)),
+    }
+}
+
+/// The event on line `number` of an event file, which is `line`.
+fn parse_event(line: &str, number: uint) -> Result<TimedEvent, ~str> {
+    let words = str::words(line);
+    let bad = fmt!("line %u: expected a time, an event and its arguments: %s", number, line);
+    if words.len() < 3 { return Err(move bad); }
+
+    let time = match float::from_str(words[0]) {
+        Some(time) => time,
+        None => return Err(move bad),
+    };
+    let n = if words.len() == 3 { int::from_str(words[2]) } else { None };
+    let xy = if words.len() == 4 {
+        (float::from_str(words[2]), float::from_str(words[3]))
+    } else {
+        (None, None)
+    };
+
+    let kind = copy words[1];
+    let event = match (n, xy) {
+        (Some(n), _) if kind == ~"key_down" => KeyDown(n),
+        (Some(n), _) if kind == ~"key_up" => KeyUp(n),
+        (Some(n), _) if kind == ~"button_down" => ButtonDown(n),
+        (Some(n), _) if kind == ~"button_up" => ButtonUp(n),
+        (_, (Some(x), Some(y))) if kind == ~"cursor" => CursorMoved(x, y),
+        (_, (Some(x), Some(y))) if kind == ~"scroll" => Scrolled(x, y),
+        _ => return Err(move bad),
+    };
+    Ok(TimedEvent { time: time, event: event })
+}
+
+/// Parses the text of an event file. The events must be in order of time.
+pub fn parse_events(text: &str) -> Result<~[TimedEvent], ~str> {
+    let mut events = ~[];
+    for vec::eachi(str::lines_any(text)) |i, line| {
+        let line = str::trim(*line);
+        if line.is_empty() || str::starts_with(line, "#") { loop; }
+
+        match parse_event(line, i + 1) {
+            Ok(move event) => {
+                if !events.is_empty() && event.time < events[events.len() - 1].time {
+                    return Err(fmt!("line %u: events must be in order of time", i + 1));
+                }
+                events.push(move event);
+            }
+            Err(move msg) => return Err(move msg),
+        }
+    }
+    Ok(move events)
+}
+
+/// Reads the event file at `path`.
+pub fn read_events(path: &Path) -> Result<~[TimedEvent], ~str> {
+    match io::read_whole_file_str(path) {
+        Ok(move text) => match parse_events(text) {
+            Ok(move events) => Ok(move events),
+            Err(move msg) => Err(fmt!("%s: %s", path.to_str(), msg)),
+        },
+        Err(move msg) => Err(fmt!("failed to read %s: %s", path.to_str(), msg)),
+    }
+}
+
+/// Writes `events` to the file at `path`, replacing it.
+pub fn write_events(path: &Path, events: &[TimedEvent]) -> Result<(), ~str> {
+    match io::file_writer(path, [io::Create, io::Truncate]) {
+        Ok(writer) => {
+            for events.each |event| {
+                writer.write_line(format_event(event));
+            }
+            Ok(())
+        }
+        Err(move msg) => Err(fmt!("failed to write %s: %s", path.to_str(), msg)),
+    }
+}
+
+/// The input of one frame, and the state of the keyboard and mouse after it.
+pub struct Input {
+    priv mut events: ~[Event],
+    priv mut keys: ~[int],
+    priv mut buttons: ~[int],
+    priv mut cursor: Option<(float, float)>,
+}
+
+impl Input {
+    /// No events, nothing held, and the cursor not seen yet.
+    static fn new() -> Input {
+        Input { events: ~[], keys: ~[], buttons: ~[], cursor: None }
+    }
+
+    /// Adds `event` to those of the current frame.
+    fn push(&self, event: Event) {
+        match event {
+            KeyDown(key) => if !self.keys.contains(&key) { self.keys.push(key); },
+            KeyUp(key) => self.keys = self.keys.filtered(|k| *k != key),
+            ButtonDown(button) => if !self.buttons.contains(&button) { self.buttons.push(button); },
+            ButtonUp(button) => self.buttons = self.buttons.filtered(|b| *b != button),
+            CursorMoved(x, y) => self.cursor = Some((x, y)),
+            Scrolled(*) => (),
+        }
+        self.events.push(event);
+    }
+
+    /// The events of the current frame, in order.
+    fn events(&self) -> ~[Event] {
+        copy self.events
+    }
+
+    /// Whether key `key` is held down.
+    fn is_key_down(&self, key: int) -> bool {
+        self.keys.contains(&key)
+    }
+
+    /// Whether mouse button `button` is held down.
+    fn is_button_down(&self, button: int) -> bool {
+        self.buttons.contains(&button)
+    }
+
+    /// Where the cursor was last seen, if it has been.
+    fn cursor(&self) -> Option<(float, float)> {
+        self.cursor
+    }
+
+    /// Forgets the events of the frame that is over, keeping what is held.
+    fn end_frame(&self) {
+        self.events = ~[];
+    }
+}
+
+/// Feeds the events of an event file to an `Input` as time passes.
+pub struct Replay {
+    priv events: ~[TimedEvent],
+    priv mut next: uint,
+}
+
+impl Replay {
+    static fn new(events: ~[TimedEvent]) -> Replay {
+        Replay { events: move events, next: 0 }
+    }
+
+    /// Pushes the events up to `time` seconds into `input`.
+    fn advance(&self, time: float, input: &Input) {
+        while self.next < self.events.len() && self.events[self.next].time <= time {
+            input.push(self.events[self.next].event);
+            self.next += 1;
+        }
+    }
+
+    /// Whether every event has been replayed.
+    fn is_done(&self) -> bool {
+        self.next == self.events.len()
+    }
+}
@@ -0,0 +1,265 @@
+/*!
+ * Building mipmap chains on the CPU.
+ *
+ * `glGenerateMipmap` is the easy way to get mipmaps, but its filter is up
+ * to the driver and it is missing from some contexts. These functions
+ * build the chain from 8-bit pixels instead, halving each side (rounding
+ * down, but never below 1) until the level is 1 by 1.
+ */
+
+/// One level of a mipmap chain: tightly packed 8-bit pixels, top row first.
+pub struct Level {
+    width: uint,
+    height: uint,
+    channels: uint,
+    data: ~[u8],
+}
+
+/// The filter a level is reduced to the next with.
+pub enum Downsample {
+    /// The average of the texels each one covers. Fast, slightly blurry.
+    BoxFilter,
+    /// A windowed sinc with three lobes. Sharper, at some risk of ringing
+    /// around hard edges.
+    LanczosFilter,
+}
+
+/// The size of the level after one `size` texels across.
+pub pure fn next_size(size: uint) -> uint {
+    uint::max(size / 2, 1)
+}
+
+/// The number of levels in a full chain for a `width` by `height` image,
+/// counting the base level.
+pub pure fn level_count(width: uint, height: uint) -> uint {
+    let mut n = 1;
+    let mut size = uint::max(width, height);
+    while size > 1 {
+        size /= 2;
+        n += 1;
+    }
+    n
+}
+
+/// Reduces `level` to the next level with `filter`.
+pub fn downsample(level: &Level, filter: Downsample) -> Level {
+    match filter {
+        BoxFilter => downsample_box(level),
+        LanczosFilter => downsample_lanczos(level),
+    }
+}
+
+/// The levels after `base`, down to 1 by 1, each reduced from the one
+/// before with `filter`.
+pub fn chain(base: &Level, filter: Downsample) -> ~[Level] {
+    let mut levels = ~[];
+    let mut width = base.width;
+    let mut height = base.height;
+    while width > 1 || height > 1 {
+        let next = if levels.is_empty() {
+            downsample(base, filter)
+        } else {
+            downsample(&levels[levels.len() - 1], filter)
+        };
+        width = next.width;
+        height = next.height;
+        levels.push(move next);
+    }
+    move levels
+}
+
+/**
+ * Averages the block of texels under each texel of the next level. The
+ * blocks are 2 by 2, except that where a side is odd the last block along
+ * it is 3 wide, so no texel is skipped.
+ */
+pub fn downsample_box(level: &Level) -> Level {
+    let width = next_size(level.width);
+    let height = next_size(level.height);
+    let channels = level.channels;
+    let mut data = vec::with_capacity(width * height * channels);
+
+    for uint::range(0, height) |y| {
+        let (y0, y1) = (y * level.height / height, (y + 1) * level.height / height);
+        for uint::range(0, width) |x| {
+            let (x0, x1) = (x * level.width / width, (x + 1) * level.width / width);
+            let count = (x1 - x0) * (y1 - y0);
+            for uint::range(0, channels) |c| {
+                let mut sum = 0u;
+                for uint::range(y0, y1) |j| {
+                    for uint::range(x0, x1) |i| {
+                        sum += level.data[(j * level.width + i) * channels + c] as uint;
+                    }
+                }
+                // Round to nearest
+                data.push(((sum + count / 2) / count) as u8);
+            }
+        }
+    }
+
+    Level { width: width, height: height, channels: channels, data: move data }
+}
+
+/// The number of lobes of the Lanczos kernel.
+const LOBES: f32 = 3.0;
+
+/// The Lanczos kernel: `sinc(x) * sinc(x / LOBES)` inside the lobes, else 0.
+pub pure fn lanczos(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    } else if x <= -LOBES || x >= LOBES {
+        0.0
+    } else {
+        let px = f32::consts::pi * x;
+        LOBES * f32::sin(px) * f32::sin(px / LOBES) / (px * px)
+    }
+}
+
+/**
+ * The weights with which the texels of a row `from` texels long contribute
+ * to texel `i` of a row `to` texels long. Returns the first texel used and
+ * the weights from there on, which sum to 1.
+ */
+pub fn lanczos_weights(from: uint, to: uint, i: uint) -> (uint, ~[f32]) {
+    // The kernel is stretched over as many source texels as one
+    // destination texel covers, so it also removes what would alias
+    let scale = from as f32 / to as f32;
+    let support = if scale > 1.0 { LOBES * scale } else { LOBES };
+    let stretch = if scale > 1.0 { scale } else { 1.0 };
+    let center = (i as f32 + 0.5) * scale - 0.5;
+
+    let first = int::max(f32::ceil(center - support) as int, 0) as uint;
+    let last = int::min(f32::floor(center + support) as int, from as int - 1) as uint;
+
+    let mut weights = vec::with_capacity(last - first + 1);
+    let mut total = 0.0f32;
+    for uint::range(first, last + 1) |j| {
+        let w = lanczos((j as f32 - center) / stretch);
+        weights.push(w);
+        total += w;
+    }
+    for uint::range(0, weights.len()) |k| {
+        weights[k] /= total;
+    }
+
+    (first, move weights)
+}
+
+/**
+ * Reduces `level` with a separable Lanczos filter, first along rows, then
+ * along columns. Edge texels are clamped rather than wrapped, and values
+ * overshooting the 8-bit range are clamped as well.
+ */
+pub fn downsample_lanczos(level: &Level) -> Level {
+    let width = next_size(level.width);
+    let height = next_size(level.height);
+    let channels = level.channels;
+
+    // Horizontal pass into floats, `width` by `level.height`
+    let mut rows = vec::with_capacity(width * level.height * channels);
+    let columns = vec::from_fn(width, |x| lanczos_weights(level.width, width, x));
+    for uint::range(0, level.height) |y| {
+        for columns.each |column| {
+            let (first, ref weights) = *column;
+            for uint::range(0, channels) |c| {
+                let mut sum = 0.0f32;
+                for vec::eachi(*weights) |k, w| {
+                    sum += *w * level.data[(y * level.width + first + k) * channels + c] as f32;
+                }
+                rows.push(sum);
+            }
+        }
+    }
+
+    // Vertical pass
+    let mut data = vec::with_capacity(width * height * channels);
+    for uint::range(0, height) |y| {
+        let (first, weights) = lanczos_weights(level.height, height, y);
+        for uint::range(0, width) |x| {
+            for uint::range(0, channels) |c| {
+                let mut sum = 0.0f32;
+                for vec::eachi(weights) |k, w| {
+                    sum += *w * rows[((first + k) * width + x) * channels + c];
+                }
+                data.push(if sum <= 0.0 { 0 } else if sum >= 255.0 { 255 } else { (sum + 0.5) as u8 });
+            }
+        }
+    }
+
+    Level { width: width, height: height, channels: channels, data: move data }
+}
+
+#[cfg(test)]
+mod tests {
+    use mipmap::*;
+
+    fn level(width: uint, height: uint, data: ~[u8]) -> Level {
+        Level { width: width, height: height, channels: 1, data: move data }
+    }
+
+    #[test]
+    fn halves_sizes_rounding_down() {
+        assert next_size(256) == 128;
+        assert next_size(5) == 2;
+        assert next_size(3) == 1;
+        assert next_size(1) == 1;
+    }
+
+    #[test]
+    fn counts_levels_down_to_1_by_1() {
+        assert level_count(1, 1) == 1;
+        assert level_count(256, 64) == 9;
+        assert level_count(5, 3) == 3;
+        assert level_count(6, 1) == 3;
+    }
+
+    #[test]
+    fn chain_ends_at_1_by_1() {
+        let base = level(5, 3, vec::from_elem(15, 128u8));
+        for vec::each([BoxFilter, LanczosFilter]) |filter| {
+            let levels = chain(&base, *filter);
+            assert levels.len() == level_count(5, 3) - 1;
+            assert levels[0].width == 2 && levels[0].height == 1;
+            let last = &levels[levels.len() - 1];
+            assert last.width == 1 && last.height == 1 && last.data.len() == 1;
+        }
+    }
+
+    #[test]
+    fn box_takes_3_texels_at_the_end_of_odd_rows() {
+        let reduced = downsample_box(&level(5, 1, ~[10, 20, 30, 40, 50]));
+        assert reduced.width == 2 && reduced.height == 1;
+        assert reduced.data == ~[15, 40];
+
+        let reduced = downsample_box(&level(1, 3, ~[10, 20, 60]));
+        assert reduced.width == 1 && reduced.height == 1;
+        assert reduced.data == ~[30];
+    }
+
+    #[test]
+    fn box_rounds_to_nearest() {
+        // 2 / 4 rounds up and 1 / 4 down, where truncating would give 0 for both
+        assert downsample_box(&level(2, 2, ~[0, 0, 1, 1])).data == ~[1];
+        assert downsample_box(&level(2, 2, ~[0, 0, 0, 1])).data == ~[0];
+        assert downsample_box(&level(3, 1, ~[0, 1, 1])).data == ~[1];
+    }
+
+    #[test]
+    fn lanczos_weights_sum_to_1() {
+        let cases = [(256u, 128u, 0u), (256, 128, 64), (256, 128, 127), (5, 2, 1), (3, 1, 0), (4, 8, 3)];
+        for vec::each(cases) |case| {
+            let (from, to, i) = *case;
+            let (first, weights) = lanczos_weights(from, to, i);
+            assert first + weights.len() <= from;
+            let total = vec::foldl(0.0f32, weights, |sum, w| sum + *w);
+            assert f32::abs(total - 1.0) < 1e-5;
+        }
+    }
+
+    #[test]
+    fn lanczos_keeps_flat_images_flat() {
+        let reduced = downsample_lanczos(&level(5, 3, vec::from_elem(15, 200u8)));
+        assert reduced.width == 2 && reduced.height == 1;
+        assert reduced.data == ~[200, 200];
+    }
+}
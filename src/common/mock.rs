@@ -0,0 +1,1614 @@
+/*!
+ * A `Gl` that records calls instead of drawing.
+ *
+ * `MockGl` keeps a trace of every call made through it, together with the
+ * bindings in effect when it was made, so the command stream of a program
+ * can be inspected: that the element buffer is bound before
+ * `glDrawElements`, that six indices are uploaded, and so on. It shadows
+ * the parts of the GL state machine the tutorials touch and, where a
+ * driver would raise `GL_INVALID_ENUM` or `GL_INVALID_OPERATION`, records
+ * the error against the call and otherwise ignores it, as GL does.
+ * Drawing or clearing while an incomplete framebuffer object is bound is
+ * recorded with `GL_INVALID_FRAMEBUFFER_OPERATION`.
+ *
+ * Shaders always compile. A program links if it has a vertex and a
+ * fragment shader, and a geometry shader, if it has one, declares the
+ * primitives it takes and emits. Its attribute and uniform locations are
+ * assigned from the `in` and `uniform` declarations of their source.
+ * Drawing primitives the geometry shader does not take is recorded with
+ * `GL_INVALID_OPERATION`.
+ *
+ * Calls setting a piece of fixed-function state, a capability, the depth
+ * function, the viewport and so on, the current program and the bound
+ * vertex array, are also checked against what that state was last set to
+ * through the mock; those that leave it as it was are redundant, and are
+ * listed by `redundant`.
+ *
+ * Buffers keep their size, so updates and mappings are checked against
+ * it, and mapping one hands out zeroed memory of the length asked for,
+ * which is thrown away when it is unmapped, unless `fail_maps` has made
+ * mappings run out of memory. Nothing is ever drawn, so fences are
+ * signalled as soon as they are inserted.
+ */
+
+use cast::transmute;
+
+use glcore::*;
+
+use gl::Gl;
+use sampler::GL_TEXTURE_MAX_ANISOTROPY_EXT;
+use soft::{Declaration, Emulation, MAX_UNIFORM_BUFFER_BINDINGS, variables, uniform_blocks, primitive_layout,
+           attribute_locations};
+
+const MAX_ATTRIBS: uint = 16;
+const MAX_TEXTURE_UNITS: uint = 16;
+
+/// An argument of a recorded call.
+pub enum Arg {
+    Enum(GLenum),
+    Int(int),
+    Float(float),
+    Text(~str),
+    Data(~[u8]),
+}
+
+impl Arg : ToStr {
+    pure fn to_str(&self) -> ~str {
+        match *self {
+            Enum(e) => enum_name(e),
+            Int(i) => int::str(i),
+            Float(f) => float::to_str(f, 4),
+            Text(ref s) => fmt!("\"%s\"", *s),
+            Data(ref d) => fmt!("<%u bytes>", d.len()),
+        }
+    }
+}
+
+/// The bindings in effect when a call was made.
+pub struct State {
+    vertex_array: GLuint,
+    array_buffer: GLuint,
+    /// The element buffer of the bound vertex array.
+    element_array_buffer: GLuint,
+    /// The active texture unit, counting from 0 rather than `GL_TEXTURE0`.
+    active_texture: uint,
+    /// The 2D texture bound to the active unit.
+    texture_2d: GLuint,
+    program: GLuint,
+    /// The framebuffer object drawn into, or 0 for the window's.
+    framebuffer: GLuint,
+}
+
+/// One call made through a `MockGl`.
+pub struct Call {
+    /// The name of the GL function, e.g. `glDrawElements`.
+    name: ~str,
+    args: ~[Arg],
+    /// The bindings just before the call.
+    state: State,
+    /// The error a driver would have raised, in which case the call had no
+    /// effect.
+    error: Option<GLenum>,
+}
+
+impl Call : ToStr {
+    pure fn to_str(&self) -> ~str {
+        let args = str::connect(self.args.map(|a| a.to_str()), ", ");
+        match self.error {
+            Some(e) => fmt!("%s(%s) -> %s", self.name, args, enum_name(e)),
+            None => fmt!("%s(%s)", self.name, args),
+        }
+    }
+}
+
+/// The symbolic name of the enums the tutorials use, or the value in hex.
+pub pure fn enum_name(e: GLenum) -> ~str {
+    let names = [
+        (GL_ARRAY_BUFFER, "GL_ARRAY_BUFFER"),
+        (GL_ELEMENT_ARRAY_BUFFER, "GL_ELEMENT_ARRAY_BUFFER"),
+        (GL_UNIFORM_BUFFER, "GL_UNIFORM_BUFFER"),
+        (GL_STATIC_DRAW, "GL_STATIC_DRAW"),
+        (GL_DYNAMIC_DRAW, "GL_DYNAMIC_DRAW"),
+        (GL_STREAM_DRAW, "GL_STREAM_DRAW"),
+        (GL_VERTEX_SHADER, "GL_VERTEX_SHADER"),
+        (GL_GEOMETRY_SHADER, "GL_GEOMETRY_SHADER"),
+        (GL_FRAGMENT_SHADER, "GL_FRAGMENT_SHADER"),
+        (GL_COMPILE_STATUS, "GL_COMPILE_STATUS"),
+        (GL_LINK_STATUS, "GL_LINK_STATUS"),
+        (GL_INFO_LOG_LENGTH, "GL_INFO_LOG_LENGTH"),
+        (GL_ACTIVE_ATTRIBUTES, "GL_ACTIVE_ATTRIBUTES"),
+        (GL_ACTIVE_UNIFORMS, "GL_ACTIVE_UNIFORMS"),
+        (GL_FLOAT, "GL_FLOAT"),
+        (GL_UNSIGNED_BYTE, "GL_UNSIGNED_BYTE"),
+        (GL_UNSIGNED_SHORT, "GL_UNSIGNED_SHORT"),
+        (GL_UNSIGNED_INT, "GL_UNSIGNED_INT"),
+        (GL_POINTS, "GL_POINTS"),
+        (GL_LINES, "GL_LINES"),
+        (GL_LINE_STRIP, "GL_LINE_STRIP"),
+        (GL_TRIANGLES, "GL_TRIANGLES"),
+        (GL_TRIANGLE_STRIP, "GL_TRIANGLE_STRIP"),
+        (GL_TRIANGLE_FAN, "GL_TRIANGLE_FAN"),
+        (GL_TEXTURE_2D, "GL_TEXTURE_2D"),
+        (GL_TEXTURE_WRAP_S, "GL_TEXTURE_WRAP_S"),
+        (GL_TEXTURE_WRAP_T, "GL_TEXTURE_WRAP_T"),
+        (GL_TEXTURE_MIN_FILTER, "GL_TEXTURE_MIN_FILTER"),
+        (GL_TEXTURE_MAG_FILTER, "GL_TEXTURE_MAG_FILTER"),
+        (GL_TEXTURE_WRAP_R, "GL_TEXTURE_WRAP_R"),
+        (GL_TEXTURE_BORDER_COLOR, "GL_TEXTURE_BORDER_COLOR"),
+        (GL_TEXTURE_LOD_BIAS, "GL_TEXTURE_LOD_BIAS"),
+        (GL_TEXTURE_MAX_LEVEL, "GL_TEXTURE_MAX_LEVEL"),
+        (GL_TEXTURE_MAX_ANISOTROPY_EXT, "GL_TEXTURE_MAX_ANISOTROPY_EXT"),
+        (GL_NEAREST, "GL_NEAREST"),
+        (GL_LINEAR, "GL_LINEAR"),
+        (GL_NEAREST_MIPMAP_NEAREST, "GL_NEAREST_MIPMAP_NEAREST"),
+        (GL_LINEAR_MIPMAP_NEAREST, "GL_LINEAR_MIPMAP_NEAREST"),
+        (GL_NEAREST_MIPMAP_LINEAR, "GL_NEAREST_MIPMAP_LINEAR"),
+        (GL_LINEAR_MIPMAP_LINEAR, "GL_LINEAR_MIPMAP_LINEAR"),
+        (GL_REPEAT, "GL_REPEAT"),
+        (GL_MIRRORED_REPEAT, "GL_MIRRORED_REPEAT"),
+        (GL_CLAMP_TO_EDGE, "GL_CLAMP_TO_EDGE"),
+        (GL_CLAMP_TO_BORDER, "GL_CLAMP_TO_BORDER"),
+        (GL_UNPACK_ALIGNMENT, "GL_UNPACK_ALIGNMENT"),
+        (GL_PACK_ALIGNMENT, "GL_PACK_ALIGNMENT"),
+        (GL_RED, "GL_RED"),
+        (GL_RG, "GL_RG"),
+        (GL_RGB, "GL_RGB"),
+        (GL_RGBA, "GL_RGBA"),
+        (GL_R8, "GL_R8"),
+        (GL_RG8, "GL_RG8"),
+        (GL_RGB8, "GL_RGB8"),
+        (GL_RGBA8, "GL_RGBA8"),
+        (GL_SRGB8, "GL_SRGB8"),
+        (GL_SRGB8_ALPHA8, "GL_SRGB8_ALPHA8"),
+        (GL_COLOR_BUFFER_BIT, "GL_COLOR_BUFFER_BIT"),
+        (GL_DEPTH_BUFFER_BIT, "GL_DEPTH_BUFFER_BIT"),
+        (GL_STENCIL_BUFFER_BIT, "GL_STENCIL_BUFFER_BIT"),
+        (GL_DEPTH_TEST, "GL_DEPTH_TEST"),
+        (GL_STENCIL_TEST, "GL_STENCIL_TEST"),
+        (GL_BLEND, "GL_BLEND"),
+        (GL_CULL_FACE, "GL_CULL_FACE"),
+        (GL_SCISSOR_TEST, "GL_SCISSOR_TEST"),
+        (GL_NEVER, "GL_NEVER"),
+        (GL_LESS, "GL_LESS"),
+        (GL_EQUAL, "GL_EQUAL"),
+        (GL_LEQUAL, "GL_LEQUAL"),
+        (GL_GREATER, "GL_GREATER"),
+        (GL_NOTEQUAL, "GL_NOTEQUAL"),
+        (GL_GEQUAL, "GL_GEQUAL"),
+        (GL_ALWAYS, "GL_ALWAYS"),
+        (GL_FRONT, "GL_FRONT"),
+        (GL_BACK, "GL_BACK"),
+        (GL_FRONT_AND_BACK, "GL_FRONT_AND_BACK"),
+        (GL_POINT, "GL_POINT"),
+        (GL_LINE, "GL_LINE"),
+        (GL_FILL, "GL_FILL"),
+        (GL_FUNC_ADD, "GL_FUNC_ADD"),
+        (GL_FUNC_SUBTRACT, "GL_FUNC_SUBTRACT"),
+        (GL_FUNC_REVERSE_SUBTRACT, "GL_FUNC_REVERSE_SUBTRACT"),
+        (GL_MIN, "GL_MIN"),
+        (GL_MAX, "GL_MAX"),
+        (GL_SRC_COLOR, "GL_SRC_COLOR"),
+        (GL_ONE_MINUS_SRC_COLOR, "GL_ONE_MINUS_SRC_COLOR"),
+        (GL_SRC_ALPHA, "GL_SRC_ALPHA"),
+        (GL_ONE_MINUS_SRC_ALPHA, "GL_ONE_MINUS_SRC_ALPHA"),
+        (GL_DST_ALPHA, "GL_DST_ALPHA"),
+        (GL_ONE_MINUS_DST_ALPHA, "GL_ONE_MINUS_DST_ALPHA"),
+        (GL_DST_COLOR, "GL_DST_COLOR"),
+        (GL_ONE_MINUS_DST_COLOR, "GL_ONE_MINUS_DST_COLOR"),
+        (GL_SRC_ALPHA_SATURATE, "GL_SRC_ALPHA_SATURATE"),
+        (GL_CONSTANT_COLOR, "GL_CONSTANT_COLOR"),
+        (GL_ONE_MINUS_CONSTANT_COLOR, "GL_ONE_MINUS_CONSTANT_COLOR"),
+        (GL_CONSTANT_ALPHA, "GL_CONSTANT_ALPHA"),
+        (GL_ONE_MINUS_CONSTANT_ALPHA, "GL_ONE_MINUS_CONSTANT_ALPHA"),
+        (GL_KEEP, "GL_KEEP"),
+        (GL_REPLACE, "GL_REPLACE"),
+        (GL_INCR, "GL_INCR"),
+        (GL_INCR_WRAP, "GL_INCR_WRAP"),
+        (GL_DECR, "GL_DECR"),
+        (GL_DECR_WRAP, "GL_DECR_WRAP"),
+        (GL_INVERT, "GL_INVERT"),
+        (GL_INVALID_ENUM, "GL_INVALID_ENUM"),
+        (GL_INVALID_VALUE, "GL_INVALID_VALUE"),
+        (GL_INVALID_OPERATION, "GL_INVALID_OPERATION"),
+        (GL_INVALID_FRAMEBUFFER_OPERATION, "GL_INVALID_FRAMEBUFFER_OPERATION"),
+        (GL_OUT_OF_MEMORY, "GL_OUT_OF_MEMORY"),
+        (GL_FRAMEBUFFER, "GL_FRAMEBUFFER"),
+        (GL_DRAW_FRAMEBUFFER, "GL_DRAW_FRAMEBUFFER"),
+        (GL_READ_FRAMEBUFFER, "GL_READ_FRAMEBUFFER"),
+        (GL_RENDERBUFFER, "GL_RENDERBUFFER"),
+        (GL_COLOR_ATTACHMENT0, "GL_COLOR_ATTACHMENT0"),
+        (GL_DEPTH_ATTACHMENT, "GL_DEPTH_ATTACHMENT"),
+        (GL_STENCIL_ATTACHMENT, "GL_STENCIL_ATTACHMENT"),
+        (GL_DEPTH_STENCIL_ATTACHMENT, "GL_DEPTH_STENCIL_ATTACHMENT"),
+        (GL_DEPTH_COMPONENT16, "GL_DEPTH_COMPONENT16"),
+        (GL_DEPTH_COMPONENT24, "GL_DEPTH_COMPONENT24"),
+        (GL_DEPTH_COMPONENT32F, "GL_DEPTH_COMPONENT32F"),
+        (GL_DEPTH24_STENCIL8, "GL_DEPTH24_STENCIL8"),
+        (GL_DEPTH32F_STENCIL8, "GL_DEPTH32F_STENCIL8"),
+        (GL_STENCIL_INDEX8, "GL_STENCIL_INDEX8"),
+        (GL_FRAMEBUFFER_COMPLETE, "GL_FRAMEBUFFER_COMPLETE"),
+        (GL_FRAMEBUFFER_INCOMPLETE_ATTACHMENT, "GL_FRAMEBUFFER_INCOMPLETE_ATTACHMENT"),
+        (GL_FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT, "GL_FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT"),
+        (GL_FRAMEBUFFER_UNSUPPORTED, "GL_FRAMEBUFFER_UNSUPPORTED"),
+    ];
+
+    if e >= GL_TEXTURE0 && e < GL_TEXTURE0 + MAX_TEXTURE_UNITS as GLenum {
+        return fmt!("GL_TEXTURE%u", (e - GL_TEXTURE0) as uint);
+    }
+    for names.each |pair| {
+        let (value, name) = *pair;
+        if value == e { return str::from_slice(name); }
+    }
+    fmt!("0x%04x", e as uint)
+}
+
+struct BufferState {
+    /// The size of its data store, in bytes.
+    size: uint,
+    /// The memory handed out for the range mapped, if it is.
+    mapping: Option<~[u8]>,
+}
+
+struct ArrayState {
+    element_buffer: GLuint,
+    enabled: ~[bool],
+}
+
+struct ProgramState {
+    shaders: ~[GLuint],
+    linked: bool,
+    attributes: ~[Declaration],
+    uniforms: ~[Declaration],
+    /// The names of the uniform blocks.
+    blocks: ~[~str],
+    /// The primitive the geometry shader takes, if there is one.
+    geometry_input: Option<GLenum>,
+}
+
+/// What is attached to an attachment point of a framebuffer object.
+enum Attachment {
+    Unattached,
+    AttachedTexture(GLuint),
+    AttachedRenderbuffer(GLuint),
+}
+
+struct FramebufferState {
+    color: Attachment,
+    depth: Attachment,
+    stencil: Attachment,
+}
+
+struct RenderbufferState {
+    /// Whether `glRenderbufferStorage` has been called on it.
+    allocated: bool,
+}
+
+/// A `Gl` recording calls into a trace.
+pub struct MockGl {
+    priv mut calls: ~[Call],
+    /// The indices in `calls` of the redundant ones.
+    priv mut redundant: ~[uint],
+    /// Each piece of state set so far, and the call that last set it.
+    priv mut settings: ~[(~str, ~str)],
+
+    priv mut buffers: ~[Option<@mut BufferState>],
+    priv mut array_buffer: GLuint,
+    priv mut uniform_buffer: GLuint,
+    priv mut uniform_buffers: ~[GLuint],
+    priv mut arrays: ~[Option<@mut ArrayState>],
+    priv mut bound_array: GLuint,
+
+    priv mut shaders: ~[Option<@mut ~str>],
+    priv mut shader_kinds: ~[GLenum],
+    priv mut programs: ~[Option<@mut ProgramState>],
+    priv mut current_program: GLuint,
+
+    priv mut textures: ~[bool],
+    /// Whether each texture has a base level image.
+    priv mut images: ~[bool],
+    priv mut active_unit: uint,
+    priv mut units: ~[GLuint],
+    priv mut samplers: ~[bool],
+
+    priv mut framebuffers: ~[Option<@mut FramebufferState>],
+    priv mut bound_framebuffer: GLuint,
+    priv mut renderbuffers: ~[Option<@mut RenderbufferState>],
+    priv mut bound_renderbuffer: GLuint,
+
+    /// Whether each fence exists, by the value of its handle.
+    priv mut syncs: ~[bool],
+    /// Whether `glMapBufferRange` runs out of memory.
+    priv mut failing_maps: bool,
+}
+
+impl MockGl {
+    static fn new() -> MockGl {
+        MockGl {
+            calls: ~[],
+            redundant: ~[],
+            settings: ~[],
+
+            buffers: ~[None],
+            array_buffer: 0,
+            uniform_buffer: 0,
+            uniform_buffers: vec::from_elem(MAX_UNIFORM_BUFFER_BINDINGS, 0),
+            // The default vertex array is not usable in a core profile, so
+            // name 0 is left out
+            arrays: ~[None],
+            bound_array: 0,
+
+            shaders: ~[None],
+            shader_kinds: ~[0],
+            programs: ~[None],
+            current_program: 0,
+
+            textures: ~[true],
+            images: ~[false],
+            active_unit: 0,
+            units: vec::from_elem(MAX_TEXTURE_UNITS, 0),
+            samplers: ~[false],
+
+            framebuffers: ~[None],
+            bound_framebuffer: 0,
+            renderbuffers: ~[None],
+            bound_renderbuffer: 0,
+
+            syncs: ~[false],
+            failing_maps: false,
+        }
+    }
+
+    /// Every call made so far, in order.
+    fn calls(&self) -> ~[Call] {
+        copy self.calls
+    }
+
+    /// The calls to the GL function `name`, in order.
+    fn calls_to(&self, name: &str) -> ~[Call] {
+        self.calls.filtered(|c| str::eq_slice(c.name, name))
+    }
+
+    /// The calls a driver would have rejected.
+    fn errors(&self) -> ~[Call] {
+        self.calls.filtered(|c| c.error.is_some())
+    }
+
+    /// The calls that set state to what it already was, in order.
+    fn redundant(&self) -> ~[Call] {
+        self.redundant.map(|i| copy self.calls[*i])
+    }
+
+    /// Makes `glMapBufferRange` fail with `GL_OUT_OF_MEMORY`, as a driver
+    /// short of address space may, or succeed again.
+    fn fail_maps(&self, fail: bool) {
+        self.failing_maps = fail;
+    }
+
+    /// Forgets the calls made so far, keeping the GL state.
+    fn clear_trace(&self) {
+        self.calls = ~[];
+        self.redundant = ~[];
+    }
+
+    /// The whole trace, one call per line.
+    fn trace(&self) -> ~str {
+        str::connect(self.calls.map(|c| c.to_str()), "\n")
+    }
+
+    priv fn state(&self) -> State {
+        State {
+            vertex_array: self.bound_array,
+            array_buffer: self.array_buffer,
+            element_array_buffer: match self.array() {
+                Some(array) => array.element_buffer,
+                None => 0,
+            },
+            active_texture: self.active_unit,
+            texture_2d: self.units[self.active_unit],
+            program: self.current_program,
+            framebuffer: self.bound_framebuffer,
+        }
+    }
+
+    /// Records a call. Returns whether it takes effect.
+    priv fn record(&self, name: &str, args: ~[Arg], error: Option<GLenum>) -> bool {
+        let call = Call { name: str::from_slice(name), args: move args, state: self.state(), error: error };
+        self.calls.push(move call);
+        error.is_none()
+    }
+
+    /**
+     * Records a call setting the piece of state `key`, which is redundant
+     * if the call that last set it had the same name and arguments.
+     * Returns whether it takes effect.
+     */
+    priv fn record_setting(&self, name: &str, key: &str, args: ~[Arg], error: Option<GLenum>) -> bool {
+        if !self.record(name, move args, error) { return false; }
+
+        let at = self.calls.len() - 1;
+        let call = self.calls[at].to_str();
+        match vec::position(self.settings, |setting| str::eq_slice(setting.first(), key)) {
+            Some(i) => {
+                if self.settings[i].second() == call { self.redundant.push(at); }
+                self.settings[i] = (str::from_slice(key), move call);
+            }
+            None => self.settings.push((str::from_slice(key), move call)),
+        }
+        true
+    }
+
+    /// Forgets what the piece of state `key` was last set to, as it has
+    /// changed some other way.
+    priv fn forget_setting(&self, key: &str) {
+        self.settings = self.settings.filtered(|setting| !str::eq_slice(setting.first(), key));
+    }
+
+    priv fn array(&self) -> Option<@mut ArrayState> {
+        lookup(self.arrays, self.bound_array)
+    }
+
+    priv fn is_buffer(&self, name: GLuint) -> bool {
+        lookup(self.buffers, name).is_some()
+    }
+
+    priv fn is_sync(&self, sync: GLsync) -> bool {
+        let name = sync_name(sync);
+        name < self.syncs.len() && self.syncs[name]
+    }
+
+    priv fn is_texture(&self, name: GLuint) -> bool {
+        (name as uint) < self.textures.len() && self.textures[name]
+    }
+
+    priv fn is_sampler(&self, name: GLuint) -> bool {
+        (name as uint) < self.samplers.len() && self.samplers[name]
+    }
+
+    priv fn bound_buffer(&self, target: GLenum) -> GLuint {
+        if target == GL_ARRAY_BUFFER {
+            self.array_buffer
+        } else if target == GL_UNIFORM_BUFFER {
+            self.uniform_buffer
+        } else {
+            match self.array() {
+                Some(array) => array.element_buffer,
+                None => 0,
+            }
+        }
+    }
+
+    /// The error for a uniform call at `location`, if any.
+    priv fn uniform_error(&self, location: GLint) -> Option<GLenum> {
+        match lookup(self.programs, self.current_program) {
+            None => Some(GL_INVALID_OPERATION),
+            Some(program) if location < -1 || location >= program.uniforms.len() as GLint => {
+                Some(GL_INVALID_OPERATION)
+            }
+            Some(_) => None,
+        }
+    }
+
+    /// Whether `framebuffer` can be drawn into, as `glCheckFramebufferStatus` reports it.
+    priv fn framebuffer_status(&self, framebuffer: @mut FramebufferState) -> GLenum {
+        let attachments = [framebuffer.color, framebuffer.depth, framebuffer.stencil];
+        if vec::all(attachments, |a| match *a { Unattached => true, _ => false }) {
+            return GL_FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT;
+        }
+        // Every attachment needs an image to draw into
+        let has_image = |attachment: &Attachment| match *attachment {
+            Unattached => true,
+            AttachedTexture(texture) => self.is_texture(texture) && self.images[texture],
+            AttachedRenderbuffer(renderbuffer) => match lookup(self.renderbuffers, renderbuffer) {
+                Some(state) => state.allocated,
+                None => false,
+            },
+        };
+        if vec::all(attachments, has_image) {
+            GL_FRAMEBUFFER_COMPLETE
+        } else {
+            GL_FRAMEBUFFER_INCOMPLETE_ATTACHMENT
+        }
+    }
+
+    /// Attaches `image` to `attachment` of the bound framebuffer object.
+    priv fn attach(&self, attachment: GLenum, image: Attachment) {
+        let framebuffer = lookup(self.framebuffers, self.bound_framebuffer).get();
+        if attachment == GL_COLOR_ATTACHMENT0 {
+            framebuffer.color = image;
+        }
+        if attachment == GL_DEPTH_ATTACHMENT || attachment == GL_DEPTH_STENCIL_ATTACHMENT {
+            framebuffer.depth = image;
+        }
+        if attachment == GL_STENCIL_ATTACHMENT || attachment == GL_DEPTH_STENCIL_ATTACHMENT {
+            framebuffer.stencil = image;
+        }
+    }
+
+    /// Whether the framebuffer drawn into is complete.
+    priv fn is_framebuffer_complete(&self) -> bool {
+        match lookup(self.framebuffers, self.bound_framebuffer) {
+            Some(framebuffer) => self.framebuffer_status(framebuffer) == GL_FRAMEBUFFER_COMPLETE,
+            None => true,
+        }
+    }
+
+    /// The error for `glDrawArrays` or, for `instances` other than 1,
+    /// `glDrawArraysInstanced`, if any.
+    priv fn draw_arrays_error(&self, mode: GLenum, first: GLint, count: GLsizei,
+                              instances: GLsizei) -> Option<GLenum> {
+        match self.draw_error(mode) {
+            None => error_if(first < 0 || count < 0 || instances < 0, GL_INVALID_VALUE),
+            error => error,
+        }
+    }
+
+    /// The error for `glDrawElements` or, for `instances` other than 1,
+    /// `glDrawElementsInstanced`, if any.
+    priv fn draw_elements_error(&self, mode: GLenum, count: GLsizei, kind: GLenum,
+                                instances: GLsizei) -> Option<GLenum> {
+        if kind != GL_UNSIGNED_BYTE && kind != GL_UNSIGNED_SHORT && kind != GL_UNSIGNED_INT {
+            Some(GL_INVALID_ENUM)
+        } else {
+            match self.draw_error(mode) {
+                None if count < 0 || instances < 0 => Some(GL_INVALID_VALUE),
+                // Client-side index arrays are gone in a core profile too
+                None => error_if(self.bound_buffer(GL_ELEMENT_ARRAY_BUFFER) == 0, GL_INVALID_OPERATION),
+                error => error,
+            }
+        }
+    }
+
+    /// The error for a draw call, if any.
+    priv fn draw_error(&self, mode: GLenum) -> Option<GLenum> {
+        if mode != GL_TRIANGLES && mode != GL_TRIANGLE_STRIP && mode != GL_TRIANGLE_FAN &&
+           mode != GL_LINES && mode != GL_LINE_STRIP && mode != GL_POINTS {
+            Some(GL_INVALID_ENUM)
+        } else if self.array().is_none() {
+            Some(GL_INVALID_OPERATION)
+        } else if !self.is_framebuffer_complete() {
+            Some(GL_INVALID_FRAMEBUFFER_OPERATION)
+        } else {
+            match lookup(self.programs, self.current_program) {
+                Some(program) if program.linked => match program.geometry_input {
+                    Some(input) if !feeds(mode, input) => Some(GL_INVALID_OPERATION),
+                    _ => None,
+                },
+                _ => Some(GL_INVALID_OPERATION),
+            }
+        }
+    }
+}
+
+fn lookup<T: Copy>(table: &[Option<T>], name: GLuint) -> Option<T> {
+    if name == 0 || name as uint >= table.len() { None } else { copy table[name] }
+}
+
+/// Whether drawing with `mode` makes the primitives a geometry shader
+/// taking `input` expects.
+fn feeds(mode: GLenum, input: GLenum) -> bool {
+    if input == GL_POINTS {
+        mode == GL_POINTS
+    } else if input == GL_LINES {
+        mode == GL_LINES || mode == GL_LINE_STRIP
+    } else {
+        mode == GL_TRIANGLES || mode == GL_TRIANGLE_STRIP || mode == GL_TRIANGLE_FAN
+    }
+}
+
+/// The index of `sync` in the table of fences.
+fn sync_name(sync: GLsync) -> uint {
+    unsafe { transmute(sync) }
+}
+
+fn is_buffer_target(target: GLenum) -> bool {
+    target == GL_ARRAY_BUFFER || target == GL_ELEMENT_ARRAY_BUFFER || target == GL_UNIFORM_BUFFER
+}
+
+fn is_sampler_parameter(pname: GLenum) -> bool {
+    pname == GL_TEXTURE_MIN_FILTER || pname == GL_TEXTURE_MAG_FILTER ||
+        pname == GL_TEXTURE_WRAP_S || pname == GL_TEXTURE_WRAP_T || pname == GL_TEXTURE_WRAP_R ||
+        pname == GL_TEXTURE_BORDER_COLOR || pname == GL_TEXTURE_LOD_BIAS ||
+        pname == GL_TEXTURE_MIN_LOD || pname == GL_TEXTURE_MAX_LOD ||
+        pname == GL_TEXTURE_MAX_ANISOTROPY_EXT
+}
+
+fn is_framebuffer_target(target: GLenum) -> bool {
+    target == GL_FRAMEBUFFER || target == GL_DRAW_FRAMEBUFFER || target == GL_READ_FRAMEBUFFER
+}
+
+fn is_attachment(attachment: GLenum) -> bool {
+    attachment == GL_COLOR_ATTACHMENT0 || attachment == GL_DEPTH_ATTACHMENT ||
+        attachment == GL_STENCIL_ATTACHMENT || attachment == GL_DEPTH_STENCIL_ATTACHMENT
+}
+
+fn is_renderbuffer_format(format: GLenum) -> bool {
+    format == GL_RGBA8 || format == GL_RGB8 || format == GL_SRGB8_ALPHA8 ||
+        format == GL_DEPTH_COMPONENT16 || format == GL_DEPTH_COMPONENT24 ||
+        format == GL_DEPTH_COMPONENT32F || format == GL_DEPTH24_STENCIL8 ||
+        format == GL_DEPTH32F_STENCIL8 || format == GL_STENCIL_INDEX8
+}
+
+fn is_capability(capability: GLenum) -> bool {
+    capability == GL_DEPTH_TEST || capability == GL_STENCIL_TEST || capability == GL_BLEND ||
+        capability == GL_CULL_FACE || capability == GL_SCISSOR_TEST
+}
+
+fn is_compare_func(func: GLenum) -> bool {
+    func == GL_NEVER || func == GL_LESS || func == GL_EQUAL || func == GL_LEQUAL ||
+        func == GL_GREATER || func == GL_NOTEQUAL || func == GL_GEQUAL || func == GL_ALWAYS
+}
+
+fn is_stencil_op(op: GLenum) -> bool {
+    op == GL_KEEP || op == GL_ZERO || op == GL_REPLACE || op == GL_INCR || op == GL_INCR_WRAP ||
+        op == GL_DECR || op == GL_DECR_WRAP || op == GL_INVERT
+}
+
+fn is_face(face: GLenum) -> bool {
+    face == GL_FRONT || face == GL_BACK || face == GL_FRONT_AND_BACK
+}
+
+fn is_blend_equation(mode: GLenum) -> bool {
+    mode == GL_FUNC_ADD || mode == GL_FUNC_SUBTRACT || mode == GL_FUNC_REVERSE_SUBTRACT ||
+        mode == GL_MIN || mode == GL_MAX
+}
+
+fn is_blend_factor(factor: GLenum) -> bool {
+    factor == GL_ZERO || factor == GL_ONE || factor == GL_SRC_COLOR || factor == GL_ONE_MINUS_SRC_COLOR ||
+        factor == GL_DST_COLOR || factor == GL_ONE_MINUS_DST_COLOR || factor == GL_SRC_ALPHA ||
+        factor == GL_ONE_MINUS_SRC_ALPHA || factor == GL_DST_ALPHA || factor == GL_ONE_MINUS_DST_ALPHA ||
+        factor == GL_CONSTANT_COLOR || factor == GL_ONE_MINUS_CONSTANT_COLOR ||
+        factor == GL_CONSTANT_ALPHA || factor == GL_ONE_MINUS_CONSTANT_ALPHA ||
+        factor == GL_SRC_ALPHA_SATURATE
+}
+
+fn error_if(condition: bool, error: GLenum) -> Option<GLenum> {
+    if condition { Some(error) } else { None }
+}
+
+impl MockGl : Gl {
+    fn clear_color(&self, red: GLfloat, green: GLfloat, blue: GLfloat, alpha: GLfloat) {
+        self.record_setting("glClearColor", "glClearColor", ~[Float(red as float), Float(green as float),
+                                                              Float(blue as float), Float(alpha as float)], None);
+    }
+
+    fn clear(&self, mask: GLbitfield) {
+        let valid = GL_COLOR_BUFFER_BIT | GL_DEPTH_BUFFER_BIT | GL_STENCIL_BUFFER_BIT;
+        let error = if mask & !valid != 0 {
+            Some(GL_INVALID_VALUE)
+        } else {
+            error_if(!self.is_framebuffer_complete(), GL_INVALID_FRAMEBUFFER_OPERATION)
+        };
+        self.record("glClear", ~[Enum(mask)], error);
+    }
+
+    fn viewport(&self, x: GLint, y: GLint, width: GLsizei, height: GLsizei) {
+        self.record_setting("glViewport", "glViewport",
+                            ~[Int(x as int), Int(y as int), Int(width as int), Int(height as int)],
+                            error_if(width < 0 || height < 0, GL_INVALID_VALUE));
+    }
+
+    fn enable(&self, capability: GLenum) {
+        // Enabling and disabling a capability set the same state
+        self.record_setting("glEnable", enum_name(capability), ~[Enum(capability)],
+                            error_if(!is_capability(capability), GL_INVALID_ENUM));
+    }
+
+    fn disable(&self, capability: GLenum) {
+        self.record_setting("glDisable", enum_name(capability), ~[Enum(capability)],
+                            error_if(!is_capability(capability), GL_INVALID_ENUM));
+    }
+
+    fn depth_func(&self, func: GLenum) {
+        self.record_setting("glDepthFunc", "glDepthFunc", ~[Enum(func)],
+                            error_if(!is_compare_func(func), GL_INVALID_ENUM));
+    }
+
+    fn depth_mask(&self, flag: GLboolean) {
+        self.record_setting("glDepthMask", "glDepthMask", ~[Int(flag as int)], None);
+    }
+
+    fn stencil_func(&self, func: GLenum, reference: GLint, mask: GLuint) {
+        self.record_setting("glStencilFunc", "glStencilFunc", ~[Enum(func), Int(reference as int), Int(mask as int)],
+                            error_if(!is_compare_func(func), GL_INVALID_ENUM));
+    }
+
+    fn stencil_op(&self, stencil_fail: GLenum, depth_fail: GLenum, depth_pass: GLenum) {
+        let error = error_if(!is_stencil_op(stencil_fail) || !is_stencil_op(depth_fail) ||
+                             !is_stencil_op(depth_pass), GL_INVALID_ENUM);
+        self.record_setting("glStencilOp", "glStencilOp", ~[Enum(stencil_fail), Enum(depth_fail), Enum(depth_pass)],
+                            error);
+    }
+
+    fn stencil_mask(&self, mask: GLuint) {
+        self.record_setting("glStencilMask", "glStencilMask", ~[Int(mask as int)], None);
+    }
+
+    fn cull_face(&self, face: GLenum) {
+        self.record_setting("glCullFace", "glCullFace", ~[Enum(face)], error_if(!is_face(face), GL_INVALID_ENUM));
+    }
+
+    fn scissor(&self, x: GLint, y: GLint, width: GLsizei, height: GLsizei) {
+        self.record_setting("glScissor", "glScissor",
+                            ~[Int(x as int), Int(y as int), Int(width as int), Int(height as int)],
+                            error_if(width < 0 || height < 0, GL_INVALID_VALUE));
+    }
+
+    fn color_mask(&self, red: GLboolean, green: GLboolean, blue: GLboolean, alpha: GLboolean) {
+        self.record_setting("glColorMask", "glColorMask",
+                            ~[Int(red as int), Int(green as int), Int(blue as int), Int(alpha as int)], None);
+    }
+
+    fn polygon_mode(&self, face: GLenum, mode: GLenum) {
+        // Front and back faces can no longer be set apart in a core profile
+        let error = error_if(face != GL_FRONT_AND_BACK || (mode != GL_POINT && mode != GL_LINE && mode != GL_FILL),
+                             GL_INVALID_ENUM);
+        self.record_setting("glPolygonMode", "glPolygonMode", ~[Enum(face), Enum(mode)], error);
+    }
+
+    fn blend_equation(&self, mode: GLenum) {
+        self.record_setting("glBlendEquation", "glBlendEquation", ~[Enum(mode)],
+                            error_if(!is_blend_equation(mode), GL_INVALID_ENUM));
+    }
+
+    fn blend_func(&self, source: GLenum, destination: GLenum) {
+        // GL_ZERO and GL_ONE print as the primitives sharing their values
+        self.record_setting("glBlendFunc", "glBlendFunc", ~[Enum(source), Enum(destination)],
+                            error_if(!is_blend_factor(source) || !is_blend_factor(destination), GL_INVALID_ENUM));
+    }
+
+    fn gen_vertex_array(&self) -> GLuint {
+        self.arrays.push(Some(@mut ArrayState {
+            element_buffer: 0,
+            enabled: vec::from_elem(MAX_ATTRIBS, false),
+        }));
+        let name = (self.arrays.len() - 1) as GLuint;
+        self.record("glGenVertexArrays", ~[Int(name as int)], None);
+        name
+    }
+
+    fn bind_vertex_array(&self, array: GLuint) {
+        let error = error_if(array != 0 && lookup(self.arrays, array).is_none(), GL_INVALID_OPERATION);
+        if self.record_setting("glBindVertexArray", "glBindVertexArray", ~[Int(array as int)], error) {
+            self.bound_array = array;
+        }
+    }
+
+    fn delete_vertex_array(&self, array: GLuint) {
+        self.record("glDeleteVertexArrays", ~[Int(array as int)], None);
+        if lookup(self.arrays, array).is_some() {
+            self.arrays[array] = None;
+            if self.bound_array == array {
+                self.bound_array = 0;
+                self.forget_setting("glBindVertexArray");
+            }
+        }
+    }
+
+    fn gen_buffer(&self) -> GLuint {
+        self.buffers.push(Some(@mut BufferState { size: 0, mapping: None }));
+        let name = (self.buffers.len() - 1) as GLuint;
+        self.record("glGenBuffers", ~[Int(name as int)], None);
+        name
+    }
+
+    fn bind_buffer(&self, target: GLenum, buffer: GLuint) {
+        let error = if !is_buffer_target(target) {
+            Some(GL_INVALID_ENUM)
+        } else if buffer != 0 && !self.is_buffer(buffer) {
+            Some(GL_INVALID_OPERATION)
+        } else if target == GL_ELEMENT_ARRAY_BUFFER && self.array().is_none() {
+            // The element buffer binding is part of the vertex array
+            Some(GL_INVALID_OPERATION)
+        } else {
+            None
+        };
+
+        if self.record("glBindBuffer", ~[Enum(target), Int(buffer as int)], error) {
+            if target == GL_ARRAY_BUFFER {
+                self.array_buffer = buffer;
+            } else if target == GL_UNIFORM_BUFFER {
+                self.uniform_buffer = buffer;
+            } else {
+                self.array().get().element_buffer = buffer;
+            }
+        }
+    }
+
+    fn buffer_data(&self, target: GLenum, data: &[u8], usage: GLenum) {
+        let error = if !is_buffer_target(target) ||
+                       (usage != GL_STATIC_DRAW && usage != GL_DYNAMIC_DRAW && usage != GL_STREAM_DRAW) {
+            Some(GL_INVALID_ENUM)
+        } else {
+            error_if(self.bound_buffer(target) == 0, GL_INVALID_OPERATION)
+        };
+        if self.record("glBufferData", ~[Enum(target), Data(vec::from_slice(data)), Enum(usage)], error) {
+            // New storage, which is not mapped
+            let buffer = lookup(self.buffers, self.bound_buffer(target)).get();
+            buffer.size = data.len();
+            buffer.mapping = None;
+        }
+    }
+
+    fn bind_buffer_base(&self, target: GLenum, index: GLuint, buffer: GLuint) {
+        let error = if target != GL_UNIFORM_BUFFER {
+            Some(GL_INVALID_ENUM)
+        } else if index as uint >= MAX_UNIFORM_BUFFER_BINDINGS || (buffer != 0 && !self.is_buffer(buffer)) {
+            Some(GL_INVALID_VALUE)
+        } else {
+            None
+        };
+
+        if self.record("glBindBufferBase", ~[Enum(target), Int(index as int), Int(buffer as int)], error) {
+            self.uniform_buffers[index] = buffer;
+            self.uniform_buffer = buffer;
+        }
+    }
+
+    fn buffer_sub_data(&self, target: GLenum, offset: uint, data: &[u8]) {
+        let error = if !is_buffer_target(target) {
+            Some(GL_INVALID_ENUM)
+        } else {
+            match lookup(self.buffers, self.bound_buffer(target)) {
+                None => Some(GL_INVALID_OPERATION),
+                Some(buffer) if offset + data.len() > buffer.size => Some(GL_INVALID_VALUE),
+                Some(buffer) => error_if(buffer.mapping.is_some(), GL_INVALID_OPERATION),
+            }
+        };
+        self.record("glBufferSubData", ~[Enum(target), Int(offset as int), Data(vec::from_slice(data))], error);
+    }
+
+    fn map_buffer_range(&self, target: GLenum, offset: uint, length: uint, access: GLbitfield) -> *mut u8 {
+        let valid = GL_MAP_READ_BIT | GL_MAP_WRITE_BIT | GL_MAP_INVALIDATE_RANGE_BIT |
+                    GL_MAP_INVALIDATE_BUFFER_BIT | GL_MAP_FLUSH_EXPLICIT_BIT | GL_MAP_UNSYNCHRONIZED_BIT;
+        // What is thrown away or may still be in use cannot be read
+        let write_only = GL_MAP_INVALIDATE_RANGE_BIT | GL_MAP_INVALIDATE_BUFFER_BIT | GL_MAP_UNSYNCHRONIZED_BIT;
+        let buffer = lookup(self.buffers, self.bound_buffer(target));
+        let error = if !is_buffer_target(target) {
+            Some(GL_INVALID_ENUM)
+        } else {
+            match buffer {
+                None => Some(GL_INVALID_OPERATION),
+                Some(buffer) if access & !valid != 0 || offset + length > buffer.size => Some(GL_INVALID_VALUE),
+                Some(buffer) => {
+                    let reads = access & GL_MAP_READ_BIT != 0;
+                    let writes = access & GL_MAP_WRITE_BIT != 0;
+                    if buffer.mapping.is_some() || (!reads && !writes) || (reads && access & write_only != 0) ||
+                       (!writes && access & GL_MAP_FLUSH_EXPLICIT_BIT != 0) {
+                        Some(GL_INVALID_OPERATION)
+                    } else {
+                        error_if(self.failing_maps, GL_OUT_OF_MEMORY)
+                    }
+                }
+            }
+        };
+
+        let args = ~[Enum(target), Int(offset as int), Int(length as int), Int(access as int)];
+        if !self.record("glMapBufferRange", move args, error) {
+            return ptr::mut_null();
+        }
+        // The memory stays where it is when moved into the buffer's state
+        let bytes = vec::from_elem(length, 0u8);
+        let pointer = unsafe { transmute(vec::raw::to_ptr(bytes)) };
+        buffer.get().mapping = Some(move bytes);
+        pointer
+    }
+
+    fn unmap_buffer(&self, target: GLenum) -> GLboolean {
+        let buffer = lookup(self.buffers, self.bound_buffer(target));
+        let error = if !is_buffer_target(target) {
+            Some(GL_INVALID_ENUM)
+        } else {
+            match buffer {
+                Some(buffer) => error_if(buffer.mapping.is_none(), GL_INVALID_OPERATION),
+                None => Some(GL_INVALID_OPERATION),
+            }
+        };
+
+        if self.record("glUnmapBuffer", ~[Enum(target)], error) {
+            buffer.get().mapping = None;
+            GL_TRUE
+        } else {
+            GL_FALSE
+        }
+    }
+
+    fn delete_buffer(&self, buffer: GLuint) {
+        self.record("glDeleteBuffers", ~[Int(buffer as int)], None);
+        if self.is_buffer(buffer) {
+            self.buffers[buffer] = None;
+            if self.array_buffer == buffer { self.array_buffer = 0; }
+            if self.uniform_buffer == buffer { self.uniform_buffer = 0; }
+            for uint::range(0, MAX_UNIFORM_BUFFER_BINDINGS) |i| {
+                if self.uniform_buffers[i] == buffer { self.uniform_buffers[i] = 0; }
+            }
+            for self.arrays.each |array| {
+                match *array {
+                    Some(array) if array.element_buffer == buffer => array.element_buffer = 0,
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    fn fence_sync(&self) -> GLsync {
+        self.syncs.push(true);
+        let name = self.syncs.len() - 1;
+        self.record("glFenceSync", ~[Int(name as int)], None);
+        unsafe { transmute(name) }
+    }
+
+    fn client_wait_sync(&self, sync: GLsync, flags: GLbitfield, timeout: GLuint64) -> GLenum {
+        let error = error_if(flags & !GL_SYNC_FLUSH_COMMANDS_BIT != 0 || !self.is_sync(sync), GL_INVALID_VALUE);
+        let args = ~[Int(sync_name(sync) as int), Int(flags as int), Int(timeout as int)];
+        if self.record("glClientWaitSync", move args, error) {
+            GL_ALREADY_SIGNALED
+        } else {
+            GL_WAIT_FAILED
+        }
+    }
+
+    fn delete_sync(&self, sync: GLsync) {
+        // Deleting the null fence is ignored
+        let name = sync_name(sync);
+        let error = error_if(name != 0 && !self.is_sync(sync), GL_INVALID_VALUE);
+        if self.record("glDeleteSync", ~[Int(name as int)], error) && name != 0 {
+            self.syncs[name] = false;
+        }
+    }
+
+    fn create_shader(&self, kind: GLenum) -> GLuint {
+        if kind != GL_VERTEX_SHADER && kind != GL_GEOMETRY_SHADER && kind != GL_FRAGMENT_SHADER {
+            self.record("glCreateShader", ~[Enum(kind)], Some(GL_INVALID_ENUM));
+            return 0;
+        }
+        self.shaders.push(Some(@mut ~""));
+        self.shader_kinds.push(kind);
+        self.record("glCreateShader", ~[Enum(kind)], None);
+        (self.shaders.len() - 1) as GLuint
+    }
+
+    fn shader_source(&self, shader: GLuint, source: &str) {
+        let error = error_if(lookup(self.shaders, shader).is_none(), GL_INVALID_VALUE);
+        if self.record("glShaderSource", ~[Int(shader as int), Text(str::from_slice(source))], error) {
+            *lookup(self.shaders, shader).get() = str::from_slice(source);
+        }
+    }
+
+    fn compile_shader(&self, shader: GLuint) {
+        self.record("glCompileShader", ~[Int(shader as int)],
+                    error_if(lookup(self.shaders, shader).is_none(), GL_INVALID_VALUE));
+    }
+
+    fn get_shader_iv(&self, shader: GLuint, pname: GLenum) -> GLint {
+        let error = if lookup(self.shaders, shader).is_none() {
+            Some(GL_INVALID_VALUE)
+        } else {
+            error_if(pname != GL_COMPILE_STATUS && pname != GL_INFO_LOG_LENGTH, GL_INVALID_ENUM)
+        };
+        self.record("glGetShaderiv", ~[Int(shader as int), Enum(pname)], error);
+        if error.is_none() && pname == GL_COMPILE_STATUS { GL_TRUE as GLint } else { 0 }
+    }
+
+    fn get_shader_info_log(&self, shader: GLuint) -> ~str {
+        self.record("glGetShaderInfoLog", ~[Int(shader as int)],
+                    error_if(lookup(self.shaders, shader).is_none(), GL_INVALID_VALUE));
+        ~""
+    }
+
+    fn delete_shader(&self, shader: GLuint) {
+        self.record("glDeleteShader", ~[Int(shader as int)], None);
+        if lookup(self.shaders, shader).is_some() {
+            self.shaders[shader] = None;
+        }
+    }
+
+    fn create_program(&self) -> GLuint {
+        self.programs.push(Some(@mut ProgramState {
+            shaders: ~[],
+            linked: false,
+            attributes: ~[],
+            uniforms: ~[],
+            blocks: ~[],
+            geometry_input: None,
+        }));
+        let name = (self.programs.len() - 1) as GLuint;
+        self.record("glCreateProgram", ~[Int(name as int)], None);
+        name
+    }
+
+    fn attach_shader(&self, program: GLuint, shader: GLuint) {
+        let error = match lookup(self.programs, program) {
+            None => Some(GL_INVALID_VALUE),
+            Some(_) if lookup(self.shaders, shader).is_none() => Some(GL_INVALID_VALUE),
+            Some(state) => error_if(state.shaders.contains(&shader), GL_INVALID_OPERATION),
+        };
+        if self.record("glAttachShader", ~[Int(program as int), Int(shader as int)], error) {
+            lookup(self.programs, program).get().shaders.push(shader);
+        }
+    }
+
+    fn detach_shader(&self, program: GLuint, shader: GLuint) {
+        let error = match lookup(self.programs, program) {
+            None => Some(GL_INVALID_VALUE),
+            Some(state) => error_if(!state.shaders.contains(&shader), GL_INVALID_OPERATION),
+        };
+        if self.record("glDetachShader", ~[Int(program as int), Int(shader as int)], error) {
+            let state = lookup(self.programs, program).get();
+            state.shaders = state.shaders.filtered(|s| *s != shader);
+        }
+    }
+
+    fn bind_frag_data_location(&self, program: GLuint, color: GLuint, name: &str) {
+        self.record("glBindFragDataLocation",
+                    ~[Int(program as int), Int(color as int), Text(str::from_slice(name))],
+                    error_if(lookup(self.programs, program).is_none(), GL_INVALID_VALUE));
+    }
+
+    fn link_program(&self, program: GLuint) {
+        let state = match lookup(self.programs, program) {
+            Some(state) => state,
+            None => {
+                self.record("glLinkProgram", ~[Int(program as int)], Some(GL_INVALID_VALUE));
+                return;
+            }
+        };
+        self.record("glLinkProgram", ~[Int(program as int)], None);
+
+        let mut attributes = ~[];
+        let mut uniforms = ~[];
+        let mut blocks = ~[];
+        let mut stages = 0u;
+        let mut geometry_input = None;
+        let mut declared = true;
+        for state.shaders.each |name| {
+            let source = match lookup(self.shaders, *name) {
+                Some(source) => source,
+                None => loop,
+            };
+            let kind = self.shader_kinds[*name];
+            if kind == GL_VERTEX_SHADER {
+                attributes = variables(*source, "in");
+                stages |= 1;
+            } else if kind == GL_GEOMETRY_SHADER {
+                let input = primitive_layout(*source, "in");
+                declared = input != 0 && primitive_layout(*source, "out") != 0;
+                geometry_input = Some(input);
+            } else {
+                stages |= 2;
+            }
+            for variables(*source, "uniform").each |uniform| {
+                if !uniforms.any(|u| u.name == uniform.name) { uniforms.push(copy *uniform); }
+            }
+            for uniform_blocks(*source).each |block| {
+                if !blocks.contains(&block.name) { blocks.push(copy block.name); }
+            }
+        }
+
+        state.linked = stages == 3 && declared;
+        state.geometry_input = geometry_input;
+        state.attributes = move attributes;
+        state.uniforms = move uniforms;
+        state.blocks = move blocks;
+    }
+
+    fn get_program_iv(&self, program: GLuint, pname: GLenum) -> GLint {
+        let state = lookup(self.programs, program);
+        let error = if state.is_none() {
+            Some(GL_INVALID_VALUE)
+        } else {
+            error_if(pname != GL_LINK_STATUS && pname != GL_INFO_LOG_LENGTH &&
+                     pname != GL_ACTIVE_ATTRIBUTES && pname != GL_ACTIVE_UNIFORMS, GL_INVALID_ENUM)
+        };
+        self.record("glGetProgramiv", ~[Int(program as int), Enum(pname)], error);
+
+        match state {
+            Some(state) if pname == GL_LINK_STATUS => {
+                if state.linked { GL_TRUE as GLint } else { GL_FALSE as GLint }
+            }
+            Some(state) if pname == GL_ACTIVE_ATTRIBUTES => state.attributes.len() as GLint,
+            Some(state) if pname == GL_ACTIVE_UNIFORMS => state.uniforms.len() as GLint,
+            _ => 0,
+        }
+    }
+
+    fn get_program_info_log(&self, program: GLuint) -> ~str {
+        self.record("glGetProgramInfoLog", ~[Int(program as int)],
+                    error_if(lookup(self.programs, program).is_none(), GL_INVALID_VALUE));
+        ~""
+    }
+
+    fn use_program(&self, program: GLuint) {
+        let error = if program == 0 {
+            None
+        } else {
+            match lookup(self.programs, program) {
+                None => Some(GL_INVALID_VALUE),
+                Some(state) => error_if(!state.linked, GL_INVALID_OPERATION),
+            }
+        };
+        if self.record_setting("glUseProgram", "glUseProgram", ~[Int(program as int)], error) {
+            self.current_program = program;
+        }
+    }
+
+    fn delete_program(&self, program: GLuint) {
+        self.record("glDeleteProgram", ~[Int(program as int)], None);
+        if lookup(self.programs, program).is_some() {
+            self.programs[program] = None;
+            if self.current_program == program {
+                self.current_program = 0;
+                self.forget_setting("glUseProgram");
+            }
+        }
+    }
+
+    fn emulate_program(&self, _program: GLuint, _emulation: @Emulation) {}
+
+    fn get_active_attrib(&self, program: GLuint, index: GLuint) -> (~str, GLint, GLenum) {
+        let state = lookup(self.programs, program);
+        let error = match state {
+            Some(state) => error_if(index as uint >= state.attributes.len(), GL_INVALID_VALUE),
+            None => Some(GL_INVALID_VALUE),
+        };
+        if !self.record("glGetActiveAttrib", ~[Int(program as int), Int(index as int)], error) {
+            return (~"", 0, 0);
+        }
+        let attribute = &state.get().attributes[index];
+        (copy attribute.name, attribute.size, attribute.kind)
+    }
+
+    fn get_active_uniform(&self, program: GLuint, index: GLuint) -> (~str, GLint, GLenum) {
+        let state = lookup(self.programs, program);
+        let error = match state {
+            Some(state) => error_if(index as uint >= state.uniforms.len(), GL_INVALID_VALUE),
+            None => Some(GL_INVALID_VALUE),
+        };
+        if !self.record("glGetActiveUniform", ~[Int(program as int), Int(index as int)], error) {
+            return (~"", 0, 0);
+        }
+        let uniform = &state.get().uniforms[index];
+        (copy uniform.name, uniform.size, uniform.kind)
+    }
+
+    fn get_attrib_location(&self, program: GLuint, name: &str) -> GLint {
+        let state = lookup(self.programs, program);
+        let error = match state {
+            None => Some(GL_INVALID_VALUE),
+            Some(state) => error_if(!state.linked, GL_INVALID_OPERATION),
+        };
+        if !self.record("glGetAttribLocation", ~[Int(program as int), Text(str::from_slice(name))], error) {
+            return -1;
+        }
+        match vec::position(state.get().attributes, |a| str::eq_slice(a.name, name)) {
+            Some(i) => attribute_locations(state.get().attributes)[i] as GLint,
+            None => -1,
+        }
+    }
+
+    fn get_uniform_location(&self, program: GLuint, name: &str) -> GLint {
+        let state = lookup(self.programs, program);
+        let error = match state {
+            None => Some(GL_INVALID_VALUE),
+            Some(state) => error_if(!state.linked, GL_INVALID_OPERATION),
+        };
+        if !self.record("glGetUniformLocation", ~[Int(program as int), Text(str::from_slice(name))], error) {
+            return -1;
+        }
+        match vec::position(state.get().uniforms, |u| str::eq_slice(u.name, name)) {
+            Some(i) => i as GLint,
+            None => -1,
+        }
+    }
+
+    fn get_uniform_block_index(&self, program: GLuint, name: &str) -> GLuint {
+        let state = lookup(self.programs, program);
+        let error = match state {
+            None => Some(GL_INVALID_VALUE),
+            Some(state) => error_if(!state.linked, GL_INVALID_OPERATION),
+        };
+        if !self.record("glGetUniformBlockIndex", ~[Int(program as int), Text(str::from_slice(name))], error) {
+            return GL_INVALID_INDEX;
+        }
+        match vec::position(state.get().blocks, |b| str::eq_slice(*b, name)) {
+            Some(i) => i as GLuint,
+            None => GL_INVALID_INDEX,
+        }
+    }
+
+    fn uniform_block_binding(&self, program: GLuint, block: GLuint, binding: GLuint) {
+        let error = match lookup(self.programs, program) {
+            None => Some(GL_INVALID_VALUE),
+            Some(state) => error_if(block as uint >= state.blocks.len() ||
+                                    binding as uint >= MAX_UNIFORM_BUFFER_BINDINGS, GL_INVALID_VALUE),
+        };
+        self.record("glUniformBlockBinding", ~[Int(program as int), Int(block as int), Int(binding as int)],
+                    error);
+    }
+
+    fn enable_vertex_attrib_array(&self, index: GLuint) {
+        let error = if index as uint >= MAX_ATTRIBS {
+            Some(GL_INVALID_VALUE)
+        } else {
+            error_if(self.array().is_none(), GL_INVALID_OPERATION)
+        };
+        if self.record("glEnableVertexAttribArray", ~[Int(index as int)], error) {
+            self.array().get().enabled[index] = true;
+        }
+    }
+
+    fn vertex_attrib_pointer(&self, index: GLuint, size: GLint, kind: GLenum,
+                             normalized: GLboolean, stride: GLsizei, offset: uint) {
+        let error = if index as uint >= MAX_ATTRIBS || size < 1 || size > 4 || stride < 0 {
+            Some(GL_INVALID_VALUE)
+        } else if self.array().is_none() || self.array_buffer == 0 {
+            // A core profile has no client-side arrays
+            Some(GL_INVALID_OPERATION)
+        } else {
+            None
+        };
+        self.record("glVertexAttribPointer",
+                    ~[Int(index as int), Int(size as int), Enum(kind), Int(normalized as int),
+                      Int(stride as int), Int(offset as int)], error);
+    }
+
+    fn vertex_attrib_divisor(&self, index: GLuint, divisor: GLuint) {
+        let error = if index as uint >= MAX_ATTRIBS {
+            Some(GL_INVALID_VALUE)
+        } else {
+            error_if(self.array().is_none(), GL_INVALID_OPERATION)
+        };
+        self.record("glVertexAttribDivisor", ~[Int(index as int), Int(divisor as int)], error);
+    }
+
+    fn uniform_1i(&self, location: GLint, x: GLint) {
+        self.record("glUniform1i", ~[Int(location as int), Int(x as int)], self.uniform_error(location));
+    }
+
+    fn uniform_1f(&self, location: GLint, x: GLfloat) {
+        self.record("glUniform1f", ~[Int(location as int), Float(x as float)], self.uniform_error(location));
+    }
+
+    fn uniform_3f(&self, location: GLint, x: GLfloat, y: GLfloat, z: GLfloat) {
+        self.record("glUniform3f", ~[Int(location as int), Float(x as float), Float(y as float),
+                                     Float(z as float)], self.uniform_error(location));
+    }
+
+    fn uniform_matrix_4fv(&self, location: GLint, transpose: GLboolean, value: &[GLfloat]) {
+        let mut args = ~[Int(location as int), Int(transpose as int)];
+        for value.each |x| { args.push(Float(*x as float)); }
+
+        let error = match self.uniform_error(location) {
+            None => error_if(value.len() % 16 != 0, GL_INVALID_VALUE),
+            error => error,
+        };
+        self.record("glUniformMatrix4fv", move args, error);
+    }
+
+    fn gen_texture(&self) -> GLuint {
+        self.textures.push(true);
+        self.images.push(false);
+        let name = (self.textures.len() - 1) as GLuint;
+        self.record("glGenTextures", ~[Int(name as int)], None);
+        name
+    }
+
+    fn active_texture(&self, unit: GLenum) {
+        let error = error_if(unit < GL_TEXTURE0 || unit >= GL_TEXTURE0 + MAX_TEXTURE_UNITS as GLenum,
+                             GL_INVALID_ENUM);
+        if self.record("glActiveTexture", ~[Enum(unit)], error) {
+            self.active_unit = (unit - GL_TEXTURE0) as uint;
+        }
+    }
+
+    fn bind_texture(&self, target: GLenum, texture: GLuint) {
+        let error = if target != GL_TEXTURE_2D {
+            Some(GL_INVALID_ENUM)
+        } else {
+            error_if(!self.is_texture(texture), GL_INVALID_OPERATION)
+        };
+        if self.record("glBindTexture", ~[Enum(target), Int(texture as int)], error) {
+            self.units[self.active_unit] = texture;
+        }
+    }
+
+    fn tex_image_2d(&self, target: GLenum, level: GLint, internal_format: GLint,
+                    width: GLsizei, height: GLsizei, format: GLenum, kind: GLenum,
+                    data: &[u8]) {
+        let error = if target != GL_TEXTURE_2D {
+            Some(GL_INVALID_ENUM)
+        } else {
+            error_if(level < 0 || width < 0 || height < 0, GL_INVALID_VALUE)
+        };
+        let recorded = self.record("glTexImage2D",
+                                   ~[Enum(target), Int(level as int), Enum(internal_format as GLenum),
+                                     Int(width as int), Int(height as int), Enum(format), Enum(kind),
+                                     Data(vec::from_slice(data))], error);
+        if recorded && level == 0 {
+            self.images[self.units[self.active_unit]] = true;
+        }
+    }
+
+    fn tex_parameter_i(&self, target: GLenum, pname: GLenum, param: GLint) {
+        self.record("glTexParameteri", ~[Enum(target), Enum(pname), Enum(param as GLenum)],
+                    error_if(target != GL_TEXTURE_2D, GL_INVALID_ENUM));
+    }
+
+    fn generate_mipmap(&self, target: GLenum) {
+        let error = if target != GL_TEXTURE_2D {
+            Some(GL_INVALID_ENUM)
+        } else {
+            error_if(self.units[self.active_unit] == 0, GL_INVALID_OPERATION)
+        };
+        self.record("glGenerateMipmap", ~[Enum(target)], error);
+    }
+
+    fn pixel_store_i(&self, pname: GLenum, param: GLint) {
+        let error = if pname != GL_PACK_ALIGNMENT && pname != GL_UNPACK_ALIGNMENT {
+            Some(GL_INVALID_ENUM)
+        } else {
+            error_if(param != 1 && param != 2 && param != 4 && param != 8, GL_INVALID_VALUE)
+        };
+        self.record("glPixelStorei", ~[Enum(pname), Int(param as int)], error);
+    }
+
+    fn delete_texture(&self, texture: GLuint) {
+        self.record("glDeleteTextures", ~[Int(texture as int)], None);
+        if texture != 0 && self.is_texture(texture) {
+            self.textures[texture] = false;
+            self.images[texture] = false;
+            for uint::range(0, MAX_TEXTURE_UNITS) |unit| {
+                if self.units[unit] == texture { self.units[unit] = 0; }
+            }
+        }
+    }
+
+    fn gen_sampler(&self) -> GLuint {
+        self.samplers.push(true);
+        let name = (self.samplers.len() - 1) as GLuint;
+        self.record("glGenSamplers", ~[Int(name as int)], None);
+        name
+    }
+
+    fn bind_sampler(&self, unit: GLuint, sampler: GLuint) {
+        let error = if unit as uint >= MAX_TEXTURE_UNITS {
+            Some(GL_INVALID_VALUE)
+        } else {
+            error_if(sampler != 0 && !self.is_sampler(sampler), GL_INVALID_OPERATION)
+        };
+        self.record("glBindSampler", ~[Int(unit as int), Int(sampler as int)], error);
+    }
+
+    fn sampler_parameter_i(&self, sampler: GLuint, pname: GLenum, param: GLint) {
+        let error = if !self.is_sampler(sampler) {
+            Some(GL_INVALID_OPERATION)
+        } else {
+            error_if(!is_sampler_parameter(pname), GL_INVALID_ENUM)
+        };
+        self.record("glSamplerParameteri", ~[Int(sampler as int), Enum(pname), Enum(param as GLenum)],
+                    error);
+    }
+
+    fn sampler_parameter_f(&self, sampler: GLuint, pname: GLenum, param: GLfloat) {
+        let error = if !self.is_sampler(sampler) {
+            Some(GL_INVALID_OPERATION)
+        } else {
+            error_if(!is_sampler_parameter(pname), GL_INVALID_ENUM)
+        };
+        self.record("glSamplerParameterf", ~[Int(sampler as int), Enum(pname), Float(param as float)],
+                    error);
+    }
+
+    fn sampler_parameter_fv(&self, sampler: GLuint, pname: GLenum, params: &[GLfloat]) {
+        let error = if !self.is_sampler(sampler) {
+            Some(GL_INVALID_OPERATION)
+        } else {
+            error_if(!is_sampler_parameter(pname), GL_INVALID_ENUM)
+        };
+        let mut args = ~[Int(sampler as int), Enum(pname)];
+        for params.each |x| { args.push(Float(*x as float)); }
+        self.record("glSamplerParameterfv", move args, error);
+    }
+
+    fn delete_sampler(&self, sampler: GLuint) {
+        self.record("glDeleteSamplers", ~[Int(sampler as int)], None);
+        if self.is_sampler(sampler) {
+            self.samplers[sampler] = false;
+        }
+    }
+
+    fn gen_framebuffer(&self) -> GLuint {
+        self.framebuffers.push(Some(@mut FramebufferState {
+            color: Unattached,
+            depth: Unattached,
+            stencil: Unattached,
+        }));
+        let name = (self.framebuffers.len() - 1) as GLuint;
+        self.record("glGenFramebuffers", ~[Int(name as int)], None);
+        name
+    }
+
+    fn bind_framebuffer(&self, target: GLenum, framebuffer: GLuint) {
+        let error = if !is_framebuffer_target(target) {
+            Some(GL_INVALID_ENUM)
+        } else {
+            error_if(framebuffer != 0 && lookup(self.framebuffers, framebuffer).is_none(), GL_INVALID_OPERATION)
+        };
+        if self.record("glBindFramebuffer", ~[Enum(target), Int(framebuffer as int)], error) {
+            self.bound_framebuffer = framebuffer;
+        }
+    }
+
+    fn framebuffer_texture_2d(&self, target: GLenum, attachment: GLenum, texture_target: GLenum,
+                              texture: GLuint, level: GLint) {
+        let error = if !is_framebuffer_target(target) || !is_attachment(attachment) ||
+                       texture_target != GL_TEXTURE_2D {
+            Some(GL_INVALID_ENUM)
+        } else if self.bound_framebuffer == 0 || (texture != 0 && !self.is_texture(texture)) {
+            Some(GL_INVALID_OPERATION)
+        } else {
+            error_if(level != 0, GL_INVALID_VALUE)
+        };
+        let args = ~[Enum(target), Enum(attachment), Enum(texture_target), Int(texture as int),
+                     Int(level as int)];
+        if self.record("glFramebufferTexture2D", move args, error) {
+            self.attach(attachment, if texture == 0 { Unattached } else { AttachedTexture(texture) });
+        }
+    }
+
+    fn framebuffer_renderbuffer(&self, target: GLenum, attachment: GLenum, renderbuffer_target: GLenum,
+                                renderbuffer: GLuint) {
+        let error = if !is_framebuffer_target(target) || !is_attachment(attachment) ||
+                       renderbuffer_target != GL_RENDERBUFFER {
+            Some(GL_INVALID_ENUM)
+        } else {
+            error_if(self.bound_framebuffer == 0 ||
+                     (renderbuffer != 0 && lookup(self.renderbuffers, renderbuffer).is_none()),
+                     GL_INVALID_OPERATION)
+        };
+        let args = ~[Enum(target), Enum(attachment), Enum(renderbuffer_target), Int(renderbuffer as int)];
+        if self.record("glFramebufferRenderbuffer", move args, error) {
+            let image = if renderbuffer == 0 { Unattached } else { AttachedRenderbuffer(renderbuffer) };
+            self.attach(attachment, image);
+        }
+    }
+
+    fn check_framebuffer_status(&self, target: GLenum) -> GLenum {
+        if !self.record("glCheckFramebufferStatus", ~[Enum(target)],
+                        error_if(!is_framebuffer_target(target), GL_INVALID_ENUM)) {
+            return 0;
+        }
+        match lookup(self.framebuffers, self.bound_framebuffer) {
+            Some(framebuffer) => self.framebuffer_status(framebuffer),
+            None => GL_FRAMEBUFFER_COMPLETE,
+        }
+    }
+
+    fn delete_framebuffer(&self, framebuffer: GLuint) {
+        self.record("glDeleteFramebuffers", ~[Int(framebuffer as int)], None);
+        if lookup(self.framebuffers, framebuffer).is_some() {
+            self.framebuffers[framebuffer] = None;
+            if self.bound_framebuffer == framebuffer { self.bound_framebuffer = 0; }
+        }
+    }
+
+    fn gen_renderbuffer(&self) -> GLuint {
+        self.renderbuffers.push(Some(@mut RenderbufferState { allocated: false }));
+        let name = (self.renderbuffers.len() - 1) as GLuint;
+        self.record("glGenRenderbuffers", ~[Int(name as int)], None);
+        name
+    }
+
+    fn bind_renderbuffer(&self, target: GLenum, renderbuffer: GLuint) {
+        let error = if target != GL_RENDERBUFFER {
+            Some(GL_INVALID_ENUM)
+        } else {
+            error_if(renderbuffer != 0 && lookup(self.renderbuffers, renderbuffer).is_none(),
+                     GL_INVALID_OPERATION)
+        };
+        if self.record("glBindRenderbuffer", ~[Enum(target), Int(renderbuffer as int)], error) {
+            self.bound_renderbuffer = renderbuffer;
+        }
+    }
+
+    fn renderbuffer_storage(&self, target: GLenum, internal_format: GLenum,
+                            width: GLsizei, height: GLsizei) {
+        let error = if target != GL_RENDERBUFFER || !is_renderbuffer_format(internal_format) {
+            Some(GL_INVALID_ENUM)
+        } else if width < 0 || height < 0 {
+            Some(GL_INVALID_VALUE)
+        } else {
+            error_if(self.bound_renderbuffer == 0, GL_INVALID_OPERATION)
+        };
+        let args = ~[Enum(target), Enum(internal_format), Int(width as int), Int(height as int)];
+        if self.record("glRenderbufferStorage", move args, error) {
+            lookup(self.renderbuffers, self.bound_renderbuffer).get().allocated = true;
+        }
+    }
+
+    fn delete_renderbuffer(&self, renderbuffer: GLuint) {
+        self.record("glDeleteRenderbuffers", ~[Int(renderbuffer as int)], None);
+        if lookup(self.renderbuffers, renderbuffer).is_some() {
+            self.renderbuffers[renderbuffer] = None;
+            if self.bound_renderbuffer == renderbuffer { self.bound_renderbuffer = 0; }
+        }
+    }
+
+    fn draw_arrays(&self, mode: GLenum, first: GLint, count: GLsizei) {
+        self.record("glDrawArrays", ~[Enum(mode), Int(first as int), Int(count as int)],
+                    self.draw_arrays_error(mode, first, count, 1));
+    }
+
+    fn draw_elements(&self, mode: GLenum, count: GLsizei, kind: GLenum, offset: uint) {
+        self.record("glDrawElements", ~[Enum(mode), Int(count as int), Enum(kind), Int(offset as int)],
+                    self.draw_elements_error(mode, count, kind, 1));
+    }
+
+    fn draw_arrays_instanced(&self, mode: GLenum, first: GLint, count: GLsizei, instances: GLsizei) {
+        self.record("glDrawArraysInstanced",
+                    ~[Enum(mode), Int(first as int), Int(count as int), Int(instances as int)],
+                    self.draw_arrays_error(mode, first, count, instances));
+    }
+
+    fn draw_elements_instanced(&self, mode: GLenum, count: GLsizei, kind: GLenum, offset: uint,
+                               instances: GLsizei) {
+        self.record("glDrawElementsInstanced",
+                    ~[Enum(mode), Int(count as int), Enum(kind), Int(offset as int), Int(instances as int)],
+                    self.draw_elements_error(mode, count, kind, instances));
+    }
+
+    fn read_pixels(&self, x: GLint, y: GLint, width: GLsizei, height: GLsizei) -> ~[u8] {
+        let error = error_if(width < 0 || height < 0, GL_INVALID_VALUE);
+        self.record("glReadPixels", ~[Int(x as int), Int(y as int), Int(width as int), Int(height as int)],
+                    error);
+        if error.is_some() { return ~[]; }
+        vec::from_elem((width * height * 4) as uint, 0u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glcore::*;
+    use gl::Gl;
+    use mock::*;
+
+    #[test]
+    fn refuses_to_draw_without_a_vertex_array() {
+        let mock = MockGl::new();
+        mock.draw_arrays(GL_TRIANGLES, 0, 3);
+
+        let errors = mock.errors();
+        assert errors.len() == 1;
+        assert errors[0].name == ~"glDrawArrays" && errors[0].error == Some(GL_INVALID_OPERATION);
+    }
+
+    #[test]
+    fn reports_setting_a_capability_to_what_it_was() {
+        let mock = MockGl::new();
+        mock.enable(GL_BLEND);
+        mock.enable(GL_BLEND);
+        mock.disable(GL_BLEND);
+        mock.enable(GL_DEPTH_TEST);
+
+        let redundant = mock.redundant();
+        assert redundant.len() == 1 && redundant[0].to_str() == ~"glEnable(GL_BLEND)";
+    }
+
+    #[test]
+    fn reports_stencil_state_set_to_what_it_was() {
+        let mock = MockGl::new();
+        mock.stencil_func(GL_ALWAYS, 1, 0xff);
+        mock.stencil_op(GL_KEEP, GL_KEEP, GL_REPLACE);
+        mock.stencil_mask(0xff);
+        mock.stencil_func(GL_EQUAL, 1, 0xff);
+        mock.stencil_mask(0x00);
+
+        // The next frame sets the same operations again
+        mock.stencil_func(GL_ALWAYS, 1, 0xff);
+        mock.stencil_op(GL_KEEP, GL_KEEP, GL_REPLACE);
+        mock.stencil_mask(0xff);
+
+        let redundant = mock.redundant();
+        assert redundant.len() == 1 && redundant[0].name == ~"glStencilOp";
+        assert mock.errors().is_empty();
+    }
+}
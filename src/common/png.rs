@@ -0,0 +1,238 @@
+/*!
+ * A minimal PNG encoder and the matching decoder.
+ *
+ * Writes 8-bit RGBA images without compression: the scanlines go into
+ * stored deflate blocks, which every decoder reads and which leaves the
+ * encoder with nothing to do but framing and checksums. The frames the
+ * runner dumps are small enough for the size not to matter.
+ *
+ * The decoder only reads what the encoder writes, which is all the
+ * golden-image tests need. Compressed or filtered files are rejected.
+ */
+
+/// An 8-bit RGBA image, top row first.
+pub struct Image {
+    width: uint,
+    height: uint,
+    rgba: ~[u8],
+}
+
+/// Encodes a `width` by `height` RGBA image, given top row first.
+pub fn encode(width: uint, height: uint, rgba: &[u8]) -> ~[u8] {
+    assert rgba.len() == width * height * 4;
+
+    let mut out = ~[137u8, 80, 78, 71, 13, 10, 26, 10];
+
+    let mut header = ~[];
+    push_u32(&mut header, width as u32);
+    push_u32(&mut header, height as u32);
+    // 8 bits per channel, RGBA, deflate, adaptive filtering, not interlaced
+    header.push_all([8u8, 6, 0, 0, 0]);
+    chunk(&mut out, "IHDR", header);
+
+    // Every scanline starts with its filter type, 0 for none
+    let stride = width * 4;
+    let mut scanlines = vec::with_capacity(height * (stride + 1));
+    for uint::range(0, height) |y| {
+        scanlines.push(0u8);
+        scanlines.push_all(vec::view(rgba, y * stride, (y + 1) * stride));
+    }
+    chunk(&mut out, "IDAT", zlib_stored(scanlines));
+
+    chunk(&mut out, "IEND", []);
+    move out
+}
+
+/// Encodes an image as `encode` does and writes it to `path`.
+pub fn write(path: &Path, width: uint, height: uint, rgba: &[u8]) -> Result<(), ~str> {
+    match io::file_writer(path, [io::Create, io::Truncate]) {
+        Ok(writer) => {
+            writer.write(encode(width, height, rgba));
+            Ok(())
+        }
+        Err(move msg) => Err(fmt!("%s: %s", path.to_str(), msg)),
+    }
+}
+
+/// Decodes a PNG written by `encode`.
+pub fn decode(data: &[u8]) -> Result<Image, ~str> {
+    if data.len() < 8 || vec::view(data, 0, 8) != [137u8, 80, 78, 71, 13, 10, 26, 10] {
+        return Err(~"not a PNG file");
+    }
+
+    let mut width = 0;
+    let mut height = 0;
+    let mut stream = ~[];
+
+    let mut at = 8;
+    loop {
+        if at + 12 > data.len() { return Err(~"truncated chunk"); }
+        let len = read_u32(data, at) as uint;
+        if at + 12 + len > data.len() { return Err(~"truncated chunk"); }
+
+        let kind = str::from_bytes(vec::view(data, at + 4, at + 8));
+        let body = vec::view(data, at + 8, at + 8 + len);
+        if crc32(vec::view(data, at + 4, at + 8 + len)) != read_u32(data, at + 8 + len) {
+            return Err(fmt!("bad CRC in %s chunk", kind));
+        }
+        at += 12 + len;
+
+        if kind == ~"IHDR" {
+            if len != 13 { return Err(~"bad IHDR chunk"); }
+            width = read_u32(body, 0) as uint;
+            height = read_u32(body, 4) as uint;
+            if vec::view(body, 8, 13) != [8u8, 6, 0, 0, 0] {
+                return Err(~"only 8-bit RGBA images without interlacing are supported");
+            }
+        } else if kind == ~"IDAT" {
+            stream.push_all(body);
+        } else if kind == ~"IEND" {
+            break;
+        }
+    }
+
+    let scanlines = match inflate_stored(stream) {
+        Ok(move bytes) => bytes,
+        Err(move msg) => return Err(move msg),
+    };
+
+    let stride = width * 4;
+    if scanlines.len() != height * (stride + 1) {
+        return Err(~"image data does not match the image size");
+    }
+
+    let mut rgba = vec::with_capacity(height * stride);
+    for uint::range(0, height) |y| {
+        let start = y * (stride + 1);
+        if scanlines[start] != 0 {
+            return Err(~"filtered scanlines are not supported");
+        }
+        rgba.push_all(vec::view(scanlines, start + 1, start + 1 + stride));
+    }
+
+    Ok(Image { width: width, height: height, rgba: move rgba })
+}
+
+/// Reads and decodes the PNG at `path`.
+pub fn read(path: &Path) -> Result<Image, ~str> {
+    match io::read_whole_file(path) {
+        Ok(move data) => match decode(data) {
+            Ok(move image) => Ok(move image),
+            Err(move msg) => Err(fmt!("%s: %s", path.to_str(), msg)),
+        },
+        Err(move msg) => Err(fmt!("%s: %s", path.to_str(), msg)),
+    }
+}
+
+fn read_u32(data: &[u8], at: uint) -> u32 {
+    (data[at] as u32 << 24) | (data[at + 1] as u32 << 16) |
+        (data[at + 2] as u32 << 8) | data[at + 3] as u32
+}
+
+fn push_u32(out: &mut ~[u8], x: u32) {
+    out.push((x >> 24) as u8);
+    out.push((x >> 16) as u8);
+    out.push((x >> 8) as u8);
+    out.push(x as u8);
+}
+
+/// Appends a chunk: length, type, data and the CRC of type and data.
+fn chunk(out: &mut ~[u8], kind: &str, data: &[u8]) {
+    push_u32(out, data.len() as u32);
+
+    let start = out.len();
+    out.push_all(str::to_bytes(kind));
+    out.push_all(data);
+
+    let crc = crc32(vec::view(*out, start, out.len()));
+    push_u32(out, crc);
+}
+
+/// Wraps `data` in a zlib stream made of stored deflate blocks.
+fn zlib_stored(data: &[u8]) -> ~[u8] {
+    // Deflate with a 32K window and no preset dictionary; the second byte
+    // makes the header a multiple of 31
+    let mut out = ~[0x78u8, 0x01];
+
+    let mut start = 0;
+    loop {
+        let len = uint::min(data.len() - start, 65535);
+        let last = start + len == data.len();
+
+        out.push(if last { 1 } else { 0 });
+        out.push(len as u8);
+        out.push((len >> 8) as u8);
+        out.push(!len as u8);
+        out.push((!len >> 8) as u8);
+        out.push_all(vec::view(data, start, start + len));
+
+        start += len;
+        if last { break; }
+    }
+
+    push_u32(&mut out, adler32(data));
+    move out
+}
+
+/// Unwraps a zlib stream made of stored deflate blocks only.
+fn inflate_stored(stream: &[u8]) -> Result<~[u8], ~str> {
+    if stream.len() < 6 || stream[0] & 0x0f != 8 || stream[1] & 0x20 != 0 {
+        return Err(~"bad zlib header");
+    }
+
+    let mut out = ~[];
+    let mut at = 2;
+    loop {
+        if at + 5 > stream.len() { return Err(~"truncated deflate block"); }
+
+        // A stored block's three header bits are padded to a whole byte
+        let header = stream[at];
+        if (header >> 1) & 3 != 0 {
+            return Err(~"compressed image data is not supported");
+        }
+        let len = stream[at + 1] as uint | (stream[at + 2] as uint << 8);
+        let nlen = stream[at + 3] as uint | (stream[at + 4] as uint << 8);
+        if len != !nlen & 0xffff || at + 5 + len > stream.len() {
+            return Err(~"bad stored deflate block");
+        }
+
+        out.push_all(vec::view(stream, at + 5, at + 5 + len));
+        at += 5 + len;
+
+        if header & 1 != 0 { break; }
+    }
+
+    if at + 4 > stream.len() || read_u32(stream, at) != adler32(out) {
+        return Err(~"bad zlib checksum");
+    }
+    Ok(move out)
+}
+
+/// The CRC-32 PNG uses for chunks (ISO 3309, reflected, polynomial 0xedb88320).
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut table = vec::with_capacity(256);
+    for uint::range(0, 256) |n| {
+        let mut c = n as u32;
+        for 8.times {
+            c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+        }
+        table.push(c);
+    }
+
+    let mut crc = 0xffffffffu32;
+    for data.each |b| {
+        crc = table[((crc ^ *b as u32) & 0xff) as uint] ^ (crc >> 8);
+    }
+    crc ^ 0xffffffff
+}
+
+/// The Adler-32 checksum ending a zlib stream.
+pub fn adler32(data: &[u8]) -> u32 {
+    let mut a = 1u32;
+    let mut b = 0u32;
+    for data.each |x| {
+        a = (a + *x as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
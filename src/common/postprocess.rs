@@ -0,0 +1,358 @@
+/*!
+ * A chain of fullscreen post-processing passes.
+ *
+ * The scene is drawn into a framebuffer between `begin` and `finish`.
+ * `finish` then runs the passes that are enabled, in the order they were
+ * added: each draws a quad over the whole screen with a fragment shader
+ * reading what the pass before it drew, from texture unit 0 through the
+ * sampler uniform `texFramebuffer`. Two framebuffers take turns being read
+ * and drawn into, and the last pass draws into the window. With no pass
+ * enabled, the scene is copied to the window as it is.
+ *
+ * Every pass shares `shaders/post.vert`, which passes the texture
+ * coordinates of the quad on as `Texcoord`, and is reloaded when its files
+ * are edited.
+ */
+
+use glcore::*;
+
+use buffer::{VertexArray, Buffer};
+use context::Context;
+use framebuffer::{Framebuffer, FramebufferError};
+use sampler::{Sampler, SamplerDesc, ClampToEdge, NearestFilter};
+use shader::{VertexShader, FragmentShader};
+use soft::{Emulation, FragmentShaderFn, VertexOut};
+use state::RenderState;
+use uniform::{Uniform, Sampler2D};
+use vertex::{ScreenVertex, bind_attributes};
+use watch::{WatchedProgram, ProgramError};
+
+/// Why a chain could not be set up.
+pub enum PostProcessError {
+    /// The program of the named pass could not be built.
+    PassFailed(~str, ProgramError),
+    /// A framebuffer to draw into could not be created.
+    TargetFailed(FramebufferError),
+}
+
+impl PostProcessError : ToStr {
+    pure fn to_str(&self) -> ~str {
+        match *self {
+            PassFailed(ref name, ref err) => fmt!("post-processing pass %s: %s", *name, err.to_str()),
+            TargetFailed(ref err) => err.to_str(),
+        }
+    }
+}
+
+/// The stand-ins for `post.vert` and the fragment shader `fragment`.
+fn emulation(fragment: FragmentShaderFn) -> Emulation {
+    Emulation {
+        vertex: |_uniforms, inputs| {
+            let position = inputs.get("position");
+            VertexOut {
+                position: [position[0], position[1], 0.0, 1.0],
+                varyings: vec::slice(inputs.get("texcoord"), 0, 2)
+            }
+        },
+        geometry: None,
+        fragment: fragment,
+    }
+}
+
+/// One fullscreen pass.
+struct Pass {
+    name: ~str,
+    program: WatchedProgram,
+    /// The quad, laid out for this pass's program.
+    vao: VertexArray,
+    mut enabled: bool,
+}
+
+/// The two framebuffers a chain draws into, `width` by `height`.
+fn create_targets(ctx: &Context, width: uint, height: uint) -> Result<~[Framebuffer], FramebufferError> {
+    let mut targets = ~[];
+    for uint::range(0, 2) |_| {
+        match Framebuffer::new(ctx, width, height) {
+            Ok(move framebuffer) => targets.push(move framebuffer),
+            Err(move err) => return Err(move err),
+        }
+    }
+    Ok(move targets)
+}
+
+/// Builds the pass `name`, drawing `quad` with the fragment shader in the file `fragment`.
+fn load_pass(ctx: &Context, quad: &Buffer<ScreenVertex>, name: &str, fragment: &str,
+             shade: FragmentShaderFn) -> Result<Pass, PostProcessError> {
+    let shaders = [(VertexShader, "shaders/post.vert"), (FragmentShader, fragment)];
+    let program = match WatchedProgram::load(ctx, shaders, ["outColor"]) {
+        Ok(move program) => program,
+        Err(move err) => return Err(PassFailed(str::from_slice(name), move err)),
+    };
+    program.emulate(emulation(shade));
+
+    let pass = Pass { name: str::from_slice(name), program: move program, vao: VertexArray::new(ctx),
+                      enabled: false };
+    resolve_pass(&pass, quad);
+    Ok(move pass)
+}
+
+/**
+ * Points the quad at the attributes of the pass's current program and
+ * tells it which unit to read. Called again whenever the program is
+ * rebuilt, as they may have moved.
+ */
+fn resolve_pass(pass: &Pass, quad: &Buffer<ScreenVertex>) {
+    pass.vao.bind();
+    quad.bind();
+    pass.program.bind();
+    do pass.program.with_program |program| {
+        // Only the layout of the vertex matters, not its values
+        bind_attributes(program, &ScreenVertex { position: [0.0, 0.0], texcoord: [0.0, 0.0] });
+
+        let tex_framebuffer: Uniform<Sampler2D> = Uniform::lookup(program, "texFramebuffer");
+        tex_framebuffer.set(&Sampler2D { unit: 0 });
+    }
+}
+
+/// A chain of passes and the framebuffers they draw into.
+pub struct PostProcess {
+    priv ctx: Context,
+    priv quad: Buffer<ScreenVertex>,
+    priv sampler: Sampler,
+    /// Draws the scene to the window when no pass is enabled.
+    priv copy: Pass,
+    priv passes: ~[Pass],
+    /// The scene is drawn into the first; passes read one and draw into the other.
+    priv mut targets: ~[Framebuffer],
+}
+
+impl PostProcess {
+    /// A chain with no passes, drawing into framebuffers the size of the window.
+    static fn new(ctx: &Context) -> Result<PostProcess, PostProcessError> {
+        // Two triangles covering the screen
+        let vertices: [ScreenVertex * 6] = [
+            ScreenVertex { position: [-1.0,  1.0], texcoord: [0.0, 1.0] },
+            ScreenVertex { position: [ 1.0,  1.0], texcoord: [1.0, 1.0] },
+            ScreenVertex { position: [ 1.0, -1.0], texcoord: [1.0, 0.0] },
+            ScreenVertex { position: [ 1.0, -1.0], texcoord: [1.0, 0.0] },
+            ScreenVertex { position: [-1.0, -1.0], texcoord: [0.0, 0.0] },
+            ScreenVertex { position: [-1.0,  1.0], texcoord: [0.0, 1.0] }
+        ];
+        let quad = Buffer::new(ctx, GL_ARRAY_BUFFER, vertices, GL_STATIC_DRAW);
+
+        let copy_texel: FragmentShaderFn = |uniforms, varyings| {
+            uniforms.texture("texFramebuffer", varyings[0], varyings[1])
+        };
+        let copy = match load_pass(ctx, &quad, "copy", "shaders/post_copy.frag", copy_texel) {
+            Ok(move pass) => pass,
+            Err(move err) => return Err(move err),
+        };
+
+        let (width, height) = ctx.framebuffer_size();
+        let targets = match create_targets(ctx, width, height) {
+            Ok(move targets) => targets,
+            Err(move err) => return Err(TargetFailed(move err)),
+        };
+
+        // Every texel of the framebuffers covers one pixel of the screen
+        let sampler = Sampler::new(ctx, &SamplerDesc {
+            wrap_s: ClampToEdge,
+            wrap_t: ClampToEdge,
+            wrap_r: ClampToEdge,
+            min_filter: NearestFilter,
+            mipmap_filter: None,
+            mag_filter: NearestFilter,
+            .. SamplerDesc::default()
+        });
+
+        Ok(PostProcess {
+            ctx: ctx.acquire(),
+            quad: move quad,
+            sampler: move sampler,
+            copy: move copy,
+            passes: ~[],
+            targets: move targets,
+        })
+    }
+
+    /**
+     * Adds a disabled pass named `name` after the others, running the
+     * fragment shader in the file `fragment`, with `shade` standing in for
+     * it on the software renderer. Its varyings are the `Texcoord` s and t.
+     */
+    fn add_pass(&mut self, name: &str, fragment: &str,
+                shade: FragmentShaderFn) -> Result<(), PostProcessError> {
+        match load_pass(&self.ctx, &self.quad, name, fragment, shade) {
+            Ok(move pass) => {
+                self.passes.push(move pass);
+                Ok(())
+            }
+            Err(move err) => Err(move err),
+        }
+    }
+
+    /// The number of passes added.
+    fn len(&self) -> uint {
+        self.passes.len()
+    }
+
+    /// Turns pass `index`, counting from 0 in the order they were added,
+    /// on if it is off and off if it is on.
+    fn toggle(&self, index: uint) {
+        if index < self.passes.len() {
+            self.passes[index].enabled = !self.passes[index].enabled;
+        }
+    }
+
+    /// Turns every pass off.
+    fn disable_all(&self) {
+        for self.passes.each |pass| {
+            pass.enabled = false;
+        }
+    }
+
+    /// The names of the passes that are on, in the order they run.
+    fn enabled(&self) -> ~[~str] {
+        let mut names = ~[];
+        for self.passes.each |pass| {
+            if pass.enabled { names.push(copy pass.name); }
+        }
+        move names
+    }
+
+    /**
+     * Advances the clock by `dt` seconds, rebuilding the programs of any
+     * passes whose files were edited.
+     */
+    fn poll(&self, dt: float) {
+        if self.copy.program.poll(dt) {
+            resolve_pass(&self.copy, &self.quad);
+        }
+        for self.passes.each |pass| {
+            if pass.program.poll(dt) {
+                resolve_pass(pass, &self.quad);
+            }
+        }
+    }
+
+    /**
+     * Binds the framebuffer the scene is drawn into, first replacing the
+     * framebuffers if the window has been resized since they were created.
+     */
+    fn begin(&self) {
+        let (width, height) = self.ctx.framebuffer_size();
+        if self.targets[0].width != width || self.targets[0].height != height {
+            match create_targets(&self.ctx, width, height) {
+                Ok(move targets) => self.targets = move targets,
+                Err(move err) => io::println(~"Warning: " + err.to_str()),
+            }
+        }
+        self.targets[0].bind();
+    }
+
+    /**
+     * Runs the passes that are enabled over what was drawn since `begin`,
+     * the last of them into the window. The render state is left as
+     * `RenderState::default()` with the stencil test disabled, the
+     * window's framebuffer bound, and texture unit 0 active with the last
+     * framebuffer read and the chain's sampler bound.
+     */
+    fn finish(&self) {
+        // The quads cover everything, whatever the scene left in the depth
+        // and stencil buffers of the framebuffer drawn into, and replace
+        // what is under them
+        let cache = self.ctx.cache();
+        cache.apply(&RenderState::default());
+        cache.disable(GL_STENCIL_TEST);
+
+        let mut enabled = ~[];
+        for vec::eachi(self.passes) |i, pass| {
+            if pass.enabled { enabled.push(i); }
+        }
+
+        if enabled.is_empty() {
+            self.draw(&self.copy, 0, None);
+        }
+        for vec::eachi(enabled) |n, i| {
+            let target = if n + 1 == enabled.len() { None } else { Some((n + 1) % 2) };
+            self.draw(&self.passes[*i], n % 2, target);
+        }
+    }
+
+    /// Runs `pass` over framebuffer `source`, drawing into framebuffer
+    /// `target`, or the window if `None`.
+    priv fn draw(&self, pass: &Pass, source: uint, target: Option<uint>) {
+        match target {
+            Some(target) => self.targets[target].bind(),
+            None => Framebuffer::bind_default(&self.ctx),
+        }
+
+        self.targets[source].bind_color_to(0);
+        self.sampler.bind_to(0);
+        pass.program.bind();
+        pass.vao.bind();
+        self.ctx.gl().draw_arrays(GL_TRIANGLES, 0, 6);
+    }
+}
+
+impl PostProcess : Drop {
+    fn finalize(&self) {
+        self.ctx.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glcore::*;
+    use context::Context;
+    use gl::Gl;
+    use mock::{MockGl, Call, Int};
+    use postprocess::*;
+
+    fn int_arg(call: &Call, i: uint) -> int {
+        match call.args[i] { Int(x) => x, _ => fail(fmt!("argument %u of %s is not an int", i, call.name)) }
+    }
+
+    /// The passes read and draw into the two framebuffers in turn, starting
+    /// from the one the scene is drawn into, and the last draws into the
+    /// window. Run from `src`, so the shaders are found.
+    #[test]
+    fn alternates_between_the_framebuffers() {
+        let mock = @MockGl::new();
+        let ctx = Context::new(mock as @Gl, 800, 600);
+        {
+            let mut post = match PostProcess::new(&ctx) {
+                Ok(move post) => post,
+                Err(move err) => fail(err.to_str()),
+            };
+            for ["invert", "grayscale", "blur"].each |name| {
+                let path = fmt!("shaders/post_%s.frag", *name);
+                assert post.add_pass(*name, path, |_uniforms, _varyings| [0.0, 0.0, 0.0, 1.0]).is_ok();
+            }
+
+            // The framebuffers, in the order they were created, and their color textures
+            let attached = mock.calls_to("glFramebufferTexture2D").map(|c| (c.state.framebuffer, int_arg(c, 3)));
+            assert attached.len() == 2;
+            let framebuffers = attached.map(|a| a.first());
+            let textures = attached.map(|a| a.second() as GLuint);
+
+            for uint::range(1, 4) |count| {
+                post.disable_all();
+                for uint::range(0, count) |i| { post.toggle(i); }
+                post.begin();
+                mock.clear_trace();
+                post.finish();
+
+                let draws = mock.calls_to("glDrawArrays");
+                assert draws.len() == count;
+                for vec::eachi(draws) |n, draw| {
+                    assert draw.state.texture_2d == textures[n % 2];
+                    let target = if n + 1 == count { 0 } else { framebuffers[(n + 1) % 2] };
+                    assert draw.state.framebuffer == target;
+                }
+            }
+        }
+        ctx.destroy();
+        assert mock.errors().is_empty();
+    }
+}
@@ -0,0 +1,383 @@
+/*!
+ * A small GLSL preprocessor, run ahead of the driver's.
+ *
+ * It resolves `#include "file"` directives, adds the `#define`s supplied
+ * from Rust right after the `#version` line, which has to stay first, and
+ * writes a `#line` directive wherever the text switches files, so the
+ * driver reports errors against the lines of the files as written. Each
+ * included file gets a source string number of its own, which `Source`
+ * maps back to the file's path. Everything else, `#ifdef` included, is
+ * left to the driver.
+ *
+ * A file is only included once per shader; later `#include`s of it are
+ * dropped. Files are read through a function passed in, so the text need
+ * not be on disk.
+ */
+
+use shader::{Source, Origin};
+
+/// Why a shader could not be preprocessed.
+pub enum PreprocessError {
+    /// The file at the path could not be read, for the reason given.
+    ReadFailed(~str, ~str),
+    /// The file and line of an `#include` of the file named last, which is
+    /// in none of the directories searched.
+    IncludeNotFound(~str, uint, ~str),
+    /// The file and line of an `#include` not followed by a quoted name.
+    BadInclude(~str, uint),
+    /// A chain of files including each other that leads back to its start.
+    IncludeCycle(~[~str]),
+}
+
+impl PreprocessError : ToStr {
+    pure fn to_str(&self) -> ~str {
+        match *self {
+            ReadFailed(ref path, ref msg) => fmt!("failed to read %s: %s", *path, *msg),
+            IncludeNotFound(ref file, line, ref name) => {
+                fmt!("%s:%u: cannot find \"%s\" to include", *file, line, *name)
+            }
+            BadInclude(ref file, line) => {
+                fmt!("%s:%u: expected a quoted file name after #include", *file, line)
+            }
+            IncludeCycle(ref files) => fmt!("include cycle: %s", str::connect(*files, " -> ")),
+        }
+    }
+}
+
+/// Reads the file at a path, or says why it could not.
+pub type ReadFn = fn(&Path) -> Result<~str, ~str>;
+
+/// Reads files from disk.
+pub fn read_file(path: &Path) -> Result<~str, ~str> {
+    io::read_whole_file_str(path)
+}
+
+/// Whether `line` is the preprocessor directive `name`, as in `# include`.
+pub pure fn is_directive(line: &str, name: &str) -> bool {
+    let line = str::trim_left(line);
+    if !str::starts_with(line, "#") { return false; }
+    let rest = str::trim_left(str::slice(line, 1, line.len()));
+    str::starts_with(rest, name) &&
+        (rest.len() == name.len() || char::is_whitespace(rest[name.len()] as char))
+}
+
+/// The file named by an `#include` directive, in quotes or angle brackets.
+pub pure fn include_name(line: &str) -> Option<~str> {
+    let line = str::trim(line);
+    let rest = match str::find_str(line, "include") {
+        Some(i) => str::trim_left(str::slice(line, i + 7, line.len())),
+        None => return None,
+    };
+    if rest.len() < 2 { return None; }
+
+    let close = match rest[0] as char {
+        '"' => '"',
+        '<' => '>',
+        _ => return None,
+    };
+    match str::find_char_from(rest, close, 1) {
+        Some(end) if end > 1 => Some(str::slice(rest, 1, end)),
+        _ => None,
+    }
+}
+
+/// The version a `#version` directive asks for, 110 if it names none.
+pub pure fn parse_version(line: &str) -> uint {
+    let line = str::trim(line);
+    let rest = str::trim_left(str::slice(line, str::find_str(line, "version").get() + 7, line.len()));
+    let digits = str::slice(rest, 0, str::find(rest, |c| !char::is_digit(c)).get_default(rest.len()));
+    uint::from_str(digits).get_default(110)
+}
+
+/**
+ * The `#line` directive after which the next line is line `line` of
+ * source string `string`. Up to GLSL 1.50 the number given is that of the
+ * directive itself; from 3.30 on it is that of the line after it.
+ */
+pub pure fn line_directive(version: uint, line: uint, string: uint) -> ~str {
+    let n = if version < 330 { line - 1 } else { line };
+    fmt!("#line %u %u", n, string)
+}
+
+/// The preprocessed text as it is built up.
+struct Output {
+    version: uint,
+    lines: ~[~str],
+    origins: ~[Origin],
+    /// Every file read, in the order of their source string numbers.
+    files: ~[~str],
+    /// The files whose contents have been included in full.
+    done: ~[~str],
+}
+
+impl Output {
+    fn push(&mut self, text: ~str, string: uint, line: uint) {
+        self.lines.push(move text);
+        self.origins.push(Origin { string: string, line: line });
+    }
+
+    /// Continues with line `line` of source string `string`.
+    fn resume(&mut self, line: uint, string: uint) {
+        let directive = line_directive(self.version, line, string);
+        self.push(move directive, string, 0);
+    }
+}
+
+/// How to preprocess shaders: where to look for includes and what to define.
+pub struct Preprocessor {
+    /// Where included files are looked for, after the directory of the
+    /// file including them.
+    search_path: ~[Path],
+    /// Each pair is added as `#define name value`.
+    defines: ~[(~str, ~str)],
+}
+
+impl Preprocessor {
+    /// Looks for includes next to the including file only, and defines nothing.
+    static fn new() -> Preprocessor {
+        Preprocessor { search_path: ~[], defines: ~[] }
+    }
+
+    /// Also looks for included files in `dir`, after the directories added before.
+    fn add_search_dir(&mut self, dir: &Path) {
+        self.search_path.push(copy *dir);
+    }
+
+    /// Defines `name` as `value`, which may be empty, in every shader.
+    fn define(&mut self, name: &str, value: &str) {
+        self.defines.push((str::from_slice(name), str::from_slice(value)));
+    }
+
+    /// Preprocesses the file at `path`, reading it from disk.
+    fn process_file(&self, path: &Path) -> Result<Source, PreprocessError> {
+        self.process(path, read_file)
+    }
+
+    /// Preprocesses the file at `path`, reading it and its includes with `read`.
+    fn process(&self, path: &Path, read: ReadFn) -> Result<Source, PreprocessError> {
+        let text = match read(path) {
+            Ok(move text) => text,
+            Err(move msg) => return Err(ReadFailed(path.to_str(), move msg)),
+        };
+        let lines = str::lines_any(text);
+        let mut out = Output {
+            version: 110,
+            lines: ~[],
+            origins: ~[],
+            files: ~[path.to_str()],
+            done: ~[],
+        };
+
+        // Nothing but blank lines may come before #version
+        let mut start = 0;
+        while start < lines.len() && str::is_whitespace(lines[start]) {
+            start += 1;
+        }
+        if start < lines.len() && is_directive(lines[start], "version") {
+            out.version = parse_version(lines[start]);
+            out.push(copy lines[start], 0, start + 1);
+            start += 1;
+        } else {
+            start = 0;
+        }
+
+        for self.defines.each |define| {
+            let (name, value) = copy *define;
+            out.push(fmt!("#define %s %s", name, value), 0, 0);
+        }
+        if !out.lines.is_empty() {
+            out.resume(start + 1, 0);
+        }
+
+        match self.expand(&mut out, lines, start, 0, path, ~[path.to_str()], read) {
+            Ok(()) => (),
+            Err(move err) => return Err(move err),
+        }
+
+        Ok(Source {
+            text: str::connect(out.lines, "\n") + ~"\n",
+            file: Some(path.to_str()),
+            line: 0,
+            includes: vec::tail(out.files),
+            origins: move out.origins,
+        })
+    }
+
+    /**
+     * Appends `lines` from index `start` on, the contents of the file at
+     * `path` with source string number `string`, replacing `#include`s
+     * with the files they name. `stack` lists the files being included,
+     * outermost first, ending with this one.
+     */
+    priv fn expand(&self, out: &mut Output, lines: &[~str], start: uint, string: uint,
+                   path: &Path, stack: ~[~str], read: ReadFn) -> Result<(), PreprocessError> {
+        for uint::range(start, lines.len()) |i| {
+            if !is_directive(lines[i], "include") {
+                out.push(copy lines[i], string, i + 1);
+                loop;
+            }
+
+            let name = match include_name(lines[i]) {
+                Some(move name) => name,
+                None => return Err(BadInclude(path.to_str(), i + 1)),
+            };
+            let (included, text) = match self.find(path, name, read) {
+                Some(move found) => found,
+                None => return Err(IncludeNotFound(path.to_str(), i + 1, move name)),
+            };
+            let key = included.to_str();
+
+            if vec::contains(stack, &key) {
+                return Err(IncludeCycle(stack + ~[move key]));
+            }
+            if vec::contains(out.done, &key) {
+                // Keep the line, so the lines after it need no #line
+                out.push(~"", string, i + 1);
+                loop;
+            }
+
+            let number = out.files.len();
+            out.files.push(copy key);
+            out.resume(1, number);
+            match self.expand(out, str::lines_any(text), 0, number, &included,
+                              stack + ~[copy key], read) {
+                Ok(()) => (),
+                Err(move err) => return Err(move err),
+            }
+            out.done.push(move key);
+            out.resume(i + 2, string);
+        }
+        Ok(())
+    }
+
+    /// The path and contents of the file `name` included from the file at `from`.
+    priv fn find(&self, from: &Path, name: &str, read: ReadFn) -> Option<(Path, ~str)> {
+        let mut dirs = ~[from.dir_path()];
+        dirs.push_all(self.search_path);
+
+        for dirs.each |dir| {
+            let path = dir.push_rel(&Path(name)).normalize();
+            match read(&path) {
+                Ok(move text) => return Some((move path, move text)),
+                Err(_) => (),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shader::{Source, Origin};
+    use preprocess::*;
+
+    /// The files the tests include from, in place of the disk.
+    fn read_test_file(path: &Path) -> Result<~str, ~str> {
+        let path = path.to_str();
+        let files = [
+            ("shaders/main.frag",
+             "#version 150\n#include \"common.glsl\"\n#include \"common.glsl\"\nvoid main() {}\n"),
+            ("shaders/common.glsl", "uniform float x;\n"),
+            ("shaders/plain.frag", "void main() {}\n"),
+            ("shaders/a.glsl", "#include \"b.glsl\"\n"),
+            ("shaders/b.glsl", "float b;\n#include \"a.glsl\"\n"),
+            ("shaders/lost.frag", "#version 330\n#include \"nowhere.glsl\"\n"),
+            ("shaders/search.frag", "#version 330\n#include \"extra.glsl\"\nvoid main() {}\n"),
+            ("lib/extra.glsl", "float extra;\n"),
+        ];
+        for vec::each(files) |file| {
+            let (name, text) = *file;
+            if str::eq_slice(path, name) {
+                return Ok(str::from_slice(text));
+            }
+        }
+        Err(~"no such file")
+    }
+
+    fn process(preprocessor: &Preprocessor, path: &str) -> Source {
+        match preprocessor.process(&Path(path), read_test_file) {
+            Ok(move source) => source,
+            Err(move err) => fail(err.to_str()),
+        }
+    }
+
+    fn is_origin(origin: &Origin, string: uint, line: uint) -> bool {
+        origin.string == string && origin.line == line
+    }
+
+    #[test]
+    fn includes_files_once() {
+        let source = process(&Preprocessor::new(), "shaders/main.frag");
+        // The second #include is kept as a blank line
+        assert source.text == ~"#version 150\n#line 1 0\n#line 0 1\nuniform float x;\n#line 2 0\n\n\
+                                void main() {}\n";
+        assert source.includes == ~[~"shaders/common.glsl"];
+    }
+
+    #[test]
+    fn looks_in_the_search_path_after_the_including_directory() {
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.add_search_dir(&Path("lib"));
+        let source = process(&preprocessor, "shaders/search.frag");
+        assert source.text == ~"#version 330\n#line 2 0\n#line 1 1\nfloat extra;\n#line 3 0\nvoid main() {}\n";
+        assert source.includes == ~[~"lib/extra.glsl"];
+    }
+
+    #[test]
+    fn defines_after_the_version() {
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.define("MIX_FACTOR", "0.5");
+        preprocessor.define("FLAT", "");
+
+        let source = process(&preprocessor, "shaders/main.frag");
+        assert str::starts_with(source.text, "#version 150\n#define MIX_FACTOR 0.5\n#define FLAT \n#line 1 0\n");
+
+        // Without a #version, the defines come first
+        let source = process(&preprocessor, "shaders/plain.frag");
+        assert source.text == ~"#define MIX_FACTOR 0.5\n#define FLAT \n#line 0 0\nvoid main() {}\n";
+    }
+
+    #[test]
+    fn maps_lines_back_to_their_files() {
+        let source = process(&Preprocessor::new(), "shaders/main.frag");
+        // Where each line of the text came from; directives come from nowhere
+        assert source.origins.len() == 7;
+        assert is_origin(&source.origins[0], 0, 1);
+        assert is_origin(&source.origins[3], 1, 1);
+        assert is_origin(&source.origins[5], 0, 3);
+        assert is_origin(&source.origins[6], 0, 4);
+
+        // And what the driver reports against them
+        assert source.location(1, 1) == ~"shaders/common.glsl:1";
+        assert source.location(0, 4) == ~"shaders/main.frag:4";
+        assert source.line_text(1, 1) == Some(~"uniform float x;");
+        assert source.line_text(0, 4) == Some(~"void main() {}");
+    }
+
+    #[test]
+    fn numbers_line_directives_by_version() {
+        // Up to 1.50 the directive names its own line, then the next one
+        assert line_directive(150, 3, 0) == ~"#line 2 0";
+        assert line_directive(330, 3, 2) == ~"#line 3 2";
+    }
+
+    #[test]
+    fn reports_include_cycles() {
+        match Preprocessor::new().process(&Path("shaders/a.glsl"), read_test_file) {
+            Err(IncludeCycle(ref files)) => {
+                assert *files == ~[~"shaders/a.glsl", ~"shaders/b.glsl", ~"shaders/a.glsl"];
+            }
+            _ => fail(~"expected an include cycle"),
+        }
+    }
+
+    #[test]
+    fn reports_missing_includes() {
+        match Preprocessor::new().process(&Path("shaders/lost.frag"), read_test_file) {
+            Err(move err) => {
+                assert err.to_str() == ~"shaders/lost.frag:2: cannot find \"nowhere.glsl\" to include";
+            }
+            Ok(_) => fail(~"expected a missing include"),
+        }
+    }
+}
@@ -0,0 +1,160 @@
+/*!
+ * Sampler objects.
+ *
+ * A sampler holds the filtering and wrapping state otherwise set on each
+ * texture with `glTexParameteri`. Bound to a texture unit, it overrides the
+ * parameters of whatever texture is bound there, so one description can be
+ * shared by any number of textures. Sampler objects are core from GL 3.3,
+ * the version the runner asks for.
+ */
+
+use glcore::*;
+
+use context::Context;
+
+/// From `EXT_texture_filter_anisotropic`, which `glcore` does not cover.
+pub const GL_TEXTURE_MAX_ANISOTROPY_EXT: GLenum = 0x84FE;
+
+/// What happens to texture coordinates outside [0, 1].
+pub enum Wrap {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+    ClampToBorder,
+}
+
+impl Wrap {
+    pure fn to_gl(&self) -> GLenum {
+        match *self {
+            Repeat => GL_REPEAT,
+            MirroredRepeat => GL_MIRRORED_REPEAT,
+            ClampToEdge => GL_CLAMP_TO_EDGE,
+            ClampToBorder => GL_CLAMP_TO_BORDER,
+        }
+    }
+}
+
+/// How texels, or mipmap levels, are combined.
+pub enum Filter {
+    NearestFilter,
+    LinearFilter,
+}
+
+/// How a texture is sampled.
+pub struct SamplerDesc {
+    wrap_s: Wrap,
+    wrap_t: Wrap,
+    wrap_r: Wrap,
+    /// The filter within a level when the texture is minified.
+    min_filter: Filter,
+    /// The filter between mipmap levels, or `None` to only sample the base
+    /// level. `Some(LinearFilter)` with a linear `min_filter` is trilinear
+    /// filtering.
+    mipmap_filter: Option<Filter>,
+    mag_filter: Filter,
+    /// Anything above 1 needs `EXT_texture_filter_anisotropic`.
+    max_anisotropy: f32,
+    /// The color sampled outside the texture with `ClampToBorder`.
+    border_color: [f32 * 4],
+    /// Added to the level of detail before a mipmap level is picked.
+    lod_bias: f32,
+}
+
+impl SamplerDesc {
+    /// The state of a texture no parameters were set on.
+    static fn default() -> SamplerDesc {
+        SamplerDesc {
+            wrap_s: Repeat,
+            wrap_t: Repeat,
+            wrap_r: Repeat,
+            min_filter: NearestFilter,
+            mipmap_filter: Some(LinearFilter),
+            mag_filter: LinearFilter,
+            max_anisotropy: 1.0,
+            border_color: [0.0, 0.0, 0.0, 0.0],
+            lod_bias: 0.0,
+        }
+    }
+
+    /// Trilinear filtering with every coordinate wrapped as `wrap`.
+    static fn trilinear(wrap: Wrap) -> SamplerDesc {
+        SamplerDesc {
+            wrap_s: wrap,
+            wrap_t: wrap,
+            wrap_r: wrap,
+            min_filter: LinearFilter,
+            mipmap_filter: Some(LinearFilter),
+            mag_filter: LinearFilter,
+            .. SamplerDesc::default()
+        }
+    }
+
+    /// The `GL_TEXTURE_MIN_FILTER` value for `min_filter` and `mipmap_filter`.
+    pure fn gl_min_filter(&self) -> GLenum {
+        match (self.min_filter, self.mipmap_filter) {
+            (NearestFilter, None) => GL_NEAREST,
+            (LinearFilter, None) => GL_LINEAR,
+            (NearestFilter, Some(NearestFilter)) => GL_NEAREST_MIPMAP_NEAREST,
+            (LinearFilter, Some(NearestFilter)) => GL_LINEAR_MIPMAP_NEAREST,
+            (NearestFilter, Some(LinearFilter)) => GL_NEAREST_MIPMAP_LINEAR,
+            (LinearFilter, Some(LinearFilter)) => GL_LINEAR_MIPMAP_LINEAR,
+        }
+    }
+
+    pure fn gl_mag_filter(&self) -> GLenum {
+        match self.mag_filter {
+            NearestFilter => GL_NEAREST,
+            LinearFilter => GL_LINEAR,
+        }
+    }
+
+    /// Whether sampling with this description reads mipmap levels.
+    pure fn uses_mipmaps(&self) -> bool {
+        self.mipmap_filter.is_some()
+    }
+}
+
+/// A sampler object, deleted when dropped.
+pub struct Sampler {
+    priv ctx: Context,
+    name: GLuint,
+}
+
+impl Sampler {
+    static fn new(ctx: &Context, desc: &SamplerDesc) -> Sampler {
+        let sampler = Sampler { ctx: ctx.acquire(), name: ctx.gl().gen_sampler() };
+        sampler.set(desc);
+        move sampler
+    }
+
+    /// Replaces the sampler's state with `desc`.
+    fn set(&self, desc: &SamplerDesc) {
+        let gl = self.ctx.gl();
+        gl.sampler_parameter_i(self.name, GL_TEXTURE_WRAP_S, desc.wrap_s.to_gl() as GLint);
+        gl.sampler_parameter_i(self.name, GL_TEXTURE_WRAP_T, desc.wrap_t.to_gl() as GLint);
+        gl.sampler_parameter_i(self.name, GL_TEXTURE_WRAP_R, desc.wrap_r.to_gl() as GLint);
+        gl.sampler_parameter_i(self.name, GL_TEXTURE_MIN_FILTER, desc.gl_min_filter() as GLint);
+        gl.sampler_parameter_i(self.name, GL_TEXTURE_MAG_FILTER, desc.gl_mag_filter() as GLint);
+        gl.sampler_parameter_fv(self.name, GL_TEXTURE_BORDER_COLOR, desc.border_color);
+        gl.sampler_parameter_f(self.name, GL_TEXTURE_LOD_BIAS, desc.lod_bias);
+
+        // Leave the extension alone unless asked for, so drivers without it
+        // do not raise an error
+        if desc.max_anisotropy > 1.0 {
+            gl.sampler_parameter_f(self.name, GL_TEXTURE_MAX_ANISOTROPY_EXT, desc.max_anisotropy);
+        }
+    }
+
+    /// Binds the sampler to texture unit `unit`.
+    fn bind_to(&self, unit: uint) {
+        self.ctx.gl().bind_sampler(unit as GLuint, self.name);
+    }
+}
+
+impl Sampler : Drop {
+    fn finalize(&self) {
+        if self.ctx.release() {
+            self.ctx.gl().delete_sampler(self.name);
+        }
+    }
+}
@@ -0,0 +1,709 @@
+/*!
+ * Compiling shaders and linking programs.
+ *
+ * Failures carry the driver's info log, split into diagnostics and, for
+ * sources read from a file or embedded in a Rust string literal, mapped
+ * back to the line of that file the offending GLSL line was written on.
+ */
+
+use glcore::*;
+
+use context::Context;
+use gl::Gl;
+use soft::Emulation;
+
+/// The pipeline stage a shader is compiled for.
+pub enum ShaderKind {
+    VertexShader,
+    /// Runs on each primitive after the vertex shader, emitting new ones.
+    GeometryShader,
+    FragmentShader,
+}
+
+impl ShaderKind {
+    pure fn to_gl(&self) -> GLenum {
+        match *self {
+            VertexShader => GL_VERTEX_SHADER,
+            GeometryShader => GL_GEOMETRY_SHADER,
+            FragmentShader => GL_FRAGMENT_SHADER,
+        }
+    }
+}
+
+impl ShaderKind : ToStr {
+    pure fn to_str(&self) -> ~str {
+        match *self {
+            VertexShader => ~"vertex shader",
+            GeometryShader => ~"geometry shader",
+            FragmentShader => ~"fragment shader",
+        }
+    }
+}
+
+/// The file and line a line of preprocessed source was written on.
+pub struct Origin {
+    /// The source string number of the file: 0 for the file preprocessed,
+    /// n for the nth one included.
+    string: uint,
+    line: uint,
+}
+
+/**
+ * GLSL source text, optionally remembering the file it was written down
+ * in, either a GLSL file of its own or a Rust one.
+ */
+pub struct Source {
+    text: ~str,
+    file: Option<~str>,
+    line: uint,
+    /// For preprocessed source, the files included, in the order of their
+    /// source string numbers.
+    includes: ~[~str],
+    /// For preprocessed source, where each line came from. Empty otherwise.
+    origins: ~[Origin],
+}
+
+impl Source {
+    /// Source that did not come from a Rust file. Errors report GLSL lines.
+    static fn new(text: &str) -> Source {
+        Source { text: str::from_slice(text), file: None, line: 0, includes: ~[], origins: ~[] }
+    }
+
+    /**
+     * Source written as a `\n\`-continued string literal, with one GLSL
+     * line per Rust line. `line` is the line of the statement opening the
+     * literal, so pass `file!()` and `line!()` from that statement and
+     * start the literal on the next line.
+     */
+    static fn embedded(file: &str, line: uint, text: &str) -> Source {
+        Source {
+            text: str::from_slice(text),
+            file: Some(str::from_slice(file)),
+            line: line,
+            includes: ~[],
+            origins: ~[],
+        }
+    }
+
+    /// The contents of the GLSL file at `path`. Errors report lines of the file.
+    static fn from_file(path: &Path) -> Result<Source, ~str> {
+        match io::read_whole_file_str(path) {
+            Ok(move text) => Ok(Source {
+                text: move text,
+                file: Some(path.to_str()),
+                line: 0,
+                includes: ~[],
+                origins: ~[],
+            }),
+            Err(move msg) => Err(move msg),
+        }
+    }
+
+    /**
+     * Where line `glsl_line` (counting from 1) of source string `string`
+     * was written. Only preprocessed source has strings other than 0.
+     */
+    pure fn location(&self, string: uint, glsl_line: uint) -> ~str {
+        if string > 0 && string <= self.includes.len() {
+            return fmt!("%s:%u", self.includes[string - 1], glsl_line);
+        }
+        match self.file {
+            Some(ref file) => fmt!("%s:%u", *file, self.line + glsl_line),
+            None => fmt!("line %u", glsl_line),
+        }
+    }
+
+    /// The text of line `glsl_line` of source string `string`, if there is
+    /// such a line.
+    pure fn line_text(&self, string: uint, glsl_line: uint) -> Option<~str> {
+        let lines = str::lines_any(self.text);
+        let index = if self.origins.is_empty() {
+            glsl_line
+        } else {
+            match vec::position(self.origins, |o| o.string == string && o.line == glsl_line) {
+                Some(i) => i + 1,
+                None => return None,
+            }
+        };
+
+        if index == 0 || index > lines.len() {
+            None
+        } else {
+            Some(str::trim(lines[index - 1]))
+        }
+    }
+}
+
+/// How serious an info log entry is.
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+impl Severity : ToStr {
+    pure fn to_str(&self) -> ~str {
+        match *self {
+            Error => ~"error",
+            Warning => ~"warning",
+            Info => ~"note",
+        }
+    }
+}
+
+/// One entry of a shader or program info log.
+pub struct Diagnostic {
+    severity: Severity,
+    /// The source string the line is in, 0 unless the source was
+    /// preprocessed with `#line` directives.
+    string: uint,
+    line: Option<uint>,
+    message: ~str,
+}
+
+/**
+ * Splits an info log into diagnostics. The formats understood are
+ *
+ * - NVIDIA: `0(12) : error C0000: syntax error, unexpected ...`
+ * - Mesa: `0:12(5): error: syntax error, unexpected ...`
+ * - AMD, Intel and Apple: `ERROR: 0:12: 'foo' : undeclared identifier`
+ *
+ * Lines in any other format are kept as `Info` without a line number.
+ */
+pub fn parse_info_log(log: &str) -> ~[Diagnostic] {
+    let mut diagnostics = ~[];
+
+    for str::lines_any(log).each |raw| {
+        let line = str::trim(*raw);
+        if str::is_empty(line) { loop; }
+
+        let diagnostic = match parse_prefixed(line) {
+            Some(move d) => d,
+            None => match parse_numbered(line) {
+                Some(move d) => d,
+                None => Diagnostic { severity: Info, string: 0, line: None, message: copy line },
+            }
+        };
+        diagnostics.push(move diagnostic);
+    }
+
+    move diagnostics
+}
+
+/// `ERROR: 0:12: message`, as written by AMD, Intel and Apple drivers.
+fn parse_prefixed(line: &str) -> Option<Diagnostic> {
+    let (severity, rest) = if str::starts_with(line, "ERROR: ") {
+        (Error, str::slice(line, 7, line.len()))
+    } else if str::starts_with(line, "WARNING: ") {
+        (Warning, str::slice(line, 9, line.len()))
+    } else {
+        return None;
+    };
+
+    match scan_number(rest, 0) {
+        Some((string, i)) if byte_is(rest, i, ':') => {
+            match scan_number(rest, i + 1) {
+                Some((n, j)) if byte_is(rest, j, ':') => {
+                    Some(Diagnostic {
+                        severity: severity,
+                        string: string,
+                        line: Some(n),
+                        message: str::trim(str::slice(rest, j + 1, rest.len())),
+                    })
+                }
+                _ => None,
+            }
+        }
+        // Summaries such as `ERROR: 1 compilation errors.`
+        _ => Some(Diagnostic { severity: severity, string: 0, line: None, message: move rest }),
+    }
+}
+
+/// `0(12) : error C0000: message` (NVIDIA) or `0:12(5): error: message` (Mesa).
+fn parse_numbered(line: &str) -> Option<Diagnostic> {
+    let (string, i) = match scan_number(line, 0) {
+        Some(move found) => found,
+        None => return None,
+    };
+
+    let (n, end) = if byte_is(line, i, '(') {
+        match scan_number(line, i + 1) {
+            Some((n, j)) if byte_is(line, j, ')') => (n, j + 1),
+            _ => return None,
+        }
+    } else if byte_is(line, i, ':') {
+        match scan_number(line, i + 1) {
+            Some((n, j)) if byte_is(line, j, '(') => {
+                match scan_number(line, j + 1) {
+                    Some((_, k)) if byte_is(line, k, ')') => (n, k + 1),
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        }
+    } else {
+        return None;
+    };
+
+    let rest = str::trim_left(str::slice(line, end, line.len()));
+    if !str::starts_with(rest, ":") { return None; }
+    let rest = str::trim_left(str::slice(rest, 1, rest.len()));
+
+    let severity = if str::starts_with(rest, "error") {
+        Error
+    } else if str::starts_with(rest, "warning") {
+        Warning
+    } else {
+        Info
+    };
+
+    // Drop the severity and any vendor code (`error C0000:`) in front of
+    // the message itself
+    let message = match severity {
+        Info => copy rest,
+        _ => match str::find_char(rest, ':') {
+            Some(k) => str::trim(str::slice(rest, k + 1, rest.len())),
+            None => copy rest,
+        }
+    };
+
+    Some(Diagnostic { severity: severity, string: string, line: Some(n), message: move message })
+}
+
+/// Reads the decimal number starting at `start`, returning it and the
+/// index just past it.
+fn scan_number(s: &str, start: uint) -> Option<(uint, uint)> {
+    let mut i = start;
+    let mut n = 0u;
+    while i < s.len() && char::is_digit(s[i] as char) {
+        n = n * 10 + (s[i] - '0' as u8) as uint;
+        i += 1;
+    }
+    if i == start { None } else { Some((n, i)) }
+}
+
+fn byte_is(s: &str, i: uint, c: char) -> bool {
+    i < s.len() && s[i] == c as u8
+}
+
+/// A shader that failed to compile.
+pub struct ShaderError {
+    kind: ShaderKind,
+    source: Source,
+    log: ~str,
+    diagnostics: ~[Diagnostic],
+}
+
+impl ShaderError : ToStr {
+    pure fn to_str(&self) -> ~str {
+        let mut out = fmt!("failed to compile %s", self.kind.to_str());
+        if self.diagnostics.is_empty() {
+            return out + ~":\n" + self.log;
+        }
+
+        for self.diagnostics.each |d| {
+            match d.line {
+                Some(n) => {
+                    out += fmt!("\n%s: %s: %s", self.source.location(d.string, n),
+                                d.severity.to_str(), d.message);
+                    match self.source.line_text(d.string, n) {
+                        Some(move text) => out += ~"\n    " + text,
+                        None => (),
+                    }
+                }
+                None => out += fmt!("\n%s: %s", d.severity.to_str(), d.message),
+            }
+        }
+        move out
+    }
+}
+
+/// A program that failed to link.
+pub struct LinkError {
+    log: ~str,
+    diagnostics: ~[Diagnostic],
+}
+
+impl LinkError : ToStr {
+    pure fn to_str(&self) -> ~str {
+        let mut out = ~"failed to link program";
+        if self.diagnostics.is_empty() {
+            return out + ~":\n" + self.log;
+        }
+
+        for self.diagnostics.each |d| {
+            out += fmt!("\n%s: %s", d.severity.to_str(), d.message);
+        }
+        move out
+    }
+}
+
+/// A compiled shader object, deleted when dropped.
+pub struct Shader {
+    priv ctx: Context,
+    name: GLuint,
+    kind: ShaderKind,
+}
+
+impl Shader {
+    /// Compiles `source`, returning the parsed info log on failure.
+    static fn compile(ctx: &Context, kind: ShaderKind, source: &Source) -> Result<Shader, ShaderError> {
+        let gl = ctx.gl();
+        let shader = Shader { ctx: ctx.acquire(), name: gl.create_shader(kind.to_gl()), kind: kind };
+        gl.shader_source(shader.name, source.text);
+        gl.compile_shader(shader.name);
+
+        if gl.get_shader_iv(shader.name, GL_COMPILE_STATUS) == GL_TRUE as GLint {
+            Ok(move shader)
+        } else {
+            let log = gl.get_shader_info_log(shader.name);
+            Err(ShaderError {
+                kind: kind,
+                source: copy *source,
+                diagnostics: parse_info_log(log),
+                log: move log,
+            })
+        }
+    }
+}
+
+impl Shader : Drop {
+    fn finalize(&self) {
+        if self.ctx.release() {
+            self.ctx.gl().delete_shader(self.name);
+        }
+    }
+}
+
+/// Calls `f` with each GLSL type the tutorials use and its GL enum, until
+/// `f` returns `false`.
+fn each_glsl_type(f: fn(&str, GLenum) -> bool) {
+    let types = [
+        ("float", GL_FLOAT),
+        ("vec2", GL_FLOAT_VEC2),
+        ("vec3", GL_FLOAT_VEC3),
+        ("vec4", GL_FLOAT_VEC4),
+        ("mat2", GL_FLOAT_MAT2),
+        ("mat3", GL_FLOAT_MAT3),
+        ("mat4", GL_FLOAT_MAT4),
+        ("int", GL_INT),
+        ("ivec2", GL_INT_VEC2),
+        ("ivec3", GL_INT_VEC3),
+        ("ivec4", GL_INT_VEC4),
+        ("uint", GL_UNSIGNED_INT),
+        ("bool", GL_BOOL),
+        ("sampler2D", GL_SAMPLER_2D),
+        ("samplerCube", GL_SAMPLER_CUBE),
+    ];
+
+    for types.each |pair| {
+        let (glsl, kind) = *pair;
+        if !f(glsl, kind) { break; }
+    }
+}
+
+/// The GL enum for the GLSL type `name`, or 0 for types the tutorials do not use.
+pub fn glsl_type(name: &str) -> GLenum {
+    let mut found = 0;
+    for each_glsl_type |glsl, kind| {
+        if str::eq_slice(glsl, name) {
+            found = kind;
+            break;
+        }
+    }
+    found
+}
+
+/// The GLSL name of the type GL reports as `kind`.
+pub fn glsl_type_name(kind: GLenum) -> ~str {
+    let mut found = fmt!("0x%x", kind as uint);
+    for each_glsl_type |glsl, k| {
+        if k == kind {
+            found = str::from_slice(glsl);
+            break;
+        }
+    }
+    move found
+}
+
+/// An active attribute or uniform of a linked program.
+pub struct Variable {
+    /// The name, without the `[0]` GL appends to arrays.
+    name: ~str,
+    /// The type, such as `GL_FLOAT_VEC3`.
+    kind: GLenum,
+    /// The number of elements if it is an array, else 1.
+    size: GLint,
+    location: GLint,
+}
+
+/// A name asked of a program that does not expose it.
+pub enum LookupError {
+    NoSuchAttribute(~str),
+    NoSuchUniform(~str),
+    NoSuchBlock(~str),
+}
+
+impl LookupError : ToStr {
+    pure fn to_str(&self) -> ~str {
+        match *self {
+            NoSuchAttribute(ref name) => {
+                fmt!("the program has no active attribute `%s` (misspelled, or unused and optimized out?)", *name)
+            }
+            NoSuchUniform(ref name) => {
+                fmt!("the program has no active uniform `%s` (misspelled, or unused and optimized out?)", *name)
+            }
+            NoSuchBlock(ref name) => {
+                fmt!("the program has no active uniform block `%s` (misspelled, or unused and optimized out?)", *name)
+            }
+        }
+    }
+}
+
+/// `name` without a trailing `[0]`.
+pure fn array_base(name: &str) -> ~str {
+    if str::ends_with(name, "[0]") {
+        str::slice(name, 0, name.len() - 3)
+    } else {
+        str::from_slice(name)
+    }
+}
+
+/**
+ * The active attributes and uniforms of a linked program. Built-in inputs
+ * such as `gl_VertexID`, which have no location, are left out.
+ */
+pub struct ProgramInfo {
+    attributes: ~[Variable],
+    uniforms: ~[Variable],
+}
+
+impl ProgramInfo {
+    /// Asks `gl` for the active variables of `program`.
+    static fn query(gl: @Gl, program: GLuint) -> ProgramInfo {
+        let mut attributes = ~[];
+        for uint::range(0, gl.get_program_iv(program, GL_ACTIVE_ATTRIBUTES) as uint) |i| {
+            let (name, size, kind) = gl.get_active_attrib(program, i as GLuint);
+            if str::starts_with(name, "gl_") { loop; }
+            let name = array_base(name);
+            let location = gl.get_attrib_location(program, name);
+            attributes.push(Variable { name: move name, kind: kind, size: size, location: location });
+        }
+
+        let mut uniforms = ~[];
+        for uint::range(0, gl.get_program_iv(program, GL_ACTIVE_UNIFORMS) as uint) |i| {
+            let (name, size, kind) = gl.get_active_uniform(program, i as GLuint);
+            if str::starts_with(name, "gl_") { loop; }
+            let name = array_base(name);
+            let location = gl.get_uniform_location(program, name);
+            uniforms.push(Variable { name: move name, kind: kind, size: size, location: location });
+        }
+
+        ProgramInfo { attributes: move attributes, uniforms: move uniforms }
+    }
+
+    pure fn attribute(&self, name: &str) -> Result<Variable, LookupError> {
+        match vec::find(self.attributes, |a| str::eq_slice(a.name, name)) {
+            Some(move attribute) => Ok(move attribute),
+            None => Err(NoSuchAttribute(str::from_slice(name))),
+        }
+    }
+
+    pure fn uniform(&self, name: &str) -> Result<Variable, LookupError> {
+        match vec::find(self.uniforms, |u| str::eq_slice(u.name, name)) {
+            Some(move uniform) => Ok(move uniform),
+            None => Err(NoSuchUniform(str::from_slice(name))),
+        }
+    }
+}
+
+/// A linked program object, deleted when dropped.
+pub struct Program {
+    priv ctx: Context,
+    name: GLuint,
+    /// Queried once the program is linked.
+    priv mut info: ProgramInfo,
+}
+
+impl Program {
+    /// Links `shaders` into a program.
+    static fn link(ctx: &Context, shaders: &[Shader]) -> Result<Program, LinkError> {
+        Program::link_with_outputs(ctx, shaders, [])
+    }
+
+    /**
+     * Links `shaders` into a program, binding the fragment shader outputs
+     * named in `outputs` to color numbers 0, 1, ... before linking.
+     */
+    static fn link_with_outputs(ctx: &Context, shaders: &[Shader],
+                                outputs: &[&str]) -> Result<Program, LinkError> {
+        let gl = ctx.gl();
+        let program = Program {
+            ctx: ctx.acquire(),
+            name: gl.create_program(),
+            info: ProgramInfo { attributes: ~[], uniforms: ~[] },
+        };
+
+        for shaders.each |shader| {
+            gl.attach_shader(program.name, shader.name);
+        }
+        for vec::eachi(outputs) |i, output| {
+            gl.bind_frag_data_location(program.name, i as GLuint, *output);
+        }
+        gl.link_program(program.name);
+
+        // The shaders are no longer needed once the program is linked
+        for shaders.each |shader| {
+            gl.detach_shader(program.name, shader.name);
+        }
+
+        if gl.get_program_iv(program.name, GL_LINK_STATUS) == GL_TRUE as GLint {
+            program.info = ProgramInfo::query(gl, program.name);
+            Ok(move program)
+        } else {
+            let log = gl.get_program_info_log(program.name);
+            Err(LinkError { diagnostics: parse_info_log(log), log: move log })
+        }
+    }
+
+    /// The backend the program lives in.
+    fn gl(&self) -> @Gl {
+        self.ctx.gl()
+    }
+
+    /// Makes this the current program.
+    fn bind(&self) {
+        self.ctx.cache().use_program(self.name);
+    }
+
+    /**
+     * Supplies the Rust stand-ins the software renderer runs in place of
+     * the program's shaders. Has no effect on the driver.
+     */
+    fn emulate(&self, emulation: Emulation) {
+        self.ctx.gl().emulate_program(self.name, @move emulation);
+    }
+
+    /// The active attributes and uniforms of the program.
+    fn info(&self) -> ProgramInfo {
+        copy self.info
+    }
+
+    fn attribute(&self, name: &str) -> Result<Variable, LookupError> {
+        self.info.attribute(name)
+    }
+
+    fn uniform(&self, name: &str) -> Result<Variable, LookupError> {
+        self.info.uniform(name)
+    }
+
+    /**
+     * Feeds uniform block `name` from the buffer bound to uniform buffer
+     * binding point `binding`. The binding is part of the program, so it
+     * has to be made again for a rebuilt one.
+     */
+    fn bind_block(&self, name: &str, binding: uint) -> Result<(), LookupError> {
+        let gl = self.ctx.gl();
+        let block = gl.get_uniform_block_index(self.name, name);
+        if block == GL_INVALID_INDEX {
+            return Err(NoSuchBlock(str::from_slice(name)));
+        }
+        gl.uniform_block_binding(self.name, block, binding as GLuint);
+        Ok(())
+    }
+
+    /// The location of attribute `name`. If the program has no such
+    /// attribute, warns and returns -1, which must not be passed on as an
+    /// attribute index.
+    fn attrib_location(&self, name: &str) -> GLint {
+        match self.attribute(name) {
+            Ok(attribute) => attribute.location,
+            Err(move err) => {
+                io::println(~"Warning: " + err.to_str());
+                -1
+            }
+        }
+    }
+
+    /// The location of uniform `name`. If the program has no such uniform,
+    /// warns and returns -1, which the `uniform_*` calls quietly ignore.
+    fn uniform_location(&self, name: &str) -> GLint {
+        match self.uniform(name) {
+            Ok(uniform) => uniform.location,
+            Err(move err) => {
+                io::println(~"Warning: " + err.to_str());
+                -1
+            }
+        }
+    }
+}
+
+impl Program : Drop {
+    fn finalize(&self) {
+        if self.ctx.release() {
+            self.ctx.cache().program_deleted(self.name);
+            self.ctx.gl().delete_program(self.name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shader::{Diagnostic, Severity, Error, Warning, Info, parse_info_log};
+
+    /// Whether `diagnostic` is as given.
+    fn is(diagnostic: &Diagnostic, severity: Severity, string: uint, line: Option<uint>,
+          message: &str) -> bool {
+        let same_severity = match (diagnostic.severity, severity) {
+            (Error, Error) | (Warning, Warning) | (Info, Info) => true,
+            _ => false
+        };
+        same_severity && diagnostic.string == string && diagnostic.line == line &&
+            str::eq_slice(diagnostic.message, message)
+    }
+
+    #[test]
+    fn parses_nvidia_logs() {
+        let log = "0(12) : error C0000: syntax error, unexpected '}' at token \"}\"\n\
+                   0(3) : warning C7533: global variable gl_FragColor is deprecated\n";
+        let diagnostics = parse_info_log(log);
+        assert diagnostics.len() == 2;
+        assert is(&diagnostics[0], Error, 0, Some(12), "syntax error, unexpected '}' at token \"}\"");
+        assert is(&diagnostics[1], Warning, 0, Some(3), "global variable gl_FragColor is deprecated");
+    }
+
+    #[test]
+    fn parses_mesa_logs() {
+        let log = "0:12(5): error: syntax error, unexpected NEW_IDENTIFIER\n\
+                   1:4(10): warning: `color' used uninitialized\n";
+        let diagnostics = parse_info_log(log);
+        assert diagnostics.len() == 2;
+        assert is(&diagnostics[0], Error, 0, Some(12), "syntax error, unexpected NEW_IDENTIFIER");
+        assert is(&diagnostics[1], Warning, 1, Some(4), "`color' used uninitialized");
+    }
+
+    #[test]
+    fn parses_amd_logs() {
+        let log = "ERROR: 0:12: 'foo' : undeclared identifier\n\
+                   WARNING: 2:7: extension not supported\n\
+                   ERROR: 1 compilation errors.  No code generated.\n";
+        let diagnostics = parse_info_log(log);
+        assert diagnostics.len() == 3;
+        assert is(&diagnostics[0], Error, 0, Some(12), "'foo' : undeclared identifier");
+        assert is(&diagnostics[1], Warning, 2, Some(7), "extension not supported");
+        // The summary has no line to point at
+        assert is(&diagnostics[2], Error, 0, None, "1 compilation errors.  No code generated.");
+    }
+
+    #[test]
+    fn keeps_unrecognised_lines() {
+        let log = "Vertex info\n-----------\n\n  0(oops) : error C0000: bad\nERROR: 0:x: bad\n";
+        let diagnostics = parse_info_log(log);
+        assert diagnostics.len() == 4;
+        assert is(&diagnostics[0], Info, 0, None, "Vertex info");
+        assert is(&diagnostics[1], Info, 0, None, "-----------");
+        assert is(&diagnostics[2], Info, 0, None, "0(oops) : error C0000: bad");
+        // Neither has a line number where the format puts one
+        assert is(&diagnostics[3], Info, 0, None, "ERROR: 0:x: bad");
+    }
+}
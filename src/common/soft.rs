@@ -0,0 +1,2307 @@
+/*!
+ * A CPU reference renderer.
+ *
+ * `SoftGl` implements `Gl` for the calls the tutorials make and rasterizes
+ * into an RGBA framebuffer in memory, so the chapters can run on machines
+ * without a GPU. GLSL is not executed: every program is given an
+ * `Emulation`, Rust closures standing in for its vertex, fragment and, if
+ * it has one, geometry shader. Attribute and uniform locations are still
+ * assigned from the `in` and `uniform` declarations of the GLSL source, so
+ * lookups and introspection behave as they would on a driver, except that
+ * every variable declared counts as active, used or not. Uniform blocks are
+ * read from the buffers bound to them, with the std140 layout, and their
+ * members looked up by name like any other uniform.
+ *
+ * `GL_TRIANGLES` and `GL_POINTS` can be drawn, and a geometry shader may
+ * emit points, line strips or triangle strips from them; an instanced draw
+ * is drawn once per instance. Points and lines are a pixel wide, lines lit
+ * along their longer axis through the centres of the pixels. Nothing is
+ * clipped: a primitive with a vertex behind the eye (w <= 0) is dropped
+ * whole, and fragments outside the near and far planes are discarded. The
+ * depth buffer holds a float per pixel and the stencil buffer 8 bits; the
+ * scissor, stencil and depth tests all run before the fragment stand-in,
+ * as no emulation writes depth or discards. Triangles are culled by their
+ * winding on the screen and drawn in any polygon mode; pixels whose
+ * centres lie on an edge two triangles share are drawn by only one of
+ * them, by the top-left rule. Fragments are blended with the 8-bit colors
+ * already drawn, which the constant color factors take to be transparent
+ * black, and written through the color mask. Framebuffer objects render
+ * into the image of the texture attached to them, which is updated when
+ * another framebuffer is bound; `GL_FRAMEBUFFER` binds both the draw and
+ * the read framebuffer, and the separate targets are not told apart.
+ * Textures are sampled from their base level with the magnification
+ * filter, so mipmaps and anisotropy, though accepted, make no difference.
+ *
+ * A range of a buffer mapped into memory is a copy of it, written back
+ * when the buffer is unmapped. Every call finishes its work before it
+ * returns, so fences are signalled as soon as they are inserted.
+ */
+
+use cast::transmute;
+use vec::raw::to_ptr;
+
+use glcore::*;
+
+use block::{member_offsets, array_stride};
+use gl::Gl;
+use shader::glsl_type;
+
+/// The number of vertex attributes and texture units a `SoftGl` offers.
+const MAX_ATTRIBS: uint = 16;
+const MAX_TEXTURE_UNITS: uint = 16;
+/// The fewest uniform buffer binding points GL allows.
+pub const MAX_UNIFORM_BUFFER_BINDINGS: uint = 36;
+
+/// A uniform value, as last set with one of the `uniform_*` calls.
+pub enum UniformValue {
+    Unset,
+    Int(GLint),
+    Floats(~[GLfloat]),
+}
+
+/// A texture image as RGBA floats, with the first uploaded row at t = 0.
+pub struct Image {
+    width: uint,
+    height: uint,
+    texels: ~[f32],
+}
+
+/// A texture object and its sampling parameters.
+pub struct Texture {
+    image: Option<@Image>,
+    min_filter: GLenum,
+    mag_filter: GLenum,
+    wrap_s: GLenum,
+    wrap_t: GLenum,
+    border: [f32 * 4],
+}
+
+impl Texture {
+    /// Samples the texture at (`s`, `t`), returning opaque black if it has
+    /// no image.
+    fn sample(&self, s: f32, t: f32) -> [f32 * 4] {
+        let image = match self.image {
+            Some(image) => image,
+            None => return [0.0, 0.0, 0.0, 1.0],
+        };
+
+        let x = s * (image.width as f32) - 0.5;
+        let y = t * (image.height as f32) - 0.5;
+
+        if self.mag_filter == GL_NEAREST {
+            return self.texel(image, f32::floor(x + 0.5) as int, f32::floor(y + 0.5) as int);
+        }
+
+        let x0 = f32::floor(x);
+        let y0 = f32::floor(y);
+        let fx = x - x0;
+        let fy = y - y0;
+        let (i, j) = (x0 as int, y0 as int);
+
+        let a = self.texel(image, i, j);
+        let b = self.texel(image, i + 1, j);
+        let c = self.texel(image, i, j + 1);
+        let d = self.texel(image, i + 1, j + 1);
+
+        let mut out = [0.0f32, 0.0, 0.0, 0.0];
+        for uint::range(0, 4) |k| {
+            let top = a[k] + (b[k] - a[k]) * fx;
+            let bottom = c[k] + (d[k] - c[k]) * fx;
+            out[k] = top + (bottom - top) * fy;
+        }
+        out
+    }
+
+    /// The texel at (`i`, `j`) after wrapping, or the border color.
+    priv fn texel(&self, image: @Image, i: int, j: int) -> [f32 * 4] {
+        match (wrap(i, image.width, self.wrap_s), wrap(j, image.height, self.wrap_t)) {
+            (Some(i), Some(j)) => {
+                let at = (j * image.width + i) * 4;
+                [image.texels[at], image.texels[at + 1], image.texels[at + 2], image.texels[at + 3]]
+            }
+            _ => self.border,
+        }
+    }
+}
+
+/// Wraps texel coordinate `i` into `0 .. size`; `None` means the border.
+fn wrap(i: int, size: uint, mode: GLenum) -> Option<uint> {
+    let n = size as int;
+    if mode == GL_REPEAT {
+        Some((((i % n) + n) % n) as uint)
+    } else if mode == GL_MIRRORED_REPEAT {
+        let m = ((i % (2 * n)) + 2 * n) % (2 * n);
+        Some(if m >= n { (2 * n - 1 - m) as uint } else { m as uint })
+    } else if i < 0 || i >= n {
+        if mode == GL_CLAMP_TO_BORDER {
+            None
+        } else if i < 0 {
+            Some(0)
+        } else {
+            Some(size - 1)
+        }
+    } else {
+        Some(i as uint)
+    }
+}
+
+/// The attribute values of one vertex, as seen by an emulated vertex shader.
+pub struct Inputs {
+    priv names: ~[~str],
+    priv values: ~[~[f32]],
+}
+
+impl Inputs {
+    /// The components of attribute `name`; (0, 0, 0, 1) if it is not enabled.
+    fn get(&self, name: &str) -> ~[f32] {
+        match vec::position(self.names, |n| str::eq_slice(*n, name)) {
+            Some(i) => copy self.values[i],
+            None => ~[0.0, 0.0, 0.0, 1.0],
+        }
+    }
+}
+
+/// The uniforms and bound textures of the program being emulated.
+pub struct Uniforms {
+    priv names: ~[~str],
+    priv values: ~[UniformValue],
+    priv units: ~[Option<Texture>],
+}
+
+impl Uniforms {
+    priv fn value(&self, name: &str) -> UniformValue {
+        match vec::position(self.names, |n| str::eq_slice(*n, name)) {
+            Some(i) => copy self.values[i],
+            None => Unset,
+        }
+    }
+
+    fn int(&self, name: &str) -> GLint {
+        match self.value(name) {
+            Int(i) => i,
+            _ => 0,
+        }
+    }
+
+    fn float(&self, name: &str) -> f32 {
+        match self.value(name) {
+            Floats(move v) => v[0],
+            _ => 0.0,
+        }
+    }
+
+    fn vec3(&self, name: &str) -> [f32 * 3] {
+        match self.value(name) {
+            Floats(move v) if v.len() >= 3 => [v[0], v[1], v[2]],
+            _ => [0.0, 0.0, 0.0],
+        }
+    }
+
+    /// A column-major matrix; the identity if the uniform was never set.
+    fn mat4(&self, name: &str) -> ~[f32] {
+        match self.value(name) {
+            Floats(move v) if v.len() >= 16 => move v,
+            _ => ~[1.0, 0.0, 0.0, 0.0,
+                   0.0, 1.0, 0.0, 0.0,
+                   0.0, 0.0, 1.0, 0.0,
+                   0.0, 0.0, 0.0, 1.0],
+        }
+    }
+
+    /// `textureSize(sampler, 0)` for the sampler uniform `sampler`, or
+    /// (0, 0) if its unit has no image.
+    fn texture_size(&self, sampler: &str) -> (uint, uint) {
+        let unit = self.int(sampler) as uint;
+        if unit >= self.units.len() { return (0, 0); }
+
+        match self.units[unit] {
+            Some(ref texture) => match texture.image {
+                Some(image) => (image.width, image.height),
+                None => (0, 0),
+            },
+            None => (0, 0),
+        }
+    }
+
+    /// `texture(sampler, vec2(s, t))` for the sampler uniform `sampler`.
+    fn texture(&self, sampler: &str, s: f32, t: f32) -> [f32 * 4] {
+        let unit = self.int(sampler) as uint;
+        if unit >= self.units.len() { return [0.0, 0.0, 0.0, 1.0]; }
+
+        match self.units[unit] {
+            Some(ref texture) => texture.sample(s, t),
+            None => [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+}
+
+/// A vertex as produced by an emulated vertex shader.
+pub struct VertexOut {
+    /// `gl_Position`, in clip space.
+    position: [f32 * 4],
+    /// The outputs interpolated across the triangle, in a layout the
+    /// fragment stand-in of the same program agrees on.
+    varyings: ~[f32],
+}
+
+pub type VertexShaderFn = fn@(&Uniforms, &Inputs) -> VertexOut;
+pub type FragmentShaderFn = fn@(&Uniforms, &[f32]) -> [f32 * 4];
+/// Given the vertices of one primitive drawn, as the vertex stand-in left
+/// them, the primitives emitted, each ended by `EndPrimitive`.
+pub type GeometryShaderFn = fn@(&Uniforms, &[VertexOut]) -> ~[~[VertexOut]];
+
+/// The Rust stand-ins for the shaders of one program.
+pub struct Emulation {
+    vertex: VertexShaderFn,
+    /// Needed if the program has a geometry shader, and then the fragment
+    /// stand-in is handed the varyings of what it emits.
+    geometry: Option<GeometryShaderFn>,
+    fragment: FragmentShaderFn,
+}
+
+/// `m * v` for a column-major 4x4 matrix, as GLSL computes `mat4 * vec4`.
+pub pure fn transform(m: &[f32], v: [f32 * 4]) -> [f32 * 4] {
+    let mut out = [0.0f32, 0.0, 0.0, 0.0];
+    for uint::range(0, 4) |row| {
+        out[row] = m[row] * v[0] + m[4 + row] * v[1] + m[8 + row] * v[2] + m[12 + row] * v[3];
+    }
+    out
+}
+
+/// Sampling parameters that override those of the texture on a unit.
+struct SamplerState {
+    min_filter: GLenum,
+    mag_filter: GLenum,
+    wrap_s: GLenum,
+    wrap_t: GLenum,
+    border: [f32 * 4],
+}
+
+struct BufferState {
+    data: ~[u8],
+    /// The offset and a copy of the range mapped, if it is.
+    mapping: Option<(uint, ~[u8])>,
+}
+
+struct AttribPointer {
+    enabled: bool,
+    size: uint,
+    stride: uint,
+    offset: uint,
+    buffer: GLuint,
+    /// Advances once every `divisor` instances, or every vertex if 0.
+    divisor: uint,
+}
+
+struct ArrayState {
+    attribs: ~[AttribPointer],
+    element_buffer: GLuint,
+}
+
+struct ShaderState {
+    kind: GLenum,
+    source: ~str,
+}
+
+struct ProgramState {
+    shaders: ~[GLuint],
+    linked: bool,
+    log: ~str,
+    attributes: ~[Declaration],
+    uniforms: ~[Declaration],
+    values: ~[UniformValue],
+    blocks: ~[Block],
+    /// The binding point of each block.
+    block_bindings: ~[uint],
+    /// The primitives the geometry shader takes and emits, if there is one.
+    geometry: Option<(GLenum, GLenum)>,
+    emulation: Option<@Emulation>,
+}
+
+/// Color, depth and stencil values drawn into: those of the window, or of
+/// the attachments of a framebuffer object.
+struct Surface {
+    width: uint,
+    height: uint,
+    /// RGBA, rounded to the 8 bits a channel is stored in.
+    color: ~[f32],
+    depth: ~[f32],
+    stencil: ~[u8],
+}
+
+fn new_surface(width: uint, height: uint) -> @mut Surface {
+    @mut Surface {
+        width: width,
+        height: height,
+        color: vec::from_elem(width * height * 4, 0.0f32),
+        depth: vec::from_elem(width * height, 1.0f32),
+        stencil: vec::from_elem(width * height, 0u8),
+    }
+}
+
+struct RenderbufferState {
+    width: uint,
+    height: uint,
+    depth: ~[f32],
+    stencil: ~[u8],
+}
+
+struct FramebufferState {
+    /// The names of the texture and the renderbuffers attached, or 0.
+    color: GLuint,
+    depth: GLuint,
+    stencil: GLuint,
+    /// What is drawn into while the framebuffer is bound, copied from the
+    /// attachments when first drawn into and back when it is unbound.
+    surface: Option<@mut Surface>,
+}
+
+fn new_array() -> @mut ArrayState {
+    let disabled = AttribPointer { enabled: false, size: 4, stride: 0, offset: 0, buffer: 0, divisor: 0 };
+    @mut ArrayState { attribs: vec::from_elem(MAX_ATTRIBS, disabled), element_buffer: 0 }
+}
+
+/// Stores `value` under a new name. Name 0 is never handed out.
+fn alloc<T>(table: &mut ~[Option<T>], value: T) -> GLuint {
+    table.push(Some(move value));
+    (table.len() - 1) as GLuint
+}
+
+fn lookup<T: Copy>(table: &[Option<T>], name: GLuint) -> Option<T> {
+    if name == 0 || name as uint >= table.len() { None } else { copy table[name] }
+}
+
+/// A variable declared in GLSL source.
+pub struct Declaration {
+    name: ~str,
+    /// The number of elements if it is an array, else 1.
+    size: GLint,
+    /// Whether it is an array, which it may be of a single element.
+    array: bool,
+    /// The type, as `glGetActiveAttrib` reports it, or 0 if not known here.
+    kind: GLenum,
+}
+
+/// The variable of type `glsl` declared by `declarator`, as in `name;` or `name[4];`.
+fn declaration(glsl: &str, declarator: &str) -> Declaration {
+    let (name, size) = match str::find(declarator, |c| c == ';' || c == '[') {
+        Some(i) if declarator[i] == '[' as u8 => {
+            let rest = str::slice(declarator, i + 1, declarator.len());
+            let digits = match str::find_char(rest, ']') {
+                Some(j) => str::slice(rest, 0, j),
+                None => copy rest,
+            };
+            (str::slice(declarator, 0, i), Some(int::from_str(digits).get_default(1) as GLint))
+        }
+        Some(i) => (str::slice(declarator, 0, i), None),
+        None => (str::from_slice(declarator), None),
+    };
+    Declaration {
+        name: move name,
+        size: size.get_default(1),
+        array: size.is_some(),
+        kind: glsl_type(glsl),
+    }
+}
+
+/// The variables declared with `qualifier` (`in`, `uniform`) at the start
+/// of a line of `source`, in order.
+pub fn variables(source: &str, qualifier: &str) -> ~[Declaration] {
+    let mut found = ~[];
+    for str::lines_any(source).each |line| {
+        let words = str::words(*line);
+        if words.len() < 3 || !str::eq_slice(words[0], qualifier) { loop; }
+        // The start of a block, read by `uniform_blocks`
+        if str::starts_with(words[2], "{") { loop; }
+
+        found.push(declaration(words[1], words[2]));
+    }
+    move found
+}
+
+/// A uniform block declared in GLSL source.
+pub struct Block {
+    name: ~str,
+    members: ~[Declaration],
+    /// The std140 offset of each member.
+    offsets: ~[uint],
+}
+
+/**
+ * The uniform blocks declared in `source`, in order. A block must open
+ * with `uniform Name {` on one line, optionally after a `layout(...)`,
+ * declare one member per line and close with a line starting with `}`.
+ * Instance names are ignored, so members are looked up by their own name.
+ */
+pub fn uniform_blocks(source: &str) -> ~[Block] {
+    let mut found = ~[];
+    let mut name = None;
+    let mut members = ~[];
+    for str::lines_any(source).each |line| {
+        let words = str::words(*line);
+        if name.is_none() {
+            match vec::position(words, |w| str::eq_slice(*w, "uniform")) {
+                Some(i) if i + 2 < words.len() && str::starts_with(words[i + 2], "{") => {
+                    name = Some(copy words[i + 1]);
+                }
+                _ => (),
+            }
+        } else if words.len() > 0 && str::starts_with(words[0], "}") {
+            let offsets = member_offsets(members.map(|m| {
+                (m.kind, if m.array { Some(m.size as uint) } else { None })
+            }));
+            found.push(Block { name: name.get(), members: move members, offsets: move offsets });
+            name = None;
+            members = ~[];
+        } else if words.len() >= 2 {
+            members.push(declaration(words[0], words[1]));
+        }
+    }
+    move found
+}
+
+/// The primitive named `name` in a geometry shader's layout, or 0.
+fn primitive(name: &str) -> GLenum {
+    let primitives = [
+        ("points", GL_POINTS),
+        ("lines", GL_LINES),
+        ("triangles", GL_TRIANGLES),
+        ("line_strip", GL_LINE_STRIP),
+        ("triangle_strip", GL_TRIANGLE_STRIP),
+    ];
+
+    for primitives.each |pair| {
+        let (glsl, mode) = *pair;
+        if str::eq_slice(glsl, name) { return mode; }
+    }
+    0
+}
+
+/**
+ * The primitive a geometry shader in `source` takes, for `qualifier`
+ * `in`, or emits, for `out`, as declared on a line of its own such as
+ * `layout(points) in;` or `layout(line_strip, max_vertices = 64) out;`.
+ * 0 if it is not declared.
+ */
+pub fn primitive_layout(source: &str, qualifier: &str) -> GLenum {
+    let ending = str::from_slice(qualifier) + ";";
+    for str::lines_any(source).each |line| {
+        let line = str::trim(*line);
+        if !str::starts_with(line, "layout(") || !str::ends_with(line, ending) { loop; }
+
+        let inside = str::slice(line, 7, line.len());
+        let end = str::find(inside, |c| c == ',' || c == ')').get_default(inside.len());
+        return primitive(str::trim(str::slice(inside, 0, end)));
+    }
+    0
+}
+
+/// The number of attribute locations an attribute of type `kind` takes:
+/// one per column for a matrix, else one.
+pub pure fn attribute_slots(kind: GLenum) -> uint {
+    if kind == GL_FLOAT_MAT4 {
+        4
+    } else if kind == GL_FLOAT_MAT3 {
+        3
+    } else if kind == GL_FLOAT_MAT2 {
+        2
+    } else {
+        1
+    }
+}
+
+/// The location of each of `attributes`, handed out in the order they are
+/// declared, as many to each as it takes.
+pub fn attribute_locations(attributes: &[Declaration]) -> ~[uint] {
+    let mut locations = ~[];
+    let mut next = 0;
+    for attributes.each |attribute| {
+        locations.push(next);
+        next += attribute_slots(attribute.kind) * attribute.size as uint;
+    }
+    move locations
+}
+
+/// The names of the `variables` declared with `qualifier`.
+pub fn declarations(source: &str, qualifier: &str) -> ~[~str] {
+    variables(source, qualifier).map(|variable| copy variable.name)
+}
+
+fn read_f32(data: &[u8], at: uint) -> f32 {
+    if at + 4 > data.len() { return 0.0; }
+    unsafe {
+        let p: *f32 = transmute(ptr::offset(to_ptr(data), at));
+        *p
+    }
+}
+
+fn read_i32(data: &[u8], at: uint) -> i32 {
+    if at + 4 > data.len() { return 0; }
+    unsafe {
+        let p: *i32 = transmute(ptr::offset(to_ptr(data), at));
+        *p
+    }
+}
+
+/// The columns and rows of the GLSL type `kind`; 1 column for vectors.
+fn shape(kind: GLenum) -> (uint, uint) {
+    if kind == GL_FLOAT_VEC2 {
+        (1, 2)
+    } else if kind == GL_FLOAT_VEC3 {
+        (1, 3)
+    } else if kind == GL_FLOAT_VEC4 {
+        (1, 4)
+    } else if kind == GL_FLOAT_MAT2 {
+        (2, 2)
+    } else if kind == GL_FLOAT_MAT3 {
+        (3, 3)
+    } else if kind == GL_FLOAT_MAT4 {
+        (4, 4)
+    } else {
+        (1, 1)
+    }
+}
+
+/// The value of the block member `member` at `offset` in `data`, every
+/// element of an array one after another.
+fn read_member(data: &[u8], member: &Declaration, offset: uint) -> UniformValue {
+    let kind = member.kind;
+    if kind == GL_INT || kind == GL_UNSIGNED_INT || kind == GL_BOOL {
+        return Int(read_i32(data, offset));
+    }
+    if kind == 0 { return Unset; }
+
+    // Every column of a matrix starts on a multiple of 16 bytes
+    let (columns, rows) = shape(kind);
+    let mut floats = ~[];
+    for uint::range(0, member.size as uint) |element| {
+        let base = offset + element * array_stride(kind);
+        for uint::range(0, columns) |column| {
+            for uint::range(0, rows) |row| {
+                floats.push(read_f32(data, base + column * 16 + row * 4));
+            }
+        }
+    }
+    Floats(move floats)
+}
+
+fn read_index(data: &[u8], kind: GLenum, i: uint) -> uint {
+    unsafe {
+        if kind == GL_UNSIGNED_BYTE {
+            data[i] as uint
+        } else if kind == GL_UNSIGNED_SHORT {
+            let p: *u16 = transmute(ptr::offset(to_ptr(data), i * 2));
+            *p as uint
+        } else {
+            let p: *u32 = transmute(ptr::offset(to_ptr(data), i * 4));
+            *p as uint
+        }
+    }
+}
+
+fn index_size(kind: GLenum) -> uint {
+    if kind == GL_UNSIGNED_BYTE { 1 } else if kind == GL_UNSIGNED_SHORT { 2 } else { 4 }
+}
+
+/// Twice the signed area of the triangle (a, b, p).
+pure fn edge(ax: f32, ay: f32, bx: f32, by: f32, px: f32, py: f32) -> f32 {
+    (bx - ax) * (py - ay) - (by - ay) * (px - ax)
+}
+
+/**
+ * Whether pixel centres lying exactly on the edge from a to b belong to
+ * the triangle of signed double area `area` it bounds. By the top-left
+ * rule they do if the triangle is to the right of the edge, or below it
+ * if it is horizontal, so of two triangles sharing an edge only one
+ * draws them.
+ */
+pure fn owns_edge(ax: f32, ay: f32, bx: f32, by: f32, area: f32) -> bool {
+    // The direction into the triangle across the edge, whatever its winding
+    let (nx, ny) = ((ay - by) / area, (bx - ax) / area);
+    nx > 0.0 || (nx == 0.0 && ny < 0.0)
+}
+
+pure fn min3(a: f32, b: f32, c: f32) -> f32 {
+    if a < b { if a < c { a } else { c } } else if b < c { b } else { c }
+}
+
+pure fn max3(a: f32, b: f32, c: f32) -> f32 {
+    if a > b { if a > c { a } else { c } } else if b > c { b } else { c }
+}
+
+/// Whether a fragment at depth `z` passes `func` against the depth `stored`.
+fn depth_passes(func: GLenum, z: f32, stored: f32) -> bool {
+    if func == GL_NEVER {
+        false
+    } else if func == GL_LESS {
+        z < stored
+    } else if func == GL_LEQUAL {
+        z <= stored
+    } else if func == GL_GREATER {
+        z > stored
+    } else if func == GL_GEQUAL {
+        z >= stored
+    } else if func == GL_EQUAL {
+        z == stored
+    } else if func == GL_NOTEQUAL {
+        z != stored
+    } else {
+        true
+    }
+}
+
+/// Whether a stencil value passes `func` against `reference`, both masked.
+fn stencil_passes(func: GLenum, reference: uint, value: uint) -> bool {
+    if func == GL_NEVER {
+        false
+    } else if func == GL_LESS {
+        reference < value
+    } else if func == GL_LEQUAL {
+        reference <= value
+    } else if func == GL_GREATER {
+        reference > value
+    } else if func == GL_GEQUAL {
+        reference >= value
+    } else if func == GL_EQUAL {
+        reference == value
+    } else if func == GL_NOTEQUAL {
+        reference != value
+    } else {
+        true
+    }
+}
+
+/// The stencil value `value` after the operation `op`.
+fn stencil_result(op: GLenum, value: u8, reference: u8) -> u8 {
+    if op == GL_ZERO {
+        0
+    } else if op == GL_REPLACE {
+        reference
+    } else if op == GL_INCR {
+        if value == 255 { 255 } else { value + 1 }
+    } else if op == GL_INCR_WRAP {
+        if value == 255 { 0 } else { value + 1 }
+    } else if op == GL_DECR {
+        if value == 0 { 0 } else { value - 1 }
+    } else if op == GL_DECR_WRAP {
+        if value == 0 { 255 } else { value - 1 }
+    } else if op == GL_INVERT {
+        !value
+    } else {
+        value
+    }
+}
+
+/// The blend factor `factor` of channel `k`, for blending `src` with `dst`.
+fn blend_factor(factor: GLenum, src: &[f32 * 4], dst: &[f32 * 4], k: uint) -> f32 {
+    if factor == GL_ONE {
+        1.0
+    } else if factor == GL_SRC_COLOR {
+        src[k]
+    } else if factor == GL_ONE_MINUS_SRC_COLOR {
+        1.0 - src[k]
+    } else if factor == GL_DST_COLOR {
+        dst[k]
+    } else if factor == GL_ONE_MINUS_DST_COLOR {
+        1.0 - dst[k]
+    } else if factor == GL_SRC_ALPHA {
+        src[3]
+    } else if factor == GL_ONE_MINUS_SRC_ALPHA {
+        1.0 - src[3]
+    } else if factor == GL_DST_ALPHA {
+        dst[3]
+    } else if factor == GL_ONE_MINUS_DST_ALPHA {
+        1.0 - dst[3]
+    } else if factor == GL_SRC_ALPHA_SATURATE {
+        if k == 3 { 1.0 } else if src[3] < 1.0 - dst[3] { src[3] } else { 1.0 - dst[3] }
+    } else if factor == GL_ONE_MINUS_CONSTANT_COLOR || factor == GL_ONE_MINUS_CONSTANT_ALPHA {
+        1.0
+    } else {
+        // GL_ZERO, and the constant color, which is never set
+        0.0
+    }
+}
+
+/**
+ * Combines `s` and `d`, a channel of the source and destination colors,
+ * with `equation`, given them already multiplied by their factors as
+ * `sf` and `df`.
+ */
+fn blend_channel(equation: GLenum, s: f32, d: f32, sf: f32, df: f32) -> f32 {
+    if equation == GL_FUNC_SUBTRACT {
+        sf - df
+    } else if equation == GL_FUNC_REVERSE_SUBTRACT {
+        df - sf
+    } else if equation == GL_MIN {
+        // The factors are ignored by GL_MIN and GL_MAX
+        if s < d { s } else { d }
+    } else if equation == GL_MAX {
+        if s > d { s } else { d }
+    } else {
+        sf + df
+    }
+}
+
+/// `x` as a pixel coordinate in `[0, limit]`.
+fn clamp_to(x: f32, limit: uint) -> uint {
+    if x <= 0.0 { 0 } else if x >= limit as f32 { limit } else { x as uint }
+}
+
+fn srgb_to_linear(x: f32) -> f32 {
+    if x <= 0.04045 { x / 12.92 } else { f32::pow((x + 0.055) / 1.055, 2.4) }
+}
+
+fn to_byte(x: f32) -> u8 {
+    if x <= 0.0 { 0 } else if x >= 1.0 { 255 } else { (x * 255.0 + 0.5) as u8 }
+}
+
+/// `x` as an 8-bit channel stores it.
+fn quantize(x: f32) -> f32 {
+    to_byte(x) as f32 / 255.0
+}
+
+/// A software implementation of `Gl` rendering into memory.
+pub struct SoftGl {
+    priv width: uint,
+    priv height: uint,
+    /// The window's framebuffer.
+    priv screen: @mut Surface,
+    priv mut clear_rgba: [f32 * 4],
+    priv mut viewport: [int * 4],
+    priv mut unpack_alignment: uint,
+
+    priv mut depth_test: bool,
+    priv mut depth_func: GLenum,
+    priv mut depth_write: bool,
+    priv mut stencil_test: bool,
+    priv mut stencil_func: GLenum,
+    priv mut stencil_ref: GLint,
+    priv mut stencil_value_mask: GLuint,
+    /// The operations on stencil fail, depth fail and depth pass.
+    priv mut stencil_ops: [GLenum * 3],
+    priv mut stencil_write_mask: GLuint,
+    priv mut cull: bool,
+    priv mut cull_face: GLenum,
+    priv mut scissor_test: bool,
+    priv mut scissor: [int * 4],
+    priv mut color_mask: [bool * 4],
+    priv mut polygon_mode: GLenum,
+    priv mut blend: bool,
+    priv mut blend_equation: GLenum,
+    /// The source and destination factors.
+    priv mut blend_func: [GLenum * 2],
+
+    priv mut buffers: ~[Option<@mut BufferState>],
+    priv mut array_buffer: GLuint,
+    priv mut uniform_buffer: GLuint,
+    priv mut uniform_buffers: ~[GLuint],
+    priv mut arrays: ~[Option<@mut ArrayState>],
+    priv mut bound_array: GLuint,
+
+    priv mut shaders: ~[Option<@mut ShaderState>],
+    priv mut programs: ~[Option<@mut ProgramState>],
+    priv mut current_program: GLuint,
+
+    priv mut textures: ~[Option<@mut Texture>],
+    priv mut active_unit: uint,
+    priv mut units: ~[GLuint],
+    priv mut samplers: ~[Option<@mut SamplerState>],
+    priv mut sampler_units: ~[GLuint],
+
+    priv mut framebuffers: ~[Option<@mut FramebufferState>],
+    priv mut bound_framebuffer: GLuint,
+    priv mut renderbuffers: ~[Option<@mut RenderbufferState>],
+    priv mut bound_renderbuffer: GLuint,
+}
+
+impl SoftGl {
+    /// A renderer with a `width` by `height` framebuffer.
+    static fn new(width: uint, height: uint) -> SoftGl {
+        SoftGl {
+            width: width,
+            height: height,
+            screen: new_surface(width, height),
+            clear_rgba: [0.0, 0.0, 0.0, 0.0],
+            viewport: [0, 0, width as int, height as int],
+            unpack_alignment: 4,
+
+            depth_test: false,
+            depth_func: GL_LESS,
+            depth_write: true,
+            stencil_test: false,
+            stencil_func: GL_ALWAYS,
+            stencil_ref: 0,
+            stencil_value_mask: 0xff,
+            stencil_ops: [GL_KEEP, GL_KEEP, GL_KEEP],
+            stencil_write_mask: 0xff,
+            cull: false,
+            cull_face: GL_BACK,
+            scissor_test: false,
+            scissor: [0, 0, width as int, height as int],
+            color_mask: [true, true, true, true],
+            polygon_mode: GL_FILL,
+            blend: false,
+            blend_equation: GL_FUNC_ADD,
+            blend_func: [GL_ONE, GL_ZERO],
+
+            buffers: ~[None],
+            array_buffer: 0,
+            uniform_buffer: 0,
+            uniform_buffers: vec::from_elem(MAX_UNIFORM_BUFFER_BINDINGS, 0),
+            // Name 0 is the default vertex array
+            arrays: ~[Some(new_array())],
+            bound_array: 0,
+
+            shaders: ~[None],
+            programs: ~[None],
+            current_program: 0,
+
+            textures: ~[None],
+            active_unit: 0,
+            units: vec::from_elem(MAX_TEXTURE_UNITS, 0),
+            samplers: ~[None],
+            sampler_units: vec::from_elem(MAX_TEXTURE_UNITS, 0),
+
+            framebuffers: ~[None],
+            bound_framebuffer: 0,
+            renderbuffers: ~[None],
+            bound_renderbuffer: 0,
+        }
+    }
+
+    fn width(&self) -> uint { self.width }
+
+    fn height(&self) -> uint { self.height }
+
+    /// The whole framebuffer of the window as RGBA bytes, bottom row first.
+    fn pixels(&self) -> ~[u8] {
+        self.screen.color.map(|x| to_byte(*x))
+    }
+
+    /**
+     * The surface drawn into: the window's, or that of the bound
+     * framebuffer object, set up from its attachments if need be. `None`
+     * if the framebuffer is incomplete, when GL draws nothing.
+     */
+    priv fn target(&self) -> Option<@mut Surface> {
+        let framebuffer = match lookup(self.framebuffers, self.bound_framebuffer) {
+            Some(framebuffer) => framebuffer,
+            None => return Some(self.screen),
+        };
+        match framebuffer.surface {
+            Some(surface) => return Some(surface),
+            None => (),
+        }
+        if self.framebuffer_status(framebuffer) != GL_FRAMEBUFFER_COMPLETE { return None; }
+
+        // Draw into the area every attachment covers
+        let mut size = None;
+        let fit = |width: uint, height: uint| {
+            size = match size {
+                Some((w, h)) => Some((uint::min(w, width), uint::min(h, height))),
+                None => Some((width, height)),
+            };
+        };
+        let color = match lookup(self.textures, framebuffer.color) {
+            Some(texture) => texture.image,
+            None => None,
+        };
+        match color {
+            Some(image) => fit(image.width, image.height),
+            None => (),
+        }
+        let depth = lookup(self.renderbuffers, framebuffer.depth);
+        let stencil = lookup(self.renderbuffers, framebuffer.stencil);
+        for vec::each([depth, stencil]) |renderbuffer| {
+            match *renderbuffer {
+                Some(renderbuffer) => fit(renderbuffer.width, renderbuffer.height),
+                None => (),
+            }
+        }
+
+        let (width, height) = size.get();
+        let surface = new_surface(width, height);
+        for uint::range(0, height) |y| {
+            for uint::range(0, width) |x| {
+                let at = y * width + x;
+                match color {
+                    Some(image) => {
+                        for uint::range(0, 4) |k| {
+                            surface.color[at * 4 + k] = image.texels[(y * image.width + x) * 4 + k];
+                        }
+                    }
+                    None => (),
+                }
+                match depth {
+                    Some(renderbuffer) => surface.depth[at] = renderbuffer.depth[y * renderbuffer.width + x],
+                    None => (),
+                }
+                match stencil {
+                    Some(renderbuffer) => surface.stencil[at] = renderbuffer.stencil[y * renderbuffer.width + x],
+                    None => (),
+                }
+            }
+        }
+        framebuffer.surface = Some(surface);
+        Some(surface)
+    }
+
+    /// Copies what was drawn into the bound framebuffer object back into
+    /// its attachments.
+    priv fn flush(&self) {
+        let framebuffer = match lookup(self.framebuffers, self.bound_framebuffer) {
+            Some(framebuffer) => framebuffer,
+            None => return,
+        };
+        let surface = match framebuffer.surface {
+            Some(surface) => surface,
+            None => return,
+        };
+        framebuffer.surface = None;
+
+        match lookup(self.textures, framebuffer.color) {
+            Some(texture) => match texture.image {
+                Some(image) => {
+                    let mut texels = copy image.texels;
+                    for uint::range(0, surface.height) |y| {
+                        for uint::range(0, surface.width * 4) |i| {
+                            texels[y * image.width * 4 + i] = surface.color[y * surface.width * 4 + i];
+                        }
+                    }
+                    texture.image = Some(@Image { width: image.width, height: image.height,
+                                                  texels: move texels });
+                }
+                None => (),
+            },
+            None => (),
+        }
+        match lookup(self.renderbuffers, framebuffer.depth) {
+            Some(renderbuffer) => {
+                for uint::range(0, surface.height) |y| {
+                    for uint::range(0, surface.width) |x| {
+                        renderbuffer.depth[y * renderbuffer.width + x] = surface.depth[y * surface.width + x];
+                    }
+                }
+            }
+            None => (),
+        }
+        match lookup(self.renderbuffers, framebuffer.stencil) {
+            Some(renderbuffer) => {
+                for uint::range(0, surface.height) |y| {
+                    for uint::range(0, surface.width) |x| {
+                        renderbuffer.stencil[y * renderbuffer.width + x] = surface.stencil[y * surface.width + x];
+                    }
+                }
+            }
+            None => (),
+        }
+    }
+
+    /// Whether `framebuffer` can be drawn into, as `glCheckFramebufferStatus` reports it.
+    priv fn framebuffer_status(&self, framebuffer: @mut FramebufferState) -> GLenum {
+        if framebuffer.color == 0 && framebuffer.depth == 0 && framebuffer.stencil == 0 {
+            return GL_FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT;
+        }
+        let color_missing = framebuffer.color != 0 && match lookup(self.textures, framebuffer.color) {
+            Some(texture) => texture.image.is_none(),
+            None => true,
+        };
+        let storage_missing = |name: GLuint| name != 0 && match lookup(self.renderbuffers, name) {
+            Some(renderbuffer) => renderbuffer.width == 0 || renderbuffer.height == 0,
+            None => true,
+        };
+        if color_missing || storage_missing(framebuffer.depth) || storage_missing(framebuffer.stencil) {
+            GL_FRAMEBUFFER_INCOMPLETE_ATTACHMENT
+        } else {
+            GL_FRAMEBUFFER_COMPLETE
+        }
+    }
+
+    priv fn array(&self) -> @mut ArrayState {
+        match self.arrays[self.bound_array] {
+            Some(array) => array,
+            None => fail(~"SoftGl: bound vertex array was deleted"),
+        }
+    }
+
+    priv fn bound_buffer(&self, target: GLenum) -> GLuint {
+        if target == GL_ELEMENT_ARRAY_BUFFER {
+            self.array().element_buffer
+        } else if target == GL_UNIFORM_BUFFER {
+            self.uniform_buffer
+        } else {
+            self.array_buffer
+        }
+    }
+
+    priv fn buffer_bytes(&self, name: GLuint) -> ~[u8] {
+        match lookup(self.buffers, name) {
+            Some(buffer) => copy buffer.data,
+            None => ~[],
+        }
+    }
+
+    priv fn bound_texture(&self) -> Option<@mut Texture> {
+        lookup(self.textures, self.units[self.active_unit])
+    }
+
+    priv fn current_uniform(&self, location: GLint, value: UniformValue) {
+        if location < 0 { return; }
+        match lookup(self.programs, self.current_program) {
+            Some(program) if (location as uint) < program.values.len() => {
+                program.values[location] = move value;
+            }
+            _ => (),
+        }
+    }
+
+    priv fn snapshot_uniforms(&self, program: @mut ProgramState) -> Uniforms {
+        let mut names = program.uniforms.map(|uniform| copy uniform.name);
+        let mut values = copy program.values;
+        for vec::eachi(program.blocks) |i, block| {
+            let data = self.buffer_bytes(self.uniform_buffers[program.block_bindings[i]]);
+            for vec::eachi(block.members) |j, member| {
+                names.push(copy member.name);
+                values.push(read_member(data, member, block.offsets[j]));
+            }
+        }
+
+        Uniforms {
+            names: move names,
+            values: move values,
+            units: do vec::from_fn(MAX_TEXTURE_UNITS) |unit| {
+                match (lookup(self.textures, self.units[unit]),
+                       lookup(self.samplers, self.sampler_units[unit])) {
+                    (Some(texture), Some(sampler)) => Some(Texture {
+                        image: texture.image,
+                        min_filter: sampler.min_filter,
+                        mag_filter: sampler.mag_filter,
+                        wrap_s: sampler.wrap_s,
+                        wrap_t: sampler.wrap_t,
+                        border: sampler.border,
+                    }),
+                    (Some(texture), None) => Some(copy *texture),
+                    (None, _) => None,
+                }
+            },
+        }
+    }
+
+    /**
+     * The attributes of vertex `index` of instance `instance`, read through
+     * the pointers at `locations`, from `buffers`, the contents of the
+     * buffer each location reads. A matrix is read a column per location.
+     */
+    priv fn fetch(&self, array: @mut ArrayState, program: @mut ProgramState, locations: &[uint],
+                  buffers: &[~[u8]], index: uint, instance: uint) -> Inputs {
+        let mut values = ~[];
+        for vec::eachi(program.attributes) |i, attribute| {
+            let mut value = ~[];
+            let slots = attribute_slots(attribute.kind) * attribute.size as uint;
+            for uint::range(locations[i], locations[i] + slots) |location| {
+                if location >= MAX_ATTRIBS || !array.attribs[location].enabled {
+                    value.push_all([0.0f32, 0.0, 0.0, 1.0]);
+                    loop;
+                }
+
+                let pointer = array.attribs[location];
+                let element = if pointer.divisor == 0 { index } else { instance / pointer.divisor };
+                let stride = if pointer.stride == 0 { pointer.size * 4 } else { pointer.stride };
+                let base = pointer.offset + element * stride;
+                for uint::range(0, pointer.size) |c| {
+                    value.push(read_f32(buffers[location], base + c * 4));
+                }
+            }
+            values.push(move value);
+        }
+        Inputs { names: program.attributes.map(|attribute| copy attribute.name), values: move values }
+    }
+
+    /// Runs the current program over the vertices `indices`, `instances` times.
+    priv fn draw(&self, mode: GLenum, indices: &[uint], instances: uint) {
+        if mode != GL_TRIANGLES && mode != GL_POINTS {
+            io::println(~"SoftGl: only GL_TRIANGLES and GL_POINTS can be drawn");
+            return;
+        }
+
+        let program = match lookup(self.programs, self.current_program) {
+            Some(program) if program.linked => program,
+            _ => return,
+        };
+        let emulation = match program.emulation {
+            Some(emulation) => emulation,
+            None => {
+                io::println(~"SoftGl: the current program has no emulation");
+                return;
+            }
+        };
+
+        let surface = match self.target() {
+            Some(surface) => surface,
+            None => return,
+        };
+
+        // The primitive the geometry shader emits, and its stand-in
+        let geometry = match program.geometry {
+            Some((input, output)) => {
+                // Drawing anything but what the geometry shader takes is an error
+                if input != mode { return; }
+                match emulation.geometry {
+                    Some(geometry) => Some((output, geometry)),
+                    None => {
+                        io::println(~"SoftGl: the current program has no emulation of its geometry shader");
+                        return;
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let array = self.array();
+        let uniforms = self.snapshot_uniforms(program);
+        let locations = attribute_locations(program.attributes);
+        let buffers = do vec::from_fn(MAX_ATTRIBS) |location| {
+            if array.attribs[location].enabled {
+                self.buffer_bytes(array.attribs[location].buffer)
+            } else {
+                ~[]
+            }
+        };
+
+        for uint::range(0, instances) |instance| {
+            let vertices = do indices.map |index| {
+                (emulation.vertex)(&uniforms, &self.fetch(array, program, locations, buffers, *index, instance))
+            };
+
+            match geometry {
+                Some((output, geometry)) => {
+                    let size = if mode == GL_POINTS { 1 } else { 3 };
+                    for uint::range(0, vertices.len() / size) |p| {
+                        let primitive = vec::slice(vertices, p * size, (p + 1) * size);
+                        for geometry(&uniforms, primitive).each |strip| {
+                            self.assemble(surface, output, *strip, emulation, &uniforms);
+                        }
+                    }
+                }
+                None => self.assemble(surface, mode, vertices, emulation, &uniforms),
+            }
+        }
+    }
+
+    /// Rasterizes `vertices` as `GL_POINTS`, `GL_LINE_STRIP`, `GL_TRIANGLES`
+    /// or `GL_TRIANGLE_STRIP`.
+    priv fn assemble(&self, surface: @mut Surface, mode: GLenum, vertices: &[VertexOut],
+                     emulation: @Emulation, uniforms: &Uniforms) {
+        if mode == GL_POINTS {
+            for vertices.each |v| {
+                self.rasterize_point(surface, v, emulation, uniforms);
+            }
+        } else if mode == GL_LINE_STRIP {
+            for uint::range(1, vertices.len()) |i| {
+                self.rasterize_line(surface, &vertices[i - 1], &vertices[i], emulation, uniforms);
+            }
+        } else if mode == GL_TRIANGLES {
+            for uint::range(0, vertices.len() / 3) |t| {
+                self.triangle(surface, &vertices[3 * t], &vertices[3 * t + 1], &vertices[3 * t + 2],
+                              emulation, uniforms);
+            }
+        } else if mode == GL_TRIANGLE_STRIP {
+            for uint::range(2, vertices.len()) |i| {
+                // Every other triangle of a strip is wound the other way
+                // round, so swap two of its corners back
+                if i % 2 == 0 {
+                    self.triangle(surface, &vertices[i - 2], &vertices[i - 1], &vertices[i], emulation, uniforms);
+                } else {
+                    self.triangle(surface, &vertices[i - 1], &vertices[i - 2], &vertices[i], emulation, uniforms);
+                }
+            }
+        }
+    }
+
+    /**
+     * Draws the triangle `a`, `b`, `c` unless the face it shows is culled:
+     * filled, as its three edges or as its three corners, as the polygon
+     * mode says. Triangles wound counter-clockwise on the screen are front
+     * facing.
+     */
+    priv fn triangle(&self, surface: @mut Surface, a: &VertexOut, b: &VertexOut, c: &VertexOut,
+                     emulation: @Emulation, uniforms: &Uniforms) {
+        if a.position[3] <= 0.0 || b.position[3] <= 0.0 || c.position[3] <= 0.0 {
+            return;
+        }
+
+        if self.cull {
+            let (ax, ay, _) = self.to_window(&a.position);
+            let (bx, by, _) = self.to_window(&b.position);
+            let (cx, cy, _) = self.to_window(&c.position);
+            let front = edge(ax, ay, bx, by, cx, cy) > 0.0;
+            if self.cull_face == GL_FRONT_AND_BACK || (self.cull_face == GL_FRONT) == front {
+                return;
+            }
+        }
+
+        if self.polygon_mode == GL_LINE {
+            self.rasterize_line(surface, a, b, emulation, uniforms);
+            self.rasterize_line(surface, b, c, emulation, uniforms);
+            self.rasterize_line(surface, c, a, emulation, uniforms);
+        } else if self.polygon_mode == GL_POINT {
+            self.rasterize_point(surface, a, emulation, uniforms);
+            self.rasterize_point(surface, b, emulation, uniforms);
+            self.rasterize_point(surface, c, emulation, uniforms);
+        } else {
+            self.rasterize(surface, a, b, c, emulation, uniforms);
+        }
+    }
+
+    /// Clip space position `p` in window coordinates, with depth from 0 at
+    /// the near plane to 1 at the far.
+    priv fn to_window(&self, p: &[f32 * 4]) -> (f32, f32, f32) {
+        // Perspective divide and viewport transform
+        let vx = self.viewport[0] as f32;
+        let vy = self.viewport[1] as f32;
+        let vw = self.viewport[2] as f32;
+        let vh = self.viewport[3] as f32;
+        ((p[0] / p[3] + 1.0) * 0.5 * vw + vx, (p[1] / p[3] + 1.0) * 0.5 * vh + vy, (p[2] / p[3] + 1.0) * 0.5)
+    }
+
+    /**
+     * Runs the scissor, stencil and depth tests for a fragment at depth
+     * `z` over pixel (`x`, `y`) of `surface` and, if it passes, the
+     * fragment stand-in, writing the color it returns.
+     */
+    priv fn shade(&self, surface: @mut Surface, x: uint, y: uint, z: f32, varyings: &[f32],
+                  emulation: @Emulation, uniforms: &Uniforms) {
+        if z < 0.0 || z > 1.0 { return; }
+        if !self.in_scissor(x, y) { return; }
+        let at = y * surface.width + x;
+        if !self.depth_stencil_test(surface, at, z) { return; }
+
+        let rgba = (emulation.fragment)(uniforms, varyings);
+        if self.blend {
+            self.write_color(surface, at, self.blended(surface, at, rgba));
+        } else {
+            self.write_color(surface, at, rgba);
+        }
+    }
+
+    /// `rgba` blended with the color of pixel `at` of `surface`.
+    priv fn blended(&self, surface: @mut Surface, at: uint, rgba: [f32 * 4]) -> [f32 * 4] {
+        // The framebuffer holds fixed-point colors, so the fragment's is
+        // clamped before it is blended
+        let clamp = |x: f32| if x < 0.0 { 0.0 } else if x > 1.0 { 1.0 } else { x };
+        let src = [clamp(rgba[0]), clamp(rgba[1]), clamp(rgba[2]), clamp(rgba[3])];
+        let dst = [surface.color[at * 4], surface.color[at * 4 + 1], surface.color[at * 4 + 2],
+                   surface.color[at * 4 + 3]];
+
+        let mut out = [0.0f32, 0.0, 0.0, 0.0];
+        for uint::range(0, 4) |k| {
+            let sf = src[k] * blend_factor(self.blend_func[0], &src, &dst, k);
+            let df = dst[k] * blend_factor(self.blend_func[1], &src, &dst, k);
+            out[k] = blend_channel(self.blend_equation, src[k], dst[k], sf, df);
+        }
+        out
+    }
+
+    /// Whether pixel (`x`, `y`) passes the scissor test.
+    priv fn in_scissor(&self, x: uint, y: uint) -> bool {
+        if !self.scissor_test { return true; }
+        let (x, y) = (x as int, y as int);
+        x >= self.scissor[0] && x < self.scissor[0] + self.scissor[2] &&
+            y >= self.scissor[1] && y < self.scissor[1] + self.scissor[3]
+    }
+
+    /// Writes `rgba` to pixel `at` of `surface`, through the color mask.
+    priv fn write_color(&self, surface: @mut Surface, at: uint, rgba: [f32 * 4]) {
+        for uint::range(0, 4) |k| {
+            if self.color_mask[k] {
+                surface.color[at * 4 + k] = quantize(rgba[k]);
+            }
+        }
+    }
+
+    /// Lights the pixel `v` falls in.
+    priv fn rasterize_point(&self, surface: @mut Surface, v: &VertexOut,
+                            emulation: @Emulation, uniforms: &Uniforms) {
+        if v.position[3] <= 0.0 { return; }
+
+        let (x, y, z) = self.to_window(&v.position);
+        if x < 0.0 || y < 0.0 || x >= surface.width as f32 || y >= surface.height as f32 { return; }
+        self.shade(surface, x as uint, y as uint, z, v.varyings, emulation, uniforms);
+    }
+
+    /**
+     * Lights a pixel in each column, or row if the line is steeper than
+     * it is wide, whose centre lies between `a` and `b`: the pixel the
+     * line crosses the centre line of the column in.
+     */
+    priv fn rasterize_line(&self, surface: @mut Surface, a: &VertexOut, b: &VertexOut,
+                           emulation: @Emulation, uniforms: &Uniforms) {
+        if a.position[3] <= 0.0 || b.position[3] <= 0.0 { return; }
+
+        let (ax, ay, az) = self.to_window(&a.position);
+        let (bx, by, bz) = self.to_window(&b.position);
+        let steep = f32::abs(by - ay) > f32::abs(bx - ax);
+        // Along the longer axis, and along the shorter one
+        let (from, to, limit) = if steep { (ay, by, surface.height) } else { (ax, bx, surface.width) };
+        let (across_from, across_to, across_limit) = if steep {
+            (ax, bx, surface.width)
+        } else {
+            (ay, by, surface.height)
+        };
+        if from == to { return; }
+
+        // The columns whose centre i + 0.5 lies in [low, high)
+        let (low, high) = if from < to { (from, to) } else { (to, from) };
+        let first = clamp_to(f32::ceil(low - 0.5), limit);
+        let last = clamp_to(f32::ceil(high - 0.5), limit);
+
+        let (inv_a, inv_b) = (1.0 / a.position[3], 1.0 / b.position[3]);
+        let count = a.varyings.len();
+
+        for uint::range(first, last) |i| {
+            let t = (i as f32 + 0.5 - from) / (to - from);
+            let across = f32::floor(across_from + t * (across_to - across_from));
+            if across < 0.0 || across >= across_limit as f32 { loop; }
+            let (x, y) = if steep { (across as uint, i) } else { (i, across as uint) };
+
+            // Interpolate in clip space so varyings stay perspective correct
+            let pa = (1.0 - t) * inv_a;
+            let pb = t * inv_b;
+            let varyings = do vec::from_fn(count) |k| {
+                (pa * a.varyings[k] + pb * b.varyings[k]) / (pa + pb)
+            };
+            self.shade(surface, x, y, az + t * (bz - az), varyings, emulation, uniforms);
+        }
+    }
+
+    priv fn rasterize(&self, surface: @mut Surface, a: &VertexOut, b: &VertexOut, c: &VertexOut,
+                      emulation: @Emulation, uniforms: &Uniforms) {
+        if a.position[3] <= 0.0 || b.position[3] <= 0.0 || c.position[3] <= 0.0 {
+            return;
+        }
+
+        let (ax, ay, az) = self.to_window(&a.position);
+        let (bx, by, bz) = self.to_window(&b.position);
+        let (cx, cy, cz) = self.to_window(&c.position);
+
+        let area = edge(ax, ay, bx, by, cx, cy);
+        if area == 0.0 { return; }
+
+        let min_x = clamp_to(f32::floor(min3(ax, bx, cx)), surface.width);
+        let min_y = clamp_to(f32::floor(min3(ay, by, cy)), surface.height);
+        let max_x = clamp_to(f32::ceil(max3(ax, bx, cx)), surface.width);
+        let max_y = clamp_to(f32::ceil(max3(ay, by, cy)), surface.height);
+
+        let (inv_a, inv_b, inv_c) = (1.0 / a.position[3], 1.0 / b.position[3], 1.0 / c.position[3]);
+        let count = a.varyings.len();
+
+        // Whether a pixel on the edge opposite each corner is drawn
+        let owns_a = owns_edge(bx, by, cx, cy, area);
+        let owns_b = owns_edge(cx, cy, ax, ay, area);
+        let owns_c = owns_edge(ax, ay, bx, by, area);
+        let covers = |w: f32, owned: bool| w > 0.0 || (w == 0.0 && owned);
+
+        for uint::range(min_y, max_y) |y| {
+            for uint::range(min_x, max_x) |x| {
+                let px = x as f32 + 0.5;
+                let py = y as f32 + 0.5;
+
+                let wa = edge(bx, by, cx, cy, px, py) / area;
+                let wb = edge(cx, cy, ax, ay, px, py) / area;
+                let wc = edge(ax, ay, bx, by, px, py) / area;
+                if !covers(wa, owns_a) || !covers(wb, owns_b) || !covers(wc, owns_c) { loop; }
+
+                // Unlike the varyings, depth is interpolated linearly on the screen
+                let z = wa * az + wb * bz + wc * cz;
+
+                // Interpolate in clip space so varyings stay perspective correct
+                let pa = wa * inv_a;
+                let pb = wb * inv_b;
+                let pc = wc * inv_c;
+                let sum = pa + pb + pc;
+
+                let varyings = do vec::from_fn(count) |i| {
+                    (pa * a.varyings[i] + pb * b.varyings[i] + pc * c.varyings[i]) / sum
+                };
+                self.shade(surface, x, y, z, varyings, emulation, uniforms);
+            }
+        }
+    }
+
+    /**
+     * Runs the stencil and depth tests for a fragment at depth `z` over
+     * pixel `at` of `surface`, updating its stencil and depth buffers as
+     * GL would. Returns whether the fragment passes both.
+     */
+    priv fn depth_stencil_test(&self, surface: @mut Surface, at: uint, z: f32) -> bool {
+        if self.stencil_test {
+            let mask = self.stencil_value_mask as uint & 0xff;
+            let reference = self.stencil_ref as uint & 0xff;
+            if !stencil_passes(self.stencil_func, reference & mask, surface.stencil[at] as uint & mask) {
+                self.update_stencil(surface, at, self.stencil_ops[0]);
+                return false;
+            }
+        }
+
+        if self.depth_test {
+            if !depth_passes(self.depth_func, z, surface.depth[at]) {
+                if self.stencil_test { self.update_stencil(surface, at, self.stencil_ops[1]); }
+                return false;
+            }
+            if self.depth_write { surface.depth[at] = z; }
+        }
+
+        if self.stencil_test { self.update_stencil(surface, at, self.stencil_ops[2]); }
+        true
+    }
+
+    /// Applies the stencil operation `op` to pixel `at` of `surface`,
+    /// through the write mask.
+    priv fn update_stencil(&self, surface: @mut Surface, at: uint, op: GLenum) {
+        let mask = self.stencil_write_mask as u8;
+        let value = stencil_result(op, surface.stencil[at], self.stencil_ref as u8);
+        surface.stencil[at] = (surface.stencil[at] & !mask) | (value & mask);
+    }
+}
+
+impl SoftGl : Gl {
+    fn clear_color(&self, red: GLfloat, green: GLfloat, blue: GLfloat, alpha: GLfloat) {
+        self.clear_rgba = [red, green, blue, alpha];
+    }
+
+    fn clear(&self, mask: GLbitfield) {
+        let surface = match self.target() {
+            Some(surface) => surface,
+            None => return,
+        };
+        // Clearing is confined to the scissor rectangle, and goes through
+        // the color, depth and stencil write masks
+        let keep = !(self.stencil_write_mask as u8);
+        for uint::range(0, surface.height) |y| {
+            for uint::range(0, surface.width) |x| {
+                if !self.in_scissor(x, y) { loop; }
+                let at = y * surface.width + x;
+                if (mask & GL_COLOR_BUFFER_BIT) != 0 {
+                    self.write_color(surface, at, self.clear_rgba);
+                }
+                if (mask & GL_DEPTH_BUFFER_BIT) != 0 && self.depth_write {
+                    surface.depth[at] = 1.0;
+                }
+                if (mask & GL_STENCIL_BUFFER_BIT) != 0 {
+                    surface.stencil[at] &= keep;
+                }
+            }
+        }
+    }
+
+    fn viewport(&self, x: GLint, y: GLint, width: GLsizei, height: GLsizei) {
+        self.viewport = [x as int, y as int, width as int, height as int];
+    }
+
+    fn enable(&self, capability: GLenum) {
+        if capability == GL_DEPTH_TEST {
+            self.depth_test = true;
+        } else if capability == GL_STENCIL_TEST {
+            self.stencil_test = true;
+        } else if capability == GL_CULL_FACE {
+            self.cull = true;
+        } else if capability == GL_SCISSOR_TEST {
+            self.scissor_test = true;
+        } else if capability == GL_BLEND {
+            self.blend = true;
+        }
+    }
+
+    fn disable(&self, capability: GLenum) {
+        if capability == GL_DEPTH_TEST {
+            self.depth_test = false;
+        } else if capability == GL_STENCIL_TEST {
+            self.stencil_test = false;
+        } else if capability == GL_CULL_FACE {
+            self.cull = false;
+        } else if capability == GL_SCISSOR_TEST {
+            self.scissor_test = false;
+        } else if capability == GL_BLEND {
+            self.blend = false;
+        }
+    }
+
+    fn depth_func(&self, func: GLenum) {
+        self.depth_func = func;
+    }
+
+    fn depth_mask(&self, flag: GLboolean) {
+        self.depth_write = flag != GL_FALSE;
+    }
+
+    fn stencil_func(&self, func: GLenum, reference: GLint, mask: GLuint) {
+        self.stencil_func = func;
+        self.stencil_ref = if reference < 0 { 0 } else if reference > 255 { 255 } else { reference };
+        self.stencil_value_mask = mask;
+    }
+
+    fn stencil_op(&self, stencil_fail: GLenum, depth_fail: GLenum, depth_pass: GLenum) {
+        self.stencil_ops = [stencil_fail, depth_fail, depth_pass];
+    }
+
+    fn stencil_mask(&self, mask: GLuint) {
+        self.stencil_write_mask = mask;
+    }
+
+    fn cull_face(&self, face: GLenum) {
+        self.cull_face = face;
+    }
+
+    fn scissor(&self, x: GLint, y: GLint, width: GLsizei, height: GLsizei) {
+        self.scissor = [x as int, y as int, width as int, height as int];
+    }
+
+    fn color_mask(&self, red: GLboolean, green: GLboolean, blue: GLboolean, alpha: GLboolean) {
+        self.color_mask = [red != GL_FALSE, green != GL_FALSE, blue != GL_FALSE, alpha != GL_FALSE];
+    }
+
+    fn polygon_mode(&self, _face: GLenum, mode: GLenum) {
+        self.polygon_mode = mode;
+    }
+
+    fn blend_equation(&self, mode: GLenum) {
+        self.blend_equation = mode;
+    }
+
+    fn blend_func(&self, source: GLenum, destination: GLenum) {
+        self.blend_func = [source, destination];
+    }
+
+    fn gen_vertex_array(&self) -> GLuint {
+        alloc(&mut self.arrays, new_array())
+    }
+
+    fn bind_vertex_array(&self, array: GLuint) {
+        self.bound_array = array;
+    }
+
+    fn delete_vertex_array(&self, array: GLuint) {
+        if array != 0 && (array as uint) < self.arrays.len() {
+            self.arrays[array] = None;
+            if self.bound_array == array { self.bound_array = 0; }
+        }
+    }
+
+    fn gen_buffer(&self) -> GLuint {
+        alloc(&mut self.buffers, @mut BufferState { data: ~[], mapping: None })
+    }
+
+    fn bind_buffer(&self, target: GLenum, buffer: GLuint) {
+        if target == GL_ARRAY_BUFFER {
+            self.array_buffer = buffer;
+        } else if target == GL_ELEMENT_ARRAY_BUFFER {
+            self.array().element_buffer = buffer;
+        } else if target == GL_UNIFORM_BUFFER {
+            self.uniform_buffer = buffer;
+        }
+    }
+
+    fn buffer_data(&self, target: GLenum, data: &[u8], _usage: GLenum) {
+        match lookup(self.buffers, self.bound_buffer(target)) {
+            Some(buffer) => {
+                // Replacing the contents unmaps the buffer
+                buffer.data = vec::from_slice(data);
+                buffer.mapping = None;
+            }
+            None => (),
+        }
+    }
+
+    fn bind_buffer_base(&self, target: GLenum, index: GLuint, buffer: GLuint) {
+        if target == GL_UNIFORM_BUFFER && (index as uint) < MAX_UNIFORM_BUFFER_BINDINGS {
+            self.uniform_buffers[index] = buffer;
+            self.uniform_buffer = buffer;
+        }
+    }
+
+    fn buffer_sub_data(&self, target: GLenum, offset: uint, data: &[u8]) {
+        match lookup(self.buffers, self.bound_buffer(target)) {
+            Some(buffer) if buffer.mapping.is_none() && offset + data.len() <= buffer.data.len() => {
+                for vec::eachi(data) |i, byte| {
+                    buffer.data[offset + i] = *byte;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn map_buffer_range(&self, target: GLenum, offset: uint, length: uint, _access: GLbitfield) -> *mut u8 {
+        match lookup(self.buffers, self.bound_buffer(target)) {
+            Some(buffer) if buffer.mapping.is_none() && offset + length <= buffer.data.len() => {
+                // The copy stays where it is when moved into the buffer's
+                // state, until it is unmapped
+                let bytes = vec::slice(buffer.data, offset, offset + length);
+                let pointer = unsafe { transmute(to_ptr(bytes)) };
+                buffer.mapping = Some((offset, move bytes));
+                pointer
+            }
+            _ => ptr::mut_null(),
+        }
+    }
+
+    fn unmap_buffer(&self, target: GLenum) -> GLboolean {
+        let buffer = match lookup(self.buffers, self.bound_buffer(target)) {
+            Some(buffer) => buffer,
+            None => return GL_FALSE,
+        };
+        let mut mapping = None;
+        mapping <-> buffer.mapping;
+        match move mapping {
+            Some((offset, move bytes)) => {
+                for vec::eachi(bytes) |i, byte| {
+                    buffer.data[offset + i] = *byte;
+                }
+                GL_TRUE
+            }
+            None => GL_FALSE,
+        }
+    }
+
+    fn delete_buffer(&self, buffer: GLuint) {
+        if buffer != 0 && (buffer as uint) < self.buffers.len() {
+            self.buffers[buffer] = None;
+        }
+    }
+
+    fn fence_sync(&self) -> GLsync {
+        // Any handle but null will do, as no fence is ever waited for
+        unsafe { transmute(1u) }
+    }
+
+    fn client_wait_sync(&self, _sync: GLsync, _flags: GLbitfield, _timeout: GLuint64) -> GLenum {
+        GL_ALREADY_SIGNALED
+    }
+
+    fn delete_sync(&self, _sync: GLsync) {}
+
+    fn create_shader(&self, kind: GLenum) -> GLuint {
+        alloc(&mut self.shaders, @mut ShaderState { kind: kind, source: ~"" })
+    }
+
+    fn shader_source(&self, shader: GLuint, source: &str) {
+        match lookup(self.shaders, shader) {
+            Some(state) => state.source = str::from_slice(source),
+            None => (),
+        }
+    }
+
+    fn compile_shader(&self, _shader: GLuint) {}
+
+    fn get_shader_iv(&self, shader: GLuint, pname: GLenum) -> GLint {
+        if pname == GL_COMPILE_STATUS {
+            match lookup(self.shaders, shader) {
+                Some(_) => GL_TRUE as GLint,
+                None => GL_FALSE as GLint,
+            }
+        } else {
+            0
+        }
+    }
+
+    fn get_shader_info_log(&self, _shader: GLuint) -> ~str {
+        ~""
+    }
+
+    fn delete_shader(&self, shader: GLuint) {
+        if shader != 0 && (shader as uint) < self.shaders.len() {
+            self.shaders[shader] = None;
+        }
+    }
+
+    fn create_program(&self) -> GLuint {
+        alloc(&mut self.programs, @mut ProgramState {
+            shaders: ~[],
+            linked: false,
+            log: ~"",
+            attributes: ~[],
+            uniforms: ~[],
+            values: ~[],
+            blocks: ~[],
+            block_bindings: ~[],
+            geometry: None,
+            emulation: None,
+        })
+    }
+
+    fn attach_shader(&self, program: GLuint, shader: GLuint) {
+        match lookup(self.programs, program) {
+            Some(state) => state.shaders.push(shader),
+            None => (),
+        }
+    }
+
+    fn detach_shader(&self, program: GLuint, shader: GLuint) {
+        match lookup(self.programs, program) {
+            Some(state) => state.shaders = state.shaders.filtered(|s| *s != shader),
+            None => (),
+        }
+    }
+
+    fn bind_frag_data_location(&self, _program: GLuint, _color: GLuint, _name: &str) {}
+
+    fn link_program(&self, program: GLuint) {
+        let state = match lookup(self.programs, program) {
+            Some(state) => state,
+            None => return,
+        };
+
+        let mut attributes = ~[];
+        let mut uniforms = ~[];
+        let mut blocks = ~[];
+        let mut stages = 0u;
+        let mut geometry = None;
+        for state.shaders.each |name| {
+            match lookup(self.shaders, *name) {
+                Some(shader) => {
+                    if shader.kind == GL_VERTEX_SHADER {
+                        attributes = variables(shader.source, "in");
+                        stages |= 1;
+                    } else if shader.kind == GL_FRAGMENT_SHADER {
+                        stages |= 2;
+                    } else if shader.kind == GL_GEOMETRY_SHADER {
+                        geometry = Some((primitive_layout(shader.source, "in"),
+                                         primitive_layout(shader.source, "out")));
+                    }
+                    for variables(shader.source, "uniform").each |uniform| {
+                        if !uniforms.any(|u| u.name == uniform.name) { uniforms.push(copy *uniform); }
+                    }
+                    for uniform_blocks(shader.source).each |block| {
+                        if !blocks.any(|b| b.name == block.name) { blocks.push(copy *block); }
+                    }
+                }
+                None => (),
+            }
+        }
+
+        let undeclared = match geometry {
+            Some((input, output)) => input == 0 || output == 0,
+            None => false,
+        };
+        state.linked = stages == 3 && !undeclared;
+        state.log = if stages != 3 {
+            ~"error: a vertex and a fragment shader must be attached"
+        } else if undeclared {
+            ~"error: the geometry shader must declare the primitives it takes and emits"
+        } else {
+            ~""
+        };
+        state.geometry = geometry;
+        state.values = vec::from_elem(uniforms.len(), Unset);
+        state.attributes = move attributes;
+        state.uniforms = move uniforms;
+        state.block_bindings = vec::from_elem(blocks.len(), 0);
+        state.blocks = move blocks;
+    }
+
+    fn get_program_iv(&self, program: GLuint, pname: GLenum) -> GLint {
+        match lookup(self.programs, program) {
+            Some(state) if pname == GL_LINK_STATUS => {
+                if state.linked { GL_TRUE as GLint } else { GL_FALSE as GLint }
+            }
+            Some(state) if pname == GL_INFO_LOG_LENGTH => (state.log.len() + 1) as GLint,
+            Some(state) if pname == GL_ACTIVE_ATTRIBUTES => state.attributes.len() as GLint,
+            Some(state) if pname == GL_ACTIVE_UNIFORMS => state.uniforms.len() as GLint,
+            _ => 0,
+        }
+    }
+
+    fn get_program_info_log(&self, program: GLuint) -> ~str {
+        match lookup(self.programs, program) {
+            Some(state) => copy state.log,
+            None => ~"",
+        }
+    }
+
+    fn use_program(&self, program: GLuint) {
+        self.current_program = program;
+    }
+
+    fn delete_program(&self, program: GLuint) {
+        if program != 0 && (program as uint) < self.programs.len() {
+            self.programs[program] = None;
+        }
+    }
+
+    fn emulate_program(&self, program: GLuint, emulation: @Emulation) {
+        match lookup(self.programs, program) {
+            Some(state) => state.emulation = Some(emulation),
+            None => (),
+        }
+    }
+
+    fn get_active_attrib(&self, program: GLuint, index: GLuint) -> (~str, GLint, GLenum) {
+        match lookup(self.programs, program) {
+            Some(state) if (index as uint) < state.attributes.len() => {
+                let attribute = &state.attributes[index];
+                (copy attribute.name, attribute.size, attribute.kind)
+            }
+            _ => (~"", 0, 0),
+        }
+    }
+
+    fn get_active_uniform(&self, program: GLuint, index: GLuint) -> (~str, GLint, GLenum) {
+        match lookup(self.programs, program) {
+            Some(state) if (index as uint) < state.uniforms.len() => {
+                let uniform = &state.uniforms[index];
+                (copy uniform.name, uniform.size, uniform.kind)
+            }
+            _ => (~"", 0, 0),
+        }
+    }
+
+    fn get_attrib_location(&self, program: GLuint, name: &str) -> GLint {
+        match lookup(self.programs, program) {
+            Some(state) => match vec::position(state.attributes, |a| str::eq_slice(a.name, name)) {
+                Some(i) => attribute_locations(state.attributes)[i] as GLint,
+                None => -1,
+            },
+            None => -1,
+        }
+    }
+
+    fn get_uniform_block_index(&self, program: GLuint, name: &str) -> GLuint {
+        match lookup(self.programs, program) {
+            Some(state) => match vec::position(state.blocks, |b| str::eq_slice(b.name, name)) {
+                Some(i) => i as GLuint,
+                None => GL_INVALID_INDEX,
+            },
+            None => GL_INVALID_INDEX,
+        }
+    }
+
+    fn uniform_block_binding(&self, program: GLuint, block: GLuint, binding: GLuint) {
+        match lookup(self.programs, program) {
+            Some(state) if (block as uint) < state.blocks.len() &&
+                           (binding as uint) < MAX_UNIFORM_BUFFER_BINDINGS => {
+                state.block_bindings[block] = binding as uint;
+            }
+            _ => (),
+        }
+    }
+
+    fn get_uniform_location(&self, program: GLuint, name: &str) -> GLint {
+        match lookup(self.programs, program) {
+            Some(state) => match vec::position(state.uniforms, |u| str::eq_slice(u.name, name)) {
+                Some(i) => i as GLint,
+                None => -1,
+            },
+            None => -1,
+        }
+    }
+
+    fn enable_vertex_attrib_array(&self, index: GLuint) {
+        if (index as uint) < MAX_ATTRIBS {
+            self.array().attribs[index].enabled = true;
+        }
+    }
+
+    fn vertex_attrib_pointer(&self, index: GLuint, size: GLint, _kind: GLenum,
+                             _normalized: GLboolean, stride: GLsizei, offset: uint) {
+        if (index as uint) >= MAX_ATTRIBS { return; }
+
+        let array = self.array();
+        let pointer = array.attribs[index];
+        array.attribs[index] = AttribPointer {
+            size: size as uint,
+            stride: stride as uint,
+            offset: offset,
+            buffer: self.array_buffer,
+            .. pointer
+        };
+    }
+
+    fn vertex_attrib_divisor(&self, index: GLuint, divisor: GLuint) {
+        if (index as uint) < MAX_ATTRIBS {
+            self.array().attribs[index].divisor = divisor as uint;
+        }
+    }
+
+    fn uniform_1i(&self, location: GLint, x: GLint) {
+        self.current_uniform(location, Int(x));
+    }
+
+    fn uniform_1f(&self, location: GLint, x: GLfloat) {
+        self.current_uniform(location, Floats(~[x]));
+    }
+
+    fn uniform_3f(&self, location: GLint, x: GLfloat, y: GLfloat, z: GLfloat) {
+        self.current_uniform(location, Floats(~[x, y, z]));
+    }
+
+    fn uniform_matrix_4fv(&self, location: GLint, transpose: GLboolean, value: &[GLfloat]) {
+        let m = if transpose == GL_TRUE {
+            vec::from_fn(16, |i| value[(i % 4) * 4 + i / 4])
+        } else {
+            vec::from_slice(value)
+        };
+        self.current_uniform(location, Floats(move m));
+    }
+
+    fn gen_texture(&self) -> GLuint {
+        alloc(&mut self.textures, @mut Texture {
+            image: None,
+            min_filter: GL_NEAREST_MIPMAP_LINEAR,
+            mag_filter: GL_LINEAR,
+            wrap_s: GL_REPEAT,
+            wrap_t: GL_REPEAT,
+            border: [0.0, 0.0, 0.0, 0.0],
+        })
+    }
+
+    fn active_texture(&self, unit: GLenum) {
+        self.active_unit = (unit - GL_TEXTURE0) as uint;
+    }
+
+    fn bind_texture(&self, _target: GLenum, texture: GLuint) {
+        self.units[self.active_unit] = texture;
+    }
+
+    fn tex_image_2d(&self, _target: GLenum, level: GLint, internal_format: GLint,
+                    width: GLsizei, height: GLsizei, format: GLenum, _kind: GLenum,
+                    data: &[u8]) {
+        // Only the base level is sampled
+        if level != 0 { return; }
+
+        let texture = match self.bound_texture() {
+            Some(texture) => texture,
+            None => return,
+        };
+        // The texture may be attached to the framebuffer being drawn into
+        self.flush();
+
+        let (w, h) = (width as uint, height as uint);
+        if data.is_empty() {
+            texture.image = Some(@Image { width: w, height: h, texels: vec::from_elem(w * h * 4, 0.0f32) });
+            return;
+        }
+
+        let channels = if format == GL_RED {
+            1
+        } else if format == GL_RG {
+            2
+        } else if format == GL_RGB {
+            3
+        } else {
+            4
+        };
+        let internal = internal_format as GLenum;
+        let srgb = internal == GL_SRGB8 || internal == GL_SRGB8_ALPHA8;
+        let row = (w * channels + self.unpack_alignment - 1) / self.unpack_alignment
+                  * self.unpack_alignment;
+
+        let mut texels = vec::with_capacity(w * h * 4);
+        for uint::range(0, h) |j| {
+            for uint::range(0, w) |i| {
+                let at = j * row + i * channels;
+                // sRGB textures are converted to linear values when sampled,
+                // which is as good as converting them up front
+                let c = |k: uint| {
+                    let x = data[at + k] as f32 / 255.0;
+                    if srgb && k < 3 { srgb_to_linear(x) } else { x }
+                };
+                match channels {
+                    1 => { texels.push(c(0)); texels.push(0.0); texels.push(0.0); texels.push(1.0); }
+                    2 => { texels.push(c(0)); texels.push(c(1)); texels.push(0.0); texels.push(1.0); }
+                    3 => { texels.push(c(0)); texels.push(c(1)); texels.push(c(2)); texels.push(1.0); }
+                    _ => { texels.push(c(0)); texels.push(c(1)); texels.push(c(2)); texels.push(c(3)); }
+                }
+            }
+        }
+
+        texture.image = Some(@Image { width: w, height: h, texels: move texels });
+    }
+
+    fn tex_parameter_i(&self, _target: GLenum, pname: GLenum, param: GLint) {
+        let texture = match self.bound_texture() {
+            Some(texture) => texture,
+            None => return,
+        };
+
+        let value = param as GLenum;
+        if pname == GL_TEXTURE_MIN_FILTER {
+            texture.min_filter = value;
+        } else if pname == GL_TEXTURE_MAG_FILTER {
+            texture.mag_filter = value;
+        } else if pname == GL_TEXTURE_WRAP_S {
+            texture.wrap_s = value;
+        } else if pname == GL_TEXTURE_WRAP_T {
+            texture.wrap_t = value;
+        }
+    }
+
+    fn generate_mipmap(&self, _target: GLenum) {}
+
+    fn pixel_store_i(&self, pname: GLenum, param: GLint) {
+        if pname == GL_UNPACK_ALIGNMENT {
+            self.unpack_alignment = param as uint;
+        }
+    }
+
+    fn delete_texture(&self, texture: GLuint) {
+        if texture != 0 && (texture as uint) < self.textures.len() {
+            self.textures[texture] = None;
+        }
+    }
+
+    fn gen_sampler(&self) -> GLuint {
+        alloc(&mut self.samplers, @mut SamplerState {
+            min_filter: GL_NEAREST_MIPMAP_LINEAR,
+            mag_filter: GL_LINEAR,
+            wrap_s: GL_REPEAT,
+            wrap_t: GL_REPEAT,
+            border: [0.0, 0.0, 0.0, 0.0],
+        })
+    }
+
+    fn bind_sampler(&self, unit: GLuint, sampler: GLuint) {
+        if (unit as uint) < MAX_TEXTURE_UNITS {
+            self.sampler_units[unit] = sampler;
+        }
+    }
+
+    fn sampler_parameter_i(&self, sampler: GLuint, pname: GLenum, param: GLint) {
+        let state = match lookup(self.samplers, sampler) {
+            Some(state) => state,
+            None => return,
+        };
+
+        let value = param as GLenum;
+        if pname == GL_TEXTURE_MIN_FILTER {
+            state.min_filter = value;
+        } else if pname == GL_TEXTURE_MAG_FILTER {
+            state.mag_filter = value;
+        } else if pname == GL_TEXTURE_WRAP_S {
+            state.wrap_s = value;
+        } else if pname == GL_TEXTURE_WRAP_T {
+            state.wrap_t = value;
+        }
+    }
+
+    fn sampler_parameter_f(&self, _sampler: GLuint, _pname: GLenum, _param: GLfloat) {}
+
+    fn sampler_parameter_fv(&self, sampler: GLuint, pname: GLenum, params: &[GLfloat]) {
+        match lookup(self.samplers, sampler) {
+            Some(state) if pname == GL_TEXTURE_BORDER_COLOR && params.len() >= 4 => {
+                state.border = [params[0], params[1], params[2], params[3]];
+            }
+            _ => (),
+        }
+    }
+
+    fn delete_sampler(&self, sampler: GLuint) {
+        if sampler != 0 && (sampler as uint) < self.samplers.len() {
+            self.samplers[sampler] = None;
+            for uint::range(0, MAX_TEXTURE_UNITS) |unit| {
+                if self.sampler_units[unit] == sampler { self.sampler_units[unit] = 0; }
+            }
+        }
+    }
+
+    fn gen_framebuffer(&self) -> GLuint {
+        alloc(&mut self.framebuffers, @mut FramebufferState { color: 0, depth: 0, stencil: 0, surface: None })
+    }
+
+    fn bind_framebuffer(&self, _target: GLenum, framebuffer: GLuint) {
+        self.flush();
+        self.bound_framebuffer = framebuffer;
+    }
+
+    fn framebuffer_texture_2d(&self, _target: GLenum, attachment: GLenum, _texture_target: GLenum,
+                              texture: GLuint, _level: GLint) {
+        let framebuffer = match lookup(self.framebuffers, self.bound_framebuffer) {
+            Some(framebuffer) => framebuffer,
+            None => return,
+        };
+        self.flush();
+
+        // Only color is attached as a texture by the tutorials
+        if attachment == GL_COLOR_ATTACHMENT0 {
+            framebuffer.color = texture;
+        }
+    }
+
+    fn framebuffer_renderbuffer(&self, _target: GLenum, attachment: GLenum, _renderbuffer_target: GLenum,
+                                renderbuffer: GLuint) {
+        let framebuffer = match lookup(self.framebuffers, self.bound_framebuffer) {
+            Some(framebuffer) => framebuffer,
+            None => return,
+        };
+        self.flush();
+
+        if attachment == GL_DEPTH_ATTACHMENT || attachment == GL_DEPTH_STENCIL_ATTACHMENT {
+            framebuffer.depth = renderbuffer;
+        }
+        if attachment == GL_STENCIL_ATTACHMENT || attachment == GL_DEPTH_STENCIL_ATTACHMENT {
+            framebuffer.stencil = renderbuffer;
+        }
+    }
+
+    fn check_framebuffer_status(&self, _target: GLenum) -> GLenum {
+        match lookup(self.framebuffers, self.bound_framebuffer) {
+            Some(framebuffer) => self.framebuffer_status(framebuffer),
+            None => GL_FRAMEBUFFER_COMPLETE,
+        }
+    }
+
+    fn delete_framebuffer(&self, framebuffer: GLuint) {
+        if framebuffer != 0 && (framebuffer as uint) < self.framebuffers.len() {
+            // Deleting the bound framebuffer binds the window's
+            if framebuffer == self.bound_framebuffer {
+                self.flush();
+                self.bound_framebuffer = 0;
+            }
+            self.framebuffers[framebuffer] = None;
+        }
+    }
+
+    fn gen_renderbuffer(&self) -> GLuint {
+        alloc(&mut self.renderbuffers, @mut RenderbufferState {
+            width: 0,
+            height: 0,
+            depth: ~[],
+            stencil: ~[],
+        })
+    }
+
+    fn bind_renderbuffer(&self, _target: GLenum, renderbuffer: GLuint) {
+        self.bound_renderbuffer = renderbuffer;
+    }
+
+    fn renderbuffer_storage(&self, _target: GLenum, _internal_format: GLenum,
+                            width: GLsizei, height: GLsizei) {
+        let renderbuffer = match lookup(self.renderbuffers, self.bound_renderbuffer) {
+            Some(renderbuffer) => renderbuffer,
+            None => return,
+        };
+        self.flush();
+
+        let (w, h) = (width as uint, height as uint);
+        renderbuffer.width = w;
+        renderbuffer.height = h;
+        renderbuffer.depth = vec::from_elem(w * h, 1.0f32);
+        renderbuffer.stencil = vec::from_elem(w * h, 0u8);
+    }
+
+    fn delete_renderbuffer(&self, renderbuffer: GLuint) {
+        if renderbuffer != 0 && (renderbuffer as uint) < self.renderbuffers.len() {
+            self.renderbuffers[renderbuffer] = None;
+            if renderbuffer == self.bound_renderbuffer { self.bound_renderbuffer = 0; }
+        }
+    }
+
+    fn draw_arrays(&self, mode: GLenum, first: GLint, count: GLsizei) {
+        self.draw_arrays_instanced(mode, first, count, 1);
+    }
+
+    fn draw_elements(&self, mode: GLenum, count: GLsizei, kind: GLenum, offset: uint) {
+        self.draw_elements_instanced(mode, count, kind, offset, 1);
+    }
+
+    fn draw_arrays_instanced(&self, mode: GLenum, first: GLint, count: GLsizei, instances: GLsizei) {
+        let indices = vec::from_fn(count as uint, |i| first as uint + i);
+        self.draw(mode, indices, instances as uint);
+    }
+
+    fn draw_elements_instanced(&self, mode: GLenum, count: GLsizei, kind: GLenum, offset: uint,
+                               instances: GLsizei) {
+        let elements = self.buffer_bytes(self.array().element_buffer);
+        let first = offset / index_size(kind);
+        let indices = vec::from_fn(count as uint, |i| read_index(elements, kind, first + i));
+        self.draw(mode, indices, instances as uint);
+    }
+
+    fn read_pixels(&self, x: GLint, y: GLint, width: GLsizei, height: GLsizei) -> ~[u8] {
+        let surface = match self.target() {
+            Some(surface) => surface,
+            None => return ~[],
+        };
+        let mut pixels = vec::with_capacity((width * height * 4) as uint);
+        for uint::range(y as uint, (y + height) as uint) |row| {
+            let start = (row * surface.width + x as uint) * 4;
+            for uint::range(start, start + width as uint * 4) |i| {
+                pixels.push(to_byte(surface.color[i]));
+            }
+        }
+        move pixels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glcore::*;
+    use buffer::{VertexArray, Buffer};
+    use context::Context;
+    use gl::Gl;
+    use shader::{Shader, Program, Source, VertexShader, FragmentShader};
+    use state::{RenderState, Blend};
+    use vertex::{Vertex2, bind_attributes};
+    use soft::{SoftGl, Emulation, VertexOut, owns_edge};
+
+    #[test]
+    fn gives_a_shared_diagonal_to_one_triangle() {
+        // The two halves of a square, wound either way
+        assert owns_edge(4.0, 4.0, 0.0, 0.0, 16.0) && !owns_edge(0.0, 0.0, 4.0, 4.0, 16.0);
+        assert owns_edge(0.0, 0.0, 4.0, 4.0, -16.0) && !owns_edge(4.0, 4.0, 0.0, 0.0, -16.0);
+    }
+
+    #[test]
+    fn gives_a_shared_horizontal_edge_to_the_triangle_below() {
+        // (0, 0), (4, 0), (2, 2) above the edge, and (0, 0), (2, -2), (4, 0) below
+        assert !owns_edge(0.0, 0.0, 4.0, 0.0, 8.0);
+        assert owns_edge(4.0, 0.0, 0.0, 0.0, 8.0);
+    }
+
+    /// Two triangles sharing the diagonal of a 4x4 target, which runs
+    /// through the centres of the pixels along it, add a quarter to every
+    /// pixel they draw.
+    #[test]
+    fn draws_each_pixel_of_a_split_square_once() {
+        let soft = @SoftGl::new(4, 4);
+        let ctx = Context::new(soft as @Gl, 4, 4);
+        let pixels = {
+            let compile = |kind, text: &str| match Shader::compile(&ctx, kind, &Source::new(text)) {
+                Ok(move shader) => move shader,
+                Err(move err) => fail(err.to_str()),
+            };
+            let shaders = [compile(VertexShader, "#version 330\nin vec2 position;\nvoid main() {}\n"),
+                           compile(FragmentShader, "#version 330\nout vec4 outColor;\nvoid main() {}\n")];
+            let program = match Program::link(&ctx, shaders) {
+                Ok(move program) => move program,
+                Err(move err) => fail(err.to_str()),
+            };
+            program.emulate(Emulation {
+                vertex: |_uniforms, inputs| {
+                    let position = inputs.get("position");
+                    VertexOut { position: [position[0], position[1], 0.0, 1.0], varyings: ~[] }
+                },
+                geometry: None,
+                fragment: |_uniforms, _varyings| [0.25, 0.25, 0.25, 0.25]
+            });
+
+            let vao = VertexArray::new(&ctx);
+            vao.bind();
+            let corners = [Vertex2 { position: [-1.0, -1.0] }, Vertex2 { position: [1.0, -1.0] },
+                           Vertex2 { position: [1.0, 1.0] }, Vertex2 { position: [-1.0, 1.0] }];
+            let vertices = [corners[0], corners[1], corners[2], corners[0], corners[2], corners[3]];
+            let vbo = Buffer::new(&ctx, GL_ARRAY_BUFFER, vertices, GL_STATIC_DRAW);
+            vbo.bind();
+            bind_attributes(&program, &corners[0]);
+
+            program.bind();
+            ctx.cache().apply(&RenderState { blend: Some(Blend::additive()), .. RenderState::default() });
+            ctx.gl().clear(GL_COLOR_BUFFER_BIT);
+            ctx.gl().draw_arrays(GL_TRIANGLES, 0, 6);
+            ctx.gl().read_pixels(0, 0, 4, 4)
+        };
+        ctx.destroy();
+
+        // 0 where a pixel was skipped and 128 where it was drawn twice
+        assert pixels.len() == 64;
+        assert pixels.all(|channel| *channel == 64);
+    }
+}
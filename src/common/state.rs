@@ -0,0 +1,497 @@
+/*!
+ * Fixed-function render state, set through a cache that skips calls
+ * changing nothing.
+ *
+ * A `RenderState` describes how a draw is tested and written: blending,
+ * the depth test, culling, the scissor rectangle, the viewport, the color
+ * mask and the polygon mode. `StateCache::apply` makes the calls needed to
+ * go from what is set to it, and no others, so a program can apply the
+ * state each of its draws needs without caring what the draw before it
+ * left behind.
+ *
+ * The cache of a `Context` remembers what it last set and, as GL's
+ * initial state is known, starts out with that. It only sees the calls
+ * made through it, so everything that sets the state it keeps should go
+ * through it, the current program and vertex array included, which the
+ * wrappers bind through it; anything that goes round it must `invalidate`
+ * it. It counts the calls it makes and those it skips.
+ */
+
+use glcore::*;
+
+use gl::Gl;
+
+/// A rectangle of the framebuffer, in pixels from its bottom-left corner.
+pub struct Rect {
+    x: GLint,
+    y: GLint,
+    width: GLsizei,
+    height: GLsizei,
+}
+
+impl Rect {
+    static fn new(x: GLint, y: GLint, width: GLsizei, height: GLsizei) -> Rect {
+        Rect { x: x, y: y, width: width, height: height }
+    }
+}
+
+pure fn same_rect(a: &Rect, b: &Rect) -> bool {
+    a.x == b.x && a.y == b.y && a.width == b.width && a.height == b.height
+}
+
+/**
+ * How a fragment is combined with the color already drawn: the source
+ * factor times it, combined by `equation` with the destination factor
+ * times what is drawn.
+ *
+ * The presets expect the fragment's color to be premultiplied by its
+ * alpha, as `texture::premultiply` leaves the texels of a texture, so that
+ * where it is transparent it leaves what is drawn as it was.
+ */
+pub struct Blend {
+    /// `GL_FUNC_ADD`, `GL_FUNC_SUBTRACT`, `GL_FUNC_REVERSE_SUBTRACT`,
+    /// `GL_MIN` or `GL_MAX`.
+    equation: GLenum,
+    source: GLenum,
+    destination: GLenum,
+}
+
+impl Blend {
+    /// Draws the fragment over what is drawn, letting `1 - alpha` of it
+    /// show through.
+    static fn premultiplied_alpha() -> Blend {
+        Blend { equation: GL_FUNC_ADD, source: GL_ONE, destination: GL_ONE_MINUS_SRC_ALPHA }
+    }
+
+    /// Adds the fragment to what is drawn, brightening it, as light does.
+    static fn additive() -> Blend {
+        Blend { equation: GL_FUNC_ADD, source: GL_ONE, destination: GL_ONE }
+    }
+
+    /// Multiplies what is drawn by the fragment, darkening it, as tinted
+    /// glass does.
+    static fn multiply() -> Blend {
+        // dst * src + dst * (1 - alpha), which is dst where alpha is 0
+        Blend { equation: GL_FUNC_ADD, source: GL_DST_COLOR, destination: GL_ONE_MINUS_SRC_ALPHA }
+    }
+}
+
+/// The fixed-function state a draw is made with.
+pub struct RenderState {
+    /// How fragments are blended with what is drawn, or `None` to replace it.
+    blend: Option<Blend>,
+    /// The comparison with the depth buffer fragments must pass, or `None`
+    /// to pass them all.
+    depth_test: Option<GLenum>,
+    /// Whether fragments write their depth, if they pass.
+    depth_write: bool,
+    /// The faces not drawn, `GL_FRONT`, `GL_BACK` or `GL_FRONT_AND_BACK`,
+    /// or `None` to draw both.
+    cull_face: Option<GLenum>,
+    /// The rectangle drawing is confined to, or `None` for everywhere.
+    scissor: Option<Rect>,
+    /// The rectangle clip space is mapped to, or `None` to keep the one
+    /// set when the framebuffer was bound.
+    viewport: Option<Rect>,
+    /// Which of red, green, blue and alpha are written.
+    color_mask: [bool * 4],
+    /// How triangles are drawn: `GL_FILL`, or as outlines with `GL_LINE`
+    /// or corners with `GL_POINT`.
+    polygon_mode: GLenum,
+}
+
+impl RenderState {
+    /// GL's initial state: no blending, depth test, culling or scissoring,
+    /// and filled triangles written in every channel.
+    static fn default() -> RenderState {
+        RenderState {
+            blend: None,
+            depth_test: None,
+            depth_write: true,
+            cull_face: None,
+            scissor: None,
+            viewport: None,
+            color_mask: [true, true, true, true],
+            polygon_mode: GL_FILL,
+        }
+    }
+}
+
+/// The capabilities a `StateCache` keeps track of.
+const CAPABILITIES: [GLenum * 5] = [GL_BLEND, GL_CULL_FACE, GL_DEPTH_TEST, GL_SCISSOR_TEST, GL_STENCIL_TEST];
+
+/// How many of the calls asked of a `StateCache` it made, and how many it
+/// skipped as they would have changed nothing.
+pub struct CacheStats {
+    made: uint,
+    elided: uint,
+}
+
+impl CacheStats : ToStr {
+    pure fn to_str(&self) -> ~str {
+        fmt!("%u calls made, %u elided", self.made, self.elided)
+    }
+}
+
+/**
+ * What GL state was last set to, through `gl`. Each setter mirrors the
+ * `Gl` method of the same name, skipping the call if the state already
+ * has the value asked for. `None` is state not known.
+ */
+pub struct StateCache {
+    priv gl: @Gl,
+    /// Whether each of `CAPABILITIES` is enabled.
+    priv mut enabled: ~[Option<bool>],
+    priv mut depth_func: Option<GLenum>,
+    priv mut depth_mask: Option<bool>,
+    priv mut cull_face: Option<GLenum>,
+    priv mut scissor: Option<Rect>,
+    priv mut viewport: Option<Rect>,
+    priv mut color_mask: Option<[bool * 4]>,
+    priv mut polygon_mode: Option<GLenum>,
+    priv mut blend_equation: Option<GLenum>,
+    priv mut blend_func: Option<(GLenum, GLenum)>,
+    priv mut clear_color: Option<[GLfloat * 4]>,
+    priv mut program: Option<GLuint>,
+    priv mut vertex_array: Option<GLuint>,
+    priv mut stats: CacheStats,
+}
+
+impl StateCache {
+    /// A cache of the initial state of a context drawing through `gl` into
+    /// a `width` by `height` window.
+    static fn new(gl: @Gl, width: uint, height: uint) -> StateCache {
+        StateCache {
+            gl: gl,
+            enabled: vec::from_elem(CAPABILITIES.len(), Some(false)),
+            depth_func: Some(GL_LESS),
+            depth_mask: Some(true),
+            cull_face: Some(GL_BACK),
+            // The scissor rectangle starts out as the window, as the
+            // viewport does
+            scissor: Some(Rect::new(0, 0, width as GLsizei, height as GLsizei)),
+            viewport: Some(Rect::new(0, 0, width as GLsizei, height as GLsizei)),
+            color_mask: Some([true, true, true, true]),
+            polygon_mode: Some(GL_FILL),
+            blend_equation: Some(GL_FUNC_ADD),
+            blend_func: Some((GL_ONE, GL_ZERO)),
+            clear_color: Some([0.0, 0.0, 0.0, 0.0]),
+            program: Some(0),
+            vertex_array: Some(0),
+            stats: CacheStats { made: 0, elided: 0 },
+        }
+    }
+
+    /// Counts a call, which is made unless `unchanged`. Returns whether to make it.
+    priv fn count(&self, unchanged: bool) -> bool {
+        if unchanged {
+            self.stats.elided += 1;
+        } else {
+            self.stats.made += 1;
+        }
+        !unchanged
+    }
+
+    /// Sets the state `state` describes, leaving the viewport alone if it
+    /// has none.
+    fn apply(&self, state: &RenderState) {
+        match state.blend {
+            Some(blend) => {
+                self.enable(GL_BLEND);
+                self.blend_equation(blend.equation);
+                self.blend_func(blend.source, blend.destination);
+            }
+            None => self.disable(GL_BLEND),
+        }
+        match state.depth_test {
+            Some(func) => {
+                self.enable(GL_DEPTH_TEST);
+                self.depth_func(func);
+            }
+            None => self.disable(GL_DEPTH_TEST),
+        }
+        self.depth_mask(if state.depth_write { GL_TRUE } else { GL_FALSE });
+        match state.cull_face {
+            Some(face) => {
+                self.enable(GL_CULL_FACE);
+                self.cull_face(face);
+            }
+            None => self.disable(GL_CULL_FACE),
+        }
+        match state.scissor {
+            Some(rect) => {
+                self.enable(GL_SCISSOR_TEST);
+                self.scissor(rect.x, rect.y, rect.width, rect.height);
+            }
+            None => self.disable(GL_SCISSOR_TEST),
+        }
+        match state.viewport {
+            Some(rect) => self.viewport(rect.x, rect.y, rect.width, rect.height),
+            None => (),
+        }
+        let mask = state.color_mask;
+        self.color_mask(mask[0], mask[1], mask[2], mask[3]);
+        self.polygon_mode(state.polygon_mode);
+    }
+
+    /// Turns `capability` on or off.
+    priv fn set_enabled(&self, capability: GLenum, on: bool) {
+        let i = match vec::position(CAPABILITIES, |c| *c == capability) {
+            Some(i) => i,
+            None => fail(fmt!("StateCache: capability 0x%04x is not cached", capability as uint)),
+        };
+        if self.count(self.enabled[i] == Some(on)) {
+            if on { self.gl.enable(capability) } else { self.gl.disable(capability) }
+            self.enabled[i] = Some(on);
+        }
+    }
+
+    /// Enables `capability`, which must be `GL_BLEND`, `GL_CULL_FACE`,
+    /// `GL_DEPTH_TEST`, `GL_SCISSOR_TEST` or `GL_STENCIL_TEST`.
+    fn enable(&self, capability: GLenum) {
+        self.set_enabled(capability, true);
+    }
+
+    /// Disables `capability`, one of those `enable` takes.
+    fn disable(&self, capability: GLenum) {
+        self.set_enabled(capability, false);
+    }
+
+    fn depth_func(&self, func: GLenum) {
+        if self.count(self.depth_func == Some(func)) {
+            self.gl.depth_func(func);
+            self.depth_func = Some(func);
+        }
+    }
+
+    fn depth_mask(&self, flag: GLboolean) {
+        let write = flag != GL_FALSE;
+        if self.count(self.depth_mask == Some(write)) {
+            self.gl.depth_mask(flag);
+            self.depth_mask = Some(write);
+        }
+    }
+
+    fn cull_face(&self, face: GLenum) {
+        if self.count(self.cull_face == Some(face)) {
+            self.gl.cull_face(face);
+            self.cull_face = Some(face);
+        }
+    }
+
+    fn scissor(&self, x: GLint, y: GLint, width: GLsizei, height: GLsizei) {
+        let rect = Rect::new(x, y, width, height);
+        let unchanged = match self.scissor {
+            Some(ref current) => same_rect(current, &rect),
+            None => false,
+        };
+        if self.count(unchanged) {
+            self.gl.scissor(x, y, width, height);
+            self.scissor = Some(rect);
+        }
+    }
+
+    fn viewport(&self, x: GLint, y: GLint, width: GLsizei, height: GLsizei) {
+        let rect = Rect::new(x, y, width, height);
+        let unchanged = match self.viewport {
+            Some(ref current) => same_rect(current, &rect),
+            None => false,
+        };
+        if self.count(unchanged) {
+            self.gl.viewport(x, y, width, height);
+            self.viewport = Some(rect);
+        }
+    }
+
+    fn color_mask(&self, red: bool, green: bool, blue: bool, alpha: bool) {
+        let unchanged = match self.color_mask {
+            Some(mask) => mask[0] == red && mask[1] == green && mask[2] == blue && mask[3] == alpha,
+            None => false,
+        };
+        if self.count(unchanged) {
+            let flag = |on: bool| if on { GL_TRUE } else { GL_FALSE };
+            self.gl.color_mask(flag(red), flag(green), flag(blue), flag(alpha));
+            self.color_mask = Some([red, green, blue, alpha]);
+        }
+    }
+
+    /// Sets the polygon mode of both faces, the only choice in a core profile.
+    fn polygon_mode(&self, mode: GLenum) {
+        if self.count(self.polygon_mode == Some(mode)) {
+            self.gl.polygon_mode(GL_FRONT_AND_BACK, mode);
+            self.polygon_mode = Some(mode);
+        }
+    }
+
+    fn blend_equation(&self, mode: GLenum) {
+        if self.count(self.blend_equation == Some(mode)) {
+            self.gl.blend_equation(mode);
+            self.blend_equation = Some(mode);
+        }
+    }
+
+    fn blend_func(&self, source: GLenum, destination: GLenum) {
+        let unchanged = match self.blend_func {
+            Some((s, d)) => s == source && d == destination,
+            None => false,
+        };
+        if self.count(unchanged) {
+            self.gl.blend_func(source, destination);
+            self.blend_func = Some((source, destination));
+        }
+    }
+
+    fn clear_color(&self, red: GLfloat, green: GLfloat, blue: GLfloat, alpha: GLfloat) {
+        let unchanged = match self.clear_color {
+            Some(rgba) => rgba[0] == red && rgba[1] == green && rgba[2] == blue && rgba[3] == alpha,
+            None => false,
+        };
+        if self.count(unchanged) {
+            self.gl.clear_color(red, green, blue, alpha);
+            self.clear_color = Some([red, green, blue, alpha]);
+        }
+    }
+
+    fn use_program(&self, program: GLuint) {
+        if self.count(self.program == Some(program)) {
+            self.gl.use_program(program);
+            self.program = Some(program);
+        }
+    }
+
+    fn bind_vertex_array(&self, array: GLuint) {
+        if self.count(self.vertex_array == Some(array)) {
+            self.gl.bind_vertex_array(array);
+            self.vertex_array = Some(array);
+        }
+    }
+
+    /// Notes that `program` is being deleted, so a new program given its
+    /// name is not taken to be current.
+    fn program_deleted(&self, program: GLuint) {
+        if self.program == Some(program) { self.program = None; }
+    }
+
+    /// Notes that `array` is being deleted, which unbinds it if it is bound.
+    fn vertex_array_deleted(&self, array: GLuint) {
+        if self.vertex_array == Some(array) { self.vertex_array = Some(0); }
+    }
+
+    /// Forgets everything, so that the next call of each setter is made.
+    /// Needed after setting any of the state without the cache.
+    fn invalidate(&self) {
+        self.enabled = vec::from_elem(CAPABILITIES.len(), None);
+        self.depth_func = None;
+        self.depth_mask = None;
+        self.cull_face = None;
+        self.scissor = None;
+        self.viewport = None;
+        self.color_mask = None;
+        self.polygon_mode = None;
+        self.blend_equation = None;
+        self.blend_func = None;
+        self.clear_color = None;
+        self.program = None;
+        self.vertex_array = None;
+    }
+
+    /// The calls made and skipped so far.
+    fn stats(&self) -> CacheStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glcore::*;
+    use context::Context;
+    use gl::Gl;
+    use mock::MockGl;
+    use state::*;
+
+    /// The calls made through `mock` since its trace was last cleared, as
+    /// the trace prints them.
+    fn traced(mock: @MockGl) -> ~[~str] {
+        let calls = mock.calls().map(|c| c.to_str());
+        mock.clear_trace();
+        move calls
+    }
+
+    fn stats_are(ctx: &Context, made: uint, elided: uint) -> bool {
+        let stats = ctx.cache().stats();
+        stats.made == made && stats.elided == elided
+    }
+
+    /// Alpha blending over a depth test that does not write depth.
+    fn transparent() -> RenderState {
+        RenderState {
+            blend: Some(Blend::premultiplied_alpha()),
+            depth_test: Some(GL_LESS),
+            depth_write: false,
+            .. RenderState::default()
+        }
+    }
+
+    #[test]
+    fn elides_what_gl_starts_with() {
+        let mock = @MockGl::new();
+        let ctx = Context::new(mock as @Gl, 800, 600);
+
+        ctx.cache().apply(&RenderState::default());
+        ctx.cache().use_program(0);
+        ctx.cache().viewport(0, 0, 800, 600);
+        assert traced(mock).is_empty();
+        assert stats_are(&ctx, 0, 9);
+    }
+
+    #[test]
+    fn makes_only_the_calls_that_change_state() {
+        let mock = @MockGl::new();
+        let ctx = Context::new(mock as @Gl, 800, 600);
+        let cache = ctx.cache();
+
+        // The blend equation and depth function are already GL's defaults
+        cache.apply(&transparent());
+        let calls = traced(mock);
+        assert calls.len() == 4;
+        assert calls[0] == ~"glEnable(GL_BLEND)";
+        assert str::starts_with(calls[1], "glBlendFunc(");
+        assert calls[2] == ~"glEnable(GL_DEPTH_TEST)";
+        assert calls[3] == ~"glDepthMask(0)";
+        assert stats_are(&ctx, 4, 6);
+
+        // Nothing changes the second time
+        cache.apply(&transparent());
+        assert traced(mock).is_empty();
+        assert stats_are(&ctx, 4, 16);
+
+        // And going back undoes only what was done
+        cache.apply(&RenderState::default());
+        assert traced(mock) == ~[~"glDisable(GL_BLEND)", ~"glDisable(GL_DEPTH_TEST)", ~"glDepthMask(1)"];
+        assert stats_are(&ctx, 7, 20);
+
+        assert mock.errors().is_empty() && mock.redundant().is_empty();
+    }
+
+    #[test]
+    fn makes_every_call_after_invalidating() {
+        let mock = @MockGl::new();
+        let ctx = Context::new(mock as @Gl, 800, 600);
+        let cache = ctx.cache();
+
+        cache.apply(&RenderState::default());
+        assert traced(mock).is_empty();
+
+        cache.invalidate();
+        cache.apply(&RenderState::default());
+        assert traced(mock) == ~[~"glDisable(GL_BLEND)", ~"glDisable(GL_DEPTH_TEST)", ~"glDepthMask(1)",
+                                 ~"glDisable(GL_CULL_FACE)", ~"glDisable(GL_SCISSOR_TEST)",
+                                 ~"glColorMask(1, 1, 1, 1)", ~"glPolygonMode(GL_FRONT_AND_BACK, GL_FILL)"];
+        assert stats_are(&ctx, 7, 7);
+
+        // After which the cache knows the state again
+        cache.apply(&RenderState::default());
+        assert traced(mock).is_empty();
+        assert stats_are(&ctx, 7, 14);
+    }
+}
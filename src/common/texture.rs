@@ -0,0 +1,239 @@
+/*!
+ * Texture objects and loading them from image files.
+ */
+
+use glcore::*;
+use stb_image::image;
+
+use context::Context;
+use mipmap::{Downsample, Level, chain};
+
+/// How the color channels of an image are encoded.
+pub enum ColorSpace {
+    /// Values are used as they are.
+    Linear,
+    /// Values are sRGB encoded, as in most images meant for display, and
+    /// are converted to linear ones when sampled. Alpha is always linear.
+    Srgb,
+}
+
+/// Which mipmap levels a new texture gets.
+pub enum Mipmaps {
+    /// Only the image itself. Sample it without a mipmap filter.
+    BaseLevelOnly,
+    /// A full chain built by `glGenerateMipmap`, however the driver likes.
+    GenerateOnGpu,
+    /// A full chain built on the CPU with the given filter.
+    Downsampled(Downsample),
+}
+
+/// Why a texture could not be loaded.
+pub enum TextureError {
+    /// The file could not be read or decoded.
+    LoadFailed(~str),
+    /// The pixels have a number of channels GL has no format for.
+    UnsupportedChannels(uint),
+}
+
+impl TextureError : ToStr {
+    pure fn to_str(&self) -> ~str {
+        match *self {
+            LoadFailed(ref path) => fmt!("failed to load %s", *path),
+            UnsupportedChannels(n) => fmt!("images with %u channels are not supported", n),
+        }
+    }
+}
+
+/**
+ * The internal format and the pixel format to upload an image with
+ * `channels` channels of 8 bits each. There are no sRGB formats with fewer
+ * than three channels, so those are always linear.
+ */
+pub pure fn formats(channels: uint, space: ColorSpace) -> Option<(GLenum, GLenum)> {
+    match (channels, space) {
+        (1, _) => Some((GL_R8, GL_RED)),
+        (2, _) => Some((GL_RG8, GL_RG)),
+        (3, Linear) => Some((GL_RGB8, GL_RGB)),
+        (3, Srgb) => Some((GL_SRGB8, GL_RGB)),
+        (4, Linear) => Some((GL_RGBA8, GL_RGBA)),
+        (4, Srgb) => Some((GL_SRGB8_ALPHA8, GL_RGBA)),
+        _ => None,
+    }
+}
+
+/// The largest row alignment GL accepts that rows of `row_bytes` bytes meet.
+pub pure fn unpack_alignment(row_bytes: uint) -> GLint {
+    if row_bytes % 8 == 0 {
+        8
+    } else if row_bytes % 4 == 0 {
+        4
+    } else if row_bytes % 2 == 0 {
+        2
+    } else {
+        1
+    }
+}
+
+/**
+ * `pixels`, 8-bit RGBA, with the color of each multiplied by its alpha, as
+ * the presets of `state::Blend` expect. Premultiplied colors also filter
+ * and downsample correctly: a transparent texel adds nothing to those it
+ * is averaged with, where it would otherwise bleed its color into them.
+ */
+pub fn premultiply(pixels: &[u8]) -> ~[u8] {
+    do vec::from_fn(pixels.len()) |i| {
+        let alpha = pixels[i - i % 4 + 3] as uint;
+        if i % 4 == 3 {
+            pixels[i]
+        } else {
+            // Rounded to the nearest
+            ((pixels[i] as uint * alpha + 127) / 255) as u8
+        }
+    }
+}
+
+/// A 2D texture object, deleted when dropped.
+pub struct Texture2D {
+    priv ctx: Context,
+    name: GLuint,
+}
+
+impl Texture2D {
+    static fn new(ctx: &Context) -> Texture2D {
+        Texture2D { ctx: ctx.acquire(), name: ctx.gl().gen_texture() }
+    }
+
+    /**
+     * Loads the image at `path` into a new texture, keeping all of its
+     * channels. The texture is left bound to the active texture unit.
+     */
+    static fn from_file(ctx: &Context, path: &str, space: ColorSpace,
+                        mipmaps: Mipmaps) -> Result<Texture2D, TextureError> {
+        match image::load(str::from_slice(path)) {
+            Some(image) => {
+                Texture2D::from_pixels(ctx, image.width, image.height, image.depth, image.data,
+                                       space, mipmaps)
+            }
+            None => Err(LoadFailed(str::from_slice(path))),
+        }
+    }
+
+    /**
+     * Creates a texture from tightly packed 8-bit pixels with `channels`
+     * channels each, top row first, or allocates the image without filling
+     * it in if `data` is empty. The texture is left bound to the active
+     * texture unit.
+     */
+    static fn from_pixels(ctx: &Context, width: uint, height: uint, channels: uint, data: &[u8],
+                          space: ColorSpace, mipmaps: Mipmaps) -> Result<Texture2D, TextureError> {
+        let (internal_format, format) = match formats(channels, space) {
+            Some(formats) => formats,
+            None => return Err(UnsupportedChannels(channels)),
+        };
+
+        let texture = Texture2D::new(ctx);
+        texture.bind();
+
+        let gl = ctx.gl();
+        let upload = |level: uint, width: uint, height: uint, data: &[u8]| {
+            // Rows are not padded, which GL only assumes if told so
+            gl.pixel_store_i(GL_UNPACK_ALIGNMENT, unpack_alignment(width * channels));
+            gl.tex_image_2d(GL_TEXTURE_2D, level as GLint, internal_format as GLint,
+                            width as GLsizei, height as GLsizei, format, GL_UNSIGNED_BYTE, data);
+        };
+        upload(0, width, height, data);
+
+        match mipmaps {
+            BaseLevelOnly => {
+                // Otherwise the texture is incomplete until a mipmap filter
+                // is swapped out for one that ignores the missing levels
+                gl.tex_parameter_i(GL_TEXTURE_2D, GL_TEXTURE_MAX_LEVEL, 0);
+            }
+            GenerateOnGpu => gl.generate_mipmap(GL_TEXTURE_2D),
+            Downsampled(filter) => {
+                let base = Level { width: width, height: height, channels: channels,
+                                   data: vec::from_slice(data) };
+                for vec::eachi(chain(&base, filter)) |i, level| {
+                    upload(i + 1, level.width, level.height, level.data);
+                }
+            }
+        }
+        gl.pixel_store_i(GL_UNPACK_ALIGNMENT, 4);
+
+        Ok(move texture)
+    }
+
+    /**
+     * Creates a `width` by `height` RGBA texture with undefined contents,
+     * to be drawn into through a framebuffer. The texture is left bound to
+     * the active texture unit.
+     */
+    static fn blank(ctx: &Context, width: uint, height: uint) -> Texture2D {
+        match Texture2D::from_pixels(ctx, width, height, 4, [], Linear, BaseLevelOnly) {
+            Ok(move texture) => move texture,
+            // Four channels always have a format
+            Err(_) => fail,
+        }
+    }
+
+    fn bind(&self) {
+        self.ctx.gl().bind_texture(GL_TEXTURE_2D, self.name);
+    }
+
+    /// Binds the texture to texture unit `unit`, leaving that unit active.
+    fn bind_to(&self, unit: uint) {
+        self.ctx.gl().active_texture(GL_TEXTURE0 + unit as GLenum);
+        self.bind();
+    }
+}
+
+impl Texture2D : Drop {
+    fn finalize(&self) {
+        if self.ctx.release() {
+            self.ctx.gl().delete_texture(self.name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glcore::*;
+    use texture::*;
+
+    #[test]
+    fn picks_formats_by_channel_count() {
+        assert formats(1, Linear) == Some((GL_R8, GL_RED));
+        assert formats(2, Linear) == Some((GL_RG8, GL_RG));
+        assert formats(3, Linear) == Some((GL_RGB8, GL_RGB));
+        assert formats(4, Linear) == Some((GL_RGBA8, GL_RGBA));
+        assert formats(0, Linear).is_none() && formats(5, Linear).is_none();
+    }
+
+    #[test]
+    fn decodes_srgb_only_with_three_or_four_channels() {
+        assert formats(1, Srgb) == Some((GL_R8, GL_RED));
+        assert formats(2, Srgb) == Some((GL_RG8, GL_RG));
+        assert formats(3, Srgb) == Some((GL_SRGB8, GL_RGB));
+        assert formats(4, Srgb) == Some((GL_SRGB8_ALPHA8, GL_RGBA));
+    }
+
+    #[test]
+    fn aligns_rows_to_what_their_length_allows() {
+        // 3 channels by widths 5, 4 and 6, and 4 channels by 2 and 1
+        assert unpack_alignment(3 * 5) == 1;
+        assert unpack_alignment(3 * 4) == 4;
+        assert unpack_alignment(3 * 6) == 2;
+        assert unpack_alignment(4 * 2) == 8;
+        assert unpack_alignment(4 * 1) == 4;
+    }
+
+    #[test]
+    fn premultiplies_rounding_to_the_nearest() {
+        let pixels = [200, 100, 50, 0,
+                      200, 100, 50, 255,
+                      200, 101, 1, 128];
+        assert premultiply(pixels) == ~[0, 0, 0, 0,
+                                        200, 100, 50, 255,
+                                        100, 51, 1, 128];
+    }
+}
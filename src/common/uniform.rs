@@ -0,0 +1,231 @@
+/*!
+ * Uniforms typed on the Rust side.
+ *
+ * A `Uniform<T>` is looked up once, at which point the type the program
+ * reports for it is checked against `T`, and from then on `set` makes the
+ * `glUniform*` call that fits `T`. Handing a `vec3` a matrix, or a
+ * `sampler2D` a float, is caught at lookup rather than left to fail
+ * silently with `GL_INVALID_OPERATION` at every frame.
+ */
+
+use glcore::*;
+use lmath::vec::vec3::Vec3;
+use lmath::mat::mat4::Mat4;
+
+use gl::Gl;
+use shader::{Program, LookupError, glsl_type_name};
+
+/// The texture unit a `sampler2D` uniform reads.
+pub struct Sampler2D {
+    unit: uint,
+}
+
+/// A Rust type that can be the value of a uniform.
+pub trait UniformType {
+    /**
+     * Whether a uniform of the GL type `kind` takes values of this type.
+     * The argument is always `None`; it is only there to pick the impl,
+     * as in `let none: Option<T> = None; UniformType::accepts(kind, none)`.
+     */
+    static fn accepts(kind: GLenum, _witness: Option<Self>) -> bool;
+
+    /// Sets the uniform at `location` of the current program to this value.
+    fn set_at(&self, gl: @Gl, location: GLint);
+}
+
+impl f32 : UniformType {
+    static fn accepts(kind: GLenum, _witness: Option<f32>) -> bool {
+        kind == GL_FLOAT
+    }
+
+    fn set_at(&self, gl: @Gl, location: GLint) {
+        gl.uniform_1f(location, *self);
+    }
+}
+
+impl GLint : UniformType {
+    static fn accepts(kind: GLenum, _witness: Option<GLint>) -> bool {
+        // Booleans can be set as either floats or ints; ints are exact
+        kind == GL_INT || kind == GL_BOOL
+    }
+
+    fn set_at(&self, gl: @Gl, location: GLint) {
+        gl.uniform_1i(location, *self);
+    }
+}
+
+impl [f32 * 3] : UniformType {
+    static fn accepts(kind: GLenum, _witness: Option<[f32 * 3]>) -> bool {
+        kind == GL_FLOAT_VEC3
+    }
+
+    fn set_at(&self, gl: @Gl, location: GLint) {
+        gl.uniform_3f(location, self[0], self[1], self[2]);
+    }
+}
+
+impl Vec3<f32> : UniformType {
+    static fn accepts(kind: GLenum, _witness: Option<Vec3<f32>>) -> bool {
+        kind == GL_FLOAT_VEC3
+    }
+
+    fn set_at(&self, gl: @Gl, location: GLint) {
+        gl.uniform_3f(location, self.x, self.y, self.z);
+    }
+}
+
+impl [f32 * 16] : UniformType {
+    static fn accepts(kind: GLenum, _witness: Option<[f32 * 16]>) -> bool {
+        kind == GL_FLOAT_MAT4
+    }
+
+    /// The elements are taken to be in column-major order, as GLSL stores them.
+    fn set_at(&self, gl: @Gl, location: GLint) {
+        gl.uniform_matrix_4fv(location, GL_FALSE, *self);
+    }
+}
+
+impl Mat4<f32> : UniformType {
+    static fn accepts(kind: GLenum, _witness: Option<Mat4<f32>>) -> bool {
+        kind == GL_FLOAT_MAT4
+    }
+
+    fn set_at(&self, gl: @Gl, location: GLint) {
+        // lmath stores the columns one after another, as GL expects
+        unsafe {
+            do vec::raw::buf_as_slice(self.to_ptr(), 16) |m| {
+                gl.uniform_matrix_4fv(location, GL_FALSE, m);
+            }
+        }
+    }
+}
+
+impl Sampler2D : UniformType {
+    static fn accepts(kind: GLenum, _witness: Option<Sampler2D>) -> bool {
+        kind == GL_SAMPLER_2D
+    }
+
+    fn set_at(&self, gl: @Gl, location: GLint) {
+        gl.uniform_1i(location, self.unit as GLint);
+    }
+}
+
+/// Why a uniform could not be looked up.
+pub enum UniformError {
+    Missing(LookupError),
+    /// The program declares the named uniform with the GL type given,
+    /// which the Rust type asked for does not match.
+    WrongType(~str, GLenum),
+}
+
+impl UniformError : ToStr {
+    pure fn to_str(&self) -> ~str {
+        match *self {
+            Missing(ref err) => err.to_str(),
+            WrongType(ref name, kind) => {
+                fmt!("uniform `%s` is a %s in the program, which the type it was looked up as cannot set",
+                     *name, unsafe { glsl_type_name(kind) })
+            }
+        }
+    }
+}
+
+/**
+ * A uniform of a program, set with values of type `T`. Only valid for the
+ * program it was looked up in, so look it up again if the program is
+ * rebuilt.
+ */
+pub struct Uniform<T> {
+    name: ~str,
+    location: GLint,
+    priv gl: @Gl,
+}
+
+impl<T: UniformType> Uniform<T> {
+    /// Looks up uniform `name` of `program`, checking that its type takes
+    /// values of type `T`.
+    static fn find(program: &Program, name: &str) -> Result<Uniform<T>, UniformError> {
+        let uniform = match program.uniform(name) {
+            Ok(move uniform) => uniform,
+            Err(move err) => return Err(Missing(move err)),
+        };
+
+        let none: Option<T> = None;
+        if !UniformType::accepts(uniform.kind, none) {
+            return Err(WrongType(move uniform.name, uniform.kind));
+        }
+
+        Ok(Uniform { name: move uniform.name, location: uniform.location, gl: program.gl() })
+    }
+
+    /// Like `find`, but if the lookup fails, warns and returns a uniform
+    /// whose `set` does nothing.
+    static fn lookup(program: &Program, name: &str) -> Uniform<T> {
+        match Uniform::find(program, name) {
+            Ok(move uniform) => uniform,
+            Err(move err) => {
+                io::println(~"Warning: " + err.to_str());
+                Uniform { name: str::from_slice(name), location: -1, gl: program.gl() }
+            }
+        }
+    }
+
+    /// Sets the uniform to `value`. The program it belongs to must be the
+    /// one in use.
+    fn set(&self, value: &T) {
+        if self.location < 0 { return; }
+        value.set_at(self.gl, self.location);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glcore::*;
+    use lmath::mat::mat4::Mat4;
+    use context::Context;
+    use gl::Gl;
+    use mock::MockGl;
+    use shader::{Shader, Program, Source, VertexShader, FragmentShader, NoSuchUniform};
+    use uniform::*;
+
+    #[test]
+    fn checks_the_type_at_lookup() {
+        let mock = @MockGl::new();
+        let ctx = Context::new(mock as @Gl, 800, 600);
+        {
+            let compile = |kind, text: &str| match Shader::compile(&ctx, kind, &Source::new(text)) {
+                Ok(move shader) => move shader,
+                Err(move err) => fail(err.to_str()),
+            };
+            let shaders = [compile(VertexShader, "#version 330\nin vec2 position;\nvoid main() {}\n"),
+                           compile(FragmentShader, "#version 330\n\
+                                                    uniform vec3 color;\n\
+                                                    out vec4 outColor;\n\
+                                                    void main() {}\n")];
+            let program = match Program::link(&ctx, shaders) {
+                Ok(move program) => move program,
+                Err(move err) => fail(err.to_str()),
+            };
+
+            let as_matrix: Result<Uniform<Mat4<f32>>, UniformError> = Uniform::find(&program, "color");
+            match as_matrix {
+                Err(WrongType(ref name, kind)) => assert *name == ~"color" && kind == GL_FLOAT_VEC3,
+                _ => fail(~"a vec3 looked up as a Mat4 was not refused"),
+            }
+            match as_matrix {
+                Err(ref err) => assert str::contains(err.to_str(), "is a vec3"),
+                Ok(_) => (),
+            }
+
+            let as_vector: Result<Uniform<[f32 * 3]>, UniformError> = Uniform::find(&program, "color");
+            assert as_vector.is_ok();
+
+            let missing: Result<Uniform<[f32 * 3]>, UniformError> = Uniform::find(&program, "colour");
+            match missing {
+                Err(Missing(NoSuchUniform(ref name))) => assert *name == ~"colour",
+                _ => fail(~"a uniform the program lacks was found"),
+            }
+        }
+        ctx.destroy();
+    }
+}
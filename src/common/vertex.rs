@@ -0,0 +1,254 @@
+/*!
+ * Describing vertex structs to GL.
+ *
+ * A vertex type lists its fields once in `VertexFormat::attributes`, and
+ * the stride and offsets handed to `glVertexAttribPointer` are taken from
+ * the struct itself instead of being counted out in floats by hand. The
+ * same goes for the data of each instance of an instanced draw, which is
+ * described as a vertex and bound with `bind_instance_attributes`.
+ */
+
+use sys::size_of;
+
+use glcore::*;
+
+use shader::Program;
+
+/// Where one attribute lives inside a vertex struct.
+pub struct Attribute {
+    name: ~str,
+    components: GLint,
+    offset: uint,
+}
+
+/// A struct that can be uploaded as vertex data.
+pub trait VertexFormat {
+    /// The attributes of the vertex, built with `attribute`.
+    fn attributes(&self) -> ~[Attribute];
+}
+
+/**
+ * Describes the field `field` of `vertex` as the attribute `name`. The
+ * field must be a `GLfloat`, an array of up to 4 of them, or a 3x3 or 4x4
+ * matrix of them stored by column, such as a `Mat4<GLfloat>`.
+ */
+pub fn attribute<V, F>(name: &str, vertex: &V, field: &F) -> Attribute {
+    Attribute {
+        name: str::from_slice(name),
+        components: (size_of::<F>() / size_of::<GLfloat>()) as GLint,
+        offset: ptr::to_uint(field) - ptr::to_uint(vertex),
+    }
+}
+
+/// The distance in bytes between consecutive vertices of type `V`.
+pub fn stride<V>() -> GLsizei {
+    size_of::<V>() as GLsizei
+}
+
+/**
+ * The number of floats and the offset in bytes of each location `attr`
+ * takes: one for a float or an array of them, and one for each column of
+ * a matrix.
+ */
+pub pure fn locations(attr: &Attribute) -> ~[(GLint, uint)] {
+    let columns = if attr.components == 16 { 4 } else if attr.components == 9 { 3 } else { 1 };
+    let rows = attr.components / columns;
+    do vec::from_fn(columns as uint) |column| {
+        (rows, attr.offset + column * rows as uint * size_of::<GLfloat>())
+    }
+}
+
+/**
+ * Points the attributes of `program` at the currently bound vertex buffer,
+ * which holds vertices laid out like `vertex`. Attributes the program does
+ * not use are skipped.
+ */
+pub fn bind_attributes<V: VertexFormat>(program: &Program, vertex: &V) {
+    bind_with_divisor(program, vertex, 0);
+}
+
+/**
+ * Points the attributes of `program` at the currently bound buffer, which
+ * holds one `V` per instance, laid out like `instance`. Every vertex of an
+ * instance drawn with `draw_instanced` reads the same one. Attributes the
+ * program does not use are skipped.
+ */
+pub fn bind_instance_attributes<V: VertexFormat>(program: &Program, instance: &V) {
+    bind_with_divisor(program, instance, 1);
+}
+
+/**
+ * `bind_attributes`, advancing to the next `V` every `divisor` instances,
+ * or every vertex if 0. The divisor is set even when it is 0, as it
+ * belongs to the vertex array: once a rebuilt program moves an attribute,
+ * its location may be one an instance attribute had. `glVertexAttribDivisor`
+ * is core from GL 3.3, the version the runner asks for.
+ */
+fn bind_with_divisor<V: VertexFormat>(program: &Program, vertex: &V, divisor: GLuint) {
+    let gl = program.gl();
+    for vertex.attributes().each |attr| {
+        let location = match program.attribute(attr.name) {
+            Ok(attribute) => attribute.location as GLuint,
+            Err(_) => loop,
+        };
+
+        for vec::eachi(locations(attr)) |column, entry| {
+            let (rows, offset) = *entry;
+            let index = location + column as GLuint;
+            gl.enable_vertex_attrib_array(index);
+            gl.vertex_attrib_pointer(index, rows, GL_FLOAT, GL_FALSE, stride::<V>(), offset);
+            gl.vertex_attrib_divisor(index, divisor);
+        }
+    }
+}
+
+/// A vertex with only a position.
+pub struct Vertex2 {
+    position: [GLfloat * 2],
+}
+
+impl Vertex2 : VertexFormat {
+    fn attributes(&self) -> ~[Attribute] {
+        ~[
+            attribute("position", self, &self.position),
+        ]
+    }
+}
+
+/// A vertex with a position and a color.
+pub struct ColorVertex {
+    position: [GLfloat * 2],
+    color: [GLfloat * 3],
+}
+
+impl ColorVertex : VertexFormat {
+    fn attributes(&self) -> ~[Attribute] {
+        ~[
+            attribute("position", self, &self.position),
+            attribute("color", self, &self.color),
+        ]
+    }
+}
+
+/// A vertex with a position, a color and texture coordinates.
+pub struct TexturedVertex {
+    position: [GLfloat * 2],
+    color: [GLfloat * 3],
+    texcoord: [GLfloat * 2],
+}
+
+impl TexturedVertex : VertexFormat {
+    fn attributes(&self) -> ~[Attribute] {
+        ~[
+            attribute("position", self, &self.position),
+            attribute("color", self, &self.color),
+            attribute("texcoord", self, &self.texcoord),
+        ]
+    }
+}
+
+/// A vertex with a position in 3D, a color and texture coordinates.
+pub struct TexturedVertex3 {
+    position: [GLfloat * 3],
+    color: [GLfloat * 3],
+    texcoord: [GLfloat * 2],
+}
+
+impl TexturedVertex3 : VertexFormat {
+    fn attributes(&self) -> ~[Attribute] {
+        ~[
+            attribute("position", self, &self.position),
+            attribute("color", self, &self.color),
+            attribute("texcoord", self, &self.texcoord),
+        ]
+    }
+}
+
+/// A vertex of a quad drawn over the whole screen, with a position in clip
+/// space and texture coordinates.
+pub struct ScreenVertex {
+    position: [GLfloat * 2],
+    texcoord: [GLfloat * 2],
+}
+
+impl ScreenVertex : VertexFormat {
+    fn attributes(&self) -> ~[Attribute] {
+        ~[
+            attribute("position", self, &self.position),
+            attribute("texcoord", self, &self.texcoord),
+        ]
+    }
+}
+
+/// A point with a color and the number of sides of the shape a geometry
+/// shader draws around it.
+pub struct ShapeVertex {
+    position: [GLfloat * 2],
+    color: [GLfloat * 3],
+    sides: GLfloat,
+}
+
+impl ShapeVertex : VertexFormat {
+    fn attributes(&self) -> ~[Attribute] {
+        ~[
+            attribute("position", self, &self.position),
+            attribute("color", self, &self.color),
+            attribute("sides", self, &self.sides),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glcore::*;
+    use vertex::*;
+
+    /// Whether `V` is `size` floats long and the attributes of `vertex` are
+    /// `expected`: their names, components and offsets in floats.
+    fn laid_out<V: VertexFormat>(vertex: &V, size: uint, expected: &[(&str, GLint, uint)]) -> bool {
+        let attributes = vertex.attributes();
+        if stride::<V>() != (size * 4) as GLsizei || attributes.len() != expected.len() {
+            return false;
+        }
+        do vec::all2(attributes, expected) |attr, entry| {
+            let (name, components, offset) = *entry;
+            str::eq_slice(attr.name, name) && attr.components == components && attr.offset == offset * 4
+        }
+    }
+
+    #[test]
+    fn lays_out_vertex2() {
+        let vertex = Vertex2 { position: [0.0, 0.0] };
+        assert laid_out(&vertex, 2, [("position", 2, 0)]);
+    }
+
+    #[test]
+    fn lays_out_color_vertex() {
+        let vertex = ColorVertex { position: [0.0, 0.0], color: [0.0, 0.0, 0.0] };
+        assert laid_out(&vertex, 5, [("position", 2, 0), ("color", 3, 2)]);
+    }
+
+    #[test]
+    fn lays_out_textured_vertex() {
+        let vertex = TexturedVertex { position: [0.0, 0.0], color: [0.0, 0.0, 0.0], texcoord: [0.0, 0.0] };
+        assert laid_out(&vertex, 7, [("position", 2, 0), ("color", 3, 2), ("texcoord", 2, 5)]);
+    }
+
+    #[test]
+    fn lays_out_textured_vertex3() {
+        let vertex = TexturedVertex3 { position: [0.0, 0.0, 0.0], color: [0.0, 0.0, 0.0], texcoord: [0.0, 0.0] };
+        assert laid_out(&vertex, 8, [("position", 3, 0), ("color", 3, 3), ("texcoord", 2, 6)]);
+    }
+
+    #[test]
+    fn lays_out_screen_vertex() {
+        let vertex = ScreenVertex { position: [0.0, 0.0], texcoord: [0.0, 0.0] };
+        assert laid_out(&vertex, 4, [("position", 2, 0), ("texcoord", 2, 2)]);
+    }
+
+    #[test]
+    fn lays_out_shape_vertex() {
+        let vertex = ShapeVertex { position: [0.0, 0.0], color: [0.0, 0.0, 0.0], sides: 0.0 };
+        assert laid_out(&vertex, 6, [("position", 2, 0), ("color", 3, 2), ("sides", 1, 5)]);
+    }
+}
@@ -0,0 +1,216 @@
+/*!
+ * Programs built from shader files, rebuilt when the files change.
+ *
+ * The files are polled rather than watched: every `POLL_INTERVAL` seconds
+ * they are read again and compared with the text the current program was
+ * built from. If an edit fails to compile or link, the log is printed and
+ * the previous program stays in use, so a typo does not end the session.
+ *
+ * The files are run through a `Preprocessor` first, so the files they
+ * include are watched too.
+ */
+
+use context::Context;
+use preprocess::{Preprocessor, PreprocessError, ReadFailed};
+use shader::{Source, Shader, ShaderKind, ShaderError, Program, LinkError};
+use soft::Emulation;
+
+/// How often the shader files are checked for changes, in seconds.
+pub const POLL_INTERVAL: float = 0.5;
+
+/// Why a program could not be built from its files.
+pub enum ProgramError {
+    PreprocessFailed(PreprocessError),
+    CompileFailed(ShaderError),
+    LinkFailed(LinkError),
+}
+
+impl ProgramError : ToStr {
+    pure fn to_str(&self) -> ~str {
+        match *self {
+            PreprocessFailed(ref err) => err.to_str(),
+            CompileFailed(ref err) => err.to_str(),
+            LinkFailed(ref err) => err.to_str(),
+        }
+    }
+}
+
+/// One shader of a program.
+struct ShaderFile {
+    kind: ShaderKind,
+    path: Path,
+}
+
+/// Reads and preprocesses the source of every shader, in order.
+fn read_all(preprocessor: &Preprocessor, files: &[ShaderFile]) -> Result<~[Source], ProgramError> {
+    let mut sources = ~[];
+    for files.each |file| {
+        match preprocessor.process_file(&file.path) {
+            Ok(move source) => sources.push(move source),
+            Err(move err) => return Err(PreprocessFailed(move err)),
+        }
+    }
+    Ok(move sources)
+}
+
+/// Compiles `sources`, one for each of `files`, and links them.
+fn build(ctx: &Context, files: &[ShaderFile], sources: &[Source],
+         outputs: &[~str]) -> Result<Program, ProgramError> {
+    let mut shaders = ~[];
+    for vec::eachi(sources) |i, source| {
+        match Shader::compile(ctx, files[i].kind, source) {
+            Ok(move shader) => shaders.push(move shader),
+            Err(move err) => return Err(CompileFailed(move err)),
+        }
+    }
+
+    let outputs = vec::map(outputs, |output| str::view(*output, 0, output.len()));
+    match Program::link_with_outputs(ctx, shaders, outputs) {
+        Ok(move program) => Ok(move program),
+        Err(move err) => Err(LinkFailed(move err)),
+    }
+}
+
+/**
+ * A program linked from shader files, replaced by a fresh one when `poll`
+ * finds the files edited. Attribute and uniform locations may move when
+ * that happens, so look them up again whenever `poll` returns `true`.
+ */
+pub struct WatchedProgram {
+    priv ctx: Context,
+    priv preprocessor: Preprocessor,
+    priv files: ~[ShaderFile],
+    priv outputs: ~[~str],
+    /// The preprocessed text of each file as of the last change seen.
+    priv mut texts: ~[~str],
+    priv mut emulation: Option<Emulation>,
+    priv mut program: Program,
+    priv mut since_poll: float,
+    /// The last preprocessing error printed, so it is not repeated at every poll.
+    priv mut preprocess_error: Option<~str>,
+}
+
+impl WatchedProgram {
+    /**
+     * Compiles the shader files in `shaders` and links them, binding the
+     * fragment shader outputs named in `outputs` to color numbers 0, 1, ...
+     * Paths are relative to the working directory.
+     */
+    static fn load(ctx: &Context, shaders: &[(ShaderKind, &str)],
+                   outputs: &[&str]) -> Result<WatchedProgram, ProgramError> {
+        WatchedProgram::load_with(ctx, Preprocessor::new(), shaders, outputs)
+    }
+
+    /// Like `load`, preprocessing the files with `preprocessor`.
+    static fn load_with(ctx: &Context, preprocessor: Preprocessor, shaders: &[(ShaderKind, &str)],
+                        outputs: &[&str]) -> Result<WatchedProgram, ProgramError> {
+        let files = do shaders.map |shader| {
+            let (kind, path) = *shader;
+            ShaderFile { kind: kind, path: Path(path) }
+        };
+        let outputs = outputs.map(|output| str::from_slice(*output));
+
+        let sources = match read_all(&preprocessor, files) {
+            Ok(move sources) => sources,
+            Err(move err) => return Err(move err),
+        };
+        let program = match build(ctx, files, sources, outputs) {
+            Ok(move program) => program,
+            Err(move err) => return Err(move err),
+        };
+
+        Ok(WatchedProgram {
+            ctx: ctx.acquire(),
+            preprocessor: move preprocessor,
+            files: move files,
+            outputs: move outputs,
+            texts: sources.map(|source| copy source.text),
+            emulation: None,
+            program: move program,
+            since_poll: 0.0,
+            preprocess_error: None,
+        })
+    }
+
+    /// Calls `f` with the current program.
+    fn with_program<R>(&self, f: fn(&Program) -> R) -> R {
+        f(&self.program)
+    }
+
+    /// Makes the current program the one in use.
+    fn bind(&self) {
+        self.program.bind();
+    }
+
+    /// Supplies the stand-ins the software renderer runs in place of the
+    /// shaders, for this program and every one it is replaced with.
+    fn emulate(&self, emulation: Emulation) {
+        self.program.emulate(copy emulation);
+        self.emulation = Some(move emulation);
+    }
+
+    /**
+     * Advances the clock by `dt` seconds and, if a poll is due, rereads
+     * the files. Returns `true` if they had changed and the program built
+     * from them replaced the current one, and is now in use.
+     */
+    fn poll(&self, dt: float) -> bool {
+        self.since_poll += dt;
+        if self.since_poll < POLL_INTERVAL { return false; }
+        self.since_poll = 0.0;
+
+        let sources = match read_all(&self.preprocessor, self.files) {
+            Ok(move sources) => sources,
+            // A file in the middle of being saved may be missing for a
+            // moment, so try again at the next poll
+            Err(PreprocessFailed(ReadFailed(*))) => return false,
+            Err(move err) => {
+                let msg = err.to_str();
+                if self.preprocess_error != Some(copy msg) {
+                    io::println(fmt!("%s\nKeeping the previous program", msg));
+                    self.preprocess_error = Some(move msg);
+                }
+                return false;
+            }
+        };
+        self.preprocess_error = None;
+
+        let mut changed = false;
+        for vec::eachi(sources) |i, source| {
+            if source.text != self.texts[i] { changed = true; }
+        }
+        if !changed { return false; }
+
+        // Remember the new text even if it does not build, so the error is
+        // printed once rather than at every poll
+        self.texts = sources.map(|source| copy source.text);
+
+        match build(&self.ctx, self.files, sources, self.outputs) {
+            Ok(move program) => {
+                program.bind();
+                match self.emulation {
+                    Some(ref emulation) => program.emulate(copy *emulation),
+                    None => (),
+                }
+                self.program = move program;
+                io::println(fmt!("Reloaded %s", self.describe()));
+                true
+            }
+            Err(move err) => {
+                io::println(fmt!("%s\nKeeping the previous program", err.to_str()));
+                false
+            }
+        }
+    }
+
+    /// The paths of the shader files, for messages.
+    priv fn describe(&self) -> ~str {
+        str::connect(self.files.map(|file| file.path.to_str()), ", ")
+    }
+}
+
+impl WatchedProgram : Drop {
+    fn finalize(&self) {
+        self.ctx.release();
+    }
+}
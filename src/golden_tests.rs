@@ -0,0 +1,432 @@
+/*!
+ * Golden-image tests for the chapters.
+ *
+ * Runs each chapter headless, from this directory so the chapters find
+ * `resources/`, and compares the frames rendered at a few fixed times
+ * against the references in `golden/<chapter>/`. A frame that strays too
+ * far gets a `-diff.png` next to it in `golden-out/<chapter>/`.
+ *
+ * Usage: golden_tests [--bless] [--bin DIR] [CHAPTER...]
+ *
+ * `--bless` replaces the references with the frames just rendered. `--bin`
+ * is where the chapter executables are, by default the current directory.
+ * Naming chapters restricts the run to them.
+ *
+ * Some chapters also have properties checked that must hold of every frame
+ * whatever the references say, so that blessing a broken frame does not
+ * go unnoticed. Those checks run with `--bless` too, as does a traced run
+ * of a few frames of every chapter, which fails on any call a driver would
+ * reject or that sets state to what it already was.
+ */
+
+extern mod lmath;
+extern mod common;
+
+use lmath::vec::vec3::Vec3;
+use lmath::mat::mat4::Mat4;
+
+use common::app::FRAME_TIME;
+use common::camera::{look_at, perspective, aspect_ratio};
+use common::golden::{Tolerance, compare, diff_image};
+use common::png;
+use common::png::Image;
+use common::soft::transform;
+
+/// A chapter and the frames of it that are checked.
+struct Case {
+    chapter: ~str,
+    frames: ~[uint],
+    /// An event file of input to replay while rendering.
+    replay: Option<~str>,
+}
+
+/// Frame n is rendered at n * FRAME_TIME seconds, so 15 is a quarter second.
+fn cases() -> ~[Case] {
+    ~[
+        Case { chapter: ~"c2_triangle", frames: ~[0], replay: None },
+        Case { chapter: ~"c2_triangle_uniform", frames: ~[0, 15, 30], replay: None },
+        Case { chapter: ~"c2_color_triangle", frames: ~[0], replay: None },
+        Case { chapter: ~"c2_streaming", frames: ~[0, 15, 30], replay: None },
+        Case { chapter: ~"c2_triangle_elements", frames: ~[0], replay: None },
+        Case { chapter: ~"c3_basic", frames: ~[0], replay: None },
+        Case { chapter: ~"c3_multitexture", frames: ~[0], replay: None },
+        Case { chapter: ~"c4_transformation", frames: ~[0, 15, 30], replay: None },
+        Case { chapter: ~"c4_instancing", frames: ~[0, 15, 30], replay: None },
+        Case { chapter: ~"c5_depth_stencil", frames: ~[0, 15, 30], replay: None },
+        // Premultiplied alpha, then additive blending, then multiplying
+        Case { chapter: ~"c5_blending", frames: ~[0, 15, 30],
+               replay: Some(~"resources/c5_blending.events") },
+        // Plain, then in grayscale, then in grayscale with edge detection
+        Case { chapter: ~"c6_framebuffers", frames: ~[0, 15, 30],
+               replay: Some(~"resources/c6_framebuffers.events") },
+        Case { chapter: ~"c7_geometry_shaders", frames: ~[0], replay: None },
+    ]
+}
+
+/// `m * v` for a matrix from `common::camera`.
+fn apply(m: &Mat4<f32>, v: [f32 * 4]) -> [f32 * 4] {
+    unsafe {
+        do vec::raw::buf_as_slice(m.to_ptr(), 16) |m| { transform(m, v) }
+    }
+}
+
+/**
+ * Whether pixel centre (`x`, `y`), counted from the bottom left, is inside
+ * the convex polygon `corners` or within `margin` pixels of it. The
+ * corners may go round either way.
+ */
+fn near_polygon(corners: &[(f32, f32)], x: f32, y: f32, margin: f32) -> bool {
+    let mut inside_left = true;
+    let mut inside_right = true;
+    for uint::range(0, corners.len()) |i| {
+        let (ax, ay) = corners[i];
+        let (bx, by) = corners[(i + 1) % corners.len()];
+        let length = f32::sqrt((bx - ax) * (bx - ax) + (by - ay) * (by - ay));
+        if length == 0.0 { loop; }
+        // The signed distance from the edge, positive on its left
+        let distance = ((bx - ax) * (y - ay) - (by - ay) * (x - ax)) / length;
+        if distance < -margin { inside_left = false; }
+        if distance > margin { inside_right = false; }
+    }
+    inside_left || inside_right
+}
+
+/**
+ * Checks that in frame `frame` of `c5_depth_stencil` the reflection of the
+ * cube shows on the floor and nowhere else. The scene is set up as the
+ * chapter sets it up: a unit cube turning half a turn a second above a
+ * 2 by 2 floor at z = -0.5, seen from (2.5, 2.5, 2).
+ */
+fn check_reflection(frame: uint, image: &Image) -> ~[~str] {
+    let view = look_at(&Vec3::new(2.5f32, 2.5f32, 2.0f32), &Vec3::new(0.0f32, 0.0f32, 0.0f32),
+                       &Vec3::new(0.0f32, 0.0f32, 1.0f32));
+    let proj = perspective(45.0, aspect_ratio(image.width, image.height), 1.0, 10.0);
+    let to_screen = |x: f32, y: f32, z: f32| {
+        let clip = apply(&proj, apply(&view, [x, y, z, 1.0]));
+        ((clip[0] / clip[3] + 1.0) * 0.5 * image.width as f32,
+         (clip[1] / clip[3] + 1.0) * 0.5 * image.height as f32)
+    };
+
+    let floor = ~[to_screen(-1.0, -1.0, -0.5), to_screen(1.0, -1.0, -0.5),
+                  to_screen(1.0, 1.0, -0.5), to_screen(-1.0, 1.0, -0.5)];
+
+    // The upright cube covers the union of its faces
+    let angle = (frame as float * FRAME_TIME) as f32 * f32::consts::pi;
+    let (c, s) = (f32::cos(angle), f32::sin(angle));
+    let corner = |x: f32, y: f32, z: f32| to_screen(c * x - s * y, s * x + c * y, z);
+    let mut faces = ~[];
+    for vec::each([-0.5f32, 0.5]) |side| {
+        let k = *side;
+        faces.push(~[corner(k, -0.5, -0.5), corner(k, 0.5, -0.5), corner(k, 0.5, 0.5), corner(k, -0.5, 0.5)]);
+        faces.push(~[corner(-0.5, k, -0.5), corner(0.5, k, -0.5), corner(0.5, k, 0.5), corner(-0.5, k, 0.5)]);
+        faces.push(~[corner(-0.5, -0.5, k), corner(0.5, -0.5, k), corner(0.5, 0.5, k), corner(-0.5, 0.5, k)]);
+    }
+
+    // Allow for pixels on the edges, which may be covered or not
+    let margin = 1.5f32;
+    let mut stray = 0u;
+    let mut reflected = 0u;
+    for uint::range(0, image.height) |row| {
+        for uint::range(0, image.width) |column| {
+            let at = (row * image.width + column) * 4;
+            let rgb = [image.rgba[at], image.rgba[at + 1], image.rgba[at + 2]];
+            // Rows are stored top first
+            let (x, y) = (column as f32 + 0.5, (image.height - row) as f32 - 0.5);
+
+            if faces.any(|face| near_polygon(*face, x, y, margin)) { loop; }
+
+            let background = vec::all(rgb, |c| *c >= 250);
+            let floor_color = vec::all(rgb, |c| *c <= 5);
+            if !near_polygon(floor, x, y, margin) {
+                if !background { stray += 1; }
+            } else if near_polygon(floor, x, y, -margin) && !background && !floor_color {
+                reflected += 1;
+            }
+        }
+    }
+
+    let mut failures = ~[];
+    if stray > 0 {
+        failures.push(fmt!("%u pixels drawn outside the floor and the cube; the reflection is not clipped",
+                           stray));
+    }
+    if reflected == 0 {
+        failures.push(~"no reflection shows on the floor");
+    }
+    move failures
+}
+
+/**
+ * Checks that frame `frame` of `c6_framebuffers` is gray all over from
+ * the time the replayed input switches the grayscale pass on, whatever
+ * other passes run after it.
+ */
+fn check_grayscale(frame: uint, image: &Image) -> ~[~str] {
+    // Frame n shows the input of the first n * FRAME_TIME seconds, and
+    // the key goes down 0.1s in
+    if frame as float * FRAME_TIME < 0.1 { return ~[]; }
+
+    let mut colored = 0u;
+    for uint::range(0, image.width * image.height) |i| {
+        let r = image.rgba[i * 4] as int;
+        let g = image.rgba[i * 4 + 1] as int;
+        let b = image.rgba[i * 4 + 2] as int;
+        // Allow for rounding
+        if int::abs(r - g) > 1 || int::abs(g - b) > 1 { colored += 1; }
+    }
+
+    if colored > 0 { ~[fmt!("%u pixels are not gray with the grayscale pass on", colored)] } else { ~[] }
+}
+
+/// The distance from (`x`, `y`) to the segment from `a` to `b`.
+fn distance_to_segment(a: (f32, f32), b: (f32, f32), x: f32, y: f32) -> f32 {
+    let ((ax, ay), (bx, by)) = (a, b);
+    let (dx, dy) = (bx - ax, by - ay);
+    let length2 = dx * dx + dy * dy;
+    let t = if length2 == 0.0 { 0.0 } else { ((x - ax) * dx + (y - ay) * dy) / length2 };
+    let t = if t < 0.0 { 0.0 } else if t > 1.0 { 1.0 } else { t };
+    let (ex, ey) = (ax + t * dx - x, ay + t * dy - y);
+    f32::sqrt(ex * ex + ey * ey)
+}
+
+/**
+ * Checks that `c7_geometry_shaders` draws the outline of a shape around
+ * each of its points, in the point's color, and nothing else. The
+ * outlines are worked out here as the geometry shader expands them: the
+ * point moved by 0.3 cos a and -0.4 sin a for `sides` angles a evenly
+ * round the circle, starting at 0.
+ */
+fn check_shapes(image: &Image) -> ~[~str] {
+    let points = [((-0.45f32, 0.45f32), 4u, [255u8, 0, 0]),
+                  ((0.45f32, 0.45f32), 8u, [0u8, 255, 0]),
+                  ((0.45f32, -0.45f32), 16u, [0u8, 0, 255]),
+                  ((-0.45f32, -0.45f32), 32u, [255u8, 255, 0])];
+    let to_screen = |x: f32, y: f32| {
+        ((x + 1.0) * 0.5 * image.width as f32, (y + 1.0) * 0.5 * image.height as f32)
+    };
+    let outlines = do vec::map(points) |point| {
+        let ((x, y), sides, _) = *point;
+        do vec::from_fn(sides) |i| {
+            let angle = f32::consts::pi * 2.0 / sides as f32 * i as f32;
+            to_screen(x + f32::cos(angle) * 0.3, y - f32::sin(angle) * 0.4)
+        }
+    };
+    let matches = |at: uint, color: &[u8 * 3]| {
+        let mut same = true;
+        for uint::range(0, 3) |k| {
+            if int::abs(image.rgba[at + k] as int - color[k] as int) > 8 { same = false; }
+        }
+        same
+    };
+
+    // Every pixel drawn lies on the outline of the shape of its color,
+    // allowing for the line being lit a pixel at a time
+    let margin = 1.5f32;
+    let mut stray = 0u;
+    for uint::range(0, image.height) |row| {
+        for uint::range(0, image.width) |column| {
+            let at = (row * image.width + column) * 4;
+            if vec::all([image.rgba[at], image.rgba[at + 1], image.rgba[at + 2]], |c| *c <= 5) { loop; }
+            // Rows are stored top first
+            let (x, y) = (column as f32 + 0.5, (image.height - row) as f32 - 0.5);
+
+            let mut on_outline = false;
+            for vec::eachi(points) |i, point| {
+                let (_, _, color) = *point;
+                if !matches(at, &color) { loop; }
+                let corners = &outlines[i];
+                for uint::range(0, corners.len()) |j| {
+                    let next = corners[(j + 1) % corners.len()];
+                    if distance_to_segment(corners[j], next, x, y) <= margin { on_outline = true; }
+                }
+            }
+            if !on_outline { stray += 1; }
+        }
+    }
+
+    // And every corner of every outline is drawn
+    let mut missing = 0u;
+    for vec::eachi(points) |i, point| {
+        let (_, _, color) = *point;
+        for outlines[i].each |corner| {
+            let (x, y) = *corner;
+            let (column, row) = (x as int, image.height as int - 1 - (y as int));
+            let mut found = false;
+            for int::range(row - 1, row + 2) |r| {
+                for int::range(column - 1, column + 2) |c| {
+                    if r < 0 || c < 0 || r >= image.height as int || c >= image.width as int { loop; }
+                    if matches(((r as uint) * image.width + c as uint) * 4, &color) { found = true; }
+                }
+            }
+            if !found { missing += 1; }
+        }
+    }
+
+    let mut failures = ~[];
+    if stray > 0 {
+        failures.push(fmt!("%u pixels drawn off the outlines of the shapes, or in the wrong color", stray));
+    }
+    if missing > 0 {
+        failures.push(fmt!("%u corners of the shapes not drawn", missing));
+    }
+    move failures
+}
+
+/// Checks the properties that must hold of frame `frame` of `chapter`.
+fn check_properties(chapter: &str, frame: uint, image: &Image) -> ~[~str] {
+    if str::eq_slice(chapter, "c5_depth_stencil") {
+        check_reflection(frame, image)
+    } else if str::eq_slice(chapter, "c6_framebuffers") {
+        check_grayscale(frame, image)
+    } else if str::eq_slice(chapter, "c7_geometry_shaders") {
+        check_shapes(image)
+    } else {
+        ~[]
+    }
+}
+
+fn frame_name(frame: uint) -> ~str {
+    fmt!("frame-%04u.png", frame)
+}
+
+/// Creates `dir` and any missing parents.
+fn make_dirs(dir: &Path) -> bool {
+    if os::path_is_dir(dir) { return true; }
+    // rwxr-xr-x
+    make_dirs(&dir.dir_path()) && os::make_dir(dir, 493)
+}
+
+/// The frames run with `--trace`, enough for the state of one frame to
+/// carry over into the next.
+const TRACED_FRAMES: uint = 3;
+
+/// Runs one chapter against the recording backend, returning the failures.
+fn check_trace(case: &Case, bin: &Path) -> ~[~str] {
+    let mut args = ~[~"--trace", ~"--frames", uint::to_str(TRACED_FRAMES, 10)];
+    match case.replay {
+        Some(ref path) => args.push_all([~"--replay", copy *path]),
+        None => (),
+    }
+    let result = run::program_output(bin.push(case.chapter).to_str(), args);
+    if result.status != 0 {
+        return ~[fmt!("%s: traced run exited with status %d\n%s", case.chapter, result.status,
+                      str::connect(str::lines(result.out).filtered(|l| str::starts_with(*l, "Error: ")), "\n"))];
+    }
+
+    let redundant = str::lines(result.out).filtered(|l| str::starts_with(*l, "Redundant: "));
+    if redundant.is_empty() {
+        io::println(fmt!("ok %s (traced)", case.chapter));
+        ~[]
+    } else {
+        ~[fmt!("%s: %u redundant state changes\n%s", case.chapter, redundant.len(), str::connect(redundant, "\n"))]
+    }
+}
+
+/// Runs one chapter and checks its frames, returning the failures.
+fn check(case: &Case, bin: &Path, bless: bool, tolerance: &Tolerance) -> ~[~str] {
+    let out = Path("golden-out").push(case.chapter);
+    let golden = Path("golden").push(case.chapter);
+    if !make_dirs(&out) || (bless && !make_dirs(&golden)) {
+        return ~[fmt!("%s: cannot create output directories", case.chapter)];
+    }
+
+    let frames = vec::foldl(0, case.frames, |a, b| uint::max(a, *b)) + 1;
+    let mut args = ~[~"--headless", ~"--frames", uint::to_str(frames, 10), ~"--out", out.to_str()];
+    match case.replay {
+        Some(ref path) => args.push_all([~"--replay", copy *path]),
+        None => (),
+    }
+    let result = run::program_output(bin.push(case.chapter).to_str(), args);
+    if result.status != 0 {
+        return ~[fmt!("%s: exited with status %d\n%s%s", case.chapter, result.status,
+                      result.out, result.err)];
+    }
+
+    let mut failures = ~[];
+    for case.frames.each |frame| {
+        let name = fmt!("%s/%s", case.chapter, frame_name(*frame));
+        let time = *frame as float * FRAME_TIME;
+
+        let actual = match png::read(&out.push(frame_name(*frame))) {
+            Ok(move image) => image,
+            Err(move msg) => {
+                failures.push(fmt!("%s: %s", name, msg));
+                loop;
+            }
+        };
+
+        for check_properties(case.chapter, *frame, &actual).each |failure| {
+            failures.push(fmt!("%s (t = %fs): %s", name, time, *failure));
+        }
+
+        if bless {
+            match png::write(&golden.push(frame_name(*frame)), actual.width, actual.height, actual.rgba) {
+                Ok(()) => io::println(fmt!("blessed %s (t = %fs)", name, time)),
+                Err(move msg) => failures.push(fmt!("%s: %s", name, msg)),
+            }
+            loop;
+        }
+
+        let expected = match png::read(&golden.push(frame_name(*frame))) {
+            Ok(move image) => image,
+            Err(move msg) => {
+                failures.push(fmt!("%s: no usable reference, run with --bless to create it (%s)",
+                                   name, msg));
+                loop;
+            }
+        };
+
+        match compare(&actual, &expected, tolerance) {
+            Ok(comparison) if comparison.passes(tolerance) => {
+                io::println(fmt!("ok %s (t = %fs)", name, time));
+            }
+            Ok(comparison) => {
+                let diff = diff_image(&actual, &expected, tolerance);
+                let path = out.push(fmt!("frame-%04u-diff.png", *frame));
+                let _ = png::write(&path, diff.width, diff.height, diff.rgba);
+                failures.push(fmt!("%s (t = %fs): %s, see %s", name, time,
+                                   comparison.to_str(), path.to_str()));
+            }
+            Err(move msg) => failures.push(fmt!("%s: %s", name, msg)),
+        }
+    }
+
+    move failures
+}
+
+fn main() {
+    let args = os::args();
+    let mut bless = false;
+    let mut bin = Path(".");
+    let mut chapters = ~[];
+
+    let mut i = 1;
+    while i < args.len() {
+        if args[i] == ~"--bless" {
+            bless = true;
+        } else if args[i] == ~"--bin" && i + 1 < args.len() {
+            bin = Path(args[i + 1]);
+            i += 1;
+        } else {
+            chapters.push(copy args[i]);
+        }
+        i += 1;
+    }
+
+    let tolerance = Tolerance::default();
+    let mut failures = ~[];
+    for cases().each |case| {
+        if chapters.is_empty() || vec::contains(chapters, &case.chapter) {
+            failures.push_all(check(case, &bin, bless, &tolerance));
+            failures.push_all(check_trace(case, &bin));
+        }
+    }
+
+    for failures.each |failure| {
+        io::println(~"FAILED " + *failure);
+    }
+    if !failures.is_empty() {
+        io::println(fmt!("%u failures", failures.len()));
+        os::set_exit_status(1);
+    }
+}
@@ -0,0 +1,8 @@
+#version 150
+
+#include "colored.glsl"
+
+void main() {
+    Color = color;
+    gl_Position = vec4(position, 0.0, 1.0);
+}
@@ -0,0 +1,9 @@
+#version 150
+
+#include "textured.glsl"
+
+void main() {
+    Color = color;
+    Texcoord = texcoord;
+    gl_Position = vec4(position, 0.0, 1.0);
+}
@@ -0,0 +1,18 @@
+#version 150
+
+#include "textured.glsl"
+
+// Where this instance of the quad goes, read once per instance
+in mat4 instanceModel;
+
+layout(std140) uniform Transform {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+};
+
+void main() {
+    Color = color;
+    Texcoord = texcoord;
+    gl_Position = proj * view * instanceModel * model * vec4(position, 0.0, 1.0);
+}
@@ -0,0 +1,10 @@
+#version 150
+
+in vec3 Color;
+in vec2 Texcoord;
+out vec4 outColor;
+uniform sampler2D texKitten;
+uniform sampler2D texPuppy;
+void main() {
+    outColor = mix(texture(texKitten, Texcoord), texture(texPuppy, Texcoord), MIX_FACTOR);
+}
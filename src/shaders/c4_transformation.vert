@@ -0,0 +1,15 @@
+#version 150
+
+#include "textured.glsl"
+
+layout(std140) uniform Transform {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+};
+
+void main() {
+    Color = color;
+    Texcoord = texcoord;
+    gl_Position = proj * view * model * vec4(position, 0.0, 1.0);
+}
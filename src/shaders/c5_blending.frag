@@ -0,0 +1,10 @@
+#version 150
+
+in vec3 Color;
+in vec2 Texcoord;
+out vec4 outColor;
+uniform sampler2D tex;
+void main() {
+    // The texture's colors are premultiplied by its alpha, and stay so tinted
+    outColor = texture(tex, Texcoord) * vec4(Color, 1.0);
+}
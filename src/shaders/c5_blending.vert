@@ -0,0 +1,16 @@
+#version 150
+
+in vec3 position;
+in vec3 color;
+in vec2 texcoord;
+out vec3 Color;
+out vec2 Texcoord;
+
+uniform mat4 view;
+uniform mat4 proj;
+
+void main() {
+    Color = color;
+    Texcoord = texcoord;
+    gl_Position = proj * view * vec4(position, 1.0);
+}
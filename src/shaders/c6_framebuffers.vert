@@ -0,0 +1,21 @@
+#version 150
+
+in vec3 position;
+in vec3 color;
+in vec2 texcoord;
+out vec3 Color;
+out vec2 Texcoord;
+
+layout(std140) uniform Transform {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+};
+
+uniform vec3 overrideColor;
+
+void main() {
+    Color = overrideColor * color;
+    Texcoord = texcoord;
+    gl_Position = proj * view * model * vec4(position, 1.0);
+}
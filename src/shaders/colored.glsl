@@ -0,0 +1,5 @@
+// The inputs of a vertex with a position and a color, which is passed on
+// to the fragment shader as Color
+in vec2 position;
+in vec3 color;
+out vec3 Color;
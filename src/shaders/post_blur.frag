@@ -0,0 +1,16 @@
+#version 150
+
+in vec2 Texcoord;
+out vec4 outColor;
+uniform sampler2D texFramebuffer;
+void main() {
+    // Average the 3x3 texels around this one
+    vec2 texel = 1.0 / vec2(textureSize(texFramebuffer, 0));
+    vec3 sum = vec3(0.0);
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            sum += texture(texFramebuffer, Texcoord + vec2(x, y) * texel).rgb;
+        }
+    }
+    outColor = vec4(sum / 9.0, 1.0);
+}
@@ -0,0 +1,10 @@
+#version 150
+
+in vec2 Texcoord;
+out vec4 outColor;
+uniform sampler2D texFramebuffer;
+void main() {
+    // Weigh the channels by how bright they look
+    float luma = dot(texture(texFramebuffer, Texcoord).rgb, vec3(0.2126, 0.7152, 0.0722));
+    outColor = vec4(luma, luma, luma, 1.0);
+}
@@ -0,0 +1,24 @@
+#version 150
+
+in vec2 Texcoord;
+out vec4 outColor;
+uniform sampler2D texFramebuffer;
+
+vec3 at(float x, float y) {
+    vec2 texel = 1.0 / vec2(textureSize(texFramebuffer, 0));
+    return texture(texFramebuffer, Texcoord + vec2(x, y) * texel).rgb;
+}
+
+void main() {
+    vec3 topLeft = at(-1.0, 1.0);
+    vec3 top = at(0.0, 1.0);
+    vec3 topRight = at(1.0, 1.0);
+    vec3 left = at(-1.0, 0.0);
+    vec3 right = at(1.0, 0.0);
+    vec3 bottomLeft = at(-1.0, -1.0);
+    vec3 bottom = at(0.0, -1.0);
+    vec3 bottomRight = at(1.0, -1.0);
+    vec3 sx = -topLeft - 2.0 * left - bottomLeft + topRight + 2.0 * right + bottomRight;
+    vec3 sy = -topLeft - 2.0 * top - topRight + bottomLeft + 2.0 * bottom + bottomRight;
+    outColor = vec4(sqrt(sx * sx + sy * sy), 1.0);
+}
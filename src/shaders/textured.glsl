@@ -0,0 +1,4 @@
+// As colored.glsl, plus texture coordinates passed on as Texcoord
+#include "colored.glsl"
+in vec2 texcoord;
+out vec2 Texcoord;